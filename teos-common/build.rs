@@ -11,9 +11,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "#[serde(with = \"crate::ser::serde_vec_bytes\")]",
         )
         .field_attribute("encrypted_blob", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("backup", "#[serde(with = \"hex::serde\")]")
         .field_attribute("dispute_txid", "#[serde(with = \"crate::ser::serde_be\")]")
         .field_attribute("penalty_txid", "#[serde(with = \"crate::ser::serde_be\")]")
         .field_attribute("penalty_rawtx", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("AppointmentOutcome.uuid", "#[serde(with = \"hex::serde\")]")
+        .field_attribute(
+            "AppointmentOutcome.kind",
+            "#[serde(with = \"crate::ser::serde_failure_kind\")]",
+        )
+        .field_attribute("RegisterRequest.plan", "#[serde(default)]")
+        .field_attribute("RegisterRequest.pow_nonce", "#[serde(default)]")
+        .field_attribute("RegisterRequest.registration_token", "#[serde(default)]")
+        .field_attribute("RegisterResponse.invoice", "#[serde(default)]")
+        .field_attribute(
+            "GetSubscriptionPlansResponse.pow_difficulty",
+            "#[serde(default)]",
+        )
+        .field_attribute(
+            "GetSubscriptionPlansResponse.registration_token_required",
+            "#[serde(default)]",
+        )
         .field_attribute(
             "GetAppointmentResponse.status",
             "#[serde(with = \"crate::ser::serde_status\")]",
@@ -2,10 +2,14 @@
 
 use serde::{Deserialize, Serialize};
 use std::array::TryFromSliceError;
-use std::{convert::TryInto, fmt};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+};
 
 use bitcoin::Txid;
 
+use crate::cryptography::DecryptingError;
 use crate::protos as msgs;
 
 pub const LOCATOR_LEN: usize = 16;
@@ -75,6 +79,8 @@ pub enum AppointmentStatus {
     NotFound = 0,
     BeingWatched = 1,
     DisputeResponded = 2,
+    DisputeResolved = 3,
+    PenaltyRejected = 4,
 }
 
 impl From<i32> for AppointmentStatus {
@@ -82,6 +88,8 @@ impl From<i32> for AppointmentStatus {
         match x {
             1 => AppointmentStatus::BeingWatched,
             2 => AppointmentStatus::DisputeResponded,
+            3 => AppointmentStatus::DisputeResolved,
+            4 => AppointmentStatus::PenaltyRejected,
             _ => AppointmentStatus::NotFound,
         }
     }
@@ -94,6 +102,8 @@ impl std::str::FromStr for AppointmentStatus {
         match s {
             "being_watched" => Ok(AppointmentStatus::BeingWatched),
             "dispute_responded" => Ok(AppointmentStatus::DisputeResponded),
+            "dispute_resolved" => Ok(AppointmentStatus::DisputeResolved),
+            "penalty_rejected" => Ok(AppointmentStatus::PenaltyRejected),
             "not_found" => Ok(AppointmentStatus::NotFound),
             _ => Err(format!("Unknown status: {s}")),
         }
@@ -105,12 +115,71 @@ impl fmt::Display for AppointmentStatus {
         let s = match self {
             AppointmentStatus::BeingWatched => "being_watched",
             AppointmentStatus::DisputeResponded => "dispute_responded",
+            AppointmentStatus::DisputeResolved => "dispute_resolved",
+            AppointmentStatus::PenaltyRejected => "penalty_rejected",
             AppointmentStatus::NotFound => "not_found",
         };
         write!(f, "{s}")
     }
 }
 
+/// The reason why the tower could not respond to a triggered appointment.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The encrypted blob could not be decrypted using the dispute transaction id.
+    Decryption = 0,
+    /// The decrypted blob is not a valid transaction.
+    Deserialization = 1,
+    /// The penalty transaction was rejected by bitcoind.
+    Rejected = 2,
+}
+
+impl TryFrom<i32> for FailureKind {
+    type Error = String;
+
+    fn try_from(x: i32) -> Result<Self, Self::Error> {
+        match x {
+            0 => Ok(FailureKind::Decryption),
+            1 => Ok(FailureKind::Deserialization),
+            2 => Ok(FailureKind::Rejected),
+            x => Err(format!("Unknown failure kind: {x}")),
+        }
+    }
+}
+
+impl From<&DecryptingError> for FailureKind {
+    fn from(e: &DecryptingError) -> Self {
+        match e {
+            DecryptingError::AED(_) => FailureKind::Decryption,
+            DecryptingError::Encode(_) => FailureKind::Deserialization,
+        }
+    }
+}
+
+impl std::str::FromStr for FailureKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decryption" => Ok(FailureKind::Decryption),
+            "deserialization" => Ok(FailureKind::Deserialization),
+            "rejected" => Ok(FailureKind::Rejected),
+            _ => Err(format!("Unknown failure kind: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            FailureKind::Decryption => "decryption",
+            FailureKind::Deserialization => "deserialization",
+            FailureKind::Rejected => "rejected",
+        };
+        write!(f, "{s}")
+    }
+}
+
 impl Appointment {
     /// Creates a new [Appointment] instance.
     pub fn new(locator: Locator, encrypted_blob: Vec<u8>, to_self_delay: u32) -> Self {
@@ -0,0 +1,55 @@
+//! Logic related to user backups shared between users and the towers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::protos as msgs;
+
+/// An opaque blob of data a user stores with the tower (e.g. an encrypted static channel backup).
+///
+/// The tower keeps a single backup per user. Backups are versioned so an outdated backup cannot replace a newer one.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    /// The encrypted backup data. The tower cannot read it.
+    #[serde(with = "hex::serde")]
+    pub data: Vec<u8>,
+    /// The backup version. Must be strictly increasing.
+    pub version: u32,
+}
+
+impl Backup {
+    /// Creates a new [Backup] instance.
+    pub fn new(data: Vec<u8>, version: u32) -> Self {
+        Backup { data, version }
+    }
+
+    /// Serializes a backup to be signed.
+    ///
+    /// `data || version`
+    ///
+    /// All values are big endian.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut result = self.data.clone();
+        result.extend(self.version.to_be_bytes().to_vec());
+        result
+    }
+}
+
+impl From<Backup> for msgs::GetBackupResponse {
+    fn from(b: Backup) -> Self {
+        Self {
+            backup: b.data,
+            version: b.version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_vec() {
+        let backup = Backup::new(vec![1, 2, 3], 258);
+        assert_eq!(backup.to_vec(), vec![1, 2, 3, 0, 0, 1, 2]);
+    }
+}
@@ -7,3 +7,5 @@ pub const IRREVOCABLY_RESOLVED: u32 = 100;
 // Temporary constants, may be changed
 /// Maximum size of encrypted blobs in appointments.
 pub const ENCRYPTED_BLOB_MAX_SIZE: usize = 2048;
+/// Maximum size of user backups.
+pub const BACKUP_MAX_SIZE: usize = 65536;
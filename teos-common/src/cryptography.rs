@@ -75,6 +75,49 @@ pub fn decrypt(encrypted_blob: &[u8], secret: &Txid) -> Result<Transaction, Decr
     }
 }
 
+/// Derives the key used to encrypt user backups from the user secret key.
+fn backup_key(sk: &SecretKey) -> sha256::Hash {
+    let mut material = b"backup".to_vec();
+    material.extend_from_slice(&sk.secret_bytes());
+    sha256::Hash::hash(&material)
+}
+
+/// Encrypts a user backup using `chacha20poly1305` and a key derived from the user secret key.
+///
+/// Given the same key is used for every version of the backup, a random nonce is drawn for each encryption.
+/// The output is `nonce || ciphertext`.
+pub fn encrypt_backup(
+    data: &[u8],
+    sk: &SecretKey,
+) -> Result<Vec<u8>, chacha20poly1305::aead::Error> {
+    let nonce_bytes = get_random_bytes(12);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+    let _k = backup_key(sk);
+    let key = Key::from_slice(&_k);
+
+    let cypher = ChaCha20Poly1305::new(key);
+    let mut encrypted = nonce_bytes.clone();
+    encrypted.extend(cypher.encrypt(nonce, data)?);
+    Ok(encrypted)
+}
+
+/// Decrypts a user backup encrypted with [encrypt_backup].
+pub fn decrypt_backup(
+    encrypted_backup: &[u8],
+    sk: &SecretKey,
+) -> Result<Vec<u8>, chacha20poly1305::aead::Error> {
+    if encrypted_backup.len() < 12 {
+        return Err(chacha20poly1305::aead::Error);
+    }
+    let (nonce_bytes, ciphertext) = encrypted_backup.split_at(12);
+    let nonce = Nonce::from_slice(nonce_bytes);
+    let _k = backup_key(sk);
+    let key = Key::from_slice(&_k);
+
+    let cypher = ChaCha20Poly1305::new(key);
+    cypher.decrypt(nonce, ciphertext)
+}
+
 /// Utility function to create a vector of pseudo random bytes.
 ///
 /// Mainly used for testing purposes.
@@ -121,4 +164,22 @@ mod tests {
         let txid = Txid::from_hex(HEX_TXID).unwrap();
         assert_eq!(decrypt(&encrypted_blob, &txid).unwrap(), expected_tx);
     }
+
+    #[test]
+    fn test_encrypt_decrypt_backup() {
+        let (sk, _) = get_random_keypair();
+        let data = get_random_bytes(100);
+
+        let encrypted = encrypt_backup(&data, &sk).unwrap();
+        assert_ne!(encrypted[12..], data[..]);
+        assert_eq!(decrypt_backup(&encrypted, &sk).unwrap(), data);
+
+        // Encrypting the same data twice yields different ciphertexts (a fresh nonce is used every time)
+        assert_ne!(encrypt_backup(&data, &sk).unwrap(), encrypted);
+
+        // Using the wrong key, or tampered data, fails
+        let (other_sk, _) = get_random_keypair();
+        assert!(decrypt_backup(&encrypted, &other_sk).is_err());
+        assert!(decrypt_backup(&encrypted[..10], &sk).is_err());
+    }
 }
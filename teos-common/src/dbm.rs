@@ -22,6 +22,7 @@ pub trait DatabaseConnection {
 
 pub trait DatabaseManager: Sized {
     fn create_tables(&mut self, tables: Vec<&str>) -> Result<(), SqliteError>;
+    fn add_column(&self, table: &str, column: &str, definition: &str) -> Result<(), SqliteError>;
     fn store_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error>;
     fn remove_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error>;
     fn update_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error>;
@@ -37,6 +38,25 @@ impl<T: DatabaseConnection> DatabaseManager for T {
         tx.commit()
     }
 
+    /// Adds a column to a given table if not present. Used to upgrade databases created by older versions.
+    fn add_column(&self, table: &str, column: &str, definition: &str) -> Result<(), SqliteError> {
+        let connection = self.get_connection();
+        let exists: bool = connection.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name=(?2)",
+            [table, column],
+            |row| row.get(0),
+        )?;
+
+        if !exists {
+            connection.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
     /// Generic method to store data into the database.
     fn store_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error> {
         match self.get_connection().execute(query, params) {
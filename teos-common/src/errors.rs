@@ -6,6 +6,7 @@ pub const WRONG_FIELD_SIZE: u8 = 4;
 pub const WRONG_FIELD_FORMAT: u8 = 5;
 pub const INVALID_REQUEST_FORMAT: u8 = 6;
 pub const INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR: u8 = 7;
+pub const RATE_LIMIT_EXCEEDED: u8 = 8;
 pub const SERVICE_UNAVAILABLE: u8 = 32;
 
 /// Appointment errors [33, 64]
@@ -16,6 +17,94 @@ pub const APPOINTMENT_NOT_FOUND: u8 = 36;
 
 /// Registration errors [65, 96]
 pub const REGISTRATION_RESOURCE_EXHAUSTED: u8 = 65;
+pub const REGISTRATION_NOT_ALLOWED: u8 = 66;
+pub const REGISTRATION_POW_REQUIRED: u8 = 67;
+pub const REGISTRATION_TOKEN_REQUIRED: u8 = 68;
+
+/// Backup errors [97, 128]
+pub const BACKUP_TOO_BIG: u8 = 97;
+pub const BACKUP_OUTDATED_VERSION: u8 = 98;
+pub const BACKUP_NOT_FOUND: u8 = 99;
 
 /// UNHANDLED
 pub const UNEXPECTED_ERROR: u8 = 255;
+
+/// Maps the [Status](tonic::Status) returned by the public gRPC interface of the tower to the error code the HTTP API
+/// reports for it, so users get the same errors no matter how they reach the tower.
+pub fn status_error_code(status: &tonic::Status) -> u8 {
+    match status.code() {
+        tonic::Code::InvalidArgument => WRONG_FIELD_FORMAT,
+        tonic::Code::NotFound => APPOINTMENT_NOT_FOUND,
+        tonic::Code::AlreadyExists => APPOINTMENT_ALREADY_TRIGGERED,
+        // Requests going over the rate limits are only refused by the public gRPC interface (the HTTP API checks them itself)
+        tonic::Code::ResourceExhausted if status.message().starts_with("Too many requests") => {
+            RATE_LIMIT_EXCEEDED
+        }
+        tonic::Code::ResourceExhausted => REGISTRATION_RESOURCE_EXHAUSTED,
+        tonic::Code::PermissionDenied => {
+            if status
+                .message()
+                .starts_with("Registration requires a valid proof of work")
+            {
+                REGISTRATION_POW_REQUIRED
+            } else if status
+                .message()
+                .starts_with("Registration requires a valid registration token")
+            {
+                REGISTRATION_TOKEN_REQUIRED
+            } else {
+                REGISTRATION_NOT_ALLOWED
+            }
+        }
+        // Both oversized backups and appointments are reported as out of range
+        tonic::Code::OutOfRange if status.message().starts_with("The provided appointment") => {
+            APPOINTMENT_FIELD_TOO_BIG
+        }
+        tonic::Code::OutOfRange => BACKUP_TOO_BIG,
+        tonic::Code::FailedPrecondition => BACKUP_OUTDATED_VERSION,
+        tonic::Code::Unauthenticated => INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR,
+        tonic::Code::Unavailable => SERVICE_UNAVAILABLE,
+        _ => UNEXPECTED_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_error_code() {
+        assert_eq!(
+            status_error_code(&tonic::Status::resource_exhausted(
+                "Subscription maximum slots count reached"
+            )),
+            REGISTRATION_RESOURCE_EXHAUSTED
+        );
+        assert_eq!(
+            status_error_code(&tonic::Status::resource_exhausted(
+                "Too many requests from this user. Try again later"
+            )),
+            RATE_LIMIT_EXCEEDED
+        );
+        assert_eq!(
+            status_error_code(&tonic::Status::permission_denied(
+                "Registration requires a valid proof of work (difficulty: 8)"
+            )),
+            REGISTRATION_POW_REQUIRED
+        );
+        assert_eq!(
+            status_error_code(&tonic::Status::permission_denied(
+                "The user is banned from the tower"
+            )),
+            REGISTRATION_NOT_ALLOWED
+        );
+        assert_eq!(
+            status_error_code(&tonic::Status::unauthenticated("Invalid signature")),
+            INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+        );
+        assert_eq!(
+            status_error_code(&tonic::Status::internal("Something went wrong")),
+            UNEXPECTED_ERROR
+        );
+    }
+}
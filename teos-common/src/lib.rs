@@ -9,11 +9,13 @@ pub mod protos {
 }
 
 pub mod appointment;
+pub mod backup;
 pub mod constants;
 pub mod cryptography;
 pub mod dbm;
 pub mod errors;
 pub mod net;
+pub mod pow;
 pub mod receipts;
 pub mod ser;
 pub mod test_utils;
@@ -0,0 +1,150 @@
+//! Minimal client for the Core Lightning (CLN) JSON-RPC interface.
+//!
+//! CLN exposes its RPC over a unix socket (`lightning-rpc` under the network directory). Only what is needed to
+//! issue, check and pay invoices is covered here, so callers pass the method name and params straight through.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// Errors that can be returned when calling the CLN RPC.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClnRpcError {
+    /// The RPC socket could not be reached, or the connection was dropped before getting a response.
+    Connection(String),
+    /// CLN replied with an error.
+    Rpc { code: i64, message: String },
+    /// CLN replied with something that is not a valid JSON-RPC response.
+    UnexpectedResponse(String),
+}
+
+impl fmt::Display for ClnRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClnRpcError::Connection(e) => write!(f, "Cannot reach the CLN RPC: {e}"),
+            ClnRpcError::Rpc { code, message } => write!(f, "CLN RPC error {code}: {message}"),
+            ClnRpcError::UnexpectedResponse(e) => write!(f, "Unexpected CLN RPC response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClnRpcError {}
+
+/// Client for the CLN JSON-RPC. A new connection is opened for every call.
+#[derive(Debug, Clone)]
+pub struct ClnRpc {
+    path: PathBuf,
+}
+
+impl ClnRpc {
+    /// Creates a new [ClnRpc] instance given the path to the RPC socket.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ClnRpc {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Calls an RPC `method` with the given `params`, returning the `result` field of the response.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, ClnRpcError> {
+        let mut stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(|e| ClnRpcError::Connection(e.to_string()))?;
+
+        let request = json!({"jsonrpc": "2.0", "id": 0, "method": method, "params": params});
+        stream
+            .write_all(request.to_string().as_bytes())
+            .await
+            .map_err(|e| ClnRpcError::Connection(e.to_string()))?;
+
+        // Responses are not length-prefixed, so keep reading until a whole JSON object has been received.
+        let mut buf = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let n = stream
+                .read(&mut chunk)
+                .await
+                .map_err(|e| ClnRpcError::Connection(e.to_string()))?;
+            if n == 0 {
+                return Err(ClnRpcError::Connection(
+                    "Connection closed before receiving a response".to_owned(),
+                ));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+
+            match serde_json::from_slice::<Value>(&buf) {
+                Ok(response) => return parse_response(response),
+                Err(e) if e.is_eof() => continue,
+                Err(e) => return Err(ClnRpcError::UnexpectedResponse(e.to_string())),
+            }
+        }
+    }
+}
+
+/// Extracts the `result` from a JSON-RPC response, or the error if the call failed.
+fn parse_response(mut response: Value) -> Result<Value, ClnRpcError> {
+    if let Some(error) = response.get("error") {
+        return Err(ClnRpcError::Rpc {
+            code: error["code"].as_i64().unwrap_or_default(),
+            message: error["message"].as_str().unwrap_or_default().to_owned(),
+        });
+    }
+
+    match response.get_mut("result") {
+        Some(result) => Ok(result.take()),
+        None => Err(ClnRpcError::UnexpectedResponse(
+            "Missing result field".to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::run_cln_rpc_mock;
+
+    #[tokio::test]
+    async fn test_call() {
+        let path = run_cln_rpc_mock(
+            |method, params| json!({"result": {"method": method, "params": params.clone()}}),
+        )
+        .await;
+
+        let result = ClnRpc::new(&path)
+            .call("invoice", json!({"amount_msat": 1000}))
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            json!({"method": "invoice", "params": {"amount_msat": 1000}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_rpc_error() {
+        let path = run_cln_rpc_mock(
+            |_, _| json!({"error": {"code": 205, "message": "Could not find a route"}}),
+        )
+        .await;
+
+        assert_eq!(
+            ClnRpc::new(&path).call("pay", json!({})).await,
+            Err(ClnRpcError::Rpc {
+                code: 205,
+                message: "Could not find a route".to_owned()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_unreachable() {
+        let rpc = ClnRpc::new("/nonexistent/lightning-rpc");
+        assert!(matches!(
+            rpc.call("getinfo", json!({})).await,
+            Err(ClnRpcError::Connection(_))
+        ));
+    }
+}
@@ -1,8 +1,13 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Endpoint {
     Register,
     AddAppointment,
     GetAppointment,
     GetSubscriptionInfo,
+    StoreBackup,
+    GetBackup,
+    GetAppointmentOutcomes,
+    GetSubscriptionPlans,
     Ping,
 }
 
@@ -16,6 +21,10 @@ impl std::fmt::Display for Endpoint {
                 Endpoint::AddAppointment => "add_appointment",
                 Endpoint::GetAppointment => "get_appointment",
                 Endpoint::GetSubscriptionInfo => "get_subscription_info",
+                Endpoint::StoreBackup => "store_backup",
+                Endpoint::GetBackup => "get_backup",
+                Endpoint::GetAppointmentOutcomes => "get_appointment_outcomes",
+                Endpoint::GetSubscriptionPlans => "get_subscription_plans",
                 Endpoint::Ping => "ping",
             }
         )
@@ -1,21 +1,30 @@
+pub mod cln_rpc;
 pub mod http;
 
 use serde::Serialize;
+use std::convert::TryFrom;
 use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 /// Represents all types of teos network addresses
 #[derive(Clone, Serialize, Debug, PartialEq, Eq)]
 pub enum AddressType {
     IpV4 = 0,
     TorV3 = 1,
+    IpV6 = 2,
+    Hostname = 3,
 }
 
-impl From<i32> for AddressType {
-    fn from(x: i32) -> Self {
+impl TryFrom<i32> for AddressType {
+    type Error = String;
+
+    fn try_from(x: i32) -> Result<Self, Self::Error> {
         match x {
-            0 => AddressType::IpV4,
-            1 => AddressType::TorV3,
-            x => panic!("Unknown address type {}", x),
+            0 => Ok(AddressType::IpV4),
+            1 => Ok(AddressType::TorV3),
+            2 => Ok(AddressType::IpV6),
+            3 => Ok(AddressType::Hostname),
+            x => Err(format!("Unknown address type: {x}")),
         }
     }
 }
@@ -27,6 +36,8 @@ impl std::str::FromStr for AddressType {
         match s {
             "ipv4" => Ok(AddressType::IpV4),
             "torv3" => Ok(AddressType::TorV3),
+            "ipv6" => Ok(AddressType::IpV6),
+            "hostname" => Ok(AddressType::Hostname),
             _ => Err(format!("Unknown type: {s}")),
         }
     }
@@ -37,17 +48,26 @@ impl fmt::Display for AddressType {
         let s = match self {
             AddressType::IpV4 => "ipv4",
             AddressType::TorV3 => "torv3",
+            AddressType::IpV6 => "ipv6",
+            AddressType::Hostname => "hostname",
         };
         write!(f, "{s}")
     }
 }
 
 impl AddressType {
+    /// Gets the type of a network address. The address may be prefixed by its scheme and followed by its port.
+    /// IPv6 literals must be enclosed in brackets if followed by a port (e.g. `[::1]:9814`).
     pub fn get_type(net_addr: &str) -> AddressType {
-        if net_addr.contains(".onion:") {
+        let host = get_host(net_addr);
+        if host.ends_with(".onion") {
             AddressType::TorV3
-        } else {
+        } else if host.parse::<Ipv4Addr>().is_ok() {
             AddressType::IpV4
+        } else if host.parse::<Ipv6Addr>().is_ok() {
+            AddressType::IpV6
+        } else {
+            AddressType::Hostname
         }
     }
 
@@ -56,15 +76,45 @@ impl AddressType {
     }
 
     pub fn is_clearnet(&self) -> bool {
-        self == &AddressType::IpV4
+        !self.is_tor()
+    }
+}
+
+/// Gets the host of a network address, stripping its scheme, port and the brackets of IPv6 literals (if any).
+pub fn get_host(net_addr: &str) -> &str {
+    let addr = net_addr
+        .split_once("://")
+        .map_or(net_addr, |(_, addr)| addr);
+    if let Some(addr) = addr.strip_prefix('[') {
+        addr.split(']').next().unwrap()
+    } else if addr.parse::<Ipv6Addr>().is_ok() {
+        // Unbracketed IPv6 literals cannot be followed by a port
+        addr
+    } else {
+        addr.rsplit_once(':').map_or(addr, |(host, _)| host)
     }
 }
 
+/// Formats a host so it can be followed by a port, enclosing IPv6 literals in brackets.
+pub fn format_host(host: &str) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{host}]")
+    } else {
+        host.to_owned()
+    }
+}
+
+/// Scheme of the addresses of towers reached through their public gRPC interface.
+pub const GRPC_SCHEME: &str = "grpcs://";
+
 #[derive(Clone, Serialize, Debug, PartialEq, Eq)]
 pub struct NetAddr {
     net_addr: String,
     #[serde(skip)]
     addr_type: AddressType,
+    /// PEM certificate the tower is pinned to. Only used for `https` addresses.
+    #[serde(skip)]
+    tls_cert: Option<String>,
 }
 
 impl NetAddr {
@@ -72,9 +122,16 @@ impl NetAddr {
         NetAddr {
             addr_type: AddressType::get_type(&net_addr),
             net_addr,
+            tls_cert: None,
         }
     }
 
+    /// Pins the address to a given certificate (or removes the pin if `None`).
+    pub fn with_tls_cert(mut self, tls_cert: Option<String>) -> Self {
+        self.tls_cert = tls_cert;
+        self
+    }
+
     pub fn net_addr(&self) -> &str {
         &self.net_addr
     }
@@ -86,6 +143,35 @@ impl NetAddr {
     pub fn is_onion(&self) -> bool {
         self.addr_type().is_tor()
     }
+
+    pub fn is_https(&self) -> bool {
+        self.net_addr.starts_with("https://")
+    }
+
+    /// Whether the tower is reached through its public gRPC interface (`grpcs://` addresses) instead of the HTTP API.
+    pub fn is_grpc(&self) -> bool {
+        self.net_addr.starts_with(GRPC_SCHEME)
+    }
+
+    /// Whether the connection to the tower is secured by TLS, and therefore its certificate can be pinned.
+    pub fn is_tls(&self) -> bool {
+        self.is_https() || self.is_grpc()
+    }
+
+    /// Gets the URL the public gRPC interface of the tower is reached at. gRPC runs over HTTP/2, so `grpcs://`
+    /// addresses map to `https://` URLs.
+    pub fn grpc_url(&self) -> String {
+        format!(
+            "https://{}",
+            self.net_addr
+                .strip_prefix(GRPC_SCHEME)
+                .unwrap_or(&self.net_addr)
+        )
+    }
+
+    pub fn tls_cert(&self) -> Option<&str> {
+        self.tls_cert.as_deref()
+    }
 }
 
 #[cfg(test)]
@@ -94,12 +180,62 @@ pub mod tests {
 
     pub const TORV3_ADDR: &str =
         "recnedb7xfhzjdrcgxongzli3a6qyrv5jwgowoho3v5g3rwk7kkglrid.onion:9814";
-    pub const IPV4_ADDR: &str = "teos.talaia.watch:9814";
+    pub const IPV4_ADDR: &str = "127.0.0.1:9814";
+    pub const IPV6_ADDR: &str = "[2001:db8::1]:9814";
+    pub const HOSTNAME_ADDR: &str = "teos.talaia.watch:9814";
 
     #[test]
     fn test_get_type() {
         assert_eq!(AddressType::get_type(TORV3_ADDR), AddressType::TorV3);
         assert_eq!(AddressType::get_type(IPV4_ADDR), AddressType::IpV4);
+        assert_eq!(AddressType::get_type(IPV6_ADDR), AddressType::IpV6);
+        assert_eq!(AddressType::get_type(HOSTNAME_ADDR), AddressType::Hostname);
+
+        // Schemes and ports are optional
+        assert_eq!(
+            AddressType::get_type(&format!("http://{TORV3_ADDR}")),
+            AddressType::TorV3
+        );
+        assert_eq!(AddressType::get_type("http://[::1]"), AddressType::IpV6);
+        assert_eq!(AddressType::get_type("::1"), AddressType::IpV6);
+        assert_eq!(
+            AddressType::get_type("https://localhost"),
+            AddressType::Hostname
+        );
+    }
+
+    #[test]
+    fn test_get_host() {
+        assert_eq!(get_host(IPV4_ADDR), "127.0.0.1");
+        assert_eq!(get_host(&format!("https://{IPV6_ADDR}")), "2001:db8::1");
+        assert_eq!(get_host("2001:db8::1"), "2001:db8::1");
+        assert_eq!(
+            get_host(&format!("http://{HOSTNAME_ADDR}")),
+            "teos.talaia.watch"
+        );
+    }
+
+    #[test]
+    fn test_format_host() {
+        assert_eq!(format_host("2001:db8::1"), "[2001:db8::1]");
+        assert_eq!(format_host("127.0.0.1"), "127.0.0.1");
+        assert_eq!(format_host("teos.talaia.watch"), "teos.talaia.watch");
+    }
+
+    #[test]
+    fn test_address_type_try_from() {
+        for address_type in [
+            AddressType::IpV4,
+            AddressType::TorV3,
+            AddressType::IpV6,
+            AddressType::Hostname,
+        ] {
+            assert_eq!(
+                AddressType::try_from(address_type.clone() as i32),
+                Ok(address_type)
+            );
+        }
+        assert!(AddressType::try_from(4).is_err());
     }
 
     #[test]
@@ -108,9 +244,48 @@ pub mod tests {
         assert!(!NetAddr::new(IPV4_ADDR.to_owned()).addr_type.is_tor());
     }
 
+    #[test]
+    fn test_is_https() {
+        assert!(NetAddr::new(format!("https://{IPV4_ADDR}")).is_https());
+        assert!(!NetAddr::new(format!("http://{IPV4_ADDR}")).is_https());
+    }
+
+    #[test]
+    fn test_is_grpc() {
+        let net_addr = NetAddr::new(format!("grpcs://{IPV4_ADDR}"));
+        assert!(net_addr.is_grpc());
+        assert!(!net_addr.is_https());
+        assert!(net_addr.is_tls());
+        assert_eq!(net_addr.grpc_url(), format!("https://{IPV4_ADDR}"));
+        assert_eq!(
+            AddressType::get_type(&format!("grpcs://{TORV3_ADDR}")),
+            AddressType::TorV3
+        );
+
+        assert!(!NetAddr::new(format!("https://{IPV4_ADDR}")).is_grpc());
+        assert!(NetAddr::new(format!("https://{IPV4_ADDR}")).is_tls());
+        assert!(!NetAddr::new(format!("http://{IPV4_ADDR}")).is_tls());
+    }
+
+    #[test]
+    fn test_with_tls_cert() {
+        let net_addr = NetAddr::new(format!("https://{IPV4_ADDR}"));
+        assert_eq!(net_addr.tls_cert(), None);
+
+        let net_addr = net_addr.with_tls_cert(Some("cert".to_owned()));
+        assert_eq!(net_addr.tls_cert(), Some("cert"));
+        // The certificate is not part of the serialized address
+        assert_eq!(
+            serde_json::to_value(&net_addr).unwrap(),
+            serde_json::json!({ "net_addr": format!("https://{IPV4_ADDR}") })
+        );
+    }
+
     #[test]
     fn test_is_clearnet() {
         assert!(!NetAddr::new(TORV3_ADDR.to_owned()).addr_type.is_clearnet());
-        assert!(NetAddr::new(IPV4_ADDR.to_owned()).addr_type.is_clearnet());
+        for addr in [IPV4_ADDR, IPV6_ADDR, HOSTNAME_ADDR] {
+            assert!(NetAddr::new(addr.to_owned()).addr_type.is_clearnet());
+        }
     }
 }
@@ -0,0 +1,90 @@
+//! Hashcash-style proof of work used to gate user registrations.
+//!
+//! A proof is a nonce such that `SHA256(tower_id || user_id || nonce)` has at least `difficulty` leading zero bits.
+//! Binding the proof to both the tower and the user makes it impossible to reuse it for a different identity or tower.
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+use crate::{TowerId, UserId};
+
+/// Maximum proof of work difficulty (in leading zero bits). Harder proofs would take users too long to solve.
+pub const MAX_POW_DIFFICULTY: u8 = 32;
+
+/// Computes the proof of work hash of a given `nonce` for a user registering with a tower.
+pub fn pow_hash(tower_id: TowerId, user_id: UserId, nonce: u64) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tower_id.to_vec());
+    engine.input(&user_id.to_vec());
+    engine.input(&nonce.to_be_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Counts the leading zero bits of a given hash.
+pub fn leading_zero_bits(hash: &sha256::Hash) -> u32 {
+    let mut zeros = 0;
+    for byte in hash.into_inner() {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Checks whether `nonce` is a valid proof of work for the given user and tower at the given `difficulty`.
+pub fn verify_pow(tower_id: TowerId, user_id: UserId, nonce: u64, difficulty: u8) -> bool {
+    leading_zero_bits(&pow_hash(tower_id, user_id, nonce)) >= difficulty as u32
+}
+
+/// Finds a nonce that is a valid proof of work for the given user and tower at the given `difficulty`.
+///
+/// The expected number of hashes to compute is `2^difficulty`.
+pub fn solve_pow(tower_id: TowerId, user_id: UserId, difficulty: u8) -> u64 {
+    (0..u64::MAX)
+        .find(|nonce| verify_pow(tower_id, user_id, *nonce, difficulty))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::get_random_user_id;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        let mut data = [0xff; 32];
+        assert_eq!(leading_zero_bits(&sha256::Hash::from_inner(data)), 0);
+
+        data[0] = 0;
+        data[1] = 0x1f;
+        assert_eq!(leading_zero_bits(&sha256::Hash::from_inner(data)), 11);
+
+        assert_eq!(leading_zero_bits(&sha256::Hash::from_inner([0; 32])), 256);
+    }
+
+    #[test]
+    fn test_solve_verify_pow() {
+        let tower_id = get_random_user_id();
+        let user_id = get_random_user_id();
+
+        // Any nonce is valid if the difficulty is zero
+        assert!(verify_pow(tower_id, user_id, 42, 0));
+
+        let difficulty = 8;
+        let nonce = solve_pow(tower_id, user_id, difficulty);
+        assert!(verify_pow(tower_id, user_id, nonce, difficulty));
+        assert!(leading_zero_bits(&pow_hash(tower_id, user_id, nonce)) >= difficulty as u32);
+
+        // The proof is bound to the user and the tower
+        let other_id = get_random_user_id();
+        assert_ne!(
+            pow_hash(other_id, user_id, nonce),
+            pow_hash(tower_id, user_id, nonce)
+        );
+        assert_ne!(
+            pow_hash(tower_id, other_id, nonce),
+            pow_hash(tower_id, user_id, nonce)
+        );
+    }
+}
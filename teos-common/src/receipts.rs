@@ -1,9 +1,15 @@
 //! Receipts issued  by towers and handed to users as commitment proof.
 
+use std::convert::TryFrom;
+
 use serde::Serialize;
 
+use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::SecretKey;
+use bitcoin::Txid;
 
+use crate::appointment::Locator;
+use crate::protos as msgs;
 use crate::{cryptography, UserId};
 
 /// Proof that a user has registered with a tower. This serves two purposes:
@@ -15,6 +21,9 @@ use crate::{cryptography, UserId};
 /// specifies a subscription period (`subscription_start` - `subscription_expiry`) and the appointment a `start_block` so inclusion
 /// can be proved.
 ///
+/// The receipt also commits to the subscription plan the user registered for. Receipts with no plan (issued for the default plan,
+/// or before plans were a thing) serialize exactly as they used to.
+///
 /// TODO: / DISCUSS: In order to minimize the amount of receipts the user has to store, the tower could batch subscription receipts
 /// as long as the user info is still known. That is, if a user has a subscription with range (S, E) and the user renews the subscription
 /// before the tower wipes their data, then the tower can create a new receipt with (S, E') for E' > E instead of a second receipt (E, E').
@@ -25,6 +34,7 @@ pub struct RegistrationReceipt {
     available_slots: u32,
     subscription_start: u32,
     subscription_expiry: u32,
+    plan: String,
     #[serde(rename = "subscription_signature")]
     signature: Option<String>,
 }
@@ -35,12 +45,14 @@ impl RegistrationReceipt {
         available_slots: u32,
         subscription_start: u32,
         subscription_expiry: u32,
+        plan: String,
     ) -> Self {
         RegistrationReceipt {
             user_id,
             available_slots,
             subscription_start,
             subscription_expiry,
+            plan,
             signature: None,
         }
     }
@@ -50,6 +62,7 @@ impl RegistrationReceipt {
         available_slots: u32,
         subscription_start: u32,
         subscription_expiry: u32,
+        plan: String,
         signature: String,
     ) -> Self {
         RegistrationReceipt {
@@ -57,6 +70,7 @@ impl RegistrationReceipt {
             available_slots,
             subscription_start,
             subscription_expiry,
+            plan,
             signature: Some(signature),
         }
     }
@@ -77,6 +91,10 @@ impl RegistrationReceipt {
         self.subscription_expiry
     }
 
+    pub fn plan(&self) -> &str {
+        &self.plan
+    }
+
     pub fn signature(&self) -> Option<String> {
         self.signature.clone()
     }
@@ -87,6 +105,7 @@ impl RegistrationReceipt {
         ser.extend_from_slice(&self.available_slots.to_be_bytes());
         ser.extend_from_slice(&self.subscription_start.to_be_bytes());
         ser.extend_from_slice(&self.subscription_expiry.to_be_bytes());
+        ser.extend_from_slice(self.plan.as_bytes());
 
         ser
     }
@@ -165,3 +184,241 @@ impl AppointmentReceipt {
         }
     }
 }
+
+/// Proof that a backup was stored with the tower.
+///
+/// Backup receipts commit to the user signature of the backup (which covers its content and version) so the user can
+/// prove which version of their backup was accepted by the tower.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackupReceipt {
+    user_signature: String,
+    version: u32,
+    signature: Option<String>,
+}
+
+impl BackupReceipt {
+    pub fn new(user_signature: String, version: u32) -> Self {
+        BackupReceipt {
+            user_signature,
+            version,
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(user_signature: String, version: u32, signature: String) -> Self {
+        BackupReceipt {
+            user_signature,
+            version,
+            signature: Some(signature),
+        }
+    }
+
+    pub fn user_signature(&self) -> &str {
+        &self.user_signature
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = Vec::new();
+        ser.extend_from_slice(self.user_signature.as_bytes());
+        ser.extend_from_slice(&self.version.to_be_bytes());
+
+        ser
+    }
+
+    pub fn sign(&mut self, sk: &SecretKey) {
+        // TODO: Check if there's any case where this can actually fail. Don't unwrap if so.
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    pub fn verify(&self, id: &UserId) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &id.0)
+        } else {
+            false
+        }
+    }
+}
+
+/// Proof that the tower responded to a breach.
+///
+/// A first receipt is issued when the penalty transaction is accepted by the tower's node, and a second one, including
+/// the `confirmation_height`, once the penalty is irrevocably resolved. Alongside the registration and appointment
+/// receipts, they can be used by the user to prove the tower provided the service it agreed on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResponseReceipt {
+    #[serde(with = "hex::serde")]
+    locator: Locator,
+    dispute_txid: Txid,
+    penalty_txid: Txid,
+    broadcast_height: u32,
+    confirmation_height: Option<u32>,
+    signature: Option<String>,
+}
+
+impl ResponseReceipt {
+    pub fn new(
+        locator: Locator,
+        dispute_txid: Txid,
+        penalty_txid: Txid,
+        broadcast_height: u32,
+        confirmation_height: Option<u32>,
+    ) -> Self {
+        ResponseReceipt {
+            locator,
+            dispute_txid,
+            penalty_txid,
+            broadcast_height,
+            confirmation_height,
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(
+        locator: Locator,
+        dispute_txid: Txid,
+        penalty_txid: Txid,
+        broadcast_height: u32,
+        confirmation_height: Option<u32>,
+        signature: String,
+    ) -> Self {
+        ResponseReceipt {
+            locator,
+            dispute_txid,
+            penalty_txid,
+            broadcast_height,
+            confirmation_height,
+            signature: Some(signature),
+        }
+    }
+
+    pub fn locator(&self) -> Locator {
+        self.locator
+    }
+
+    pub fn dispute_txid(&self) -> Txid {
+        self.dispute_txid
+    }
+
+    pub fn penalty_txid(&self) -> Txid {
+        self.penalty_txid
+    }
+
+    pub fn broadcast_height(&self) -> u32 {
+        self.broadcast_height
+    }
+
+    pub fn confirmation_height(&self) -> Option<u32> {
+        self.confirmation_height
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    /// Whether the receipt covers the appointment identified by `locator`. Both the receipt locator and the locator
+    /// derived from its dispute transaction must match.
+    pub fn is_for(&self, locator: Locator) -> bool {
+        self.locator == locator && Locator::new(self.dispute_txid) == locator
+    }
+
+    /// Whether the receipt proves the penalty was irrevocably resolved.
+    pub fn is_resolved(&self) -> bool {
+        self.confirmation_height.is_some()
+    }
+
+    /// Sets the height the penalty was confirmed at, dropping the signature (if any) since it does not cover it anymore.
+    pub fn set_confirmation_height(&mut self, height: u32) {
+        self.confirmation_height = Some(height);
+        self.signature = None;
+    }
+
+    /// Serializes the receipt to be signed:
+    ///
+    /// `locator || dispute_txid || penalty_txid || broadcast_height || confirmation_height`
+    ///
+    /// Heights are big endian. A missing `confirmation_height` is encoded as 0.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = Vec::new();
+        ser.extend_from_slice(&self.locator.to_vec());
+        ser.extend_from_slice(&self.dispute_txid);
+        ser.extend_from_slice(&self.penalty_txid);
+        ser.extend_from_slice(&self.broadcast_height.to_be_bytes());
+        ser.extend_from_slice(&self.confirmation_height.unwrap_or(0).to_be_bytes());
+
+        ser
+    }
+
+    pub fn sign(&mut self, sk: &SecretKey) {
+        // TODO: Check if there's any case where this can actually fail. Don't unwrap if so.
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    pub fn verify(&self, id: &UserId) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &id.0)
+        } else {
+            false
+        }
+    }
+}
+
+impl From<ResponseReceipt> for msgs::ResponseReceipt {
+    fn from(r: ResponseReceipt) -> Self {
+        msgs::ResponseReceipt {
+            locator: r.locator.to_vec(),
+            dispute_txid: r.dispute_txid.to_vec(),
+            penalty_txid: r.penalty_txid.to_vec(),
+            broadcast_height: r.broadcast_height,
+            confirmation_height: r.confirmation_height.unwrap_or(0),
+            signature: r.signature.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<msgs::ResponseReceipt> for ResponseReceipt {
+    type Error = String;
+
+    fn try_from(r: msgs::ResponseReceipt) -> Result<Self, Self::Error> {
+        Ok(ResponseReceipt::with_signature(
+            Locator::from_slice(&r.locator).map_err(|_| "Wrong locator format")?,
+            Txid::from_slice(&r.dispute_txid).map_err(|_| "Wrong dispute_txid format")?,
+            Txid::from_slice(&r.penalty_txid).map_err(|_| "Wrong penalty_txid format")?,
+            r.broadcast_height,
+            (r.confirmation_height != 0).then_some(r.confirmation_height),
+            r.signature,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cryptography::get_random_keypair;
+    use crate::test_utils::{get_random_locator, get_random_response_receipt};
+
+    #[test]
+    fn test_response_receipt_is_for() {
+        let receipt = get_random_response_receipt(get_random_keypair().0);
+        assert!(receipt.is_for(receipt.locator()));
+        assert!(!receipt.is_for(get_random_locator()));
+
+        // The locator must also match the dispute transaction
+        let forged = ResponseReceipt::new(
+            get_random_locator(),
+            receipt.dispute_txid(),
+            receipt.penalty_txid(),
+            receipt.broadcast_height(),
+            None,
+        );
+        assert!(!forged.is_for(forged.locator()));
+    }
+}
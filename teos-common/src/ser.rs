@@ -144,3 +144,46 @@ pub mod serde_status {
         deserializer.deserialize_any(StatusVisitor)
     }
 }
+
+pub mod serde_failure_kind {
+    use super::*;
+    use serde::de::{self, Deserializer};
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    use crate::appointment::FailureKind;
+
+    pub fn serialize<S>(kind: &i32, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let kind = FailureKind::try_from(*kind).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&kind.to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct KindVisitor;
+
+        impl<'de> de::Visitor<'de> for KindVisitor {
+            type Value = i32;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a string containing the failure kind")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let kind = FailureKind::from_str(v)
+                    .map_err(|_| E::custom("given failure kind is unknown"))?;
+                Ok(kind as i32)
+            }
+        }
+
+        deserializer.deserialize_any(KindVisitor)
+    }
+}
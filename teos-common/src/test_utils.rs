@@ -1,4 +1,6 @@
 use std::convert::TryInto;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use hex::FromHex;
 use rand::distributions::Standard;
@@ -9,9 +11,13 @@ use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::SecretKey;
 use bitcoin::{consensus, Script, Transaction, TxOut, Txid};
 
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+
 use crate::appointment::{Appointment, Locator};
 use crate::cryptography;
-use crate::receipts::{AppointmentReceipt, RegistrationReceipt};
+use crate::receipts::{AppointmentReceipt, RegistrationReceipt, ResponseReceipt};
 use crate::UserId;
 
 pub static TXID_HEX: &str = "338bda693c4a26e0d41a01f7f2887aaf48bf0bdf93e6415c9110b29349349d3e";
@@ -68,8 +74,13 @@ pub fn generate_random_appointment(dispute_txid: Option<&Txid>) -> Appointment {
 pub fn get_random_registration_receipt() -> RegistrationReceipt {
     let (sk, _) = cryptography::get_random_keypair();
     let start = get_random_int();
-    let mut receipt =
-        RegistrationReceipt::new(get_random_user_id(), get_random_int(), start, start + 420);
+    let mut receipt = RegistrationReceipt::new(
+        get_random_user_id(),
+        get_random_int(),
+        start,
+        start + 420,
+        "default".to_owned(),
+    );
     receipt.sign(&sk);
 
     receipt
@@ -82,6 +93,7 @@ pub fn get_registration_receipt_from_previous(r: &RegistrationReceipt) -> Regist
         r.available_slots() + 1 + get_random_int::<u8>() as u32,
         r.subscription_start(),
         r.subscription_expiry() + 1 + get_random_int::<u8>() as u32,
+        r.plan().to_owned(),
     );
     receipt.sign(&sk);
 
@@ -94,3 +106,62 @@ pub fn get_random_appointment_receipt(tower_sk: SecretKey) -> AppointmentReceipt
 
     receipt
 }
+
+pub fn get_random_response_receipt(tower_sk: SecretKey) -> ResponseReceipt {
+    let dispute_txid = Txid::from_slice(&cryptography::get_random_bytes(32)).unwrap();
+    let mut receipt = ResponseReceipt::new(
+        Locator::new(dispute_txid),
+        dispute_txid,
+        Txid::from_slice(&cryptography::get_random_bytes(32)).unwrap(),
+        42,
+        None,
+    );
+    receipt.sign(&tower_sk);
+
+    receipt
+}
+
+/// Runs a mock of the CLN JSON-RPC interface in the background, returning the path to its unix socket.
+///
+/// Every request is answered with the response built by `handler` out of the requested method and params. The
+/// response is expected to hold either a `result` or an `error` field.
+pub async fn run_cln_rpc_mock<F>(handler: F) -> PathBuf
+where
+    F: Fn(&str, &Value) -> Value + Send + Sync + 'static,
+{
+    let path = std::env::temp_dir().join(format!("lightning-rpc-{}", get_random_int::<u64>()));
+    let listener = UnixListener::bind(&path).unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0; 4096];
+                let request = loop {
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                    if let Ok(request) = serde_json::from_slice::<Value>(&buf) {
+                        break request;
+                    }
+                };
+
+                let mut response = handler(
+                    request["method"].as_str().unwrap_or_default(),
+                    &request["params"],
+                );
+                response["jsonrpc"] = "2.0".into();
+                response["id"] = request["id"].clone();
+                stream
+                    .write_all(format!("{response}\n\n").as_bytes())
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    path
+}
@@ -0,0 +1,165 @@
+//! Benchmarks the time it takes the Watcher to process a block depending on the number of appointments held by the tower.
+//!
+//! Run with `cargo bench -p teos`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rusqlite::{params, Connection};
+use tempdir::TempDir;
+
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::{Block, BlockHash, BlockHeader, OutPoint, Script, Transaction, TxIn, TxOut, Witness};
+use bitcoincore_rpc::{Auth, Client};
+use lightning::chain::Listen;
+
+use teos::carrier::Carrier;
+use teos::dbm::DBM;
+use teos::gatekeeper::Gatekeeper;
+use teos::responder::Responder;
+use teos::watcher::{RegistrationGate, Watcher, WatcherConfig};
+
+use teos_common::cryptography::{get_random_bytes, get_random_keypair};
+use teos_common::test_utils::{get_random_locator, get_random_user_id};
+
+/// Number of transactions in the benchmarked blocks (roughly the size of a mainnet block).
+const TXS_PER_BLOCK: usize = 2500;
+const APPOINTMENT_COUNTS: [usize; 4] = [0, 1_000, 10_000, 100_000];
+
+/// Creates a tower database holding `n` appointments (all of them from the same user).
+fn create_db(tmp_dir: &TempDir, n: usize) -> DBM {
+    let db_path = tmp_dir.path().join(format!("teos_db_{n}.sql3"));
+    // Create the tables
+    DBM::new(db_path.clone()).unwrap();
+
+    let mut conn = Connection::open(&db_path).unwrap();
+    let tx = conn.transaction().unwrap();
+    let user_id = get_random_user_id();
+    tx.execute(
+        "INSERT INTO users (user_id, available_slots, subscription_start, subscription_expiry) VALUES (?1, ?2, ?3, ?4)",
+        params![user_id.to_vec(), u32::MAX, 0, u32::MAX],
+    )
+    .unwrap();
+    for _ in 0..n {
+        tx.execute(
+            "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                get_random_bytes(20),
+                get_random_locator().to_vec(),
+                get_random_bytes(100),
+                42,
+                "",
+                0,
+                user_id.to_vec()
+            ],
+        )
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    // Load it back so the tower picks up the appointments
+    DBM::new(db_path).unwrap()
+}
+
+fn create_watcher(dbm: DBM) -> Watcher {
+    let dbm = Arc::new(Mutex::new(dbm));
+    let gatekeeper = Arc::new(Gatekeeper::new(
+        0,
+        u32::MAX,
+        u32::MAX,
+        6,
+        BTreeMap::new(),
+        false,
+        dbm.clone(),
+    ));
+    // The carrier is never reached given there are no breaches in the benchmarked blocks.
+    let rpc = Arc::new(Client::new("http://127.0.0.1:1", Auth::None).unwrap());
+    let carrier = Carrier::new(rpc, Arc::new((Mutex::new(true), Condvar::new())), 0);
+    let (tower_sk, _) = get_random_keypair();
+    let responder = Arc::new(Responder::new(
+        &[],
+        0,
+        100,
+        carrier,
+        gatekeeper.clone(),
+        tower_sk,
+        dbm.clone(),
+    ));
+
+    Watcher::new(
+        gatekeeper,
+        responder,
+        &[],
+        0,
+        WatcherConfig::new(6, RegistrationGate::default()),
+        tower_sk,
+        dbm,
+    )
+}
+
+fn get_random_block(n_txs: usize) -> Block {
+    let txdata = (0..n_txs)
+        .map(|_| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    bitcoin::Txid::from_slice(&get_random_bytes(32)).unwrap(),
+                    0,
+                ),
+                script_sig: Script::new(),
+                witness: Witness::new(),
+                sequence: 0,
+            }],
+            output: vec![TxOut {
+                script_pubkey: Builder::new().push_int(1).into_script(),
+                value: 1000,
+            }],
+        })
+        .collect();
+
+    Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::default(),
+            merkle_root: TxMerkleNode::default(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        },
+        txdata,
+    }
+}
+
+fn block_processing(c: &mut Criterion) {
+    let tmp_dir = TempDir::new("teos_bench").unwrap();
+    let block = get_random_block(TXS_PER_BLOCK);
+
+    let mut group = c.benchmark_group("block_connected");
+    group.throughput(Throughput::Elements(TXS_PER_BLOCK as u64));
+    for n in APPOINTMENT_COUNTS {
+        let watcher = create_watcher(create_db(&tmp_dir, n));
+        let mut height = 0;
+
+        group.bench_with_input(BenchmarkId::new("appointments", n), &n, |b, _| {
+            b.iter_batched(
+                || {
+                    // Every block needs a different hash so the locator cache can be updated
+                    height += 1;
+                    let mut block = block.clone();
+                    block.header.nonce = height;
+                    (block, height)
+                },
+                |(block, height)| watcher.block_connected(&block, height),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, block_processing);
+criterion_main!(benches);
@@ -16,6 +16,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "NetworkAddress.address_type",
             "#[serde(rename = \"type\", with = \"crate::api::serde::serde_address_type\")]",
         )
+        .field_attribute("MissedBreach.uuid", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("MissedBreach.locator", "#[serde(with = \"hex::serde\")]")
+        .field_attribute(
+            "MissedBreach.dispute_txid",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
+        .field_attribute(
+            "MissedBreach.kind",
+            "#[serde(with = \"crate::api::serde::serde_missed_breach_kind\")]",
+        )
+        .field_attribute(
+            "RescanProgress.block_hash",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
         .compile(
             &[
                 "proto/teos/v2/appointment.proto",
@@ -0,0 +1,315 @@
+//! Logic related to serving the public gRPC interface directly to users.
+//!
+//! Users can either reach the tower through the HTTP API, which proxies their requests to the [InternalAPI], or talk
+//! to the [PublicTowerServices] themselves over TLS. Requests reaching the latter are subject to the same rate limits
+//! as the ones going through the HTTP API.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+use triggered::{Listener, Trigger};
+
+use teos_common::appointment::{Appointment, Locator};
+use teos_common::net::http::Endpoint;
+use teos_common::protos as common_msgs;
+use teos_common::UserId;
+
+use crate::api::internal::InternalAPI;
+use crate::api::rate_limit::{recover_user_id, RateLimited, RateLimiter};
+use crate::protos::public_tower_services_server::{PublicTowerServices, PublicTowerServicesServer};
+use crate::tls::{accept_tls, HandshakeLimits, ReloadableCertificate};
+
+impl From<RateLimited> for Status {
+    fn from(e: RateLimited) -> Self {
+        Status::new(Code::ResourceExhausted, e.to_string())
+    }
+}
+
+/// Public gRPC interface of the tower. Rate limits requests and forwards them to the [InternalAPI].
+pub struct PublicGrpcAPI {
+    /// The [InternalAPI] requests are forwarded to.
+    internal_api: Arc<InternalAPI>,
+    /// Rate limiter of the public API. Shared with the HTTP API.
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl PublicGrpcAPI {
+    /// Creates a new [PublicGrpcAPI] instance.
+    pub fn new(internal_api: Arc<InternalAPI>, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            internal_api,
+            rate_limiter,
+        }
+    }
+
+    /// Checks a request against the rate limits of its endpoint.
+    fn check_rate_limits<T>(
+        &self,
+        endpoint: Endpoint,
+        request: &Request<T>,
+        user_id: Option<UserId>,
+    ) -> Result<(), RateLimited> {
+        self.rate_limiter
+            .check(endpoint, request.remote_addr().map(|a| a.ip()), user_id)
+            .inspect_err(|e| log::debug!("Request rate limited: {e}"))
+    }
+}
+
+#[tonic::async_trait]
+impl PublicTowerServices for PublicGrpcAPI {
+    async fn register(
+        &self,
+        request: Request<common_msgs::RegisterRequest>,
+    ) -> Result<Response<common_msgs::RegisterResponse>, Status> {
+        // Register requests are not signed, so they are only limited by address
+        self.check_rate_limits(Endpoint::Register, &request, None)?;
+        self.internal_api.register(request).await
+    }
+
+    async fn add_appointment(
+        &self,
+        request: Request<common_msgs::AddAppointmentRequest>,
+    ) -> Result<Response<common_msgs::AddAppointmentResponse>, Status> {
+        // Malformed appointments are only limited by address, the InternalAPI will reject them anyway
+        let req_data = request.get_ref();
+        let user_id = req_data.appointment.as_ref().and_then(|a| {
+            let locator = Locator::from_slice(&a.locator).ok()?;
+            let appointment = Appointment::new(locator, a.encrypted_blob.clone(), a.to_self_delay);
+            recover_user_id(&appointment.to_vec(), &req_data.signature)
+        });
+        self.check_rate_limits(Endpoint::AddAppointment, &request, user_id)?;
+        self.internal_api.add_appointment(request).await
+    }
+
+    async fn get_appointment(
+        &self,
+        request: Request<common_msgs::GetAppointmentRequest>,
+    ) -> Result<Response<common_msgs::GetAppointmentResponse>, Status> {
+        let req_data = request.get_ref();
+        let user_id = Locator::from_slice(&req_data.locator)
+            .ok()
+            .and_then(|locator| {
+                recover_user_id(
+                    format!("get appointment {locator}").as_bytes(),
+                    &req_data.signature,
+                )
+            });
+        self.check_rate_limits(Endpoint::GetAppointment, &request, user_id)?;
+        self.internal_api.get_appointment(request).await
+    }
+
+    async fn get_subscription_info(
+        &self,
+        request: Request<common_msgs::GetSubscriptionInfoRequest>,
+    ) -> Result<Response<common_msgs::GetSubscriptionInfoResponse>, Status> {
+        let user_id = recover_user_id(b"get subscription info", &request.get_ref().signature);
+        self.check_rate_limits(Endpoint::GetSubscriptionInfo, &request, user_id)?;
+        self.internal_api.get_subscription_info(request).await
+    }
+
+    async fn store_backup(
+        &self,
+        request: Request<common_msgs::StoreBackupRequest>,
+    ) -> Result<Response<common_msgs::StoreBackupResponse>, Status> {
+        self.internal_api.store_backup(request).await
+    }
+
+    async fn get_backup(
+        &self,
+        request: Request<common_msgs::GetBackupRequest>,
+    ) -> Result<Response<common_msgs::GetBackupResponse>, Status> {
+        self.internal_api.get_backup(request).await
+    }
+
+    async fn get_appointment_outcomes(
+        &self,
+        request: Request<common_msgs::GetAppointmentOutcomesRequest>,
+    ) -> Result<Response<common_msgs::GetAppointmentOutcomesResponse>, Status> {
+        self.internal_api.get_appointment_outcomes(request).await
+    }
+
+    async fn get_subscription_plans(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<common_msgs::GetSubscriptionPlansResponse>, Status> {
+        self.internal_api.get_subscription_plans(request).await
+    }
+}
+
+/// Serves the public gRPC interface over TLS.
+///
+/// The `certificate` is shared with the HTTP API, which takes care of reloading it when its files change.
+pub async fn serve(
+    grpc_bind: SocketAddr,
+    public_api: PublicGrpcAPI,
+    certificate: Arc<ReloadableCertificate>,
+    service_ready: Trigger,
+    shutdown_signal: Listener,
+) {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = certificate;
+    config.set_protocols(&[b"h2".to_vec()]);
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind(grpc_bind).await.unwrap();
+
+    // Connections are handed to the server once the TLS handshake succeeds, so a failed handshake does not bring
+    // the whole server down
+    let (tx, rx) = mpsc::unbounded_channel();
+    let accept_task = tokio::spawn(accept_tls(
+        listener,
+        acceptor,
+        HandshakeLimits::default(),
+        shutdown_signal.clone(),
+        move |stream, _| {
+            tx.send(stream).ok();
+            async {}
+        },
+    ));
+
+    service_ready.trigger();
+    Server::builder()
+        .add_service(PublicTowerServicesServer::new(public_api))
+        .serve_with_incoming_shutdown(
+            UnboundedReceiverStream::new(rx).map(Ok::<_, std::io::Error>),
+            shutdown_signal,
+        )
+        .await
+        .unwrap();
+    accept_task.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+
+    use crate::api::rate_limit::{RateLimit, RateLimits};
+    use crate::protos::public_tower_services_client::PublicTowerServicesClient;
+    use crate::test_utils::{create_api, create_api_with_config, ApiConfig};
+
+    use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::test_utils::get_random_user_id;
+
+    async fn run_public_grpc(
+        internal_api: Arc<InternalAPI>,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> (
+        PublicTowerServicesClient<Channel>,
+        tempdir::TempDir,
+        Trigger,
+    ) {
+        let tmp_dir = tempdir::TempDir::new("teos_public_grpc").unwrap();
+        let cert_path = tmp_dir.path().join("cert.pem");
+        let key_path = tmp_dir.path().join("key.pem");
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        std::fs::write(&cert_path, &cert_pem).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        let certificate = Arc::new(ReloadableCertificate::new(cert_path, key_path).unwrap());
+
+        let grpc_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        tokio::spawn(serve(
+            grpc_addr,
+            PublicGrpcAPI::new(internal_api, rate_limiter),
+            certificate,
+            service_ready,
+            shutdown_signal,
+        ));
+        ready_signal.await;
+
+        let channel = Channel::from_shared(format!("https://localhost:{}", grpc_addr.port()))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(cert_pem))
+                    .domain_name("localhost"),
+            )
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        (
+            PublicTowerServicesClient::new(channel),
+            tmp_dir,
+            shutdown_trigger,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let (internal_api, _s) = create_api().await;
+        let (mut client, _tmp_dir, shutdown_trigger) = run_public_grpc(
+            internal_api,
+            Arc::new(RateLimiter::new(RateLimits::unlimited())),
+        )
+        .await;
+
+        let user_id = get_random_user_id();
+        let response = client
+            .register(common_msgs::RegisterRequest {
+                user_id: user_id.to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.user_id, user_id.to_vec());
+
+        // Malformed requests are rejected
+        let status = client
+            .add_appointment(common_msgs::AddAppointmentRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        shutdown_trigger.trigger();
+    }
+
+    #[tokio::test]
+    async fn test_serve_rate_limited() {
+        let mut limits = RateLimits::unlimited();
+        // Requests from loopback addresses are only limited per user
+        limits.get_subscription_info = RateLimit::new(1, 1, 1, 1);
+        let rate_limiter = Arc::new(RateLimiter::new(limits));
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::default().rate_limiter(rate_limiter.clone())).await;
+        let (mut client, _tmp_dir, shutdown_trigger) =
+            run_public_grpc(internal_api, rate_limiter).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        client
+            .register(common_msgs::RegisterRequest {
+                user_id: UserId(user_pk).to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let request = common_msgs::GetSubscriptionInfoRequest {
+            signature: cryptography::sign(b"get subscription info", &user_sk).unwrap(),
+        };
+        client.get_subscription_info(request.clone()).await.unwrap();
+        let status = client.get_subscription_info(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "Too many requests from this user. Try again later"
+        );
+
+        shutdown_trigger.trigger();
+    }
+}
@@ -2,24 +2,46 @@ use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::time::Duration;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
 use tonic::transport::Channel;
 use triggered::{Listener, Trigger};
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::{service_fn, Service};
 use warp::{http::StatusCode, reject, reply, Filter, Rejection, Reply};
 
-use teos_common::appointment::LOCATOR_LEN;
+use teos_common::appointment::{Appointment, Locator, LOCATOR_LEN};
+use teos_common::constants::BACKUP_MAX_SIZE;
 use teos_common::net::http::Endpoint;
 use teos_common::protos as common_msgs;
-use teos_common::{errors, USER_ID_LEN};
+use teos_common::{errors, UserId, USER_ID_LEN};
 
+use crate::api::rate_limit::{recover_user_id, RateLimiter};
 use crate::protos::public_tower_services_client::PublicTowerServicesClient;
+use crate::tls::{accept_tls, HandshakeLimits, ReloadableCertificate};
 
 // TODO: Limit the body length for /add_appointment should not be needed, since slots are consumed proportionally to it.
 // Setting a limit for now just to prevent spam to some extend, but this is likely to be lifted.
-const REGISTER_BODY_LEN: u64 = 87;
+// Leaves room for a plan name of up to 48 characters.
+const REGISTER_BODY_LEN: u64 = 150;
 const ADD_APPOINTMENT_BODY_LEN: u64 = 2048;
 const GET_APPOINTMENT_BODY_LEN: u64 = 178;
 const GET_SUBSCRIPTION_INFO_BODY_LEN: u64 = 127;
+// Backups are hex encoded, so the body doubles their size (plus some room for the rest of the fields).
+const STORE_BACKUP_BODY_LEN: u64 = 2 * BACKUP_MAX_SIZE as u64 + 256;
+const GET_BACKUP_BODY_LEN: u64 = 127;
+const GET_APPOINTMENT_OUTCOMES_BODY_LEN: u64 = 178;
+
+/// How often the TLS certificate files are checked for changes.
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Address of the peer of a TLS connection. Connections not accepted by warp itself carry their remote address
+/// as a request extension.
+#[derive(Clone, Copy, Debug)]
+struct RemoteAddr(SocketAddr);
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct ApiError {
@@ -64,29 +86,51 @@ fn with_grpc(
     warp::any().map(move || grpc_endpoint.clone())
 }
 
+/// Extracts the remote address of a request, no matter if it was received over plain HTTP or TLS.
+fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<RemoteAddr>())
+        .map(|addr: Option<SocketAddr>, tls_addr: Option<RemoteAddr>| {
+            addr.or_else(|| tls_addr.map(|a| a.0))
+        })
+}
+
+fn with_rate_limiter(
+    rate_limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract = (Arc<RateLimiter>,), Error = Infallible> + Clone {
+    warp::any().map(move || rate_limiter.clone())
+}
+
+/// Checks a request against the rate limits of its endpoint. Returns the reply to send back if they are exceeded.
+fn check_rate_limits(
+    rate_limiter: &RateLimiter,
+    endpoint: Endpoint,
+    addr: Option<SocketAddr>,
+    user_id: Option<UserId>,
+) -> Result<(), reply::WithStatus<reply::Json>> {
+    rate_limiter
+        .check(endpoint, addr.map(|a| a.ip()), user_id)
+        .map_err(|e| {
+            log::debug!("Request rate limited: {e}");
+            reply::with_status(
+                reply::json(&ApiError::new(e.to_string(), errors::RATE_LIMIT_EXCEEDED)),
+                StatusCode::TOO_MANY_REQUESTS,
+            )
+        })
+}
+
 fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
-    let mut status_code = StatusCode::BAD_REQUEST;
-    let error_code = match s.code() {
-        tonic::Code::InvalidArgument => errors::WRONG_FIELD_FORMAT,
-        tonic::Code::NotFound => {
-            status_code = StatusCode::NOT_FOUND;
-            errors::APPOINTMENT_NOT_FOUND
-        }
-        tonic::Code::AlreadyExists => errors::APPOINTMENT_ALREADY_TRIGGERED,
-        tonic::Code::ResourceExhausted => errors::REGISTRATION_RESOURCE_EXHAUSTED,
-        tonic::Code::Unauthenticated => {
-            status_code = StatusCode::UNAUTHORIZED;
-            errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
-        }
-        tonic::Code::Unavailable => {
-            status_code = StatusCode::SERVICE_UNAVAILABLE;
-            errors::SERVICE_UNAVAILABLE
-        }
-        _ => {
-            log::debug!("Unexpected error ocurred: {}", s.message());
-            errors::UNEXPECTED_ERROR
-        }
+    let status_code = match s.code() {
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+        tonic::Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
     };
+    let error_code = errors::status_error_code(s);
+    if error_code == errors::UNEXPECTED_ERROR {
+        log::debug!("Unexpected error ocurred: {}", s.message());
+    }
 
     (status_code, error_code)
 }
@@ -117,6 +161,7 @@ async fn register(
     req: common_msgs::RegisterRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> std::result::Result<impl Reply, Rejection> {
     log::debug!(
         "Received a register request from {}",
@@ -135,6 +180,11 @@ async fn register(
         ));
     }
 
+    // Register requests are not signed, so they are only limited by address
+    if let Err(r) = check_rate_limits(&rate_limiter, Endpoint::Register, addr, None) {
+        return Ok(r);
+    }
+
     let (body, status) = parse_grpc_response(grpc_conn.register(req).await);
     Ok(reply::with_status(body, status))
 }
@@ -143,13 +193,14 @@ async fn add_appointment(
     req: common_msgs::AddAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> std::result::Result<impl Reply, Rejection> {
     log::debug!(
         "Received an add_appointment request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
 
-    if let Some(a) = &req.appointment {
+    let appointment = if let Some(a) = &req.appointment {
         if a.locator.is_empty() {
             return Err(ApiError::empty_field("locator"));
         }
@@ -160,13 +211,27 @@ async fn add_appointment(
                 LOCATOR_LEN,
             ));
         }
+        Appointment::new(
+            Locator::from_slice(&a.locator).unwrap(),
+            a.encrypted_blob.clone(),
+            a.to_self_delay,
+        )
     } else {
         return Err(ApiError::missing_field("appointment"));
-    }
+    };
     if req.signature.is_empty() {
         return Err(ApiError::empty_field("signature"));
     }
 
+    if let Err(r) = check_rate_limits(
+        &rate_limiter,
+        Endpoint::AddAppointment,
+        addr,
+        recover_user_id(&appointment.to_vec(), &req.signature),
+    ) {
+        return Ok(r);
+    }
+
     let (body, status) = parse_grpc_response(grpc_conn.add_appointment(req).await);
     Ok(reply::with_status(body, status))
 }
@@ -175,6 +240,7 @@ async fn get_appointment(
     req: common_msgs::GetAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> std::result::Result<impl Reply, Rejection> {
     log::debug!(
         "Received an get_appointment request from {}",
@@ -195,6 +261,19 @@ async fn get_appointment(
         return Err(ApiError::empty_field("signature"));
     }
 
+    let message = format!(
+        "get appointment {}",
+        Locator::from_slice(&req.locator).unwrap()
+    );
+    if let Err(r) = check_rate_limits(
+        &rate_limiter,
+        Endpoint::GetAppointment,
+        addr,
+        recover_user_id(message.as_bytes(), &req.signature),
+    ) {
+        return Ok(r);
+    }
+
     let (body, status) = parse_grpc_response(grpc_conn.get_appointment(req).await);
     Ok(reply::with_status(body, status))
 }
@@ -203,6 +282,7 @@ async fn get_subscription_info(
     req: common_msgs::GetSubscriptionInfoRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> std::result::Result<impl Reply, Rejection> {
     log::debug!(
         "Received an get_subscription_info request from {}",
@@ -213,10 +293,106 @@ async fn get_subscription_info(
         return Err(ApiError::empty_field("signature"));
     }
 
+    if let Err(r) = check_rate_limits(
+        &rate_limiter,
+        Endpoint::GetSubscriptionInfo,
+        addr,
+        recover_user_id(b"get subscription info", &req.signature),
+    ) {
+        return Ok(r);
+    }
+
     let (body, status) = parse_grpc_response(grpc_conn.get_subscription_info(req).await);
     Ok(reply::with_status(body, status))
 }
 
+async fn store_backup(
+    req: common_msgs::StoreBackupRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    log::debug!(
+        "Received a store_backup request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.backup.is_empty() {
+        return Err(ApiError::empty_field("backup"));
+    }
+    if req.signature.is_empty() {
+        return Err(ApiError::empty_field("signature"));
+    }
+
+    let (body, status) = parse_grpc_response(grpc_conn.store_backup(req).await);
+    Ok(reply::with_status(body, status))
+}
+
+async fn get_backup(
+    req: common_msgs::GetBackupRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    log::debug!(
+        "Received a get_backup request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.signature.is_empty() {
+        return Err(ApiError::empty_field("signature"));
+    }
+
+    let (body, status) = match grpc_conn.get_backup(req).await {
+        // NotFound is mapped to APPOINTMENT_NOT_FOUND by default
+        Err(s) if s.code() == tonic::Code::NotFound => (
+            reply::json(&ApiError::new(s.message().into(), errors::BACKUP_NOT_FOUND)),
+            StatusCode::NOT_FOUND,
+        ),
+        result => parse_grpc_response(result),
+    };
+    Ok(reply::with_status(body, status))
+}
+
+async fn get_appointment_outcomes(
+    req: common_msgs::GetAppointmentOutcomesRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    log::debug!(
+        "Received a get_appointment_outcomes request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.locator.is_empty() {
+        return Err(ApiError::empty_field("locator"));
+    }
+    if req.locator.len() != LOCATOR_LEN {
+        return Err(ApiError::wrong_field_length(
+            "locator",
+            req.locator.len(),
+            LOCATOR_LEN,
+        ));
+    }
+    if req.signature.is_empty() {
+        return Err(ApiError::empty_field("signature"));
+    }
+
+    let (body, status) = parse_grpc_response(grpc_conn.get_appointment_outcomes(req).await);
+    Ok(reply::with_status(body, status))
+}
+
+async fn get_subscription_plans(
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    log::debug!(
+        "Received a get_subscription_plans request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    let (body, status) = parse_grpc_response(grpc_conn.get_subscription_plans(()).await);
+    Ok(reply::with_status(body, status))
+}
+
 async fn ping(addr: Option<SocketAddr>) -> Result<impl Reply, Rejection> {
     log::debug!(
         "Received a ping request from {}",
@@ -227,26 +403,30 @@ async fn ping(addr: Option<SocketAddr>) -> Result<impl Reply, Rejection> {
 
 fn router(
     grpc_conn: PublicTowerServicesClient<Channel>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     let register = warp::post()
         .and(warp::path(Endpoint::Register.to_string()))
         .and(warp::body::content_length_limit(REGISTER_BODY_LEN).and(warp::body::json()))
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and(with_grpc(grpc_conn.clone()))
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and_then(register);
 
     let add_appointment = warp::post()
         .and(warp::path(Endpoint::AddAppointment.to_string()))
         .and(warp::body::content_length_limit(ADD_APPOINTMENT_BODY_LEN).and(warp::body::json()))
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and(with_grpc(grpc_conn.clone()))
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and_then(add_appointment);
 
     let get_appointment = warp::post()
         .and(warp::path(Endpoint::GetAppointment.to_string()))
         .and(warp::body::content_length_limit(GET_APPOINTMENT_BODY_LEN).and(warp::body::json()))
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and(with_grpc(grpc_conn.clone()))
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and_then(get_appointment);
 
     let get_subscription_info = warp::post()
@@ -255,19 +435,54 @@ fn router(
             warp::body::content_length_limit(GET_SUBSCRIPTION_INFO_BODY_LEN)
                 .and(warp::body::json()),
         )
-        .and(warp::addr::remote())
-        .and(with_grpc(grpc_conn))
+        .and(remote_addr())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and_then(get_subscription_info);
 
+    let store_backup = warp::post()
+        .and(warp::path(Endpoint::StoreBackup.to_string()))
+        .and(warp::body::content_length_limit(STORE_BACKUP_BODY_LEN).and(warp::body::json()))
+        .and(remote_addr())
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(store_backup);
+
+    let get_backup = warp::post()
+        .and(warp::path(Endpoint::GetBackup.to_string()))
+        .and(warp::body::content_length_limit(GET_BACKUP_BODY_LEN).and(warp::body::json()))
+        .and(remote_addr())
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(get_backup);
+
+    let get_appointment_outcomes = warp::post()
+        .and(warp::path(Endpoint::GetAppointmentOutcomes.to_string()))
+        .and(
+            warp::body::content_length_limit(GET_APPOINTMENT_OUTCOMES_BODY_LEN)
+                .and(warp::body::json()),
+        )
+        .and(remote_addr())
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(get_appointment_outcomes);
+
+    let get_subscription_plans = warp::get()
+        .and(warp::path(Endpoint::GetSubscriptionPlans.to_string()))
+        .and(remote_addr())
+        .and(with_grpc(grpc_conn))
+        .and_then(get_subscription_plans);
+
     let ping = warp::get()
         .and(warp::path(Endpoint::Ping.to_string()))
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and_then(ping);
 
     register
         .or(add_appointment)
         .or(get_appointment)
         .or(get_subscription_info)
+        .or(store_backup)
+        .or(get_backup)
+        .or(get_appointment_outcomes)
+        .or(get_subscription_plans)
         .or(ping)
         .recover(handle_rejection)
 }
@@ -302,9 +517,12 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
     }
 }
 
+/// Serves the public API over HTTP, or HTTPS if a `certificate` is provided.
 pub async fn serve(
     http_bind: SocketAddr,
     grpc_bind: SocketAddr,
+    rate_limiter: Arc<RateLimiter>,
+    certificate: Option<Arc<ReloadableCertificate>>,
     service_ready: Trigger,
     shutdown_signal: Listener,
 ) {
@@ -317,23 +535,89 @@ pub async fn serve(
             }
         }
     };
-    let (_, server) =
-        warp::serve(router(grpc_conn)).bind_with_graceful_shutdown(http_bind, shutdown_signal);
+
+    if let Some(certificate) = certificate {
+        return serve_tls(
+            http_bind,
+            router(grpc_conn, rate_limiter),
+            certificate,
+            service_ready,
+            shutdown_signal,
+        )
+        .await;
+    }
+
+    let (_, server) = warp::serve(router(grpc_conn, rate_limiter))
+        .bind_with_graceful_shutdown(http_bind, shutdown_signal);
     service_ready.trigger();
     server.await
 }
 
+/// Serves a given `filter` over TLS. The certificate is reloaded every [TLS_RELOAD_INTERVAL] if its files have changed.
+///
+/// Handshakes are bound by the default [HandshakeLimits].
+async fn serve_tls<F>(
+    http_bind: SocketAddr,
+    filter: F,
+    certificate: Arc<ReloadableCertificate>,
+    service_ready: Trigger,
+    shutdown_signal: Listener,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = certificate.clone();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind(http_bind).await.unwrap();
+    let service = warp::service(filter);
+
+    let reload_task = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TLS_RELOAD_INTERVAL).await;
+            match certificate.reload_if_changed() {
+                Ok(true) => log::info!("TLS certificate reloaded"),
+                Ok(false) => (),
+                Err(e) => log::error!("Cannot reload the TLS certificate. {e}"),
+            }
+        }
+    });
+
+    service_ready.trigger();
+    accept_tls(
+        listener,
+        acceptor,
+        HandshakeLimits::default(),
+        shutdown_signal,
+        move |stream, addr| {
+            let service = service.clone();
+            async move {
+                let service = service_fn(move |mut req| {
+                    req.extensions_mut().insert(RemoteAddr(addr));
+                    service.clone().call(req)
+                });
+                if let Err(e) = Http::new().serve_connection(stream, service).await {
+                    log::debug!("Connection with {addr} failed. {e}");
+                }
+            }
+        },
+    )
+    .await;
+
+    reload_task.abort();
+}
+
 #[cfg(test)]
 mod test_helpers {
     use super::*;
 
     use serde::de::DeserializeOwned;
     use serde_json::Value;
-    use std::sync::Arc;
     use tokio::net::TcpListener;
     use tonic::transport::Server;
 
     use crate::api::internal::InternalAPI;
+    use crate::api::rate_limit::RateLimits;
     use crate::protos::public_tower_services_server::PublicTowerServicesServer;
     use crate::test_utils::{create_api_with_config, ApiConfig, BitcoindStopper};
 
@@ -344,6 +628,10 @@ mod test_helpers {
         Body(&'a str),
     }
 
+    pub(crate) fn unlimited_rate_limiter() -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(RateLimits::unlimited()))
+    }
+
     pub(crate) async fn run_tower_in_background_with_config(
         api_config: ApiConfig,
     ) -> (SocketAddr, Arc<InternalAPI>, BitcoindStopper) {
@@ -403,7 +691,9 @@ mod test_helpers {
                 .body(b),
         };
 
-        let res = req.reply(&router(grpc_conn)).await;
+        let res = req
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
+            .await;
         (
             serde_json::from_slice::<ApiError>(res.body()).unwrap(),
             res.status(),
@@ -431,7 +721,7 @@ mod test_helpers {
             .method("POST")
             .path(&endpoint.path())
             .json(&serde_json::json!(body))
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
             .await;
 
         serde_json::from_slice::<T>(res.body())
@@ -440,7 +730,9 @@ mod test_helpers {
 
 #[cfg(test)]
 mod tests_failures {
-    use super::test_helpers::{check_api_error, run_tower_in_background, RequestBody};
+    use super::test_helpers::{
+        check_api_error, run_tower_in_background, unlimited_rate_limiter, RequestBody,
+    };
     use super::*;
 
     use teos_common::test_utils::get_random_user_id;
@@ -585,7 +877,7 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .path(&Endpoint::Register.path())
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
             .await;
 
         assert_eq!(res.status(), StatusCode::LENGTH_REQUIRED);
@@ -605,8 +897,13 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .path(&Endpoint::Register.path())
-            .json(&format!("{}{}", get_random_user_id(), get_random_user_id()))
-            .reply(&router(grpc_conn))
+            .json(&format!(
+                "{}{}{}",
+                get_random_user_id(),
+                get_random_user_id(),
+                get_random_user_id()
+            ))
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
             .await;
 
         assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
@@ -626,7 +923,7 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
             .await;
 
         assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
@@ -645,7 +942,7 @@ mod tests_failures {
 
         let res = warp::test::request()
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
             .await;
 
         assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
@@ -656,16 +953,20 @@ mod tests_failures {
 mod tests_methods {
     use super::test_helpers::{
         check_api_error, request_to_api, run_tower_in_background,
-        run_tower_in_background_with_config, RequestBody,
+        run_tower_in_background_with_config, unlimited_rate_limiter, RequestBody,
     };
     use super::*;
 
+    use crate::api::rate_limit::{RateLimit, RateLimitHits, RateLimits};
+    use crate::gatekeeper::SubscriptionPlan;
     use crate::responder::{ConfirmationStatus, TransactionTracker};
     use crate::test_utils::{
         generate_dummy_appointment, get_random_tx, ApiConfig, DURATION, SLOTS,
     };
-    use crate::watcher::Breach;
+    use crate::watcher::{Breach, RegistrationGate};
 
+    use teos_common::backup::Backup;
+    use teos_common::cryptography::get_random_bytes;
     use teos_common::test_utils::get_random_user_id;
     use teos_common::{cryptography, UserId};
 
@@ -677,6 +978,8 @@ mod tests_methods {
                 Endpoint::Register,
                 common_msgs::RegisterRequest {
                     user_id: get_random_user_id().to_vec(),
+                    plan: String::new(),
+                    ..Default::default()
                 },
                 server_addr,
             )
@@ -695,6 +998,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_id.to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -707,6 +1012,8 @@ mod tests_methods {
                 Endpoint::Register,
                 RequestBody::Json(serde_json::json!(common_msgs::RegisterRequest {
                     user_id: user_id.to_vec(),
+                    plan: String::new(),
+                    ..Default::default()
                 })),
                 server_addr,
             )
@@ -721,6 +1028,72 @@ mod tests_methods {
         );
     }
 
+    #[tokio::test]
+    async fn test_register_private_mode() {
+        let (server_addr, _, _s) =
+            run_tower_in_background_with_config(ApiConfig::new(SLOTS, DURATION).private_mode())
+                .await;
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::Register,
+                RequestBody::Json(serde_json::json!(common_msgs::RegisterRequest {
+                    user_id: get_random_user_id().to_vec(),
+                    plan: String::new(),
+                    ..Default::default()
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "The tower is running in private mode and the user is not allowed to register"
+                        .into(),
+                    errors::REGISTRATION_NOT_ALLOWED
+                ),
+                StatusCode::FORBIDDEN
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_gated() {
+        for (gate, error) in [
+            (
+                RegistrationGate::new(8, false),
+                ApiError::new(
+                    "Registration requires a valid proof of work (difficulty: 8)".into(),
+                    errors::REGISTRATION_POW_REQUIRED,
+                ),
+            ),
+            (
+                RegistrationGate::new(0, true),
+                ApiError::new(
+                    "Registration requires a valid registration token".into(),
+                    errors::REGISTRATION_TOKEN_REQUIRED,
+                ),
+            ),
+        ] {
+            let (server_addr, _, _s) = run_tower_in_background_with_config(
+                ApiConfig::new(SLOTS, DURATION).registration_gate(gate),
+            )
+            .await;
+
+            assert_eq!(
+                check_api_error(
+                    Endpoint::Register,
+                    RequestBody::Json(serde_json::json!(common_msgs::RegisterRequest {
+                        user_id: get_random_user_id().to_vec(),
+                        ..Default::default()
+                    })),
+                    server_addr,
+                )
+                .await,
+                (error, StatusCode::FORBIDDEN)
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_register_service_unavailable() {
         let (server_addr, _, _s) = run_tower_in_background_with_config(
@@ -735,6 +1108,8 @@ mod tests_methods {
                 Endpoint::Register,
                 RequestBody::Json(serde_json::json!(common_msgs::RegisterRequest {
                     user_id: user_id.to_vec(),
+                    plan: String::new(),
+                    ..Default::default()
                 })),
                 server_addr,
             )
@@ -759,6 +1134,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -827,6 +1204,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -907,6 +1286,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -952,6 +1333,47 @@ mod tests_methods {
         ));
     }
 
+    #[tokio::test]
+    async fn test_get_appointment_outcomes() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Nothing went wrong with the appointment, so there are no outcomes
+        let appointment = generate_dummy_appointment(None).inner;
+        let response = request_to_api::<
+            common_msgs::GetAppointmentOutcomesRequest,
+            common_msgs::GetAppointmentOutcomesResponse,
+        >(
+            Endpoint::GetAppointmentOutcomes,
+            common_msgs::GetAppointmentOutcomesRequest {
+                locator: appointment.locator.to_vec(),
+                signature: cryptography::sign(
+                    format!("get appointment outcomes {}", appointment.locator).as_bytes(),
+                    &user_sk,
+                )
+                .unwrap(),
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        assert!(response.outcomes.is_empty());
+    }
+
     #[tokio::test]
     async fn test_get_appointment_non_registered() {
         let (server_addr, _s) = run_tower_in_background().await;
@@ -995,6 +1417,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -1073,6 +1497,8 @@ mod tests_methods {
             Endpoint::Register,
             common_msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
             },
             server_addr,
         )
@@ -1153,4 +1579,440 @@ mod tests_methods {
             )
         );
     }
+
+    #[tokio::test]
+    async fn test_store_get_backup() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                plan: String::new(),
+                ..Default::default()
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // There is no backup yet
+        let get_backup_request = common_msgs::GetBackupRequest {
+            signature: cryptography::sign("get backup".as_bytes(), &user_sk).unwrap(),
+        };
+        assert_eq!(
+            check_api_error(
+                Endpoint::GetBackup,
+                RequestBody::Json(serde_json::json!(get_backup_request)),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new("Backup not found".into(), errors::BACKUP_NOT_FOUND),
+                StatusCode::NOT_FOUND
+            )
+        );
+
+        // Store one and get it back
+        let backup = Backup::new(get_random_bytes(100), 1);
+        let response =
+            request_to_api::<common_msgs::StoreBackupRequest, common_msgs::StoreBackupResponse>(
+                Endpoint::StoreBackup,
+                common_msgs::StoreBackupRequest {
+                    backup: backup.data.clone(),
+                    version: backup.version,
+                    signature: cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+                },
+                server_addr,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.version, backup.version);
+
+        let response = request_to_api::<
+            common_msgs::GetBackupRequest,
+            common_msgs::GetBackupResponse,
+        >(Endpoint::GetBackup, get_backup_request, server_addr)
+        .await
+        .unwrap();
+        assert_eq!(response, backup.clone().into());
+
+        // Backups with an outdated version are rejected
+        assert_eq!(
+            check_api_error(
+                Endpoint::StoreBackup,
+                RequestBody::Json(serde_json::json!(common_msgs::StoreBackupRequest {
+                    backup: backup.data.clone(),
+                    version: backup.version,
+                    signature: cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "The provided backup is outdated. Latest stored version is 1".into(),
+                    errors::BACKUP_OUTDATED_VERSION
+                ),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_backup_non_registered() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        let (user_sk, _) = cryptography::get_random_keypair();
+        let backup = Backup::new(get_random_bytes(100), 1);
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::StoreBackup,
+                RequestBody::Json(serde_json::json!(common_msgs::StoreBackupRequest {
+                    backup: backup.data.clone(),
+                    version: backup.version,
+                    signature: cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "Invalid signature or user does not have enough slots available".into(),
+                    errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+                ),
+                StatusCode::UNAUTHORIZED
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_too_big() {
+        let (server_addr, _, _s) = run_tower_in_background_with_config(
+            ApiConfig::default().plan("small", SubscriptionPlan::new(SLOTS, DURATION, 10, 0)),
+        )
+        .await;
+
+        // Register under a plan whose blob size limit is smaller than the appointment
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                plan: "small".to_owned(),
+                ..Default::default()
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::AddAppointment,
+                RequestBody::Json(serde_json::json!(common_msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "The provided appointment is too big for your subscription plan (max 10 bytes)"
+                        .into(),
+                    errors::APPOINTMENT_FIELD_TOO_BIG
+                ),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_subscription_plans() {
+        let (server_addr, _, _s) = run_tower_in_background_with_config(
+            ApiConfig::default().plan("premium", SubscriptionPlan::new(SLOTS * 2, DURATION, 0, 0)),
+        )
+        .await;
+
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+
+        let res = warp::test::request()
+            .method("GET")
+            .path(&Endpoint::GetSubscriptionPlans.path())
+            .reply(&router(grpc_conn, unlimited_rate_limiter()))
+            .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let response =
+            serde_json::from_slice::<common_msgs::GetSubscriptionPlansResponse>(res.body())
+                .unwrap();
+        assert_eq!(
+            response
+                .plans
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["default", "premium"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_by_address() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimits {
+            register: RateLimit::new(1, 1, 0, 0),
+            ..RateLimits::unlimited()
+        }));
+        let router = router(grpc_conn, rate_limiter.clone());
+
+        let mut statuses = Vec::new();
+        for _ in 0..2 {
+            let res = warp::test::request()
+                .method("POST")
+                .path(&Endpoint::Register.path())
+                .remote_addr("1.2.3.4:9814".parse().unwrap())
+                .json(&common_msgs::RegisterRequest {
+                    user_id: get_random_user_id().to_vec(),
+                    ..Default::default()
+                })
+                .reply(&router)
+                .await;
+            statuses.push(res.status());
+
+            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                assert_eq!(
+                    serde_json::from_slice::<ApiError>(res.body()).unwrap(),
+                    ApiError::new(
+                        "Too many requests from this address. Try again later".into(),
+                        errors::RATE_LIMIT_EXCEEDED
+                    )
+                );
+            }
+        }
+
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+        assert_eq!(
+            rate_limiter.get_hits()[0],
+            (
+                Endpoint::Register,
+                RateLimitHits {
+                    by_address: 1,
+                    by_user: 0
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_not_rate_limited_by_user() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimits::default()));
+        let router = router(grpc_conn, rate_limiter.clone());
+
+        // Someone floods register claiming to be the user, until their address gets limited
+        let user_id = get_random_user_id();
+        let register = |addr: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&Endpoint::Register.path())
+                .remote_addr(addr.parse().unwrap())
+                .json(&common_msgs::RegisterRequest {
+                    user_id: user_id.to_vec(),
+                    ..Default::default()
+                })
+                .reply(&router)
+        };
+        let mut limited = false;
+        for _ in 0..=RateLimits::default().register.ip_burst {
+            if register("1.2.3.4:9814").await.status() == StatusCode::TOO_MANY_REQUESTS {
+                limited = true;
+                break;
+            }
+        }
+        assert!(limited);
+
+        // The actual user can still register from its own address
+        assert_eq!(register("4.3.2.1:9814").await.status(), StatusCode::OK);
+        assert_eq!(rate_limiter.get_hits()[0].1.by_user, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_by_user() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimits {
+            get_subscription_info: RateLimit::new(0, 0, 1, 1),
+            ..RateLimits::unlimited()
+        }));
+        let router = router(grpc_conn, rate_limiter.clone());
+
+        // Register a user
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        let res = warp::test::request()
+            .method("POST")
+            .path(&Endpoint::Register.path())
+            .json(&common_msgs::RegisterRequest {
+                user_id: UserId(user_pk).to_vec(),
+                ..Default::default()
+            })
+            .reply(&router)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // The user can only get its subscription info once in a row, no matter the address it uses
+        let signature = cryptography::sign("get subscription info".as_bytes(), &user_sk).unwrap();
+        let mut statuses = Vec::new();
+        for addr in ["1.2.3.4:9814", "4.3.2.1:9814"] {
+            let res = warp::test::request()
+                .method("POST")
+                .path(&Endpoint::GetSubscriptionInfo.path())
+                .remote_addr(addr.parse().unwrap())
+                .json(&common_msgs::GetSubscriptionInfoRequest {
+                    signature: signature.clone(),
+                })
+                .reply(&router)
+                .await;
+            statuses.push(res.status());
+        }
+
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+        assert_eq!(
+            rate_limiter.get_hits()[3],
+            (
+                Endpoint::GetSubscriptionInfo,
+                RateLimitHits {
+                    by_address: 0,
+                    by_user: 1
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_addr() {
+        let addr: SocketAddr = "1.2.3.4:9814".parse().unwrap();
+        let filter = remote_addr();
+
+        // Addresses are either set by warp or carried as an extension by TLS connections
+        assert_eq!(
+            warp::test::request()
+                .remote_addr(addr)
+                .filter(&filter)
+                .await
+                .unwrap(),
+            Some(addr)
+        );
+        assert_eq!(
+            warp::test::request()
+                .extension(RemoteAddr(addr))
+                .filter(&filter)
+                .await
+                .unwrap(),
+            Some(addr)
+        );
+        assert_eq!(warp::test::request().filter(&filter).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls() {
+        let (grpc_addr, _s) = run_tower_in_background().await;
+
+        let tmp_dir = tempdir::TempDir::new("teos_api_tls").unwrap();
+        let cert_path = tmp_dir.path().join("cert.pem");
+        let key_path = tmp_dir.path().join("key.pem");
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        std::fs::write(&cert_path, &cert_pem).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        let certificate = Arc::new(ReloadableCertificate::new(cert_path, key_path).unwrap());
+
+        let http_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let server = tokio::spawn(serve(
+            http_addr,
+            grpc_addr,
+            unlimited_rate_limiter(),
+            Some(certificate),
+            service_ready,
+            shutdown_signal,
+        ));
+        ready_signal.await;
+
+        let url = format!(
+            "https://localhost:{}{}",
+            http_addr.port(),
+            Endpoint::Ping.path()
+        );
+
+        // The certificate is not trusted by default
+        assert!(reqwest::get(&url).await.is_err());
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(cert_pem.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Requests are proxied to the tower as usual
+        let response = client
+            .post(format!(
+                "https://localhost:{}{}",
+                http_addr.port(),
+                Endpoint::Register.path()
+            ))
+            .json(&common_msgs::RegisterRequest {
+                user_id: get_random_user_id().to_vec(),
+                ..Default::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response
+            .json::<common_msgs::RegisterResponse>()
+            .await
+            .is_ok());
+
+        shutdown_trigger.trigger();
+        server.await.unwrap();
+    }
 }
@@ -1,4 +1,6 @@
+pub mod grpc;
 pub mod http;
 pub mod internal;
+pub mod rate_limit;
 pub mod serde;
 pub mod tor;
@@ -0,0 +1,609 @@
+//! Logic related to rate limiting requests to the public API.
+//!
+//! Limits are enforced using token buckets, kept both per remote address and per user. Each bucket holds up to
+//! `burst` tokens and is refilled at `per_minute` tokens per minute. Every request consumes a token from the
+//! buckets it maps to, and is refused if any of them is empty.
+//!
+//! Users are identified by the key that signed the request, so per user limits only apply to signed endpoints.
+//! Register requests are not signed (anyone can claim any user id), so they are only limited per remote address.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use teos_common::cryptography;
+use teos_common::net::http::Endpoint;
+use teos_common::UserId;
+
+/// Endpoints of the public API that are subject to rate limiting.
+pub const RATE_LIMITED_ENDPOINTS: [Endpoint; 4] = [
+    Endpoint::Register,
+    Endpoint::AddAppointment,
+    Endpoint::GetAppointment,
+    Endpoint::GetSubscriptionInfo,
+];
+
+/// Rate limited endpoints whose requests are signed by the user, and can therefore be limited per user.
+const SIGNED_ENDPOINTS: [Endpoint; 3] = [
+    Endpoint::AddAppointment,
+    Endpoint::GetAppointment,
+    Endpoint::GetSubscriptionInfo,
+];
+
+/// Number of buckets an endpoint can track (per key type). Once reached, the least recently used one is dropped.
+const MAX_TRACKED_BUCKETS: usize = 100_000;
+
+/// Length of the prefix IPv6 addresses are rate limited by. A single host usually controls a whole /64.
+const IPV6_PREFIX_LEN: u32 = 64;
+
+/// Rate limits for one of the public API endpoints. A zero `*_per_minute` disables the corresponding limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Maximum number of requests a remote address can send in a row.
+    pub ip_burst: u32,
+    /// Number of requests per minute a remote address is allowed to send, once the burst has been consumed.
+    pub ip_per_minute: u32,
+    /// Maximum number of requests a user can send in a row.
+    pub user_burst: u32,
+    /// Number of requests per minute a user is allowed to send, once the burst has been consumed.
+    pub user_per_minute: u32,
+}
+
+impl RateLimit {
+    /// Creates a new [RateLimit] instance.
+    pub fn new(ip_burst: u32, ip_per_minute: u32, user_burst: u32, user_per_minute: u32) -> Self {
+        RateLimit {
+            ip_burst,
+            ip_per_minute,
+            user_burst,
+            user_per_minute,
+        }
+    }
+}
+
+/// Rate limits for all the rate limited endpoints of the public API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub register: RateLimit,
+    pub add_appointment: RateLimit,
+    pub get_appointment: RateLimit,
+    pub get_subscription_info: RateLimit,
+}
+
+impl RateLimits {
+    /// Rate limits that let every request through.
+    pub fn unlimited() -> Self {
+        RateLimits {
+            register: RateLimit::default(),
+            add_appointment: RateLimit::default(),
+            get_appointment: RateLimit::default(),
+            get_subscription_info: RateLimit::default(),
+        }
+    }
+
+    /// Gets the rate limits for a given endpoint, if the endpoint is rate limited.
+    pub fn get(&self, endpoint: Endpoint) -> Option<&RateLimit> {
+        match endpoint {
+            Endpoint::Register => Some(&self.register),
+            Endpoint::AddAppointment => Some(&self.add_appointment),
+            Endpoint::GetAppointment => Some(&self.get_appointment),
+            Endpoint::GetSubscriptionInfo => Some(&self.get_subscription_info),
+            _ => None,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            register: RateLimit::new(10, 10, 0, 0),
+            add_appointment: RateLimit::new(200, 600, 100, 300),
+            get_appointment: RateLimit::new(30, 120, 20, 60),
+            get_subscription_info: RateLimit::new(10, 60, 10, 30),
+        }
+    }
+}
+
+/// Reason why a request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimited {
+    /// The remote address went over its rate limit.
+    Address,
+    /// The user went over its rate limit.
+    User,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimited::Address => {
+                write!(f, "Too many requests from this address. Try again later")
+            }
+            RateLimited::User => write!(f, "Too many requests from this user. Try again later"),
+        }
+    }
+}
+
+/// Number of requests to an endpoint that have been refused for going over the rate limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitHits {
+    pub by_address: u64,
+    pub by_user: u64,
+}
+
+/// A bucket of tokens, refilled over time.
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// Sequence number of the last time the bucket was used, to tell the least recently used buckets apart.
+    last_used: u64,
+}
+
+impl TokenBucket {
+    /// Creates a new [TokenBucket] holding `burst` tokens.
+    fn new(burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst as f64,
+            last_refill: now,
+            last_used: 0,
+        }
+    }
+
+    /// Adds the tokens accrued since the last refill, up to `burst`.
+    fn refill(&mut self, burst: u32, per_minute: u32, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_minute as f64 / 60.0).min(burst as f64);
+        self.last_refill = now;
+    }
+}
+
+/// Token buckets for an endpoint, indexed by key (e.g. remote address or user id).
+///
+/// At most [MAX_TRACKED_BUCKETS] buckets are kept. Once the limit is reached, the least recently used bucket is
+/// dropped to make room for a new one.
+struct Buckets<K> {
+    burst: u32,
+    per_minute: u32,
+    buckets: HashMap<K, TokenBucket>,
+    /// Keys of the tracked buckets by the last time they were used (see [TokenBucket::last_used]).
+    lru: BTreeMap<u64, K>,
+    /// Sequence number of the last bucket access.
+    seq: u64,
+}
+
+impl<K: Hash + Eq + Copy> Buckets<K> {
+    /// Creates a new [Buckets] instance.
+    fn new(burst: u32, per_minute: u32) -> Self {
+        Buckets {
+            burst,
+            per_minute,
+            buckets: HashMap::new(),
+            lru: BTreeMap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Takes a token from the bucket of a given key. Returns false if the bucket was empty.
+    fn try_consume(&mut self, key: K, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+
+        if self.buckets.len() >= MAX_TRACKED_BUCKETS && !self.buckets.contains_key(&key) {
+            if let Some((_, oldest)) = self.lru.pop_first() {
+                self.buckets.remove(&oldest);
+            }
+        }
+
+        self.seq += 1;
+        let (burst, per_minute) = (self.burst, self.per_minute);
+        let bucket = self
+            .buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(burst, now));
+        self.lru.remove(&bucket.last_used);
+        self.lru.insert(self.seq, key);
+        bucket.last_used = self.seq;
+
+        bucket.refill(burst, per_minute, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Maps a remote address to the key it is rate limited by. IPv6 addresses are grouped by their /64 prefix, given
+/// hosts can trivially rotate the addresses within it.
+fn address_key(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => addr,
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX << (128 - IPV6_PREFIX_LEN);
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+        }
+    }
+}
+
+/// Rate limiting state of an endpoint.
+struct EndpointLimiter {
+    by_address: Buckets<IpAddr>,
+    by_user: Buckets<UserId>,
+    hits: RateLimitHits,
+}
+
+impl EndpointLimiter {
+    /// Creates a new [EndpointLimiter] instance.
+    fn new(limit: &RateLimit) -> Self {
+        EndpointLimiter {
+            by_address: Buckets::new(limit.ip_burst, limit.ip_per_minute),
+            by_user: Buckets::new(limit.user_burst, limit.user_per_minute),
+            hits: RateLimitHits::default(),
+        }
+    }
+}
+
+/// Component in charge of rate limiting requests to the public API.
+pub struct RateLimiter {
+    endpoints: Mutex<HashMap<Endpoint, EndpointLimiter>>,
+}
+
+impl RateLimiter {
+    /// Creates a new [RateLimiter] instance.
+    pub fn new(limits: RateLimits) -> Self {
+        let endpoints = RATE_LIMITED_ENDPOINTS
+            .iter()
+            .map(|endpoint| {
+                (
+                    *endpoint,
+                    EndpointLimiter::new(limits.get(*endpoint).unwrap()),
+                )
+            })
+            .collect();
+
+        RateLimiter {
+            endpoints: Mutex::new(endpoints),
+        }
+    }
+
+    /// Checks whether a request to `endpoint` is within the rate limits of both its remote address and user,
+    /// if known. Refused requests are accounted for in the endpoint [RateLimitHits].
+    ///
+    /// The user is only taken into account for signed endpoints ([SIGNED_ENDPOINTS]). Requests coming from loopback
+    /// addresses (such as the ones forwarded by the Tor onion service) are only limited per user, given they may be
+    /// shared by any number of clients.
+    pub fn check(
+        &self,
+        endpoint: Endpoint,
+        addr: Option<IpAddr>,
+        user_id: Option<UserId>,
+    ) -> Result<(), RateLimited> {
+        self.check_at(endpoint, addr, user_id, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        endpoint: Endpoint,
+        addr: Option<IpAddr>,
+        user_id: Option<UserId>,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let limiter = match endpoints.get_mut(&endpoint) {
+            Some(limiter) => limiter,
+            None => return Ok(()),
+        };
+
+        if let Some(addr) = addr.filter(|addr| !addr.is_loopback()) {
+            if !limiter.by_address.try_consume(address_key(addr), now) {
+                limiter.hits.by_address += 1;
+                return Err(RateLimited::Address);
+            }
+        }
+        if let Some(user_id) = user_id.filter(|_| SIGNED_ENDPOINTS.contains(&endpoint)) {
+            if !limiter.by_user.try_consume(user_id, now) {
+                limiter.hits.by_user += 1;
+                return Err(RateLimited::User);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the number of refused requests for every rate limited endpoint.
+    pub fn get_hits(&self) -> Vec<(Endpoint, RateLimitHits)> {
+        let endpoints = self.endpoints.lock().unwrap();
+        RATE_LIMITED_ENDPOINTS
+            .iter()
+            .map(|endpoint| (*endpoint, endpoints[endpoint].hits))
+            .collect()
+    }
+}
+
+/// Recovers the id of the user that signed a request. The signature is not checked against the registered
+/// users here, so this is only meant to tell requests coming from different keys apart.
+pub fn recover_user_id(message: &[u8], signature: &str) -> Option<UserId> {
+    cryptography::recover_pk(message, signature)
+        .ok()
+        .map(UserId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use teos_common::test_utils::get_random_user_id;
+
+    fn limits(limit: RateLimit) -> RateLimits {
+        RateLimits {
+            register: limit,
+            add_appointment: limit,
+            ..RateLimits::unlimited()
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(2, 60);
+
+        // The burst can be consumed straight away
+        assert!(buckets.try_consume(1, now));
+        assert!(buckets.try_consume(1, now));
+        assert!(!buckets.try_consume(1, now));
+        // Other keys have their own buckets
+        assert!(buckets.try_consume(2, now));
+
+        // Tokens are refilled at per_minute / 60 per second
+        assert!(!buckets.try_consume(1, now + Duration::from_millis(500)));
+        assert!(buckets.try_consume(1, now + Duration::from_secs(1)));
+        assert!(!buckets.try_consume(1, now + Duration::from_secs(1)));
+
+        // But never over the burst
+        let later = now + Duration::from_secs(3600);
+        assert!(buckets.try_consume(1, later));
+        assert!(buckets.try_consume(1, later));
+        assert!(!buckets.try_consume(1, later));
+    }
+
+    #[test]
+    fn test_token_bucket_disabled() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(0, 0);
+
+        for _ in 0..100 {
+            assert!(buckets.try_consume(1, now));
+        }
+        assert!(buckets.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_token_bucket_eviction() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(1, 60);
+
+        for key in 0..MAX_TRACKED_BUCKETS {
+            assert!(buckets.try_consume(key, now));
+        }
+        assert_eq!(buckets.buckets.len(), MAX_TRACKED_BUCKETS);
+
+        // Using a bucket makes it the most recently used one
+        assert!(!buckets.try_consume(0, now));
+
+        // Once the limit is reached, the least recently used bucket is dropped to make room for new ones, even if
+        // none of them is full again
+        assert!(buckets.try_consume(MAX_TRACKED_BUCKETS, now));
+        assert_eq!(buckets.buckets.len(), MAX_TRACKED_BUCKETS);
+        assert_eq!(buckets.lru.len(), MAX_TRACKED_BUCKETS);
+        assert!(!buckets.buckets.contains_key(&1));
+        assert!(!buckets.try_consume(0, now));
+
+        // So the dropped key starts over with a full bucket
+        assert!(buckets.try_consume(1, now));
+        assert!(!buckets.buckets.contains_key(&2));
+    }
+
+    #[test]
+    fn test_address_key() {
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        assert_eq!(address_key(v4), v4);
+
+        // IPv6 addresses are keyed by their /64 prefix
+        assert_eq!(
+            address_key("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "2001:db8:1:2::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            address_key("2001:db8:1:2::1".parse().unwrap()),
+            address_key("2001:db8:1:2:ffff::".parse().unwrap())
+        );
+        assert_ne!(
+            address_key("2001:db8:1:2::1".parse().unwrap()),
+            address_key("2001:db8:1:3::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_check_by_address() {
+        let now = Instant::now();
+        let rate_limiter = RateLimiter::new(limits(RateLimit::new(1, 1, 0, 0)));
+        let addr: IpAddr = "1.2.3.4".parse().unwrap();
+
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::Register, Some(addr), None, now),
+            Ok(())
+        );
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::Register, Some(addr), None, now),
+            Err(RateLimited::Address)
+        );
+        // Other addresses, and unknown ones, are not affected
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::Register,
+                Some("4.3.2.1".parse().unwrap()),
+                None,
+                now
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::Register, None, None, now),
+            Ok(())
+        );
+        // Neither are other endpoints
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::GetAppointment, Some(addr), None, now),
+            Ok(())
+        );
+        // Addresses within the same IPv6 /64 share their limits
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::Register,
+                Some("2001:db8::1".parse().unwrap()),
+                None,
+                now
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::Register,
+                Some("2001:db8::2".parse().unwrap()),
+                None,
+                now
+            ),
+            Err(RateLimited::Address)
+        );
+        // Loopback addresses are not limited
+        for _ in 0..10 {
+            assert_eq!(
+                rate_limiter.check_at(
+                    Endpoint::Register,
+                    Some("127.0.0.1".parse().unwrap()),
+                    None,
+                    now
+                ),
+                Ok(())
+            );
+        }
+
+        assert_eq!(
+            rate_limiter.get_hits()[0],
+            (
+                Endpoint::Register,
+                RateLimitHits {
+                    by_address: 2,
+                    by_user: 0
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_check_by_user() {
+        let now = Instant::now();
+        let rate_limiter = RateLimiter::new(limits(RateLimit::new(0, 0, 2, 1)));
+        let user_id = get_random_user_id();
+        let localhost = Some("127.0.0.1".parse().unwrap());
+
+        for _ in 0..2 {
+            assert_eq!(
+                rate_limiter.check_at(Endpoint::AddAppointment, localhost, Some(user_id), now),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::AddAppointment, localhost, Some(user_id), now),
+            Err(RateLimited::User)
+        );
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::AddAppointment,
+                localhost,
+                Some(get_random_user_id()),
+                now
+            ),
+            Ok(())
+        );
+
+        // After a minute the user gets a new token
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::AddAppointment, localhost, Some(user_id), later),
+            Ok(())
+        );
+        assert_eq!(
+            rate_limiter.check_at(Endpoint::AddAppointment, localhost, Some(user_id), later),
+            Err(RateLimited::User)
+        );
+
+        let hits = rate_limiter.get_hits();
+        assert_eq!(
+            hits.iter().map(|(e, _)| *e).collect::<Vec<_>>(),
+            RATE_LIMITED_ENDPOINTS.to_vec()
+        );
+        assert_eq!(
+            hits[1].1,
+            RateLimitHits {
+                by_address: 0,
+                by_user: 2
+            }
+        );
+        assert!(hits
+            .iter()
+            .filter(|(e, _)| *e != Endpoint::AddAppointment)
+            .all(|(_, hits)| *hits == RateLimitHits::default()));
+    }
+
+    #[test]
+    fn test_check_register_not_by_user() {
+        let now = Instant::now();
+        let rate_limiter = RateLimiter::new(limits(RateLimit::new(1, 1, 1, 1)));
+        let user_id = get_random_user_id();
+
+        // Register requests are not signed, so flooding them with someone else's user id from one address does not
+        // lock the actual user out
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::Register,
+                Some("1.2.3.4".parse().unwrap()),
+                Some(user_id),
+                now
+            ),
+            Ok(())
+        );
+        for _ in 0..10 {
+            assert_eq!(
+                rate_limiter.check_at(
+                    Endpoint::Register,
+                    Some("1.2.3.4".parse().unwrap()),
+                    Some(user_id),
+                    now
+                ),
+                Err(RateLimited::Address)
+            );
+        }
+        assert_eq!(
+            rate_limiter.check_at(
+                Endpoint::Register,
+                Some("4.3.2.1".parse().unwrap()),
+                Some(user_id),
+                now
+            ),
+            Ok(())
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::protos as msgs;
 
-use teos_common::net::AddressType;
+use teos_common::net::{get_host, AddressType};
+
+use crate::recovery::MissedBreachKind;
 
 impl msgs::NetworkAddress {
     pub fn from_ipv4(address: String, port: u16) -> Self {
@@ -18,11 +20,22 @@ impl msgs::NetworkAddress {
             port: port as u32,
         }
     }
+
+    /// Builds a clearnet address, classifying it as IPv4, IPv6 or hostname. IPv6 literals are stored without brackets.
+    pub fn from_clearnet(address: String, port: u16) -> Self {
+        let address = get_host(&address).to_owned();
+        Self {
+            address_type: AddressType::get_type(&address) as i32,
+            address,
+            port: port as u32,
+        }
+    }
 }
 
 pub mod serde_address_type {
     use serde::de::{self, Deserializer};
     use serde::Serializer;
+    use std::convert::TryFrom;
     use std::str::FromStr;
 
     use super::AddressType;
@@ -31,7 +44,8 @@ pub mod serde_address_type {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&AddressType::from(*status).to_string())
+        let address_type = AddressType::try_from(*status).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&address_type.to_string())
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<i32, D::Error>
@@ -60,3 +74,88 @@ pub mod serde_address_type {
         deserializer.deserialize_any(StatusVisitor)
     }
 }
+
+pub mod serde_missed_breach_kind {
+    use serde::de::{self, Deserializer};
+    use serde::Serializer;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    use super::MissedBreachKind;
+
+    pub fn serialize<S>(kind: &i32, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let kind = MissedBreachKind::try_from(*kind).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&kind.to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct KindVisitor;
+
+        impl<'de> de::Visitor<'de> for KindVisitor {
+            type Value = i32;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a string containing the missed breach kind")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let kind = MissedBreachKind::from_str(v)
+                    .map_err(|_| E::custom("given missed breach kind is unknown"))?;
+                Ok(kind as i32)
+            }
+        }
+
+        deserializer.deserialize_any(KindVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_address_from_clearnet() {
+        for (address, expected_address, address_type) in [
+            ("127.0.0.1", "127.0.0.1", AddressType::IpV4),
+            ("::1", "::1", AddressType::IpV6),
+            ("[::1]", "::1", AddressType::IpV6),
+            (
+                "teos.talaia.watch",
+                "teos.talaia.watch",
+                AddressType::Hostname,
+            ),
+        ] {
+            let network_address = msgs::NetworkAddress::from_clearnet(address.to_owned(), 9814);
+            assert_eq!(network_address.address, expected_address);
+            assert_eq!(network_address.address_type, address_type as i32);
+            assert_eq!(network_address.port, 9814);
+        }
+    }
+
+    #[test]
+    fn test_serialize_address_type() {
+        let network_address = msgs::NetworkAddress::from_clearnet("::1".to_owned(), 9814);
+        let json = serde_json::to_value(&network_address).unwrap();
+        assert_eq!(json["type"], "ipv6");
+        assert_eq!(
+            serde_json::from_value::<msgs::NetworkAddress>(json).unwrap(),
+            network_address
+        );
+
+        // Unknown address types cannot be serialized
+        let network_address = msgs::NetworkAddress {
+            address_type: 42,
+            ..network_address
+        };
+        assert!(serde_json::to_value(&network_address).is_err());
+    }
+}
@@ -1,19 +1,35 @@
+use std::borrow::Cow;
 use std::convert::TryInto;
 use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use torut::control::UnauthenticatedConn;
+use torut::control::{TorAuthData, UnauthenticatedConn};
 use torut::onion::TorSecretKeyV3;
 use triggered::{Listener, Trigger};
 
+/// Method used to authenticate with the Tor control port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorControlAuth {
+    /// Picks a method based on what the Tor daemon reports as supported.
+    Auto,
+    /// Authenticates using the cookie stored at the given path.
+    Cookie(PathBuf),
+    /// Authenticates using the given password.
+    Password(String),
+}
+
 pub struct TorAPI {
     sk: TorSecretKeyV3,
     api_endpoint: SocketAddr,
     onion_port: u16,
     tor_control_port: u16,
+    control_auth: TorControlAuth,
+    /// Public keys of the clients authorized to reach the onion service. The service is public if empty.
+    client_auth_keys: Vec<String>,
 }
 
 impl TorAPI {
@@ -21,6 +37,8 @@ impl TorAPI {
         api_endpoint: SocketAddr,
         onion_port: u16,
         tor_control_port: u16,
+        control_auth: TorControlAuth,
+        client_auth_keys: Vec<String>,
         path: PathBuf,
     ) -> Self {
         let key = if let Some(key) = TorAPI::load_sk(path.clone()).await {
@@ -37,6 +55,8 @@ impl TorAPI {
             api_endpoint,
             onion_port,
             tor_control_port,
+            control_auth,
+            client_auth_keys,
         }
     }
 
@@ -79,48 +99,109 @@ impl TorAPI {
         Ok(sock)
     }
 
-    /// Expose an onion service that re-directs to the public api.
-    pub async fn expose_onion_service(
-        &self,
-        service_ready: Trigger,
-        shutdown_signal_tor: Listener,
-    ) -> Result<(), Error> {
-        let stream = self
-            .connect_tor_cp()
-            .await
-            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
-
+    /// Authenticates with the Tor control port using the configured method.
+    async fn authenticate(&self, stream: &mut TcpStream) -> Result<(), Error> {
         let mut unauth_conn = UnauthenticatedConn::new(stream);
 
-        let pre_auth = unauth_conn
-            .load_protocol_info()
-            .await
-            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
-
-        let auth_data = pre_auth
-            .make_auth_data()?
-            .expect("failed to make auth data");
+        let auth_data = match &self.control_auth {
+            TorControlAuth::Auto => unauth_conn
+                .load_protocol_info()
+                .await
+                .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?
+                .make_auth_data()?
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::PermissionDenied,
+                        "no supported Tor authentication method found",
+                    )
+                })?,
+            TorControlAuth::Cookie(path) => {
+                TorAuthData::SafeCookie(Cow::Owned(fs::read(path).await.map_err(|e| {
+                    Error::new(e.kind(), format!("failed to read Tor auth cookie: {e}"))
+                })?))
+            }
+            TorControlAuth::Password(password) => {
+                TorAuthData::HashedPassword(Cow::Owned(password.clone()))
+            }
+        };
 
         unauth_conn.authenticate(&auth_data).await.map_err(|_| {
             Error::new(
                 ErrorKind::PermissionDenied,
                 "failed to authenticate with Tor",
             )
-        })?;
+        })
+    }
 
-        let mut auth_conn = unauth_conn.into_authenticated().await;
+    /// Builds the `ADD_ONION` command for the onion service. Client authorization is enabled if any client key is set.
+    fn add_onion_command(&self) -> String {
+        let mut flags = vec!["DiscardPK"];
+        if !self.client_auth_keys.is_empty() {
+            flags.push("V3Auth");
+        }
 
-        auth_conn.set_async_event_handler(Some(|_| async move { Ok(()) }));
+        let mut command = format!(
+            "ADD_ONION ED25519-V3:{} Flags={} Port={},{}",
+            bitcoin::base64::encode(&self.sk.as_bytes()),
+            flags.join(","),
+            self.onion_port,
+            self.api_endpoint
+        );
+        for key in self.client_auth_keys.iter() {
+            command.push_str(&format!(" ClientAuthV3={key}"));
+        }
+        command
+    }
 
-        auth_conn
-            .add_onion_v3(
-                &self.sk,
-                false,
-                false,
-                false,
-                None,
-                &mut [(self.onion_port, self.api_endpoint)].iter(),
-            )
+    /// Sends a command through an (authenticated) control port connection and waits for the reply.
+    ///
+    /// Commands are sent raw since `torut` does not support onion services with client authorization.
+    async fn send_command(stream: &mut TcpStream, command: &str) -> Result<(), Error> {
+        stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+
+        // Replies are made of lines in the form `<code>[-+ ]<data>`, the last one using a space as separator
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Tor control port connection closed",
+                ));
+            }
+            let line = line.trim_end();
+            if line.len() < 4 || !line.is_char_boundary(3) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected reply from Tor: {line}"),
+                ));
+            }
+            if line[3..].starts_with(' ') {
+                return if line.starts_with("250") {
+                    Ok(())
+                } else {
+                    Err(Error::other(format!("Tor rejected the command: {line}")))
+                };
+            }
+        }
+    }
+
+    /// Expose an onion service that re-directs to the public api.
+    pub async fn expose_onion_service(
+        &self,
+        service_ready: Trigger,
+        shutdown_signal_tor: Listener,
+    ) -> Result<(), Error> {
+        let mut stream = self
+            .connect_tor_cp()
+            .await
+            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
+
+        self.authenticate(&mut stream).await?;
+
+        TorAPI::send_command(&mut stream, &self.add_onion_command())
             .await
             .map_err(|e| {
                 Error::new(
@@ -134,20 +215,26 @@ impl TorAPI {
             self.get_onion_address(),
             self.onion_port
         );
+        if !self.client_auth_keys.is_empty() {
+            log::info!(
+                "Onion service restricted to {} authorized client(s)",
+                self.client_auth_keys.len()
+            );
+        }
         service_ready.trigger();
         shutdown_signal_tor.await;
 
-        auth_conn
-            .del_onion(
-                &self
-                    .sk
+        TorAPI::send_command(
+            &mut stream,
+            &format!(
+                "DEL_ONION {}",
+                self.sk
                     .public()
                     .get_onion_address()
-                    .get_address_without_dot_onion(),
-            )
-            .await
-            .unwrap();
-        Ok(())
+                    .get_address_without_dot_onion()
+            ),
+        )
+        .await
     }
 }
 
@@ -155,6 +242,7 @@ impl TorAPI {
 mod tests {
     use super::*;
     use tempdir::TempDir;
+    use tokio::net::TcpListener;
 
     use teos_common::test_utils::get_random_user_id;
 
@@ -196,6 +284,8 @@ mod tests {
             "127.0.1.1:9814".parse().unwrap(),
             9814,
             wrong_cp,
+            TorControlAuth::Auto,
+            Vec::new(),
             tmp_path.path().into(),
         )
         .await;
@@ -207,4 +297,128 @@ mod tests {
             }
         }
     }
+
+    const CLIENT_AUTH_KEY: &str = "N2NU7BSRL6YODZCYPN4CREB54TYLKGIE2KYOQWLFYC23ZJVCE5DQ";
+
+    #[tokio::test]
+    async fn test_add_onion_command() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let mut tor_api = TorAPI::new(
+            "127.0.0.1:9814".parse().unwrap(),
+            9814,
+            9051,
+            TorControlAuth::Auto,
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+
+        let command = tor_api.add_onion_command();
+        assert!(command.starts_with("ADD_ONION ED25519-V3:"));
+        assert!(command.ends_with(" Flags=DiscardPK Port=9814,127.0.0.1:9814"));
+
+        // Client authorization is only enabled if there are authorized clients
+        tor_api.client_auth_keys = vec![CLIENT_AUTH_KEY.to_owned(); 2];
+        let command = tor_api.add_onion_command();
+        assert!(command.ends_with(&format!(
+            " Flags=DiscardPK,V3Auth Port=9814,127.0.0.1:9814 ClientAuthV3={CLIENT_AUTH_KEY} ClientAuthV3={CLIENT_AUTH_KEY}"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_expose_onion_service() {
+        // Mock the Tor control port, replying to the commands the TorAPI is expected to send
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_port = listener.local_addr().unwrap().port();
+        let control_task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut commands = Vec::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply = if line.starts_with("ADD_ONION") {
+                    "250-ServiceID=service\r\n250 OK\r\n"
+                } else if line.starts_with("AUTHENTICATE") && line != "AUTHENTICATE \"password\"" {
+                    "515 Authentication failed\r\n"
+                } else {
+                    "250 OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                commands.push(line);
+            }
+            commands
+        });
+
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let tor_api = TorAPI::new(
+            "127.0.0.1:9814".parse().unwrap(),
+            9814,
+            control_port,
+            TorControlAuth::Password("password".to_owned()),
+            vec![CLIENT_AUTH_KEY.to_owned()],
+            tmp_path.path().into(),
+        )
+        .await;
+
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let onion_address = tor_api.get_onion_address();
+        let tor_task = tokio::spawn(async move {
+            tor_api
+                .expose_onion_service(service_ready, shutdown_signal)
+                .await
+        });
+        ready_signal.await;
+        shutdown_trigger.trigger();
+        tor_task.await.unwrap().unwrap();
+
+        let commands = control_task.await.unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0], "AUTHENTICATE \"password\"");
+        assert!(commands[1].contains(&format!(
+            "Flags=DiscardPK,V3Auth Port=9814,127.0.0.1:9814 ClientAuthV3={CLIENT_AUTH_KEY}"
+        )));
+        assert_eq!(
+            commands[2],
+            format!("DEL_ONION {}", onion_address.trim_end_matches(".onion"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expose_onion_service_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply = if line.starts_with("ADD_ONION") {
+                    "512 Bad arguments to ADD_ONION\r\n"
+                } else {
+                    "250 OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let tor_api = TorAPI::new(
+            "127.0.0.1:9814".parse().unwrap(),
+            9814,
+            control_port,
+            TorControlAuth::Password("password".to_owned()),
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+
+        let (service_ready, _) = triggered::trigger();
+        let (_, shutdown_signal) = triggered::trigger();
+        let e = tor_api
+            .expose_onion_service(service_ready, shutdown_signal)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("Bad arguments to ADD_ONION"));
+    }
 }
@@ -0,0 +1,193 @@
+//! Logic related to the audit log, the append-only record of the calls made to the private API.
+//!
+//! Every call is recorded alongside the identity of the client that made it, a digest of its parameters and its
+//! outcome. Entries older than the configured retention period are pruned as new ones are recorded.
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::{sha256, Hash};
+
+use crate::dbm::{DBReader, DBM};
+use crate::tls::ClientIdentity;
+
+/// Name of the [ClientIdentity] of the trusted callers that run within the tower itself.
+pub const INTERNAL_CLIENT: &str = "internal";
+
+/// Name recorded for calls that carry no client identity. These are rejected, but still recorded so they show up.
+pub const UNAUTHENTICATED_CLIENT: &str = "unauthenticated";
+
+/// Result recorded for successful calls.
+pub const RESULT_OK: &str = "ok";
+
+/// A call to the private API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Position of the entry in the log. Assigned by the database.
+    pub id: u64,
+    /// When the call was made (UNIX time, in seconds).
+    pub timestamp: u64,
+    /// Common name of the certificate of the client that made the call.
+    pub client: String,
+    /// Serial number of the certificate of the client that made the call.
+    pub client_serial: String,
+    /// The called method.
+    pub method: String,
+    /// SHA256 digest of the (protobuf encoded) call parameters.
+    pub params_digest: sha256::Hash,
+    /// The outcome of the call. Either [RESULT_OK] or the returned error.
+    pub result: String,
+}
+
+/// Current UNIX time, in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Component in charge of recording the calls to the private API.
+#[derive(Debug)]
+pub struct AuditLog {
+    /// A [DBM] (database manager) instance. Used to persist the log.
+    dbm: Arc<Mutex<DBM>>,
+    /// A pool of read-only [DBM]s. Used to query the log without blocking the writer.
+    db_reader: DBReader,
+    /// For how long (in seconds) entries are kept. Entries are kept forever if zero.
+    retention: u64,
+}
+
+impl AuditLog {
+    /// Creates a new [AuditLog] instance. Entries are kept for `retention_days`, or forever if zero.
+    pub fn new(dbm: Arc<Mutex<DBM>>, retention_days: u32) -> Self {
+        let db_reader = dbm.lock().unwrap().reader();
+        AuditLog {
+            dbm,
+            db_reader,
+            retention: retention_days as u64 * 24 * 3600,
+        }
+    }
+
+    /// Records a call to the private API, pruning the entries that have gone past the retention period.
+    pub(crate) fn record(
+        &self,
+        client: Option<&ClientIdentity>,
+        method: &str,
+        params: &[u8],
+        result: String,
+    ) {
+        let timestamp = now();
+        let entry = AuditEntry {
+            id: 0,
+            timestamp,
+            client: client.map_or(UNAUTHENTICATED_CLIENT.to_owned(), |c| c.name.clone()),
+            client_serial: client.map_or(String::new(), |c| c.serial.clone()),
+            method: method.to_owned(),
+            params_digest: sha256::Hash::hash(params),
+            result,
+        };
+
+        let dbm = self.dbm.lock().unwrap();
+        if self.retention > 0 {
+            dbm.remove_audit_entries_before(timestamp.saturating_sub(self.retention));
+        }
+        // Failing to store an entry is already logged by the DBM, and should not make the call fail
+        dbm.store_audit_entry(&entry).ok();
+    }
+
+    /// Gets the entries recorded at or after `since`, newest first. At most `limit` entries are returned, or all of
+    /// them if `limit` is zero.
+    pub(crate) fn get(&self, since: u64, limit: u32) -> Vec<AuditEntry> {
+        self.db_reader.get().load_audit_log(since, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tls::ClientRole;
+    use teos_common::dbm::DatabaseConnection;
+
+    fn client() -> ClientIdentity {
+        ClientIdentity {
+            name: "monitoring".to_owned(),
+            serial: "0102".to_owned(),
+            role: ClientRole::ReadOnly,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let audit_log = AuditLog::new(Arc::new(Mutex::new(DBM::in_memory().unwrap())), 0);
+
+        audit_log.record(Some(&client()), "get_users", &[], RESULT_OK.to_owned());
+        audit_log.record(None, "stop", &[1, 2, 3], "PermissionDenied".to_owned());
+
+        // Entries are returned newest first
+        let entries = audit_log.get(0, 0);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].client, UNAUTHENTICATED_CLIENT);
+        assert_eq!(entries[0].client_serial, "");
+        assert_eq!(entries[0].method, "stop");
+        assert_eq!(entries[0].params_digest, sha256::Hash::hash(&[1, 2, 3]));
+        assert_eq!(entries[0].result, "PermissionDenied");
+        assert_eq!(entries[1].client, "monitoring");
+        assert_eq!(entries[1].client_serial, "0102");
+        assert_eq!(entries[1].result, RESULT_OK);
+        assert!(entries[0].id > entries[1].id);
+
+        // The output can be limited both in number of entries and time
+        assert_eq!(audit_log.get(0, 1), entries[..1]);
+        assert!(audit_log.get(now() + 10, 0).is_empty());
+    }
+
+    #[test]
+    fn test_record_prunes_old_entries() {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let audit_log = AuditLog::new(dbm.clone(), 1);
+
+        // Store an entry from two days ago
+        let old_entry = AuditEntry {
+            id: 0,
+            timestamp: now() - 2 * 24 * 3600,
+            client: "monitoring".to_owned(),
+            client_serial: "0102".to_owned(),
+            method: "get_users".to_owned(),
+            params_digest: sha256::Hash::hash(&[]),
+            result: RESULT_OK.to_owned(),
+        };
+        dbm.lock().unwrap().store_audit_entry(&old_entry).unwrap();
+        assert_eq!(audit_log.get(0, 0).len(), 1);
+
+        audit_log.record(Some(&client()), "get_users", &[], RESULT_OK.to_owned());
+        let entries = audit_log.get(0, 0);
+        assert_eq!(entries.len(), 1);
+        assert_ne!(entries[0].timestamp, old_entry.timestamp);
+    }
+
+    #[test]
+    fn test_get_does_not_block_on_the_writer() {
+        let audit_log = AuditLog::new(Arc::new(Mutex::new(DBM::in_memory().unwrap())), 0);
+        audit_log.record(Some(&client()), "get_users", &[], RESULT_OK.to_owned());
+
+        // The log is read through the reader pool, so it can be queried while the writer is busy
+        let _writer = audit_log.dbm.lock().unwrap();
+        assert_eq!(audit_log.get(0, 0).len(), 1);
+    }
+
+    #[test]
+    fn test_audit_log_append_only() {
+        let dbm = DBM::in_memory().unwrap();
+        let audit_log = AuditLog::new(Arc::new(Mutex::new(dbm)), 0);
+        audit_log.record(Some(&client()), "stop", &[], RESULT_OK.to_owned());
+
+        let dbm = audit_log.dbm.lock().unwrap();
+        assert!(dbm
+            .get_connection()
+            .execute("UPDATE audit_log SET result='tampered'", [])
+            .is_err());
+        assert_eq!(dbm.load_audit_log(0, 0)[0].result, RESULT_OK);
+    }
+}
@@ -24,6 +24,37 @@ use lightning_block_sync::http::{HttpEndpoint, JsonResponse};
 use lightning_block_sync::rpc::RpcClient;
 use lightning_block_sync::{AsyncBlockSourceResult, BlockHeaderData, BlockSource};
 
+/// A [BlockSource] that can also look up the blocks of the best chain by height.
+pub trait BlockHashSource: BlockSource {
+    /// Gets the hash of the block at a given height of the best chain.
+    fn get_block_hash(&self, height: u32) -> AsyncBlockSourceResult<'_, BlockHash>;
+}
+
+impl BlockHashSource for RpcClient {
+    fn get_block_hash(&self, height: u32) -> AsyncBlockSourceResult<'_, BlockHash> {
+        // A wrapper type to parse the hash returned by getblockhash.
+        struct BlockHashResponse(BlockHash);
+        impl TryInto<BlockHashResponse> for JsonResponse {
+            type Error = std::io::Error;
+            fn try_into(self) -> std::io::Result<BlockHashResponse> {
+                self.0
+                    .as_str()
+                    .and_then(|hash| hash.parse().ok())
+                    .map(BlockHashResponse)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "expected a block hash"))
+            }
+        }
+
+        Box::pin(async move {
+            let height = serde_json::json!(height);
+            let hash = self
+                .call_method::<BlockHashResponse>("getblockhash", &[height])
+                .await?;
+            Ok(hash.0)
+        })
+    }
+}
+
 /// A simple implementation of a bitcoind client (`bitcoin-cli`) with the minimal functionality required by the tower.
 pub struct BitcoindClient<'a> {
     /// The underlying RPC client.
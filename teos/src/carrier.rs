@@ -24,6 +24,9 @@ pub struct Carrier {
     issued_receipts: HashMap<Txid, ConfirmationStatus>,
     /// The last known block height.
     block_height: u32,
+    /// Whether transactions are actually sent to the network. Standby towers follow the chain with broadcasting
+    /// disabled until they take over.
+    broadcasting: bool,
 }
 
 impl Carrier {
@@ -38,9 +41,15 @@ impl Carrier {
             bitcoind_reachable,
             issued_receipts: HashMap::new(),
             block_height: last_known_block_height,
+            broadcasting: true,
         }
     }
 
+    /// Enables or disables broadcasting. While disabled, transactions are reported as accepted without being sent.
+    pub fn set_broadcasting(&mut self, broadcasting: bool) {
+        self.broadcasting = broadcasting
+    }
+
     /// The last known block height.
     pub(crate) fn block_height(&self) -> u32 {
         self.block_height
@@ -85,6 +94,15 @@ impl Carrier {
             return *receipt;
         }
 
+        // No receipt is issued, so the transaction is actually sent if requested again once broadcasting is enabled
+        if !self.broadcasting {
+            log::info!(
+                "Broadcasting disabled. Not pushing transaction: {}",
+                tx.txid()
+            );
+            return ConfirmationStatus::InMempoolSince(self.block_height);
+        }
+
         log::info!("Pushing transaction to the network: {}", tx.txid());
         let receipt = match self.bitcoin_cli.send_raw_transaction(tx) {
             Ok(_) => {
@@ -264,6 +282,33 @@ mod tests {
         assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
     }
 
+    #[test]
+    fn test_send_transaction_broadcasting_disabled() {
+        // The node would reject the transaction, so getting it accepted means it was never sent
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_error(
+            rpc_errors::RPC_VERIFY_REJECTED as i64,
+        ));
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(bitcoind_mock.server);
+
+        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        carrier.set_broadcasting(false);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        let r = carrier.send_transaction(&tx);
+
+        assert_eq!(r, ConfirmationStatus::InMempoolSince(start_height));
+        assert!(carrier.issued_receipts.is_empty());
+
+        // Once enabled, the transaction is actually sent
+        carrier.set_broadcasting(true);
+        assert_eq!(
+            carrier.send_transaction(&tx),
+            ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_REJECTED)
+        );
+    }
+
     #[test]
     fn test_send_transaction_verify_rejected() {
         let bitcoind_mock = BitcoindMock::new(MockOptions::with_error(
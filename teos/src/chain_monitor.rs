@@ -113,14 +113,26 @@ where
 
     /// Monitors `bitcoind` polling the best chain tip every [polling_delta](Self::polling_delta).
     pub async fn monitor_chain(&mut self) {
+        self.monitor_chain_until(self.shutdown_signal.clone()).await
+    }
+
+    /// Monitors `bitcoind` (see [monitor_chain](Self::monitor_chain)) until either the tower shuts down or
+    /// `stop_signal` is received. Tips are never left half processed.
+    pub async fn monitor_chain_until(&mut self, stop_signal: Listener) {
         loop {
             self.poll_best_tip().await;
-            // Sleep for self.polling_delta seconds or shutdown if the signal is received.
-            if timeout(self.polling_delta, self.shutdown_signal.clone())
-                .await
-                .is_ok()
+            // Sleep for self.polling_delta seconds or stop if any of the signals is received.
+            let shutdown_signal = self.shutdown_signal.clone();
+            let stop_signal = stop_signal.clone();
+            if timeout(self.polling_delta, async {
+                tokio::select! {
+                    _ = shutdown_signal => log::debug!("Received shutting down signal. Shutting down"),
+                    _ = stop_signal => log::debug!("Received stop signal. Stopping"),
+                }
+            })
+            .await
+            .is_ok()
             {
-                log::debug!("Received shutting down signal. Shutting down");
                 break;
             }
         }
@@ -236,6 +248,44 @@ mod tests {
         assert!(listener.disconnected_blocks.borrow().is_empty());
     }
 
+    #[tokio::test]
+    async fn test_monitor_chain_until() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let new_tip = chain.tip();
+        let old_tip = chain.at_height(START_HEIGHT - 1);
+
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (_shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let (stop_trigger, stop_signal) = triggered::trigger();
+        let listener = DummyListener::new();
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        // A long polling delta, so the test would time out if the stop signal was not honored
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm,
+            u16::MAX,
+            shutdown_signal,
+            bitcoind_reachable,
+        )
+        .await;
+
+        // The tip is polled before stopping, even if the stop signal is already received
+        stop_trigger.trigger();
+        tokio::time::timeout(
+            time::Duration::from_secs(10),
+            cm.monitor_chain_until(stop_signal),
+        )
+        .await
+        .unwrap();
+        assert_eq!(cm.last_known_block_header, new_tip);
+    }
+
     #[tokio::test]
     async fn test_poll_best_tip_worse() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
@@ -11,6 +11,7 @@ use teos::config;
 use teos::protos as msgs;
 use teos::protos::private_tower_services_client::PrivateTowerServicesClient;
 use teos_common::appointment::Locator;
+use teos_common::net::format_host;
 use teos_common::UserId;
 
 /// Prints the cli error to standard error and exits the process
@@ -19,6 +20,16 @@ fn handle_error<T: std::fmt::Display>(error: T) {
     std::process::exit(1);
 }
 
+/// Parses a user id given as a command line argument, exiting the process if it is not valid
+fn parse_user_id(user_id: &str) -> Vec<u8> {
+    UserId::from_str(user_id)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+        .to_vec()
+}
+
 #[tokio::main]
 async fn main() {
     let opt = Opt::from_args();
@@ -32,48 +43,74 @@ async fn main() {
 
     let command = opt.command.clone();
 
-    // Load conf (from file or defaults) and patch it with the command line parameters received (if any)
+    // Load conf (from file or defaults) and patch it with the selected profile and the command line parameters received
+    // (if any)
     let mut conf = config::from_file::<Config>(&path.join("teos.toml"));
+    if let Some(ref profile) = opt.profile {
+        conf.apply_profile(profile).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    }
     conf.patch_with_options(opt);
 
-    let key = fs::read(&path.join("client-key.pem"))
-        .await
-        .expect("unable to read client key from disk");
-    let certificate = fs::read(path.join("client.pem"))
-        .await
-        .expect("unable to read client cert from disk");
-    let ca_cert = Certificate::from_pem(
-        fs::read(path.join("ca.pem"))
-            .await
-            .expect("unable to read ca cert from disk"),
-    );
+    let (cert_path, key_path) = conf.get_client_paths(&path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let ca_cert_path = conf.get_ca_cert_path(&path);
+    let key = fs::read(&key_path).await.unwrap_or_else(|e| {
+        eprintln!("Unable to read client key from {}: {e}", key_path.display());
+        std::process::exit(1);
+    });
+    let certificate = fs::read(&cert_path).await.unwrap_or_else(|e| {
+        eprintln!(
+            "Unable to read client cert from {}: {e}",
+            cert_path.display()
+        );
+        std::process::exit(1);
+    });
+    let ca_cert = Certificate::from_pem(fs::read(&ca_cert_path).await.unwrap_or_else(|e| {
+        eprintln!(
+            "Unable to read ca cert from {}: {e}",
+            ca_cert_path.display()
+        );
+        std::process::exit(1);
+    }));
 
     let tls = ClientTlsConfig::new()
-        .domain_name("localhost")
+        .domain_name(conf.tls_domain.clone())
         .ca_certificate(ca_cert)
         .identity(Identity::from_pem(certificate, key));
 
-    let channel = Channel::from_shared(format!("http://{}:{}", conf.rpc_bind, conf.rpc_port))
-        .expect("Cannot create channel from endpoint")
-        .tls_config(tls)
-        .unwrap_or_else(|e| {
-            eprintln!("Could not configure tls: {e:?}");
-            std::process::exit(1);
-        })
-        .connect()
-        .await
-        .unwrap_or_else(|_| {
-            eprintln!("Could not connect to tower. Is teosd running?");
-            std::process::exit(1);
-        });
+    let channel = Channel::from_shared(format!(
+        "http://{}:{}",
+        format_host(&conf.rpc_bind),
+        conf.rpc_port
+    ))
+    .expect("Cannot create channel from endpoint")
+    .tls_config(tls)
+    .unwrap_or_else(|e| {
+        eprintln!("Could not configure tls: {e:?}");
+        std::process::exit(1);
+    })
+    .connect()
+    .await
+    .unwrap_or_else(|_| {
+        eprintln!(
+            "Could not connect to tower at {}:{}. Is teosd running?",
+            conf.rpc_bind, conf.rpc_port
+        );
+        std::process::exit(1);
+    });
 
     let mut client = PrivateTowerServicesClient::new(channel);
 
     match command {
-        Command::GetAllAppointments => {
-            let appointments = client.get_all_appointments(Request::new(())).await.unwrap();
-            println!("{}", pretty_json(&appointments.into_inner()).unwrap());
-        }
+        Command::GetAllAppointments => match client.get_all_appointments(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
         Command::GetAppointments(appointments_data) => {
             match Locator::from_hex(&appointments_data.locator) {
                 Ok(locator) => {
@@ -92,13 +129,23 @@ async fn main() {
                 Err(e) => handle_error(e),
             };
         }
-        Command::GetTowerInfo => {
-            let info = client.get_tower_info(Request::new(())).await.unwrap();
-            println!("{}", pretty_json(&info.into_inner()).unwrap())
-        }
-        Command::GetUsers => {
-            let users = client.get_users(Request::new(())).await.unwrap();
-            println!("{}", pretty_json(&users.into_inner()).unwrap());
+        Command::GetTowerInfo => match client.get_tower_info(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::GetUsers => match client.get_users(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::GetMissedBreaches => match client.get_missed_breaches(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::GetAppointmentOutcomes => {
+            match client.get_all_appointment_outcomes(Request::new(())).await {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
         }
         Command::GetUser(user) => {
             match UserId::from_str(&user.user_id) {
@@ -118,9 +165,189 @@ async fn main() {
                 Err(e) => handle_error(e),
             };
         }
-        Command::Stop => {
-            println!("Shutting down tower");
-            client.stop(Request::new(())).await.unwrap();
+        Command::AddAllowedUser(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .add_allowed_user(Request::new(msgs::UserRequest { user_id }))
+                .await
+            {
+                Ok(_) => println!("User {} added to the allow-list", data.user_id),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::RemoveAllowedUser(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .remove_allowed_user(Request::new(msgs::UserRequest { user_id }))
+                .await
+            {
+                Ok(_) => println!("User {} removed from the allow-list", data.user_id),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::GetAllowedUsers => match client.get_allowed_users(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::CreateRegistrationTokens(data) => {
+            match client
+                .create_registration_tokens(Request::new(msgs::CreateRegistrationTokensRequest {
+                    count: data.count,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::GetRegistrationTokens => {
+            match client.get_registration_tokens(Request::new(())).await {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::ExtendSubscription(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .extend_subscription(Request::new(msgs::ExtendSubscriptionRequest {
+                    user_id,
+                    blocks: data.blocks,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::SetUserSlots(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .set_user_slots(Request::new(msgs::SetUserSlotsRequest {
+                    user_id,
+                    available_slots: data.available_slots,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::BanUser(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .ban_user(Request::new(msgs::UserRequest { user_id }))
+                .await
+            {
+                Ok(_) => println!("User {} banned", data.user_id),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::UnbanUser(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .unban_user(Request::new(msgs::UserRequest { user_id }))
+                .await
+            {
+                Ok(_) => println!("User {} unbanned", data.user_id),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::GetBannedUsers => match client.get_banned_users(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::DeleteUser(data) => {
+            let user_id = parse_user_id(&data.user_id);
+            match client
+                .delete_user(Request::new(msgs::UserRequest { user_id }))
+                .await
+            {
+                Ok(_) => println!("User {} deleted", data.user_id),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::Rescan(data) => {
+            match client
+                .rescan(Request::new(msgs::RescanRequest {
+                    start_height: data.start_height,
+                    end_height: data.end_height,
+                }))
+                .await
+            {
+                Ok(response) => {
+                    let mut progress = response.into_inner();
+                    loop {
+                        match progress.message().await {
+                            Ok(Some(block)) => println!("{}", pretty_json(&block).unwrap()),
+                            Ok(None) => break,
+                            Err(status) => handle_error(status.message()),
+                        }
+                    }
+                }
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::IssueClientCertificate(data) => {
+            match client
+                .issue_client_certificate(Request::new(msgs::IssueClientCertificateRequest {
+                    name: data.name,
+                    role: data.role,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::RevokeClientCertificate(data) => {
+            match client
+                .revoke_client_certificate(Request::new(msgs::ClientCertificateRequest {
+                    name: data.name.clone(),
+                }))
+                .await
+            {
+                Ok(_) => println!("Client certificate {} revoked", data.name),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::GetClientCertificates => {
+            match client.get_client_certificates(Request::new(())).await {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::Audit(data) => {
+            match client
+                .get_audit_log(Request::new(msgs::GetAuditLogRequest {
+                    limit: data.limit,
+                    since: data.since,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::Backup(data) => {
+            let path = std::env::current_dir()
+                .unwrap_or_else(|e| {
+                    eprintln!("Cannot resolve the backup path: {e}");
+                    std::process::exit(1);
+                })
+                .join(data.path);
+            match client
+                .backup_database(Request::new(msgs::BackupDatabaseRequest {
+                    path: path.to_string_lossy().into_owned(),
+                }))
+                .await
+            {
+                Ok(_) => println!("Tower database backed up to {}", path.display()),
+                Err(status) => handle_error(status.message()),
+            }
         }
+        Command::Stop => match client.stop(Request::new(())).await {
+            Ok(_) => println!("Shutting down tower"),
+            Err(status) => handle_error(status.message()),
+        },
     };
 }
@@ -1,8 +1,13 @@
 //! Logic related to the tower CLI configuration and command line parameter parsing.
 
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
+use crate::config;
+use crate::tls::CLIENTS_DIR;
+
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(rename_all = "lower_case")]
 pub enum Command {
@@ -16,6 +21,45 @@ pub enum Command {
     GetUsers,
     /// Gets information about a specific user
     GetUser(GetUserData),
+    /// Adds a user to the allow-list (only users in the list can register if the tower runs in private mode)
+    AddAllowedUser(UserData),
+    /// Removes a user from the allow-list
+    RemoveAllowedUser(UserData),
+    /// Gets an array with the user ids in the allow-list
+    GetAllowedUsers,
+    /// Issues one-time tokens new users can redeem to register with the tower (if registration_tokens is enabled)
+    CreateRegistrationTokens(CreateRegistrationTokensData),
+    /// Gets an array with the registration tokens that have not been redeemed yet
+    GetRegistrationTokens,
+    /// Extends the subscription of a user by a given number of blocks
+    ExtendSubscription(ExtendSubscriptionData),
+    /// Sets the number of available slots of a user
+    SetUserSlots(SetUserSlotsData),
+    /// Bans a user from the tower, deleting all their data
+    BanUser(UserData),
+    /// Lifts the ban of a user
+    UnbanUser(UserData),
+    /// Gets an array with the user ids of all the users banned from the tower
+    GetBannedUsers,
+    /// Deletes a user alongside all their data (appointments, trackers and backups)
+    DeleteUser(UserData),
+    /// Gets the appointments that may have been breached while the tower was offline
+    GetMissedBreaches,
+    /// Gets the reasons why the tower could not respond to triggered appointments
+    GetAppointmentOutcomes,
+    /// Checks a range of historical blocks for breaches of the appointments being watched
+    Rescan(RescanData),
+    /// Issues a new client certificate with a given role (readonly, operator or admin)
+    IssueClientCertificate(IssueClientCertificateData),
+    /// Revokes a client certificate, so it cannot be used to reach the tower anymore
+    RevokeClientCertificate(ClientCertificateData),
+    /// Gets the client certificates issued by the tower alongside their role and whether they have been revoked
+    GetClientCertificates,
+    /// Gets the audit log of the calls made to the tower, newest first
+    Audit(AuditData),
+    /// Writes a consistent snapshot of the tower database to a given path of the tower host, which can later be
+    /// restored using teosd restore
+    Backup(BackupData),
     /// Requests a graceful shutdown of the tower
     Stop,
 }
@@ -27,6 +71,82 @@ pub struct GetUserData {
     pub user_id: String,
 }
 
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct UserData {
+    /// The user identifier (33-byte compressed public key).
+    pub user_id: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct CreateRegistrationTokensData {
+    /// The number of tokens to issue.
+    #[structopt(default_value = "1")]
+    pub count: u32,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct ExtendSubscriptionData {
+    /// The user identifier (33-byte compressed public key).
+    pub user_id: String,
+    /// The number of blocks to extend the subscription by.
+    pub blocks: u32,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct SetUserSlotsData {
+    /// The user identifier (33-byte compressed public key).
+    pub user_id: String,
+    /// The new number of available slots.
+    pub available_slots: u32,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct RescanData {
+    /// The height of the first block to scan.
+    pub start_height: u32,
+    /// The height of the last block to scan.
+    pub end_height: u32,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct IssueClientCertificateData {
+    /// The name of the client (letters, numbers, dashes and underscores).
+    pub name: String,
+    /// The role granted to the client (readonly, operator or admin).
+    pub role: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct ClientCertificateData {
+    /// The name of the client.
+    pub name: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct AuditData {
+    /// The maximum number of entries to get (0 for all of them).
+    #[structopt(long, default_value = "50")]
+    pub limit: u32,
+    /// Only get the entries recorded since this time (UNIX time, in seconds).
+    #[structopt(long, default_value = "0")]
+    pub since: u64,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct BackupData {
+    /// The path of the snapshot. Relative paths are resolved against the current directory.
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+}
+
 #[derive(Debug, StructOpt, Clone)]
 pub struct GetAppointmentsData {
     /// The locator of the appointments (16-byte hexadecimal string).
@@ -50,6 +170,30 @@ pub struct Opt {
     #[structopt(long)]
     pub rpc_port: Option<u16>,
 
+    /// Connection profile (defined in the config file under [profiles.<name>]) to use
+    #[structopt(long)]
+    pub profile: Option<String>,
+
+    /// Name the teos RPC server certificate is verified against [default: localhost]
+    #[structopt(long)]
+    pub tls_domain: Option<String>,
+
+    /// Path to the CA certificate of the tower (relative paths start at the data dir) [default: ca.pem]
+    #[structopt(long)]
+    pub ca_cert_path: Option<String>,
+
+    /// Name of the issued client certificate to connect with [default: the default client certificate]
+    #[structopt(long)]
+    pub client_name: Option<String>,
+
+    /// Path to the client certificate (relative paths start at the data dir). Overrides client_name
+    #[structopt(long)]
+    pub client_cert_path: Option<String>,
+
+    /// Path to the client key (relative paths start at the data dir). Overrides client_name
+    #[structopt(long)]
+    pub client_key_path: Option<String>,
+
     /// Specify data directory
     #[structopt(long, default_value = "~/.teos")]
     pub data_dir: String,
@@ -59,20 +203,76 @@ pub struct Opt {
     pub command: Command,
 }
 
+/// Connection settings for a given tower, so several towers can be managed from the same machine.
+///
+/// Profiles are defined in the configuration file under `[profiles.<name>]` and picked using `--profile`. Settings
+/// missing from the profile keep their top level value.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Profile {
+    pub rpc_bind: Option<String>,
+    pub rpc_port: Option<u16>,
+    pub tls_domain: Option<String>,
+    pub ca_cert_path: Option<String>,
+    pub client_name: Option<String>,
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+}
+
 /// Holds all configuration options.
 ///
 /// The overwrite policy goes, from less to more:
 /// - Defaults
 /// - Configuration file
+/// - Selected profile
 /// - Command line options
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(default)]
 pub struct Config {
     pub rpc_bind: String,
     pub rpc_port: u16,
+    pub tls_domain: String,
+    pub ca_cert_path: String,
+    pub client_name: Option<String>,
+    pub client_cert_path: String,
+    pub client_key_path: String,
+    pub profiles: BTreeMap<String, Profile>,
 }
 
 impl Config {
+    /// Patches the configuration options with the ones from a given profile.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        let profile = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown profile: {name}"))?;
+
+        if let Some(rpc_bind) = profile.rpc_bind {
+            self.rpc_bind = rpc_bind;
+        }
+        if let Some(rpc_port) = profile.rpc_port {
+            self.rpc_port = rpc_port;
+        }
+        if let Some(tls_domain) = profile.tls_domain {
+            self.tls_domain = tls_domain;
+        }
+        if let Some(ca_cert_path) = profile.ca_cert_path {
+            self.ca_cert_path = ca_cert_path;
+        }
+        if profile.client_name.is_some() {
+            self.client_name = profile.client_name;
+        }
+        if let Some(client_cert_path) = profile.client_cert_path {
+            self.client_cert_path = client_cert_path;
+        }
+        if let Some(client_key_path) = profile.client_key_path {
+            self.client_key_path = client_key_path;
+        }
+
+        Ok(())
+    }
+
     /// Patches the configuration options with the command line options.
     pub fn patch_with_options(&mut self, options: Opt) {
         if options.rpc_bind.is_some() {
@@ -81,20 +281,149 @@ impl Config {
         if options.rpc_port.is_some() {
             self.rpc_port = options.rpc_port.unwrap();
         }
+        if let Some(tls_domain) = options.tls_domain {
+            self.tls_domain = tls_domain;
+        }
+        if let Some(ca_cert_path) = options.ca_cert_path {
+            self.ca_cert_path = ca_cert_path;
+        }
+        if options.client_name.is_some() {
+            self.client_name = options.client_name;
+        }
+        if let Some(client_cert_path) = options.client_cert_path {
+            self.client_cert_path = client_cert_path;
+        }
+        if let Some(client_key_path) = options.client_key_path {
+            self.client_key_path = client_key_path;
+        }
+    }
+
+    /// Gets the path to the CA certificate of the tower.
+    pub fn get_ca_cert_path(&self, data_dir: &Path) -> PathBuf {
+        resolve_path(data_dir, &self.ca_cert_path)
+    }
+
+    /// Gets the paths to the client certificate and key to connect with.
+    ///
+    /// Explicit paths take precedence over the client name, which takes precedence over the default client.
+    pub fn get_client_paths(&self, data_dir: &Path) -> Result<(PathBuf, PathBuf), String> {
+        match (
+            self.client_cert_path.is_empty(),
+            self.client_key_path.is_empty(),
+        ) {
+            (false, false) => Ok((
+                resolve_path(data_dir, &self.client_cert_path),
+                resolve_path(data_dir, &self.client_key_path),
+            )),
+            (true, true) => Ok(match self.client_name {
+                Some(ref name) => (
+                    data_dir.join(CLIENTS_DIR).join(format!("{name}.pem")),
+                    data_dir.join(CLIENTS_DIR).join(format!("{name}-key.pem")),
+                ),
+                None => (data_dir.join("client.pem"), data_dir.join("client-key.pem")),
+            }),
+            _ => Err("client_cert_path and client_key_path must be set together".to_owned()),
+        }
     }
 }
 
+/// Resolves a path from the configuration. Relative paths start at the data directory.
+fn resolve_path(data_dir: &Path, path: &str) -> PathBuf {
+    data_dir.join(config::data_dir_absolute_path(path.to_owned()))
+}
+
 impl Default for Config {
-    /// Sets the tower [Config] defaults.
-    ///
-    /// Notice the defaults are not enough, and the tower will refuse to run on them.
-    /// For instance, the defaults do set the `bitcoind` `rpu_user` and `rpc_password`
-    /// to empty strings so the user is forced the set them (and most importantly so the
-    /// user does not use any values provided here).
+    /// Sets the CLI [Config] defaults.
     fn default() -> Self {
         Self {
             rpc_bind: "localhost".into(),
             rpc_port: 8814,
+            tls_domain: "localhost".into(),
+            ca_cert_path: "ca.pem".into(),
+            client_name: None,
+            client_cert_path: String::new(),
+            client_key_path: String::new(),
+            profiles: BTreeMap::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA_DIR: &str = "/home/teos/.teos";
+
+    fn config_with_profiles() -> Config {
+        toml::from_str(
+            r#"
+            rpc_port = 9000
+
+            [profiles.remote]
+            rpc_bind = "tower.example.com"
+            tls_domain = "tower.example.com"
+            ca_cert_path = "towers/remote/ca.pem"
+            client_name = "ops"
+
+            [profiles.paths]
+            client_cert_path = "/etc/teos/client.pem"
+            client_key_path = "/etc/teos/client-key.pem"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let mut config = config_with_profiles();
+        config.apply_profile("remote").unwrap();
+
+        // Settings missing from the profile keep their top level value
+        assert_eq!(config.rpc_bind, "tower.example.com");
+        assert_eq!(config.rpc_port, 9000);
+        assert_eq!(config.tls_domain, "tower.example.com");
+        assert_eq!(
+            config.get_ca_cert_path(Path::new(DATA_DIR)),
+            PathBuf::from(DATA_DIR).join("towers/remote/ca.pem")
+        );
+        assert_eq!(
+            config.get_client_paths(Path::new(DATA_DIR)).unwrap(),
+            (
+                PathBuf::from(DATA_DIR).join("clients/ops.pem"),
+                PathBuf::from(DATA_DIR).join("clients/ops-key.pem")
+            )
+        );
+
+        assert_eq!(
+            config.apply_profile("unknown"),
+            Err("Unknown profile: unknown".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_get_client_paths() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.get_client_paths(Path::new(DATA_DIR)).unwrap(),
+            (
+                PathBuf::from(DATA_DIR).join("client.pem"),
+                PathBuf::from(DATA_DIR).join("client-key.pem")
+            )
+        );
+
+        // Explicit paths take precedence over the client name
+        let mut config_paths = config_with_profiles();
+        config_paths.client_name = Some("ops".to_owned());
+        config_paths.apply_profile("paths").unwrap();
+        assert_eq!(
+            config_paths.get_client_paths(Path::new(DATA_DIR)).unwrap(),
+            (
+                PathBuf::from("/etc/teos/client.pem"),
+                PathBuf::from("/etc/teos/client-key.pem")
+            )
+        );
+
+        config.client_cert_path = "client.pem".to_owned();
+        assert!(config.get_client_paths(Path::new(DATA_DIR)).is_err());
+    }
+}
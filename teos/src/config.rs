@@ -1,9 +1,17 @@
 //! Logic related to the tower configuration and command line parameter parsing.
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
+use teos_common::constants::IRREVOCABLY_RESOLVED;
+use teos_common::pow::MAX_POW_DIFFICULTY;
+
+use crate::api::rate_limit::{RateLimits, RATE_LIMITED_ENDPOINTS};
+use crate::gatekeeper::{SubscriptionPlan, DEFAULT_PLAN};
+
 pub fn data_dir_absolute_path(data_dir: String) -> PathBuf {
     if let Some(a) = data_dir.strip_prefix('~') {
         if let Some(b) = data_dir.strip_prefix("~/") {
@@ -16,6 +24,18 @@ pub fn data_dir_absolute_path(data_dir: String) -> PathBuf {
     }
 }
 
+/// Resolves the socket address to bind a server to. `host` can be an IPv4 or IPv6 literal (with or without brackets)
+/// or a hostname.
+pub fn resolve_bind_addr(host: &str, port: u16) -> std::io::Result<SocketAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{host} cannot be resolved"),
+        )
+    })
+}
+
 pub fn from_file<T: Default + serde::de::DeserializeOwned>(path: &PathBuf) -> T {
     match std::fs::read(path) {
         Ok(file_content) => toml::from_slice::<T>(&file_content).map_or_else(
@@ -98,6 +118,22 @@ pub struct Opt {
     #[structopt(long, default_value = "~/.teos")]
     pub data_dir: String,
 
+    /// Number of block headers kept in the header cache (the deepest reorg that can be handled) [default: 100]
+    #[structopt(long)]
+    pub header_cache_depth: Option<u32>,
+
+    /// Persists the header cache, and the transaction ids of the latest blocks, in the database so they survive restarts
+    #[structopt(long)]
+    pub persist_header_cache: bool,
+
+    /// Number of blocks kept in the Watcher's locator cache [default: 6]
+    #[structopt(long)]
+    pub locator_cache_depth: Option<u32>,
+
+    /// Number of blocks kept in the Responder's transaction index [default: 100]
+    #[structopt(long)]
+    pub tx_index_depth: Option<u32>,
+
     /// Runs teos in debug mode
     #[structopt(long)]
     pub debug: bool,
@@ -114,11 +150,25 @@ pub struct Opt {
     #[structopt(long)]
     pub tor_support: bool,
 
+    /// If set, the HTTP API is only reachable through the Tor endpoint and no clearnet address is advertised.
+    /// Requires tor_support
+    #[structopt(long)]
+    pub tor_only: bool,
+
     /// Forces the tower to run even if the underlying chain has gone too far out of sync. This can only happen
     /// if the node is being run in pruned mode.
     #[structopt(long)]
     pub force_update: bool,
 
+    /// Uses compact block filters (BIP158) to check blocks that cannot be fetched when recovering from a forced update.
+    /// Requires bitcoind to be run with blockfilterindex=1
+    #[structopt(long)]
+    pub use_block_filters: bool,
+
+    /// Only allows users in the allow-list (managed via teos-cli) to register with the tower
+    #[structopt(long)]
+    pub private_mode: bool,
+
     /// Tor control port [default: 9051]
     #[structopt(long)]
     pub tor_control_port: Option<u16>,
@@ -126,6 +176,28 @@ pub struct Opt {
     /// Port for the onion hidden service to listen on [default: 9814]
     #[structopt(long)]
     pub onion_hidden_service_port: Option<u16>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Maintenance commands. The tower runs normally if none is given.
+#[derive(StructOpt, Debug, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub enum Command {
+    /// Restores the tower database from a snapshot (created with teos-cli backup) and runs the tower
+    Restore(FileData),
+    /// Exports the users, appointments and trackers of the tower to a JSON lines file and exits
+    Export(FileData),
+    /// Imports the users, appointments and trackers from an export (created with teosd export) and runs the tower
+    Import(FileData),
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct FileData {
+    /// The path of the file.
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
 }
 
 /// Holds all configuration options.
@@ -140,10 +212,16 @@ pub struct Config {
     // API
     pub api_bind: String,
     pub api_port: u16,
+    pub api_tls_cert_path: String,
+    pub api_tls_key_path: String,
+    pub public_grpc: bool,
+    pub public_grpc_port: u16,
 
     // RPC
     pub rpc_bind: String,
     pub rpc_port: u16,
+    pub rpc_tls_alt_names: Vec<String>,
+    pub audit_log_retention_days: u32,
 
     // Bitcoind
     pub btc_network: String,
@@ -158,6 +236,9 @@ pub struct Config {
     pub deps_debug: bool,
     pub overwrite_key: bool,
     pub force_update: bool,
+    pub use_block_filters: bool,
+    pub persist_header_cache: bool,
+    pub private_mode: bool,
 
     // General
     pub subscription_slots: u32,
@@ -165,6 +246,9 @@ pub struct Config {
     pub expiry_delta: u32,
     pub min_to_self_delay: u16,
     pub polling_delta: u16,
+    pub header_cache_depth: u32,
+    pub locator_cache_depth: u32,
+    pub tx_index_depth: u32,
 
     // Internal API
     pub internal_api_bind: String,
@@ -172,8 +256,40 @@ pub struct Config {
 
     // Tor
     pub tor_support: bool,
+    pub tor_only: bool,
     pub tor_control_port: u16,
+    pub tor_control_cookie_path: String,
+    pub tor_control_password: String,
+    pub tor_client_auth_keys: Vec<String>,
     pub onion_hidden_service_port: u16,
+
+    // Payments
+    pub invoice_backend: String,
+    pub cln_rpc_path: String,
+    pub lnd_rest_address: String,
+    pub lnd_macaroon_path: String,
+    pub lnd_cert_path: String,
+    pub invoice_expiry: u32,
+
+    // Registration gating
+    pub registration_pow_difficulty: u8,
+    pub registration_tokens: bool,
+
+    // Replication
+    pub replication: String,
+    pub replication_primary: String,
+    pub replication_tls_domain: String,
+    pub replication_ca_cert_path: String,
+    pub replication_cert_path: String,
+    pub replication_key_path: String,
+    pub replication_heartbeat_interval: u64,
+    pub replication_failover_timeout: u64,
+
+    // Rate limits (per public API endpoint)
+    pub rate_limits: RateLimits,
+
+    // Subscription plans (offered alongside the default one, defined by subscription_slots and subscription_duration)
+    pub plans: BTreeMap<String, SubscriptionPlan>,
 }
 
 impl Config {
@@ -227,6 +343,15 @@ impl Config {
         if options.btc_rpc_port.is_some() {
             self.btc_rpc_port = options.btc_rpc_port.unwrap();
         }
+        if options.header_cache_depth.is_some() {
+            self.header_cache_depth = options.header_cache_depth.unwrap();
+        }
+        if options.locator_cache_depth.is_some() {
+            self.locator_cache_depth = options.locator_cache_depth.unwrap();
+        }
+        if options.tx_index_depth.is_some() {
+            self.tx_index_depth = options.tx_index_depth.unwrap();
+        }
         if options.tor_control_port.is_some() {
             self.tor_control_port = options.tor_control_port.unwrap();
         }
@@ -235,10 +360,14 @@ impl Config {
         }
 
         self.tor_support |= options.tor_support;
+        self.tor_only |= options.tor_only;
         self.debug |= options.debug;
         self.deps_debug |= options.deps_debug;
         self.overwrite_key = options.overwrite_key;
         self.force_update = options.force_update;
+        self.use_block_filters |= options.use_block_filters;
+        self.persist_header_cache |= options.persist_header_cache;
+        self.private_mode |= options.private_mode;
     }
 
     /// Verifies that [Config] is properly built.
@@ -246,6 +375,12 @@ impl Config {
     /// This includes:
     /// - `bitcoind` credentials have been set
     /// - The Bitcoin network has been properly set (to either bitcoin, testnet, signet or regtest)
+    /// - The cache depths are not zero, and the transaction index can hold [IRREVOCABLY_RESOLVED] blocks on mainnet
+    /// - Subscription plans are not named after the [DEFAULT_PLAN], and give some slots for some time
+    /// - The invoice backend is either `cln` or `lnd` (with the data needed to reach it), if paid plans are offered
+    /// - Enabled rate limits allow for at least one request in a row
+    /// - The public API TLS certificate and key are either both set or both unset
+    /// - The replication role is either `primary` or `standby` (with the primary to follow), if set
     ///
     /// This will also assign the default `btc_rpc_port` depending on the network if it has not
     /// been overwritten at this point.
@@ -273,6 +408,180 @@ impl Config {
             _ => return Err(ConfigError(format!("btc_network not recognized. Expected {{mainnet, testnet, signet, regtest}}, received {}", self.btc_network)))
         };
 
+        if self.header_cache_depth == 0 {
+            return Err(ConfigError(
+                "header_cache_depth must be greater than zero".to_owned(),
+            ));
+        }
+        if self.locator_cache_depth == 0 {
+            return Err(ConfigError(
+                "locator_cache_depth must be greater than zero".to_owned(),
+            ));
+        }
+        if self.tx_index_depth == 0 {
+            return Err(ConfigError(
+                "tx_index_depth must be greater than zero".to_owned(),
+            ));
+        }
+        // The Responder needs to be able to track reorgs up to the point trackers are considered irrevocably resolved.
+        if self.btc_network == "main" && self.tx_index_depth < IRREVOCABLY_RESOLVED {
+            return Err(ConfigError(format!(
+                "tx_index_depth must be at least {IRREVOCABLY_RESOLVED} on mainnet"
+            )));
+        }
+
+        for (name, plan) in self.plans.iter() {
+            if name == DEFAULT_PLAN {
+                return Err(ConfigError(format!(
+                    "The {DEFAULT_PLAN} plan is set via subscription_slots and subscription_duration"
+                )));
+            }
+            if plan.slots == 0 || plan.duration == 0 {
+                return Err(ConfigError(format!(
+                    "Plan {name} must have non-zero slots and duration"
+                )));
+            }
+            if plan.price_msat > 0 && self.invoice_backend.is_empty() {
+                return Err(ConfigError(format!(
+                    "Plan {name} has a price but no invoice_backend is set"
+                )));
+            }
+        }
+
+        match self.invoice_backend.as_str() {
+            "" => (),
+            "cln" => {
+                if self.cln_rpc_path.is_empty() {
+                    return Err(ConfigError(
+                        "cln_rpc_path must be set when using the cln invoice_backend".to_owned(),
+                    ));
+                }
+            }
+            "lnd" => {
+                if self.lnd_rest_address.is_empty() || self.lnd_macaroon_path.is_empty() {
+                    return Err(ConfigError(
+                        "lnd_rest_address and lnd_macaroon_path must be set when using the lnd invoice_backend"
+                            .to_owned(),
+                    ));
+                }
+            }
+            _ => {
+                return Err(ConfigError(format!(
+                    "invoice_backend not recognized. Expected {{cln, lnd}}, received {}",
+                    self.invoice_backend
+                )))
+            }
+        }
+        if !self.invoice_backend.is_empty() && self.invoice_expiry == 0 {
+            return Err(ConfigError(
+                "invoice_expiry must be greater than zero".to_owned(),
+            ));
+        }
+
+        if self.registration_pow_difficulty > MAX_POW_DIFFICULTY {
+            return Err(ConfigError(format!(
+                "registration_pow_difficulty cannot be greater than {MAX_POW_DIFFICULTY}"
+            )));
+        }
+
+        if self.api_tls_cert_path.is_empty() != self.api_tls_key_path.is_empty() {
+            return Err(ConfigError(
+                "api_tls_cert_path and api_tls_key_path must be set together".to_owned(),
+            ));
+        }
+        if self.tor_only && !self.tor_support {
+            return Err(ConfigError("tor_only requires tor_support".to_owned()));
+        }
+        if !self.tor_control_cookie_path.is_empty() && !self.tor_control_password.is_empty() {
+            return Err(ConfigError(
+                "Multiple Tor control port auth provided. Pick a single one (either tor_control_cookie_path or tor_control_password)"
+                    .to_owned(),
+            ));
+        }
+        // Client authorization keys are base32 encoded x25519 public keys
+        for key in self.tor_client_auth_keys.iter() {
+            if key.len() != 52
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || ('2'..='7').contains(&c))
+            {
+                return Err(ConfigError(format!(
+                    "Invalid Tor client authorization key: {key}. Expected a base32 encoded x25519 public key"
+                )));
+            }
+        }
+
+        // rustls does not support IP addresses as server names, so remote clients connecting by IP must be given one
+        // of these names to verify the tower certificate against
+        for name in self.rpc_tls_alt_names.iter() {
+            if name.parse::<std::net::IpAddr>().is_ok()
+                || name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ['-', '.', '*'].contains(&c))
+            {
+                return Err(ConfigError(format!(
+                    "Invalid rpc_tls_alt_names entry: {name}. Expected a DNS name"
+                )));
+            }
+        }
+
+        if self.public_grpc && self.api_tls_cert_path.is_empty() {
+            return Err(ConfigError(
+                "public_grpc requires api_tls_cert_path and api_tls_key_path to be set".to_owned(),
+            ));
+        }
+
+        match self.replication.as_str() {
+            "" | "primary" => (),
+            "standby" => {
+                if self.replication_primary.is_empty()
+                    || self.replication_ca_cert_path.is_empty()
+                    || self.replication_cert_path.is_empty()
+                    || self.replication_key_path.is_empty()
+                {
+                    return Err(ConfigError(
+                        "Standby towers require replication_primary, replication_ca_cert_path, replication_cert_path and replication_key_path to be set".to_owned(),
+                    ));
+                }
+                // Standbys take over using the key of their primary
+                if self.overwrite_key {
+                    return Err(ConfigError(
+                        "overwrite_key cannot be used by standby towers".to_owned(),
+                    ));
+                }
+            }
+            other => {
+                return Err(ConfigError(format!(
+                    "replication not recognized. Expected {{primary, standby}} (or empty), received {other}"
+                )))
+            }
+        }
+        if self.replication_heartbeat_interval == 0
+            || self.replication_failover_timeout <= self.replication_heartbeat_interval
+        {
+            return Err(ConfigError(
+                "replication_failover_timeout must be greater than replication_heartbeat_interval (which cannot be zero)".to_owned(),
+            ));
+        }
+
+        for endpoint in RATE_LIMITED_ENDPOINTS {
+            let limit = self.rate_limits.get(endpoint).unwrap();
+            if (limit.ip_per_minute > 0 && limit.ip_burst == 0)
+                || (limit.user_per_minute > 0 && limit.user_burst == 0)
+            {
+                return Err(ConfigError(format!(
+                    "The {endpoint} rate limits must have a non-zero burst if they are enabled"
+                )));
+            }
+        }
+        // Register requests are not signed, so the user they claim to come from cannot be trusted
+        if self.rate_limits.register.user_per_minute > 0 {
+            return Err(ConfigError(
+                "The register rate limits cannot be set per user, only per address".to_owned(),
+            ));
+        }
+
         // Set the port to it's default (depending on the network) if it has not been
         // overwritten at this point.
         if self.btc_rpc_port == 0 {
@@ -320,11 +629,21 @@ impl Default for Config {
         Self {
             api_bind: "127.0.0.1".into(),
             api_port: 9814,
+            api_tls_cert_path: String::new(),
+            api_tls_key_path: String::new(),
+            public_grpc: false,
+            public_grpc_port: 9815,
             tor_support: false,
+            tor_only: false,
             tor_control_port: 9051,
+            tor_control_cookie_path: String::new(),
+            tor_control_password: String::new(),
+            tor_client_auth_keys: Vec::new(),
             onion_hidden_service_port: 9814,
             rpc_bind: "127.0.0.1".into(),
             rpc_port: 8814,
+            rpc_tls_alt_names: Vec::new(),
+            audit_log_retention_days: 90,
             btc_network: "mainnet".into(),
             btc_rpc_user: String::new(),
             btc_rpc_password: String::new(),
@@ -336,13 +655,37 @@ impl Default for Config {
             deps_debug: false,
             overwrite_key: false,
             force_update: false,
+            use_block_filters: false,
+            persist_header_cache: false,
+            private_mode: false,
             subscription_slots: 10000,
             subscription_duration: 4320,
             expiry_delta: 6,
             min_to_self_delay: 20,
             polling_delta: 60,
+            header_cache_depth: 100,
+            locator_cache_depth: 6,
+            tx_index_depth: IRREVOCABLY_RESOLVED,
             internal_api_bind: "127.0.0.1".into(),
             internal_api_port: 50051,
+            invoice_backend: String::new(),
+            cln_rpc_path: String::new(),
+            lnd_rest_address: "https://localhost:8080".into(),
+            lnd_macaroon_path: String::new(),
+            lnd_cert_path: String::new(),
+            invoice_expiry: 3600,
+            registration_pow_difficulty: 0,
+            registration_tokens: false,
+            replication: String::new(),
+            replication_primary: String::new(),
+            replication_tls_domain: "localhost".into(),
+            replication_ca_cert_path: String::new(),
+            replication_cert_path: String::new(),
+            replication_key_path: String::new(),
+            replication_heartbeat_interval: 5,
+            replication_failover_timeout: 30,
+            rate_limits: RateLimits::default(),
+            plans: BTreeMap::new(),
         }
     }
 }
@@ -351,12 +694,15 @@ impl Default for Config {
 mod tests {
     use super::*;
 
+    use crate::api::rate_limit::RateLimit;
+
     impl Default for Opt {
         fn default() -> Self {
             Self {
                 api_bind: None,
                 api_port: None,
                 tor_support: false,
+                tor_only: false,
                 tor_control_port: None,
                 onion_hidden_service_port: None,
                 rpc_bind: None,
@@ -368,11 +714,18 @@ mod tests {
                 btc_rpc_connect: None,
                 btc_rpc_port: None,
                 data_dir: String::from("~/.teos"),
+                header_cache_depth: None,
+                persist_header_cache: false,
+                locator_cache_depth: None,
+                tx_index_depth: None,
 
                 debug: false,
                 deps_debug: false,
                 overwrite_key: false,
                 force_update: false,
+                use_block_filters: false,
+                private_mode: false,
+                command: None,
             }
         }
     }
@@ -431,6 +784,318 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_config_verify_zero_header_cache_depth() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            header_cache_depth: 0,
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("header_cache_depth must be greater than zero"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_zero_cache_depths() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            locator_cache_depth: 0,
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("locator_cache_depth must be greater than zero"))
+        );
+
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tx_index_depth: 0,
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("tx_index_depth must be greater than zero"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_shallow_tx_index() {
+        // A shallow transaction index is fine for test networks
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            btc_network: "regtest".to_owned(),
+            tx_index_depth: 6,
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        // But not for mainnet
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            btc_network: "mainnet".to_owned(),
+            tx_index_depth: 6,
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("tx_index_depth must be at least"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_plans() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            plans: BTreeMap::from([("premium".to_owned(), SubscriptionPlan::new(100, 10, 0, 0))]),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        // The default plan cannot be overwritten
+        config.plans.insert(
+            DEFAULT_PLAN.to_owned(),
+            SubscriptionPlan::new(100, 10, 0, 0),
+        );
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("is set via subscription_slots"))
+        );
+
+        // Plans with no slots or duration make no sense
+        config.plans.remove(DEFAULT_PLAN);
+        config
+            .plans
+            .insert("empty".to_owned(), SubscriptionPlan::new(0, 10, 0, 0));
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("must have non-zero slots and duration"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_invoice_backend() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            plans: BTreeMap::from([(
+                "premium".to_owned(),
+                SubscriptionPlan::new(100, 10, 0, 1000),
+            )]),
+            ..Default::default()
+        };
+
+        // Paid plans need an invoice backend
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("no invoice_backend is set"))
+        );
+
+        config.invoice_backend = "eclair".to_owned();
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("invoice_backend not recognized"))
+        );
+
+        config.invoice_backend = "cln".to_owned();
+        assert!(matches!(config.verify(), Err(ConfigError(e)) if e.contains("cln_rpc_path")));
+        config.cln_rpc_path = "~/.lightning/bitcoin/lightning-rpc".to_owned();
+        config.verify().unwrap();
+
+        config.invoice_backend = "lnd".to_owned();
+        assert!(matches!(config.verify(), Err(ConfigError(e)) if e.contains("lnd_macaroon_path")));
+        config.lnd_macaroon_path = "~/.lnd/invoice.macaroon".to_owned();
+        config.verify().unwrap();
+
+        config.invoice_expiry = 0;
+        assert!(matches!(config.verify(), Err(ConfigError(e)) if e.contains("invoice_expiry")));
+    }
+
+    #[test]
+    fn test_config_verify_registration_pow_difficulty() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            registration_pow_difficulty: MAX_POW_DIFFICULTY,
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.registration_pow_difficulty += 1;
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("registration_pow_difficulty cannot be greater than")
+        ));
+    }
+
+    #[test]
+    fn test_config_verify_api_tls() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            api_tls_cert_path: "cert.pem".to_owned(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("must be set together")
+        ));
+
+        config.api_tls_key_path = "key.pem".to_owned();
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_public_grpc() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            public_grpc: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("public_grpc requires")
+        ));
+
+        config.api_tls_cert_path = "cert.pem".to_owned();
+        config.api_tls_key_path = "key.pem".to_owned();
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_rpc_tls_alt_names() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            rpc_tls_alt_names: vec!["tower.example.com".to_owned(), "*.example.org".to_owned()],
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        for name in ["10.0.0.1", "::1", "", "tower example"] {
+            config.rpc_tls_alt_names = vec![name.to_owned()];
+            assert!(matches!(
+                config.verify(),
+                Err(ConfigError(e)) if e.contains("Invalid rpc_tls_alt_names entry")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_config_verify_replication() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            replication: "primary".to_owned(),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.replication = "secondary".to_owned();
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("replication not recognized")
+        ));
+
+        // Standbys need to know how to reach their primary
+        config.replication = "standby".to_owned();
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("Standby towers require")
+        ));
+        config.replication_primary = "tower.example.com:8814".to_owned();
+        config.replication_ca_cert_path = "primary_ca.pem".to_owned();
+        config.replication_cert_path = "standby.pem".to_owned();
+        config.replication_key_path = "standby-key.pem".to_owned();
+        config.verify().unwrap();
+
+        config.overwrite_key = true;
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("overwrite_key")
+        ));
+        config.overwrite_key = false;
+
+        // The primary must have some time to send a heartbeat before the standby takes over
+        config.replication_failover_timeout = config.replication_heartbeat_interval;
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("replication_failover_timeout")
+        ));
+    }
+
+    #[test]
+    fn test_config_verify_rate_limits() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        // Disabled limits do not need a burst
+        config.rate_limits.get_appointment = RateLimit::default();
+        config.verify().unwrap();
+
+        config.rate_limits.get_appointment.user_per_minute = 10;
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("get_appointment rate limits must have a non-zero burst")
+        ));
+
+        // Register requests can only be limited by address
+        config.rate_limits.get_appointment = RateLimit::default();
+        config.rate_limits.register = RateLimit::new(1, 1, 1, 1);
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("register rate limits cannot be set per user")
+        ));
+    }
+
+    #[test]
+    fn test_config_rate_limits_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [rate_limits.register]
+            ip_burst = 1
+            ip_per_minute = 2
+            "#,
+        )
+        .unwrap();
+
+        // Missing fields are disabled, and missing endpoints keep their defaults
+        assert_eq!(config.rate_limits.register, RateLimit::new(1, 2, 0, 0));
+        assert_eq!(
+            config.rate_limits.add_appointment,
+            RateLimits::default().add_appointment
+        );
+    }
+
+    #[test]
+    fn test_config_plans_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            subscription_slots = 42
+
+            [plans.premium]
+            slots = 100
+            duration = 10
+            max_blob_size = 4096
+            price_msat = 1000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.subscription_slots, 42);
+        assert_eq!(
+            config.plans,
+            BTreeMap::from([(
+                "premium".to_owned(),
+                SubscriptionPlan::new(100, 10, 4096, 1000)
+            )])
+        );
+    }
+
     #[test]
     fn test_config_verify_tor_set() {
         let mut config = Config {
@@ -442,4 +1107,84 @@ mod tests {
 
         config.verify().unwrap()
     }
+
+    #[test]
+    fn test_resolve_bind_addr() {
+        assert_eq!(
+            resolve_bind_addr("127.0.0.1", 9814).unwrap(),
+            "127.0.0.1:9814".parse().unwrap()
+        );
+        for host in ["::1", "[::1]"] {
+            assert_eq!(
+                resolve_bind_addr(host, 9814).unwrap(),
+                "[::1]:9814".parse().unwrap()
+            );
+        }
+        assert!(resolve_bind_addr("localhost", 9814)
+            .unwrap()
+            .ip()
+            .is_loopback());
+        assert!(resolve_bind_addr("not an address", 9814).is_err());
+    }
+
+    #[test]
+    fn test_config_verify_tor_only() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tor_only: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("tor_only requires tor_support")
+        ));
+
+        config.tor_support = true;
+        config.verify().unwrap()
+    }
+
+    #[test]
+    fn test_config_verify_tor_control_auth() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tor_support: true,
+            tor_control_cookie_path: "/var/lib/tor/control_auth_cookie".to_owned(),
+            tor_control_password: "password".to_owned(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.verify(),
+            Err(ConfigError(e)) if e.contains("Multiple Tor control port auth provided")
+        ));
+
+        config.tor_control_password = String::new();
+        config.verify().unwrap()
+    }
+
+    #[test]
+    fn test_config_verify_tor_client_auth_keys() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tor_support: true,
+            tor_client_auth_keys: vec![
+                "N2NU7BSRL6YODZCYPN4CREB54TYLKGIE2KYOQWLFYC23ZJVCE5DQ".to_owned()
+            ],
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        for key in [
+            "N2NU7BSRL6YODZCYPN4CREB54TYLKGIE2KYOQWLFYC23ZJVCE5D",
+            "N2NU7BSRL6YODZCYPN4CREB54TYLKGIE2KYOQWLFYC23ZJVCE5D1",
+        ] {
+            config.tor_client_auth_keys = vec![key.to_owned()];
+            assert!(matches!(
+                config.verify(),
+                Err(ConfigError(e)) if e.contains("Invalid Tor client authorization key")
+            ));
+        }
+    }
 }
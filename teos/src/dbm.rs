@@ -1,33 +1,52 @@
 //! Logic related to the tower database manager (DBM), component in charge of persisting data on disk.
 //!
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
 
+use rusqlite::backup::{Backup as SqliteBackup, Progress, StepResult};
 use rusqlite::limits::Limit;
-use rusqlite::{params, params_from_iter, Connection, Error as SqliteError};
+use rusqlite::{
+    ffi, params, params_from_iter, Connection, DatabaseName, Error as SqliteError, OpenFlags,
+};
 
 use bitcoin::consensus;
-use bitcoin::hashes::Hash;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::SecretKey;
-use bitcoin::BlockHash;
-
-use teos_common::appointment::{Appointment, Locator};
+use bitcoin::util::uint::Uint256;
+use bitcoin::{BlockHash, Txid};
+use lightning::ln::PaymentHash;
+use lightning_block_sync::poll::{Validate, ValidatedBlockHeader};
+use lightning_block_sync::BlockHeaderData;
+
+use teos_common::appointment::{Appointment, FailureKind, Locator};
+use teos_common::backup::Backup;
 use teos_common::dbm::{DatabaseConnection, DatabaseManager, Error};
+use teos_common::receipts::ResponseReceipt;
 use teos_common::UserId;
 
+use crate::audit::AuditEntry;
 use crate::extended_appointment::{ExtendedAppointment, UUID};
 use crate::gatekeeper::UserInfo;
+use crate::locator_filter::LocatorFilter;
+use crate::payments::{Invoice, PendingPayment};
+use crate::recovery::{MissedBreach, MissedBreachKind};
 use crate::responder::{ConfirmationStatus, PenaltySummary, TransactionTracker};
+use crate::tx_index::BlockTxids;
+use crate::watcher::AppointmentOutcome;
 
-const TABLES: [&str; 6] = [
+const TABLES: [&str; 21] = [
     "CREATE TABLE IF NOT EXISTS users (
     user_id INT PRIMARY KEY,
     available_slots INT NOT NULL,
     subscription_start INT NOT NULL,
-    subscription_expiry INT NOT NULL
+    subscription_expiry INT NOT NULL,
+    plan TEXT NOT NULL DEFAULT 'default'
 )",
     "CREATE TABLE IF NOT EXISTS appointments (
     UUID INT PRIMARY KEY,
@@ -61,16 +80,228 @@ const TABLES: [&str; 6] = [
 )",
     "CREATE INDEX IF NOT EXISTS locators_index ON appointments (
         locator
+)",
+    "CREATE TABLE IF NOT EXISTS missed_breaches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    UUID INT NOT NULL,
+    locator INT NOT NULL,
+    user_id INT NOT NULL,
+    kind INT NOT NULL,
+    height INT NOT NULL,
+    dispute_txid INT,
+    UNIQUE(UUID, kind, height)
+)",
+    "CREATE TABLE IF NOT EXISTS headers (
+    block_hash INT PRIMARY KEY,
+    height INT NOT NULL,
+    chainwork INT NOT NULL,
+    header BLOB NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS block_txids (
+    block_hash INT PRIMARY KEY,
+    height INT NOT NULL,
+    txids BLOB NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS backups (
+    user_id INT PRIMARY KEY,
+    data BLOB NOT NULL,
+    version INT NOT NULL,
+    FOREIGN KEY(user_id)
+        REFERENCES users(user_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS response_receipts (
+    UUID INT PRIMARY KEY,
+    user_id INT NOT NULL,
+    locator INT NOT NULL,
+    dispute_txid INT NOT NULL,
+    penalty_txid INT NOT NULL,
+    broadcast_height INT NOT NULL,
+    confirmation_height INT,
+    signature BLOB NOT NULL,
+    FOREIGN KEY(user_id)
+        REFERENCES users(user_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS appointment_outcomes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    UUID INT NOT NULL,
+    user_id INT NOT NULL,
+    locator INT NOT NULL,
+    dispute_txid INT NOT NULL,
+    kind INT NOT NULL,
+    rpc_error_code INT,
+    height INT NOT NULL,
+    FOREIGN KEY(user_id)
+        REFERENCES users(user_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS allowed_users (
+    user_id INT PRIMARY KEY
+)",
+    "CREATE TABLE IF NOT EXISTS banned_users (
+    user_id INT PRIMARY KEY
+)",
+    "CREATE TABLE IF NOT EXISTS rebroadcasts (
+    UUID INT PRIMARY KEY,
+    attempts INT NOT NULL,
+    FOREIGN KEY(UUID)
+        REFERENCES trackers(UUID)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS pending_payments (
+    user_id INT PRIMARY KEY,
+    plan TEXT NOT NULL,
+    invoice TEXT NOT NULL,
+    payment_hash INT NOT NULL,
+    expires_at INT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS registration_tokens (
+    token TEXT PRIMARY KEY
+)",
+    "CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INT NOT NULL,
+    client TEXT NOT NULL,
+    client_serial TEXT NOT NULL,
+    method TEXT NOT NULL,
+    params_digest BLOB NOT NULL,
+    result TEXT NOT NULL
+)",
+    // The audit log is append-only. Entries can only be removed once they go past the retention period.
+    "CREATE TRIGGER IF NOT EXISTS audit_log_append_only BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END",
+    "CREATE TABLE IF NOT EXISTS replication_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    table_name TEXT NOT NULL,
+    row_key INT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS replication_state (
+    id INT PRIMARY KEY,
+    seq INT NOT NULL
 )",
 ];
 
+/// Tables whose changes are recorded in the replication log, alongside the column holding their key.
+const REPLICATED_TABLES: [(&str, &str); 9] = [
+    ("users", "user_id"),
+    ("allowed_users", "user_id"),
+    ("banned_users", "user_id"),
+    ("registration_tokens", "token"),
+    ("pending_payments", "user_id"),
+    ("backups", "user_id"),
+    ("appointments", "UUID"),
+    ("trackers", "UUID"),
+    ("response_receipts", "UUID"),
+];
+
+/// Number of entries kept in the replication log. Standbys falling further behind are fully resynced.
+const REPLICATION_LOG_SIZE: u64 = 100_000;
+
+/// Number of read-only connections kept by the [DBReader].
+const READ_POOL_SIZE: usize = 4;
+
 /// Component in charge of interacting with the underlying database.
 ///
 /// Currently works for `SQLite`. `PostgreSQL` should also be added in the future.
+///
+/// The database is run in WAL mode: there is a single writer (this [DBM], shared behind a mutex) and a pool of read-only
+/// connections ([DBReader]) that can be used by read-only paths without contending with the writer.
 #[derive(Debug)]
 pub struct DBM {
     /// The underlying database connection.
     connection: Connection,
+    /// An in-memory copy of the locators of the stored appointments. Used to screen locators before querying the database.
+    /// Only kept by the writer, given it is updated as appointments are written. Not set for the read-only [DBM]s.
+    locators: Option<LocatorFilter>,
+    /// The pool of read-only connections to the database. Not set for the read-only [DBM]s themselves.
+    reader: Option<DBReader>,
+}
+
+/// The pool of connections backing a [DBReader].
+#[derive(Debug)]
+struct ReadPool {
+    /// The idle read-only [DBM]s.
+    idle: Mutex<Vec<DBM>>,
+    /// Used to wait for a [DBM] to be released when all of them are in use.
+    released: Condvar,
+}
+
+/// A pool of read-only connections to the tower database.
+///
+/// Given the database is in WAL mode, readers neither block nor are blocked by the writer, so they can be used to serve
+/// queries (e.g. from the APIs) while blocks are being processed.
+#[derive(Debug, Clone)]
+pub struct DBReader {
+    pool: Arc<ReadPool>,
+}
+
+impl DBReader {
+    /// Creates a new [DBReader] with `size` connections to the database at `path`.
+    fn new<P: AsRef<Path>>(path: P, flags: OpenFlags, size: usize) -> Result<Self, SqliteError> {
+        let mut idle = Vec::with_capacity(size);
+        for _ in 0..size {
+            let connection = Connection::open_with_flags(&path, flags)?;
+            // Opening flags are not enough to prevent writes on shared-cache databases.
+            connection.execute("PRAGMA query_only=1;", [])?;
+            // This only has effect on shared-cache databases, where readers would otherwise be locked out by an ongoing write.
+            connection.execute("PRAGMA read_uncommitted=1;", [])?;
+            idle.push(DBM {
+                connection,
+                locators: None,
+                reader: None,
+            });
+        }
+
+        Ok(DBReader {
+            pool: Arc::new(ReadPool {
+                idle: Mutex::new(idle),
+                released: Condvar::new(),
+            }),
+        })
+    }
+
+    /// Gets a read-only [DBM] from the pool, waiting for one to be released if all of them are in use.
+    ///
+    /// Write queries will fail on the returned instance.
+    pub fn get(&self) -> PooledDBM {
+        let mut idle = self.pool.idle.lock().unwrap();
+        loop {
+            if let Some(dbm) = idle.pop() {
+                return PooledDBM {
+                    dbm: Some(dbm),
+                    pool: self.pool.clone(),
+                };
+            }
+            idle = self.pool.released.wait(idle).unwrap();
+        }
+    }
+}
+
+/// A read-only [DBM] borrowed from a [DBReader]. It is given back to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledDBM {
+    dbm: Option<DBM>,
+    pool: Arc<ReadPool>,
+}
+
+impl Deref for PooledDBM {
+    type Target = DBM;
+
+    fn deref(&self) -> &DBM {
+        self.dbm.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledDBM {
+    fn drop(&mut self) {
+        if let Some(dbm) = self.dbm.take() {
+            self.pool.idle.lock().unwrap().push(dbm);
+            self.pool.released.notify_one();
+        }
+    }
 }
 
 impl DatabaseConnection for DBM {
@@ -86,18 +317,150 @@ impl DatabaseConnection for DBM {
 impl DBM {
     /// Creates a new [DBM] instance.
     pub fn new(db_path: PathBuf) -> Result<Self, SqliteError> {
-        let connection = Connection::open(db_path)?;
+        let connection = Connection::open(&db_path)?;
+        // The journal mode is persisted in the database file, and reported back by the query.
+        connection.query_row("PRAGMA journal_mode=WAL;", [], |_| Ok(()))?;
+        let mut dbm = Self::from_connection(connection)?;
+        dbm.reader = Some(DBReader::new(
+            &db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            READ_POOL_SIZE,
+        )?);
+
+        Ok(dbm)
+    }
+
+    /// Builds the writer [DBM] on top of a given connection, creating the tables if needed.
+    fn from_connection(connection: Connection) -> Result<Self, SqliteError> {
         connection.execute("PRAGMA foreign_keys=1;", [])?;
-        let mut dbm = Self { connection };
-        dbm.create_tables(Vec::from_iter(TABLES))?;
+        let mut dbm = Self {
+            connection,
+            locators: Some(LocatorFilter::new()),
+            reader: None,
+        };
+        dbm.init()?;
 
         Ok(dbm)
     }
 
+    /// Brings the database schema up to date and loads the [LocatorFilter].
+    fn init(&mut self) -> Result<(), SqliteError> {
+        self.create_tables(Vec::from_iter(TABLES))?;
+        // Databases created before subscription plans were introduced have no plan column.
+        self.add_column("users", "plan", "TEXT NOT NULL DEFAULT 'default'")?;
+        self.load_locator_filter();
+
+        Ok(())
+    }
+
+    /// Loads a database snapshot (created by [DBM::backup]) into an in-memory [DBM], so it can be inspected without
+    /// modifying the snapshot file.
+    pub fn load_snapshot(path: &Path) -> Result<Self, SqliteError> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.restore(DatabaseName::Main, path, None::<fn(Progress)>)?;
+        Self::from_connection(connection)
+    }
+
+    /// Writes a consistent snapshot of the database to `path` using the SQLite backup API.
+    ///
+    /// Can be called on read-only [DBM]s, so the database can be backed up while the tower keeps running.
+    pub fn backup(&self, path: &Path) -> Result<(), SqliteError> {
+        let mut snapshot = Connection::open(path)?;
+        // Copying all the pages in a single step keeps the source read-locked throughout, so writes made by other
+        // connections meanwhile do not make it into the snapshot (nor force the backup to restart).
+        let backup = SqliteBackup::new(&self.connection, &mut snapshot)?;
+        match backup.step(-1)? {
+            StepResult::Done => Ok(()),
+            _ => Err(SqliteError::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_BUSY),
+                None,
+            )),
+        }
+    }
+
+    /// Replaces the content of the database with a snapshot created by [DBM::backup].
+    ///
+    /// This is meant to be done on startup, before any other component has loaded data from the database.
+    pub fn restore(&mut self, path: &Path) -> Result<(), SqliteError> {
+        self.connection
+            .restore(DatabaseName::Main, path, None::<fn(Progress)>)?;
+        // The snapshot may have been taken by an older version of the tower
+        self.init()
+    }
+
+    /// Gets a handle to the pool of read-only connections to the database.
+    pub fn reader(&self) -> DBReader {
+        self.reader
+            .clone()
+            .expect("Read-only DBMs do not have a reader")
+    }
+
+    /// Populates the [LocatorFilter] with the locators of all the stored appointments.
+    fn load_locator_filter(&mut self) {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT locator FROM appointments")
+            .unwrap();
+        let locators = stmt
+            .query_map([], |row| {
+                let raw_locator: Vec<u8> = row.get(0).unwrap();
+                let locator = Locator::from_slice(&raw_locator).unwrap();
+                Ok(locator)
+            })
+            .unwrap()
+            .map(|res| res.unwrap())
+            .collect();
+        drop(stmt);
+
+        let locators: LocatorFilter = locators;
+        log::debug!("Locator filter loaded ({} locators)", locators.len());
+        self.locators = Some(locators);
+    }
+
+    /// Gets the [LocatorFilter].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on a read-only [DBM] (see [DBReader]), which do not keep one.
+    fn locator_filter(&self) -> &LocatorFilter {
+        self.locators
+            .as_ref()
+            .expect("The locator filter is only kept by the writer DBM")
+    }
+
+    /// Gets a mutable reference to the [LocatorFilter].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on a read-only [DBM] (see [DBReader]), which do not keep one.
+    fn locator_filter_mut(&mut self) -> &mut LocatorFilter {
+        self.locators
+            .as_mut()
+            .expect("The locator filter is only kept by the writer DBM")
+    }
+
+    /// Loads the locators of the appointments matching `query`, which is completed with as many placeholders as `chunk` items.
+    ///
+    /// Used to know what locators are about to be deleted so the [LocatorFilter] can be kept in sync.
+    fn load_locators_in(connection: &Connection, query: &str, chunk: &[Vec<u8>]) -> Vec<Locator> {
+        let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+        let mut stmt = connection
+            .prepare(&format!("{query}{placeholders}"))
+            .unwrap();
+        stmt.query_map(params_from_iter(chunk), |row| {
+            let raw_locator: Vec<u8> = row.get(0).unwrap();
+            let locator = Locator::from_slice(&raw_locator).unwrap();
+            Ok(locator)
+        })
+        .unwrap()
+        .map(|res| res.unwrap())
+        .collect()
+    }
+
     /// Stores a user ([UserInfo]) into the database.
     pub(crate) fn store_user(&self, user_id: UserId, user_info: &UserInfo) -> Result<(), Error> {
         let query =
-        "INSERT INTO users (user_id, available_slots, subscription_start, subscription_expiry) VALUES (?1, ?2, ?3, ?4)";
+        "INSERT INTO users (user_id, available_slots, subscription_start, subscription_expiry, plan) VALUES (?1, ?2, ?3, ?4, ?5)";
 
         match self.store_data(
             query,
@@ -106,6 +469,7 @@ impl DBM {
                 user_info.available_slots,
                 user_info.subscription_start,
                 user_info.subscription_expiry,
+                user_info.plan,
             ],
         ) {
             Ok(x) => {
@@ -122,13 +486,14 @@ impl DBM {
     /// Updates an existing user ([UserInfo]) in the database.
     pub(crate) fn update_user(&self, user_id: UserId, user_info: &UserInfo) {
         let query =
-        "UPDATE users SET available_slots=(?1), subscription_start=(?2), subscription_expiry=(?3) WHERE user_id=(?4)";
+        "UPDATE users SET available_slots=(?1), subscription_start=(?2), subscription_expiry=(?3), plan=(?4) WHERE user_id=(?5)";
         match self.update_data(
             query,
             params![
                 user_info.available_slots,
                 user_info.subscription_start,
                 user_info.subscription_expiry,
+                user_info.plan,
                 user_id.to_vec(),
             ],
         ) {
@@ -158,12 +523,32 @@ impl DBM {
         .collect()
     }
 
+    /// Loads a user from the database.
+    pub(crate) fn load_user(&self, user_id: UserId) -> Option<UserInfo> {
+        let key = user_id.to_vec();
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT available_slots, subscription_start, subscription_expiry, plan
+                    FROM users WHERE user_id=(?)",
+            )
+            .unwrap();
+        stmt.query_row([&key], |row| {
+            let slots = row.get(0).unwrap();
+            let start = row.get(1).unwrap();
+            let expiry = row.get(2).unwrap();
+            let plan = row.get(3).unwrap();
+            Ok(UserInfo::new(slots, start, expiry).with_plan(plan))
+        })
+        .ok()
+    }
+
     /// Loads all users from the database.
     pub(crate) fn load_all_users(&self) -> HashMap<UserId, UserInfo> {
         let mut users = HashMap::new();
         let mut stmt = self
             .connection
-            .prepare("SELECT user_id, available_slots, subscription_start, subscription_expiry FROM users")
+            .prepare("SELECT user_id, available_slots, subscription_start, subscription_expiry, plan FROM users")
             .unwrap();
         let mut rows = stmt.query([]).unwrap();
 
@@ -173,8 +558,9 @@ impl DBM {
             let slots = row.get(1).unwrap();
             let start = row.get(2).unwrap();
             let expiry = row.get(3).unwrap();
+            let plan = row.get(4).unwrap();
 
-            users.insert(user_id, UserInfo::new(slots, start, expiry));
+            users.insert(user_id, UserInfo::new(slots, start, expiry).with_plan(plan));
         }
 
         users
@@ -188,8 +574,16 @@ impl DBM {
             .iter()
             .map(|uuid| uuid.to_vec())
             .collect::<Vec<Vec<u8>>>();
+        let mut removed_locators = Vec::new();
 
         for chunk in iter.chunks(limit) {
+            // Appointments are deleted on cascade, so their locators need to be dropped from the filter too.
+            removed_locators.extend(Self::load_locators_in(
+                &tx,
+                "SELECT locator FROM appointments WHERE user_id IN ",
+                chunk,
+            ));
+
             let query = "DELETE FROM users WHERE user_id IN ".to_owned();
             let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
 
@@ -200,138 +594,583 @@ impl DBM {
         }
 
         match tx.commit() {
-            Ok(_) => log::debug!("Users successfully deleted"),
+            Ok(_) => {
+                log::debug!("Users successfully deleted");
+                removed_locators
+                    .iter()
+                    .for_each(|locator| self.locator_filter_mut().remove(locator));
+            }
             Err(e) => log::error!("Couldn't delete users. Error: {e:?}"),
         }
 
         (users.len() as f64 / limit as f64).ceil() as usize
     }
 
-    /// Get the number of stored appointments.
-    pub(crate) fn get_appointments_count(&self) -> usize {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT COUNT(*) FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID WHERE t.UUID IS NULL")
-            .unwrap();
-        stmt.query_row([], |row| row.get(0)).unwrap()
+    /// Adds a user to the allow-list used by towers running in private mode. Adding an allowed user is a no-op.
+    pub(crate) fn store_allowed_user(&self, user_id: UserId) -> Result<(), Error> {
+        let query = "INSERT OR IGNORE INTO allowed_users (user_id) VALUES (?)";
+        match self.store_data(query, params![user_id.to_vec()]) {
+            Ok(x) => {
+                log::debug!("Allowed user successfully stored: {user_id}");
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store allowed user: {user_id}. Error: {e:?}");
+                Err(e)
+            }
+        }
     }
 
-    /// Get the number of stored trackers.
-    pub(crate) fn get_trackers_count(&self) -> usize {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT COUNT(*) FROM trackers")
-            .unwrap();
-        stmt.query_row([], |row| row.get(0)).unwrap()
+    /// Removes a user from the allow-list.
+    pub(crate) fn remove_allowed_user(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM allowed_users WHERE user_id=(?)",
+            params![user_id.to_vec()],
+        )
     }
 
-    /// Stores an [Appointment] into the database.
-    pub(crate) fn store_appointment(
-        &self,
-        uuid: UUID,
-        appointment: &ExtendedAppointment,
-    ) -> Result<(), Error> {
-        let query = "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
-        match self.store_data(
-            query,
-            params![
-                uuid.to_vec(),
-                appointment.locator().to_vec(),
-                appointment.encrypted_blob(),
-                appointment.to_self_delay(),
-                appointment.user_signature,
-                appointment.start_block,
-                appointment.user_id.to_vec(),
-            ],
-        ) {
+    /// Loads the allow-list from the database.
+    pub(crate) fn load_allowed_users(&self) -> HashSet<UserId> {
+        self.load_user_ids("SELECT user_id FROM allowed_users")
+    }
+
+    /// Adds a user to the list of users banned from the tower. Banning a banned user is a no-op.
+    pub(crate) fn store_banned_user(&self, user_id: UserId) -> Result<(), Error> {
+        let query = "INSERT OR IGNORE INTO banned_users (user_id) VALUES (?)";
+        match self.store_data(query, params![user_id.to_vec()]) {
             Ok(x) => {
-                log::debug!("Appointment successfully stored: {uuid}");
+                log::debug!("Banned user successfully stored: {user_id}");
                 Ok(x)
             }
             Err(e) => {
-                log::error!("Couldn't store appointment: {uuid}. Error: {e:?}");
+                log::error!("Couldn't store banned user: {user_id}. Error: {e:?}");
                 Err(e)
             }
         }
     }
 
-    /// Updates an existing [Appointment] in the database.
-    pub(crate) fn update_appointment(
+    /// Removes a user from the list of banned users.
+    pub(crate) fn remove_banned_user(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM banned_users WHERE user_id=(?)",
+            params![user_id.to_vec()],
+        )
+    }
+
+    /// Loads the banned users from the database.
+    pub(crate) fn load_banned_users(&self) -> HashSet<UserId> {
+        self.load_user_ids("SELECT user_id FROM banned_users")
+    }
+
+    /// Stores the [PendingPayment] of a given user. The previous pending payment of the user (if any) is overwritten.
+    pub(crate) fn store_pending_payment(
         &self,
-        uuid: UUID,
-        appointment: &ExtendedAppointment,
+        user_id: UserId,
+        pending: &PendingPayment,
     ) -> Result<(), Error> {
-        // DISCUSS: Check what fields we'd like to make updatable. e_blob and signature are the obvious, to_self_delay and start_block may not be necessary (or even risky)
-        let query =
-            "UPDATE appointments SET encrypted_blob=(?1), to_self_delay=(?2), user_signature=(?3), start_block=(?4) WHERE UUID=(?5)";
-        match self.update_data(
+        let query = "INSERT OR REPLACE INTO pending_payments (user_id, plan, invoice, payment_hash, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)";
+        match self.store_data(
             query,
             params![
-                appointment.encrypted_blob(),
-                appointment.to_self_delay(),
-                appointment.user_signature,
-                appointment.start_block,
-                uuid.to_vec(),
+                user_id.to_vec(),
+                pending.plan,
+                pending.invoice.bolt11,
+                pending.invoice.payment_hash.0.to_vec(),
+                pending.expires_at,
             ],
         ) {
-            Ok(_) => {
-                log::debug!("Appointment successfully updated: {uuid}");
-                Ok(())
+            Ok(x) => {
+                log::debug!("Pending payment successfully stored: {user_id}");
+                Ok(x)
             }
             Err(e) => {
-                log::error!("Appointment not found, data cannot be updated: {uuid}. Error: {e:?}");
+                log::error!("Couldn't store pending payment: {user_id}. Error: {e:?}");
                 Err(e)
             }
         }
     }
 
-    /// Loads an [Appointment] from the database.
-    pub(crate) fn load_appointment(&self, uuid: UUID) -> Option<ExtendedAppointment> {
-        let key = uuid.to_vec();
+    /// Loads the [PendingPayment] of a given user from the database.
+    pub(crate) fn load_pending_payment(&self, user_id: UserId) -> Option<PendingPayment> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT plan, invoice, payment_hash, expires_at FROM pending_payments WHERE user_id=(?)")
+            .unwrap();
+
+        stmt.query_row([user_id.to_vec()], |row| {
+            let raw_payment_hash: Vec<u8> = row.get(2)?;
+            Ok(PendingPayment::new(
+                row.get(0)?,
+                Invoice {
+                    bolt11: row.get(1)?,
+                    payment_hash: PaymentHash(raw_payment_hash.try_into().unwrap()),
+                },
+                row.get(3)?,
+            ))
+        })
+        .ok()
+    }
+
+    /// Loads the [PendingPayment]s of all users from the database.
+    pub(crate) fn load_pending_payments(&self) -> HashMap<UserId, PendingPayment> {
         let mut stmt = self
             .connection
             .prepare(
-                "SELECT locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id
-                    FROM appointments WHERE UUID=(?)"
+                "SELECT user_id, plan, invoice, payment_hash, expires_at FROM pending_payments",
             )
             .unwrap();
 
-        stmt.query_row([key], |row| {
-            let raw_locator: Vec<u8> = row.get(0).unwrap();
-            let encrypted_blob = row.get(1).unwrap();
-            let to_self_delay = row.get(2).unwrap();
-            let user_signature = row.get(3).unwrap();
-            let start_block = row.get(4).unwrap();
-            let raw_userid: Vec<u8> = row.get(5).unwrap();
-
-            let locator = Locator::from_slice(&raw_locator).unwrap();
-            let user_id = UserId::from_slice(&raw_userid).unwrap();
-            let appointment = Appointment::new(locator, encrypted_blob, to_self_delay);
-            Ok(ExtendedAppointment::new(
-                appointment,
-                user_id,
-                user_signature,
-                start_block,
+        stmt.query_map([], |row| {
+            let raw_userid: Vec<u8> = row.get(0)?;
+            let raw_payment_hash: Vec<u8> = row.get(3)?;
+            Ok((
+                UserId::from_slice(&raw_userid).unwrap(),
+                PendingPayment::new(
+                    row.get(1)?,
+                    Invoice {
+                        bolt11: row.get(2)?,
+                        payment_hash: PaymentHash(raw_payment_hash.try_into().unwrap()),
+                    },
+                    row.get(4)?,
+                ),
             ))
         })
-        .ok()
+        .unwrap()
+        .map(|pending| pending.unwrap())
+        .collect()
     }
 
-    /// Check if an appointment with `uuid` exists.
-    pub(crate) fn appointment_exists(&self, uuid: UUID) -> bool {
-        self.connection
-            .prepare("SELECT UUID FROM appointments WHERE UUID=(?)")
-            .unwrap()
-            .exists([uuid.to_vec()])
-            .unwrap()
+    /// Removes the [PendingPayment] of a given user.
+    pub(crate) fn remove_pending_payment(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM pending_payments WHERE user_id=(?)",
+            params![user_id.to_vec()],
+        )
     }
 
-    /// Loads appointments from the database. If a locator is given, this method loads only the appointments
-    /// matching this locator. If no locator is given, all the appointments in the database would be returned.
-    pub(crate) fn load_appointments(
-        &self,
-        locator: Option<Locator>,
-    ) -> HashMap<UUID, ExtendedAppointment> {
+    /// Stores (or updates) a user ([UserInfo]) that has paid for their subscription, redeeming the [PendingPayment]
+    /// identified by `payment_hash` in the same transaction. The payment can only be redeemed once, so
+    /// [Error::NotFound] is returned (and the user is left untouched) if there is no such pending payment.
+    pub(crate) fn store_paid_user(
+        &mut self,
+        user_id: UserId,
+        user_info: &UserInfo,
+        payment_hash: &PaymentHash,
+    ) -> Result<(), Error> {
+        let tx = self.connection.transaction().unwrap();
+        match tx.execute(
+            "DELETE FROM pending_payments WHERE user_id=(?1) AND payment_hash=(?2)",
+            params![user_id.to_vec(), payment_hash.0.to_vec()],
+        ) {
+            Ok(0) => {
+                log::error!("Pending payment not found, cannot be redeemed: {user_id}");
+                return Err(Error::NotFound);
+            }
+            Ok(_) => log::debug!("Pending payment redemption added to db transaction"),
+            Err(e) => return Err(Error::Unknown(e)),
+        }
+
+        let updated = tx
+            .execute(
+                "UPDATE users SET available_slots=(?1), subscription_start=(?2), subscription_expiry=(?3), plan=(?4) WHERE user_id=(?5)",
+                params![
+                    user_info.available_slots,
+                    user_info.subscription_start,
+                    user_info.subscription_expiry,
+                    user_info.plan,
+                    user_id.to_vec(),
+                ],
+            )
+            .map_err(Error::Unknown)?;
+        if updated == 0 {
+            tx.execute(
+                "INSERT INTO users (user_id, available_slots, subscription_start, subscription_expiry, plan) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    user_id.to_vec(),
+                    user_info.available_slots,
+                    user_info.subscription_start,
+                    user_info.subscription_expiry,
+                    user_info.plan,
+                ],
+            )
+            .map_err(Error::Unknown)?;
+        }
+
+        match tx.commit() {
+            Ok(_) => {
+                log::debug!("Paid user successfully stored: {user_id}");
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Couldn't store paid user: {user_id}. Error: {e:?}");
+                Err(Error::Unknown(e))
+            }
+        }
+    }
+
+    /// Stores a one-time registration token issued by the tower admin.
+    pub(crate) fn store_registration_token(&self, token: &str) -> Result<(), Error> {
+        let query = "INSERT INTO registration_tokens (token) VALUES (?)";
+        match self.store_data(query, params![token]) {
+            Ok(x) => {
+                log::debug!("Registration token successfully stored");
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store registration token. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks whether a registration token has been issued and not redeemed yet.
+    pub(crate) fn has_registration_token(&self, token: &str) -> bool {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT token FROM registration_tokens WHERE token=(?)")
+            .unwrap();
+        stmt.exists([token]).unwrap()
+    }
+
+    /// Removes a registration token. Used to redeem it, so it cannot be used more than once.
+    pub(crate) fn remove_registration_token(&self, token: &str) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM registration_tokens WHERE token=(?)",
+            params![token],
+        )
+    }
+
+    /// Loads all the registration tokens that have not been redeemed yet.
+    pub(crate) fn load_registration_tokens(&self) -> Vec<String> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT token FROM registration_tokens")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|token| token.unwrap())
+            .collect()
+    }
+
+    /// Appends an [AuditEntry] to the audit log. The entry id is assigned by the database.
+    pub(crate) fn store_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let query = "INSERT INTO audit_log (timestamp, client, client_serial, method, params_digest, result) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        match self.store_data(
+            query,
+            params![
+                entry.timestamp,
+                entry.client,
+                entry.client_serial,
+                entry.method,
+                entry.params_digest.to_vec(),
+                entry.result,
+            ],
+        ) {
+            Ok(x) => {
+                log::debug!("Audit entry successfully stored: {}", entry.method);
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store audit entry: {}. Error: {e:?}", entry.method);
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads the audit log entries recorded at or after a given time, newest first.
+    ///
+    /// At most `limit` entries are loaded, or all of them if `limit` is zero.
+    pub(crate) fn load_audit_log(&self, since: u64, limit: u32) -> Vec<AuditEntry> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT id, timestamp, client, client_serial, method, params_digest, result FROM audit_log
+                    WHERE timestamp>=(?1) ORDER BY id DESC LIMIT (?2)",
+            )
+            .unwrap();
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+
+        stmt.query_map(params![since, limit], |row| {
+            let raw_digest: Vec<u8> = row.get(5)?;
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                client: row.get(2)?,
+                client_serial: row.get(3)?,
+                method: row.get(4)?,
+                params_digest: sha256::Hash::from_slice(&raw_digest).unwrap(),
+                result: row.get(6)?,
+            })
+        })
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+    }
+
+    /// Removes the audit log entries recorded before a given time.
+    pub(crate) fn remove_audit_entries_before(&self, timestamp: u64) {
+        match self.connection.execute(
+            "DELETE FROM audit_log WHERE timestamp<(?)",
+            params![timestamp],
+        ) {
+            Ok(n) => log::debug!("{n} audit entries successfully deleted"),
+            Err(e) => log::error!("Couldn't delete audit entries. Error: {e:?}"),
+        }
+    }
+
+    /// Enables or disables the replication log.
+    ///
+    /// While enabled, every change made to the [REPLICATED_TABLES] is recorded (by triggers), so it can be streamed to
+    /// standby towers. Only the latest [REPLICATION_LOG_SIZE] entries are kept. Disabling the log clears it.
+    pub fn set_replication_log(&self, enabled: bool) -> Result<(), SqliteError> {
+        let mut batch = String::new();
+        // Keys are logged as blobs so text keys (registration tokens) are not coerced by the column affinity
+        for (table, key) in REPLICATED_TABLES.iter() {
+            for (event, row) in [("INSERT", "NEW"), ("UPDATE", "NEW"), ("DELETE", "OLD")].iter() {
+                let trigger = format!("replicate_{table}_{}", event.to_lowercase());
+                batch.push_str(&if enabled {
+                    format!(
+                        "CREATE TRIGGER IF NOT EXISTS {trigger} AFTER {event} ON {table}
+                        BEGIN
+                            INSERT INTO replication_log (table_name, row_key) VALUES ('{table}', CAST({row}.{key} AS BLOB));
+                        END;"
+                    )
+                } else {
+                    format!("DROP TRIGGER IF EXISTS {trigger};")
+                });
+            }
+        }
+        batch.push_str(&if enabled {
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS replication_log_prune AFTER INSERT ON replication_log
+                BEGIN
+                    DELETE FROM replication_log WHERE seq<=NEW.seq-{REPLICATION_LOG_SIZE};
+                END;"
+            )
+        } else {
+            "DROP TRIGGER IF EXISTS replication_log_prune; DELETE FROM replication_log;".to_owned()
+        });
+
+        self.connection.execute_batch(&batch)
+    }
+
+    /// Gets the sequence numbers of the oldest and the latest entries of the replication log.
+    ///
+    /// The latest sequence number survives the log being pruned (or cleared). If the log is empty, the oldest one is
+    /// right after the latest.
+    pub(crate) fn get_replication_log_bounds(&self) -> (u64, u64) {
+        let latest = self
+            .connection
+            .query_row(
+                "SELECT seq FROM sqlite_sequence WHERE name='replication_log'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        let oldest: Option<u64> = self
+            .connection
+            .query_row("SELECT MIN(seq) FROM replication_log", [], |row| row.get(0))
+            .unwrap();
+
+        (oldest.unwrap_or(latest + 1), latest)
+    }
+
+    /// Loads (at most `limit`) entries of the replication log with a sequence number greater than `after`, as
+    /// `(seq, table, key)` tuples.
+    pub(crate) fn load_replication_log(
+        &self,
+        after: u64,
+        limit: u32,
+    ) -> Vec<(u64, String, Vec<u8>)> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT seq, table_name, row_key FROM replication_log WHERE seq>(?1) ORDER BY seq LIMIT (?2)",
+            )
+            .unwrap();
+
+        stmt.query_map(params![after, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+    }
+
+    /// Stores the sequence number of the last change applied by a standby tower.
+    pub(crate) fn store_replication_seq(&self, seq: u64) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO replication_state (id, seq) VALUES (0, ?)";
+        self.store_data(query, params![seq])
+    }
+
+    /// Loads the sequence number of the last change applied by a standby tower, if it is in sync with its primary.
+    pub fn load_replication_seq(&self) -> Option<u64> {
+        self.connection
+            .query_row("SELECT seq FROM replication_state WHERE id=0", [], |row| {
+                row.get(0)
+            })
+            .ok()
+    }
+
+    /// Removes the replication sequence number, flagging the tower as out of sync with its primary (if any).
+    pub fn remove_replication_seq(&self) -> Result<(), SqliteError> {
+        self.connection
+            .execute("DELETE FROM replication_state WHERE id=0", [])
+            .map(|_| ())
+    }
+
+    /// Removes the data of all the [REPLICATED_TABLES] from the database (alongside everything that depends on it).
+    ///
+    /// Used by standby towers before resyncing with their primary.
+    pub(crate) fn clear_replicated_data(&mut self) -> Result<(), SqliteError> {
+        self.connection.execute_batch(
+            "DELETE FROM appointments; DELETE FROM users; DELETE FROM allowed_users; DELETE FROM banned_users;
+            DELETE FROM registration_tokens; DELETE FROM pending_payments;",
+        )?;
+        self.load_locator_filter();
+
+        Ok(())
+    }
+
+    /// Loads a set of user ids using the given query. The user id is expected to be the only column in the result.
+    fn load_user_ids(&self, query: &str) -> HashSet<UserId> {
+        let mut stmt = self.connection.prepare(query).unwrap();
+
+        stmt.query_map([], |row| {
+            let raw_userid: Vec<u8> = row.get(0)?;
+            Ok(UserId::from_slice(&raw_userid).unwrap())
+        })
+        .unwrap()
+        .map(|user_id| user_id.unwrap())
+        .collect()
+    }
+
+    /// Get the number of stored appointments.
+    pub(crate) fn get_appointments_count(&self) -> usize {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT COUNT(*) FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID WHERE t.UUID IS NULL")
+            .unwrap();
+        stmt.query_row([], |row| row.get(0)).unwrap()
+    }
+
+    /// Get the number of stored trackers.
+    pub(crate) fn get_trackers_count(&self) -> usize {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT COUNT(*) FROM trackers")
+            .unwrap();
+        stmt.query_row([], |row| row.get(0)).unwrap()
+    }
+
+    /// Stores an [Appointment] into the database.
+    pub(crate) fn store_appointment(
+        &mut self,
+        uuid: UUID,
+        appointment: &ExtendedAppointment,
+    ) -> Result<(), Error> {
+        let query = "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+        match self.store_data(
+            query,
+            params![
+                uuid.to_vec(),
+                appointment.locator().to_vec(),
+                appointment.encrypted_blob(),
+                appointment.to_self_delay(),
+                appointment.user_signature,
+                appointment.start_block,
+                appointment.user_id.to_vec(),
+            ],
+        ) {
+            Ok(x) => {
+                log::debug!("Appointment successfully stored: {uuid}");
+                self.locator_filter_mut().insert(appointment.locator());
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store appointment: {uuid}. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Updates an existing [Appointment] in the database.
+    pub(crate) fn update_appointment(
+        &self,
+        uuid: UUID,
+        appointment: &ExtendedAppointment,
+    ) -> Result<(), Error> {
+        // DISCUSS: Check what fields we'd like to make updatable. e_blob and signature are the obvious, to_self_delay and start_block may not be necessary (or even risky)
+        let query =
+            "UPDATE appointments SET encrypted_blob=(?1), to_self_delay=(?2), user_signature=(?3), start_block=(?4) WHERE UUID=(?5)";
+        match self.update_data(
+            query,
+            params![
+                appointment.encrypted_blob(),
+                appointment.to_self_delay(),
+                appointment.user_signature,
+                appointment.start_block,
+                uuid.to_vec(),
+            ],
+        ) {
+            Ok(_) => {
+                log::debug!("Appointment successfully updated: {uuid}");
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Appointment not found, data cannot be updated: {uuid}. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads an [Appointment] from the database.
+    pub(crate) fn load_appointment(&self, uuid: UUID) -> Option<ExtendedAppointment> {
+        let key = uuid.to_vec();
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id
+                    FROM appointments WHERE UUID=(?)"
+            )
+            .unwrap();
+
+        stmt.query_row([key], |row| {
+            let raw_locator: Vec<u8> = row.get(0).unwrap();
+            let encrypted_blob = row.get(1).unwrap();
+            let to_self_delay = row.get(2).unwrap();
+            let user_signature = row.get(3).unwrap();
+            let start_block = row.get(4).unwrap();
+            let raw_userid: Vec<u8> = row.get(5).unwrap();
+
+            let locator = Locator::from_slice(&raw_locator).unwrap();
+            let user_id = UserId::from_slice(&raw_userid).unwrap();
+            let appointment = Appointment::new(locator, encrypted_blob, to_self_delay);
+            Ok(ExtendedAppointment::new(
+                appointment,
+                user_id,
+                user_signature,
+                start_block,
+            ))
+        })
+        .ok()
+    }
+
+    /// Check if an appointment with `uuid` exists.
+    pub(crate) fn appointment_exists(&self, uuid: UUID) -> bool {
+        self.connection
+            .prepare("SELECT UUID FROM appointments WHERE UUID=(?)")
+            .unwrap()
+            .exists([uuid.to_vec()])
+            .unwrap()
+    }
+
+    /// Loads appointments from the database. If a locator is given, this method loads only the appointments
+    /// matching this locator. If no locator is given, all the appointments in the database would be returned.
+    pub(crate) fn load_appointments(
+        &self,
+        locator: Option<Locator>,
+    ) -> HashMap<UUID, ExtendedAppointment> {
         let mut appointments = HashMap::new();
 
         let mut sql =
@@ -400,11 +1239,19 @@ impl DBM {
     }
 
     /// Removes an [Appointment] from the database.
-    pub(crate) fn remove_appointment(&self, uuid: UUID) {
+    pub(crate) fn remove_appointment(&mut self, uuid: UUID) {
+        let locator = Self::load_locators_in(
+            &self.connection,
+            "SELECT locator FROM appointments WHERE UUID IN ",
+            &[uuid.to_vec()],
+        );
         let query = "DELETE FROM appointments WHERE UUID=(?)";
         match self.remove_data(query, params![uuid.to_vec()]) {
             Ok(_) => {
                 log::debug!("Appointment successfully removed: {uuid}");
+                locator
+                    .iter()
+                    .for_each(|locator| self.locator_filter_mut().remove(locator));
             }
             Err(_) => {
                 log::error!("Appointment not found, data cannot be removed: {uuid}");
@@ -426,8 +1273,15 @@ impl DBM {
             .iter()
             .map(|uuid| uuid.to_vec())
             .collect::<Vec<Vec<u8>>>();
+        let mut removed_locators = Vec::new();
 
         for chunk in iter.chunks(limit) {
+            removed_locators.extend(Self::load_locators_in(
+                &tx,
+                "SELECT locator FROM appointments WHERE UUID IN ",
+                chunk,
+            ));
+
             let query = "DELETE FROM appointments WHERE UUID IN ".to_owned();
             let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
 
@@ -446,7 +1300,12 @@ impl DBM {
         }
 
         match tx.commit() {
-            Ok(_) => log::debug!("Appointments successfully deleted"),
+            Ok(_) => {
+                log::debug!("Appointments successfully deleted");
+                removed_locators
+                    .iter()
+                    .for_each(|locator| self.locator_filter_mut().remove(locator));
+            }
             Err(e) => log::error!("Couldn't delete appointments. Error: {e:?}"),
         }
 
@@ -471,9 +1330,16 @@ impl DBM {
     }
 
     /// Filters the given set of [`Locator`]s by including only the ones which trigger any of our stored appointments.
+    ///
+    /// Locators are screened using the in-memory [LocatorFilter] first, so the database is only queried for the ones
+    /// that are known to be watched. Therefore, this must be called on the writer [DBM].
     pub(crate) fn batch_check_locators_exist(&self, locators: Vec<&Locator>) -> Vec<Locator> {
         let mut registered_locators = Vec::new();
-        let locators: Vec<Vec<u8>> = locators.iter().map(|l| l.to_vec()).collect();
+        let locators: Vec<Vec<u8>> = locators
+            .iter()
+            .filter(|l| self.locator_filter().contains(l))
+            .map(|l| l.to_vec())
+            .collect();
         let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
 
         for chunk in locators.chunks(limit) {
@@ -529,9 +1395,18 @@ impl DBM {
         }
     }
 
-    /// Updates the tracker status in the database.
-    ///
-    /// The only updatable fields are `height` and `confirmed`.
+    /// Removes a [TransactionTracker] from the database. The appointment it comes from is left untouched.
+    pub(crate) fn remove_tracker(&self, uuid: UUID) {
+        let query = "DELETE FROM trackers WHERE UUID=(?)";
+        match self.remove_data(query, params![uuid.to_vec()]) {
+            Ok(_) => log::debug!("Tracker successfully removed: {uuid}"),
+            Err(_) => log::error!("Tracker not found, data cannot be removed: {uuid}"),
+        }
+    }
+
+    /// Updates the tracker status in the database.
+    ///
+    /// The only updatable fields are `height` and `confirmed`.
     pub(crate) fn update_tracker_status(
         &self,
         uuid: UUID,
@@ -552,6 +1427,33 @@ impl DBM {
         }
     }
 
+    /// Increases the number of times the penalty of a given tracker has been rebroadcast.
+    pub(crate) fn increase_rebroadcast_attempts(&self, uuid: UUID) -> Result<(), Error> {
+        let query = "INSERT INTO rebroadcasts (UUID, attempts) VALUES (?1, 1)
+            ON CONFLICT(UUID) DO UPDATE SET attempts=attempts+1";
+        match self.store_data(query, params![uuid.to_vec()]) {
+            Ok(x) => {
+                log::debug!("Rebroadcast attempts successfully updated: {uuid}");
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't update rebroadcast attempts: {uuid}. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads the number of times the penalty of a given tracker has been rebroadcast.
+    pub(crate) fn load_rebroadcast_attempts(&self, uuid: UUID) -> u32 {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT attempts FROM rebroadcasts WHERE UUID=(?)")
+            .unwrap();
+
+        stmt.query_row([uuid.to_vec()], |row| row.get(0))
+            .unwrap_or(0)
+    }
+
     /// Loads a [TransactionTracker] from the database.
     pub(crate) fn load_tracker(&self, uuid: UUID) -> Option<TransactionTracker> {
         let key = uuid.to_vec();
@@ -674,7 +1576,7 @@ impl DBM {
         let mut stmt = self
             .connection
             .prepare(
-                "SELECT t.UUID, t.penalty_tx, t.height, t.confirmed
+                "SELECT t.UUID, t.penalty_tx, t.height, t.confirmed, a.user_id
                     FROM trackers as t INNER JOIN appointments as a ON t.UUID=a.UUID",
             )
             .unwrap();
@@ -685,6 +1587,7 @@ impl DBM {
             let raw_penalty_tx: Vec<u8> = row.get(1).unwrap();
             let height: u32 = row.get(2).unwrap();
             let confirmed: bool = row.get(3).unwrap();
+            let raw_userid: Vec<u8> = row.get(4).unwrap();
 
             // DISCUSS: Should we store the txids to avoid pulling raw txs and deserializing then hashing them.
             let penalty_txid = consensus::deserialize::<bitcoin::Transaction>(&raw_penalty_tx)
@@ -695,6 +1598,7 @@ impl DBM {
                 PenaltySummary::new(
                     penalty_txid,
                     ConfirmationStatus::from_db_data(height, confirmed),
+                    UserId::from_slice(&raw_userid).unwrap(),
                 ),
             );
         }
@@ -721,6 +1625,435 @@ impl DBM {
         .ok()
     }
 
+    /// Stores the findings of a recovery scan ([MissedBreach]) into the database.
+    ///
+    /// Findings that have already been reported are ignored. Returns the number of new findings stored.
+    pub(crate) fn store_missed_breaches(&mut self, missed_breaches: &[MissedBreach]) -> usize {
+        let tx = self.connection.transaction().unwrap();
+        let mut stored = 0;
+
+        for breach in missed_breaches {
+            match tx.execute(
+                "INSERT OR IGNORE INTO missed_breaches (UUID, locator, user_id, kind, height, dispute_txid)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    breach.uuid.to_vec(),
+                    breach.locator.to_vec(),
+                    breach.user_id.to_vec(),
+                    breach.kind as i32,
+                    breach.height,
+                    breach.dispute_txid.map(|txid| txid.to_vec()),
+                ],
+            ) {
+                Ok(n) => {
+                    stored += n;
+                    log::debug!("Missed breach added to db transaction: {}", breach.uuid)
+                }
+                Err(e) => log::error!("Couldn't add missed breach to transaction. Error: {e:?}"),
+            }
+        }
+
+        match tx.commit() {
+            Ok(_) => {
+                log::debug!("Missed breaches successfully stored");
+                stored
+            }
+            Err(e) => {
+                log::error!("Couldn't store missed breaches. Error: {e:?}");
+                0
+            }
+        }
+    }
+
+    /// Loads all the findings of past recovery scans from the database, in the order they were reported.
+    pub(crate) fn load_missed_breaches(&self) -> Vec<MissedBreach> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT UUID, locator, user_id, kind, height, dispute_txid FROM missed_breaches ORDER BY id",
+            )
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            let raw_uuid: Vec<u8> = row.get(0)?;
+            let raw_locator: Vec<u8> = row.get(1)?;
+            let raw_userid: Vec<u8> = row.get(2)?;
+            let kind: i32 = row.get(3)?;
+            let raw_txid: Option<Vec<u8>> = row.get(5)?;
+
+            Ok(MissedBreach::new(
+                UUID::from_slice(&raw_uuid).unwrap(),
+                Locator::from_slice(&raw_locator).unwrap(),
+                UserId::from_slice(&raw_userid).unwrap(),
+                MissedBreachKind::try_from(kind).unwrap(),
+                row.get(4)?,
+                raw_txid.map(|txid| Txid::from_slice(&txid).unwrap()),
+            ))
+        })
+        .unwrap()
+        .map(|breach| breach.unwrap())
+        .collect()
+    }
+
+    /// Stores the [AppointmentOutcome]s of triggered appointments the tower could not respond to into the database.
+    pub(crate) fn store_appointment_outcomes(&mut self, outcomes: &[AppointmentOutcome]) {
+        let tx = self.connection.transaction().unwrap();
+
+        for outcome in outcomes {
+            match tx.execute(
+                "INSERT INTO appointment_outcomes (UUID, user_id, locator, dispute_txid, kind, rpc_error_code, height)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    outcome.uuid.to_vec(),
+                    outcome.user_id.to_vec(),
+                    outcome.locator.to_vec(),
+                    outcome.dispute_txid.to_vec(),
+                    outcome.kind as i32,
+                    outcome.rpc_error_code,
+                    outcome.height,
+                ],
+            ) {
+                Ok(_) => log::debug!("Appointment outcome added to db transaction: {}", outcome.uuid),
+                Err(e) => log::error!("Couldn't add appointment outcome to transaction. Error: {e:?}"),
+            }
+        }
+
+        match tx.commit() {
+            Ok(_) => log::debug!("Appointment outcomes successfully stored"),
+            Err(e) => log::error!("Couldn't store appointment outcomes. Error: {e:?}"),
+        }
+    }
+
+    /// Loads the [AppointmentOutcome]s from the database, in the order they were recorded.
+    ///
+    /// If a [UUID] is given, only the outcomes of the matching appointment are loaded.
+    pub(crate) fn load_appointment_outcomes(&self, uuid: Option<UUID>) -> Vec<AppointmentOutcome> {
+        let mut sql = "SELECT UUID, user_id, locator, dispute_txid, kind, rpc_error_code, height FROM appointment_outcomes"
+            .to_owned();
+        let mut params = Vec::new();
+        if let Some(uuid) = uuid {
+            sql.push_str(" WHERE UUID=(?)");
+            params.push(uuid.to_vec());
+        }
+        sql.push_str(" ORDER BY id");
+        let mut stmt = self.connection.prepare(&sql).unwrap();
+
+        stmt.query_map(params_from_iter(params), |row| {
+            let raw_uuid: Vec<u8> = row.get(0)?;
+            let raw_userid: Vec<u8> = row.get(1)?;
+            let raw_locator: Vec<u8> = row.get(2)?;
+            let raw_txid: Vec<u8> = row.get(3)?;
+            let kind: i32 = row.get(4)?;
+
+            Ok(AppointmentOutcome::new(
+                UUID::from_slice(&raw_uuid).unwrap(),
+                Locator::from_slice(&raw_locator).unwrap(),
+                UserId::from_slice(&raw_userid).unwrap(),
+                Txid::from_slice(&raw_txid).unwrap(),
+                FailureKind::try_from(kind).unwrap(),
+                row.get(5)?,
+                row.get(6)?,
+            ))
+        })
+        .unwrap()
+        .map(|outcome| outcome.unwrap())
+        .collect()
+    }
+
+    /// Stores a user [Backup] into the database. The previous backup of the user (if any) is overwritten.
+    pub(crate) fn store_backup(&self, user_id: UserId, backup: &Backup) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO backups (user_id, data, version) VALUES (?1, ?2, ?3)";
+        match self.store_data(
+            query,
+            params![user_id.to_vec(), backup.data, backup.version],
+        ) {
+            Ok(x) => {
+                log::debug!("Backup successfully stored: {user_id}");
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store backup: {user_id}. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads the [Backup] of a given user from the database.
+    pub(crate) fn load_backup(&self, user_id: UserId) -> Option<Backup> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT data, version FROM backups WHERE user_id=(?)")
+            .unwrap();
+
+        stmt.query_row([user_id.to_vec()], |row| {
+            Ok(Backup::new(row.get(0).unwrap(), row.get(1).unwrap()))
+        })
+        .ok()
+    }
+
+    /// Loads the [Backup]s of all users from the database.
+    pub(crate) fn load_backups(&self) -> HashMap<UserId, Backup> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT user_id, data, version FROM backups")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            let raw_userid: Vec<u8> = row.get(0)?;
+            Ok((
+                UserId::from_slice(&raw_userid).unwrap(),
+                Backup::new(row.get(1)?, row.get(2)?),
+            ))
+        })
+        .unwrap()
+        .map(|backup| backup.unwrap())
+        .collect()
+    }
+
+    /// Removes the [Backup] of a given user.
+    pub(crate) fn remove_backup(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM backups WHERE user_id=(?)",
+            params![user_id.to_vec()],
+        )
+    }
+
+    /// Gets the version and length (the length of `backup.data`) of the [Backup] of a given user.
+    pub(crate) fn get_backup_version_and_length(&self, user_id: UserId) -> Option<(u32, usize)> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT version, length(data) FROM backups WHERE user_id=(?)")
+            .unwrap();
+
+        stmt.query_row([user_id.to_vec()], |row| {
+            Ok((row.get(0).unwrap(), row.get(1).unwrap()))
+        })
+        .ok()
+    }
+
+    /// Stores a [ResponseReceipt] into the database. Existing receipts for the same appointment are overwritten.
+    ///
+    /// Receipts are not bound to the appointment they were issued for, so they outlive it once the tracker is completed.
+    pub(crate) fn store_response_receipt(
+        &self,
+        uuid: UUID,
+        user_id: UserId,
+        receipt: &ResponseReceipt,
+    ) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO response_receipts (UUID, user_id, locator, dispute_txid, penalty_txid, broadcast_height, confirmation_height, signature) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+        match self.store_data(
+            query,
+            params![
+                uuid.to_vec(),
+                user_id.to_vec(),
+                receipt.locator().to_vec(),
+                receipt.dispute_txid().to_vec(),
+                receipt.penalty_txid().to_vec(),
+                receipt.broadcast_height(),
+                receipt.confirmation_height(),
+                receipt.signature(),
+            ],
+        ) {
+            Ok(x) => {
+                log::debug!("Response receipt successfully stored: {uuid}");
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store response receipt: {uuid}. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads the [ResponseReceipt] issued for a given appointment from the database.
+    pub(crate) fn load_response_receipt(&self, uuid: UUID) -> Option<ResponseReceipt> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT locator, dispute_txid, penalty_txid, broadcast_height, confirmation_height, signature FROM response_receipts WHERE UUID=(?)")
+            .unwrap();
+
+        stmt.query_row([uuid.to_vec()], |row| {
+            let raw_locator: Vec<u8> = row.get(0).unwrap();
+            let raw_dispute_txid: Vec<u8> = row.get(1).unwrap();
+            let raw_penalty_txid: Vec<u8> = row.get(2).unwrap();
+            Ok(ResponseReceipt::with_signature(
+                Locator::from_slice(&raw_locator).unwrap(),
+                Txid::from_slice(&raw_dispute_txid).unwrap(),
+                Txid::from_slice(&raw_penalty_txid).unwrap(),
+                row.get(3).unwrap(),
+                row.get(4).unwrap(),
+                row.get(5).unwrap(),
+            ))
+        })
+        .ok()
+    }
+
+    /// Loads the [ResponseReceipt]s from the database, alongside the user they were issued for.
+    ///
+    /// If a `uuid` is given, only the receipt issued for that appointment (if any) is loaded.
+    pub(crate) fn load_response_receipts(
+        &self,
+        uuid: Option<UUID>,
+    ) -> HashMap<UUID, (UserId, ResponseReceipt)> {
+        let mut sql = "SELECT UUID, user_id, locator, dispute_txid, penalty_txid, broadcast_height, confirmation_height, signature FROM response_receipts".to_string();
+        if uuid.is_some() {
+            sql.push_str(" WHERE UUID=(?)");
+        }
+        let mut stmt = self.connection.prepare(&sql).unwrap();
+
+        let mut rows = if let Some(uuid) = uuid {
+            stmt.query([uuid.to_vec()]).unwrap()
+        } else {
+            stmt.query([]).unwrap()
+        };
+
+        let mut receipts = HashMap::new();
+        while let Ok(Some(row)) = rows.next() {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            let raw_userid: Vec<u8> = row.get(1).unwrap();
+            let raw_locator: Vec<u8> = row.get(2).unwrap();
+            let raw_dispute_txid: Vec<u8> = row.get(3).unwrap();
+            let raw_penalty_txid: Vec<u8> = row.get(4).unwrap();
+            receipts.insert(
+                UUID::from_slice(&raw_uuid).unwrap(),
+                (
+                    UserId::from_slice(&raw_userid).unwrap(),
+                    ResponseReceipt::with_signature(
+                        Locator::from_slice(&raw_locator).unwrap(),
+                        Txid::from_slice(&raw_dispute_txid).unwrap(),
+                        Txid::from_slice(&raw_penalty_txid).unwrap(),
+                        row.get(5).unwrap(),
+                        row.get(6).unwrap(),
+                        row.get(7).unwrap(),
+                    ),
+                ),
+            );
+        }
+
+        receipts
+    }
+
+    /// Removes the [ResponseReceipt] issued for a given appointment.
+    pub(crate) fn remove_response_receipt(&self, uuid: UUID) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM response_receipts WHERE UUID=(?)",
+            params![uuid.to_vec()],
+        )
+    }
+
+    /// Stores a block header into the database. Existing headers are overwritten.
+    pub(crate) fn store_header(&self, header: &ValidatedBlockHeader) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO headers (block_hash, height, chainwork, header) VALUES (?1, ?2, ?3, ?4)";
+        self.store_data(
+            query,
+            params![
+                header.header.block_hash().to_vec(),
+                header.height,
+                header.chainwork.to_be_bytes().to_vec(),
+                consensus::serialize(&header.header),
+            ],
+        )
+    }
+
+    /// Removes a block header from the database.
+    pub(crate) fn remove_header(&self, block_hash: &BlockHash) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM headers WHERE block_hash=(?)",
+            params![block_hash.to_vec()],
+        )
+    }
+
+    /// Removes all the block headers up to a given height (included) from the database.
+    pub(crate) fn remove_headers_up_to(&self, height: u32) {
+        match self
+            .connection
+            .execute("DELETE FROM headers WHERE height<=(?)", params![height])
+        {
+            Ok(n) => log::debug!("{n} headers successfully deleted"),
+            Err(e) => log::error!("Couldn't delete headers. Error: {e:?}"),
+        }
+    }
+
+    /// Loads all the block headers from the database.
+    pub(crate) fn load_headers(&self) -> Vec<ValidatedBlockHeader> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT block_hash, height, chainwork, header FROM headers")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            let raw_hash: Vec<u8> = row.get(0)?;
+            let raw_chainwork: Vec<u8> = row.get(2)?;
+            let raw_header: Vec<u8> = row.get(3)?;
+            let block_hash = BlockHash::from_slice(&raw_hash).unwrap();
+
+            Ok(BlockHeaderData {
+                header: consensus::deserialize(&raw_header).unwrap(),
+                height: row.get(1)?,
+                chainwork: Uint256::from_be_bytes(raw_chainwork.try_into().unwrap()),
+            }
+            .validate(block_hash)
+            .unwrap())
+        })
+        .unwrap()
+        .map(|header| header.unwrap())
+        .collect()
+    }
+
+    /// Stores the transaction ids of a block into the database. Existing ones are overwritten.
+    pub(crate) fn store_block_txids(&self, block: &BlockTxids, height: u32) -> Result<(), Error> {
+        let query =
+            "INSERT OR REPLACE INTO block_txids (block_hash, height, txids) VALUES (?1, ?2, ?3)";
+        self.store_data(
+            query,
+            params![
+                block.header.block_hash().to_vec(),
+                height,
+                block
+                    .txids
+                    .iter()
+                    .flat_map(|txid| txid.to_vec())
+                    .collect::<Vec<u8>>(),
+            ],
+        )
+    }
+
+    /// Loads the transaction ids of a block from the database (if found).
+    pub(crate) fn load_block_txids(&self, block_hash: &BlockHash) -> Option<Vec<Txid>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT txids FROM block_txids WHERE block_hash=(?)")
+            .unwrap();
+
+        stmt.query_row([block_hash.to_vec()], |row| {
+            let raw_txids: Vec<u8> = row.get(0)?;
+            Ok(raw_txids
+                .chunks(32)
+                .map(|raw_txid| Txid::from_slice(raw_txid).unwrap())
+                .collect())
+        })
+        .ok()
+    }
+
+    /// Removes the transaction ids of a block from the database.
+    pub(crate) fn remove_block_txids(&self, block_hash: &BlockHash) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM block_txids WHERE block_hash=(?)",
+            params![block_hash.to_vec()],
+        )
+    }
+
+    /// Removes the transaction ids of all the blocks up to a given height (included) from the database.
+    pub(crate) fn remove_block_txids_up_to(&self, height: u32) {
+        match self
+            .connection
+            .execute("DELETE FROM block_txids WHERE height<=(?)", params![height])
+        {
+            Ok(n) => log::debug!("Transaction ids of {n} blocks successfully deleted"),
+            Err(e) => log::error!("Couldn't delete block transaction ids. Error: {e:?}"),
+        }
+    }
+
     /// Stores the tower secret key into the database.
     ///
     /// When a new key is generated, old keys are not overwritten but are not retrievable from the API either.
@@ -752,55 +2085,230 @@ impl DBM {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
     use std::iter::FromIterator;
+    use tempdir::TempDir;
 
     use teos_common::cryptography::{get_random_bytes, get_random_keypair};
-    use teos_common::test_utils::{get_random_locator, get_random_user_id};
+    use teos_common::test_utils::{
+        get_random_locator, get_random_response_receipt, get_random_user_id,
+    };
 
     use crate::rpc_errors;
     use crate::test_utils::{
         generate_dummy_appointment, generate_dummy_appointment_with_user, generate_uuid,
-        get_random_tracker, get_random_tx, AVAILABLE_SLOTS, SUBSCRIPTION_EXPIRY,
-        SUBSCRIPTION_START,
+        get_random_tracker, get_random_tx, store_appointment_and_its_user, Blockchain,
+        AVAILABLE_SLOTS, SUBSCRIPTION_EXPIRY, SUBSCRIPTION_START,
     };
 
     impl DBM {
         pub(crate) fn in_memory() -> Result<Self, SqliteError> {
-            let connection = Connection::open_in_memory()?;
-            connection.execute("PRAGMA foreign_keys=1;", [])?;
-            let mut dbm = Self { connection };
-            dbm.create_tables(Vec::from_iter(TABLES))?;
+            // A named, shared-cache, in-memory database so the readers can access it too.
+            let uri = format!(
+                "file:teos_{}?mode=memory&cache=shared",
+                hex::encode(get_random_bytes(16))
+            );
+            let connection = Connection::open_with_flags(
+                &uri,
+                OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_READ_WRITE
+                    | OpenFlags::SQLITE_OPEN_CREATE,
+            )?;
+            let mut dbm = Self::from_connection(connection)?;
+            dbm.reader = Some(DBReader::new(
+                &uri,
+                OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_READ_ONLY,
+                READ_POOL_SIZE,
+            )?);
 
             Ok(dbm)
         }
 
-        pub(crate) fn load_user(&self, user_id: UserId) -> Option<UserInfo> {
-            let key = user_id.to_vec();
-            let mut stmt = self
-                .connection
-                .prepare(
-                    "SELECT available_slots, subscription_start, subscription_expiry
-                        FROM users WHERE user_id=(?)",
-                )
-                .unwrap();
-            stmt.query_row([&key], |row| {
-                let slots = row.get(0).unwrap();
-                let start = row.get(1).unwrap();
-                let expiry = row.get(2).unwrap();
-                Ok(UserInfo::new(slots, start, expiry))
-            })
-            .ok()
+        /// Creates a WAL database in a temporary directory, set up the same way it is in production. The directory is
+        /// deleted once the returned [TempDir] is dropped.
+        pub(crate) fn in_temp_dir() -> (Self, TempDir) {
+            let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+            let dbm = DBM::new(tmp_path.path().join("teos_db.sql3")).unwrap();
+            (dbm, tmp_path)
         }
     }
 
     #[test]
     fn test_create_tables() {
         let connection = Connection::open_in_memory().unwrap();
-        let mut dbm = DBM { connection };
+        let mut dbm = DBM {
+            connection,
+            locators: None,
+            reader: None,
+        };
         dbm.create_tables(Vec::from_iter(TABLES)).unwrap();
     }
 
+    #[test]
+    fn test_reader() {
+        let (dbm, _tmp_path) = DBM::in_temp_dir();
+        let reader = dbm.reader();
+
+        // Data written by the DBM can be read through the pool
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        assert_eq!(reader.get().load_user(user_id).unwrap(), user);
+
+        // But not written
+        assert!(reader
+            .get()
+            .store_user(get_random_user_id(), &user)
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "only kept by the writer")]
+    fn test_reader_batch_check_locators_exist() {
+        let (mut dbm, _tmp_path) = DBM::in_temp_dir();
+        let appointment = generate_dummy_appointment(None);
+        store_appointment_and_its_user(&mut dbm, &appointment);
+
+        // Readers do not keep a locator filter, so they cannot screen locators
+        dbm.reader()
+            .get()
+            .batch_check_locators_exist(vec![&appointment.locator()]);
+    }
+
+    #[test]
+    fn test_reader_not_blocked_by_writer() {
+        let (dbm, _tmp_path) = DBM::in_temp_dir();
+        let reader = dbm.reader();
+
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Readers are not blocked by an ongoing write, they see the last committed state instead
+        dbm.connection.execute("BEGIN IMMEDIATE", []).unwrap();
+        dbm.store_user(get_random_user_id(), &user).unwrap();
+        let handle = std::thread::spawn(move || reader.get().load_all_users());
+        assert_eq!(
+            handle.join().unwrap(),
+            HashMap::from_iter([(user_id, user)])
+        );
+        dbm.connection.execute("COMMIT", []).unwrap();
+        assert_eq!(dbm.reader().get().load_all_users().len(), 2);
+    }
+
+    #[test]
+    fn test_reader_pool_exhausted() {
+        let (dbm, _tmp_path) = DBM::in_temp_dir();
+        let reader = dbm.reader();
+
+        // Once all connections are in use, getting one waits until another is released
+        let in_use: Vec<PooledDBM> = (0..READ_POOL_SIZE).map(|_| reader.get()).collect();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = {
+            let reader = reader.clone();
+            std::thread::spawn(move || {
+                let dbm = reader.get();
+                sender.send(()).unwrap();
+                dbm.load_all_users()
+            })
+        };
+        assert!(receiver
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+
+        drop(in_use);
+        receiver.recv().unwrap();
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backup_restore() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let mut dbm = DBM::new(tmp_path.path().join("teos_db.sql3")).unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+
+        // Snapshots are taken from readers, so they do not include uncommitted writes
+        let snapshot_path = tmp_path.path().join("snapshot.sql3");
+        dbm.connection.execute("BEGIN IMMEDIATE", []).unwrap();
+        dbm.store_user(get_random_user_id(), &user).unwrap();
+        dbm.reader().get().backup(&snapshot_path).unwrap();
+        dbm.connection.execute("COMMIT", []).unwrap();
+
+        let snapshot = DBM::load_snapshot(&snapshot_path).unwrap();
+        assert_eq!(
+            snapshot.load_all_users(),
+            HashMap::from_iter([(user_id, user)])
+        );
+
+        // Restoring replaces the whole database, including the locator filter
+        let mut restored = DBM::new(tmp_path.path().join("restored.sql3")).unwrap();
+        restored
+            .store_user(get_random_user_id(), &UserInfo::new(1, 2, 3))
+            .unwrap();
+        restored.restore(&snapshot_path).unwrap();
+        assert_eq!(restored.load_all_users(), snapshot.load_all_users());
+        assert_eq!(restored.load_appointment(uuid).unwrap(), appointment);
+        assert!(restored.locator_filter().contains(&appointment.locator()));
+        assert_eq!(restored.reader().get().load_all_users().len(), 1);
+    }
+
+    #[test]
+    fn test_replication_log() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+
+        // Changes are not recorded unless the log is enabled
+        dbm.store_user(user_id, &user).unwrap();
+        assert_eq!(dbm.get_replication_log_bounds(), (1, 0));
+
+        dbm.set_replication_log(true).unwrap();
+        dbm.update_user(user_id, &user);
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        dbm.remove_appointment(uuid);
+        assert_eq!(
+            dbm.load_replication_log(0, 10),
+            vec![
+                (1, "users".to_owned(), user_id.to_vec()),
+                (2, "appointments".to_owned(), uuid.to_vec()),
+                (3, "appointments".to_owned(), uuid.to_vec()),
+            ]
+        );
+        assert_eq!(
+            dbm.load_replication_log(1, 1),
+            vec![(2, "appointments".to_owned(), uuid.to_vec())]
+        );
+        assert_eq!(dbm.get_replication_log_bounds(), (1, 3));
+
+        // Disabling the log clears it, but sequence numbers keep growing
+        dbm.set_replication_log(false).unwrap();
+        dbm.batch_remove_users(&[user_id]);
+        assert_eq!(dbm.get_replication_log_bounds(), (4, 3));
+        dbm.set_replication_log(true).unwrap();
+        dbm.store_user(user_id, &user).unwrap();
+        assert_eq!(
+            dbm.load_replication_log(0, 10),
+            vec![(4, "users".to_owned(), user_id.to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_store_load_remove_replication_seq() {
+        let dbm = DBM::in_memory().unwrap();
+        assert_eq!(dbm.load_replication_seq(), None);
+
+        dbm.store_replication_seq(21).unwrap();
+        dbm.store_replication_seq(42).unwrap();
+        assert_eq!(dbm.load_replication_seq(), Some(42));
+
+        dbm.remove_replication_seq().unwrap();
+        assert_eq!(dbm.load_replication_seq(), None);
+    }
+
     #[test]
     fn test_store_load_user() {
         let dbm = DBM::in_memory().unwrap();
@@ -837,14 +2345,59 @@ mod tests {
         dbm.store_user(user_id, &user).unwrap();
         assert_eq!(dbm.load_user(user_id).unwrap(), user);
 
-        user.available_slots *= 2;
-        dbm.update_user(user_id, &user);
-        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+        user.available_slots *= 2;
+        dbm.update_user(user_id, &user);
+        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+
+        // The plan is updated too
+        user.plan = "premium".to_owned();
+        dbm.update_user(user_id, &user);
+        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+    }
+
+    #[test]
+    fn test_add_plan_column() {
+        // Databases created before plans were a thing have no plan column in the users table. It should be added when
+        // loading the database, with the existing users subscribed to the default plan.
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE users (
+                    user_id INT PRIMARY KEY,
+                    available_slots INT NOT NULL,
+                    subscription_start INT NOT NULL,
+                    subscription_expiry INT NOT NULL
+                )",
+                [],
+            )
+            .unwrap();
+        let user_id = get_random_user_id();
+        connection
+            .execute(
+                "INSERT INTO users VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    user_id.to_vec(),
+                    AVAILABLE_SLOTS,
+                    SUBSCRIPTION_START,
+                    SUBSCRIPTION_EXPIRY
+                ],
+            )
+            .unwrap();
+
+        let dbm = DBM::from_connection(connection).unwrap();
+        assert_eq!(
+            dbm.load_user(user_id).unwrap(),
+            UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY)
+        );
+
+        // Adding the column is idempotent
+        dbm.add_column("users", "plan", "TEXT NOT NULL DEFAULT 'default'")
+            .unwrap();
     }
 
     #[test]
     fn test_load_user_locators() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -878,8 +2431,8 @@ mod tests {
                 SUBSCRIPTION_START + i,
                 SUBSCRIPTION_EXPIRY + i,
             );
-            users.insert(user_id, user);
             dbm.store_user(user_id, &user).unwrap();
+            users.insert(user_id, user);
         }
 
         assert_eq!(dbm.load_all_users(), users);
@@ -915,6 +2468,158 @@ mod tests {
         assert_eq!(rest, dbm.load_all_users().keys().cloned().collect());
     }
 
+    #[test]
+    fn test_store_load_remove_allowed_users() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_allowed_users().is_empty());
+
+        // Users can be allowed before registering
+        let user_ids: HashSet<UserId> = (0..5).map(|_| get_random_user_id()).collect();
+        for user_id in user_ids.iter() {
+            dbm.store_allowed_user(*user_id).unwrap();
+        }
+        assert_eq!(dbm.load_allowed_users(), user_ids);
+
+        // Allowing the same user twice is a no-op
+        let user_id = *user_ids.iter().next().unwrap();
+        dbm.store_allowed_user(user_id).unwrap();
+        assert_eq!(dbm.load_allowed_users(), user_ids);
+
+        dbm.remove_allowed_user(user_id).unwrap();
+        assert!(!dbm.load_allowed_users().contains(&user_id));
+        assert!(matches!(
+            dbm.remove_allowed_user(user_id),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_store_load_remove_banned_users() {
+        let mut dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_banned_users().is_empty());
+
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        dbm.store_banned_user(user_id).unwrap();
+        dbm.store_banned_user(user_id).unwrap();
+        assert_eq!(dbm.load_banned_users(), HashSet::from_iter([user_id]));
+
+        // Bans outlive the user data
+        dbm.batch_remove_users(&[user_id]);
+        assert_eq!(dbm.load_banned_users(), HashSet::from_iter([user_id]));
+
+        dbm.remove_banned_user(user_id).unwrap();
+        assert!(dbm.load_banned_users().is_empty());
+        assert!(matches!(
+            dbm.remove_banned_user(user_id),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_store_load_remove_registration_tokens() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_registration_tokens().is_empty());
+
+        let tokens: HashSet<String> = (0..5).map(|i| format!("token{i}")).collect();
+        for token in tokens.iter() {
+            dbm.store_registration_token(token).unwrap();
+            assert!(dbm.has_registration_token(token));
+        }
+        assert_eq!(HashSet::from_iter(dbm.load_registration_tokens()), tokens);
+
+        // Tokens are unique
+        assert!(matches!(
+            dbm.store_registration_token("token0"),
+            Err(Error::AlreadyExists)
+        ));
+
+        // Tokens can only be removed (redeemed) once
+        dbm.remove_registration_token("token0").unwrap();
+        assert!(!dbm.has_registration_token("token0"));
+        assert!(matches!(
+            dbm.remove_registration_token("token0"),
+            Err(Error::NotFound)
+        ));
+        assert!(!dbm.has_registration_token("unknown"));
+    }
+
+    #[test]
+    fn test_store_load_pending_payment() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        assert_eq!(dbm.load_pending_payment(user_id), None);
+
+        // Pending payments do not require the user to be registered
+        let mut pending = PendingPayment::new(
+            "premium".to_owned(),
+            Invoice {
+                bolt11: "lnbcrt10n1".to_owned(),
+                payment_hash: PaymentHash([1; 32]),
+            },
+            42,
+        );
+        dbm.store_pending_payment(user_id, &pending).unwrap();
+        assert_eq!(dbm.load_pending_payment(user_id), Some(pending.clone()));
+
+        // Storing a new one overwrites the previous
+        pending.plan = "gold".to_owned();
+        pending.invoice.payment_hash = PaymentHash([2; 32]);
+        dbm.store_pending_payment(user_id, &pending).unwrap();
+        assert_eq!(dbm.load_pending_payment(user_id), Some(pending));
+    }
+
+    #[test]
+    fn test_store_paid_user() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let pending = PendingPayment::new(
+            "premium".to_owned(),
+            Invoice {
+                bolt11: "lnbcrt10n1".to_owned(),
+                payment_hash: PaymentHash([1; 32]),
+            },
+            42,
+        );
+        let user = UserInfo::new(21, 42, 100).with_plan("premium".to_owned());
+
+        // Users can only be stored redeeming a pending payment
+        assert!(matches!(
+            dbm.store_paid_user(user_id, &user, &pending.invoice.payment_hash),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(dbm.load_user(user_id), None);
+
+        // The payment hash must match the one of the pending payment
+        dbm.store_pending_payment(user_id, &pending).unwrap();
+        assert!(matches!(
+            dbm.store_paid_user(user_id, &user, &PaymentHash([2; 32])),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(dbm.load_user(user_id), None);
+        assert_eq!(dbm.load_pending_payment(user_id), Some(pending.clone()));
+
+        // Redeeming the payment stores the user and clears the pending payment
+        dbm.store_paid_user(user_id, &user, &pending.invoice.payment_hash)
+            .unwrap();
+        assert_eq!(dbm.load_user(user_id), Some(user.clone()));
+        assert_eq!(dbm.load_pending_payment(user_id), None);
+
+        // A payment cannot be redeemed twice
+        assert!(matches!(
+            dbm.store_paid_user(user_id, &user, &pending.invoice.payment_hash),
+            Err(Error::NotFound)
+        ));
+
+        // Existing users are updated
+        dbm.store_pending_payment(user_id, &pending).unwrap();
+        let updated_user = UserInfo::new(42, 42, 200).with_plan("premium".to_owned());
+        dbm.store_paid_user(user_id, &updated_user, &pending.invoice.payment_hash)
+            .unwrap();
+        assert_eq!(dbm.load_user(user_id), Some(updated_user));
+    }
+
     #[test]
     fn test_batch_remove_users_cascade() {
         // Test that removing users cascade deleted appointments and trackers
@@ -964,7 +2669,7 @@ mod tests {
 
     #[test]
     fn test_get_appointments_trackers_count() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let n_users = 100;
         let n_app_per_user = 4;
         let n_trk_per_user = 6;
@@ -995,7 +2700,7 @@ mod tests {
 
     #[test]
     fn test_store_load_appointment() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         // In order to add an appointment we need the associated user to be present
         let user_id = get_random_user_id();
@@ -1019,7 +2724,7 @@ mod tests {
 
     #[test]
     fn test_store_appointment_missing_user() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let uuid = generate_uuid();
         let appointment = generate_dummy_appointment(None);
@@ -1041,7 +2746,7 @@ mod tests {
 
     #[test]
     fn test_appointment_exists() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1057,7 +2762,7 @@ mod tests {
 
     #[test]
     fn test_update_appointment() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1089,7 +2794,7 @@ mod tests {
 
     #[test]
     fn test_load_all_appointments() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let mut appointments = HashMap::new();
 
         for i in 1..11 {
@@ -1127,7 +2832,7 @@ mod tests {
 
     #[test]
     fn test_load_appointments_with_locator() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let mut appointments = HashMap::new();
         let dispute_tx = get_random_tx();
         let dispute_txid = dispute_tx.txid();
@@ -1180,7 +2885,7 @@ mod tests {
 
     #[test]
     fn test_get_appointment_length() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1198,7 +2903,7 @@ mod tests {
 
     #[test]
     fn test_get_appointment_user_and_length() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1251,7 +2956,7 @@ mod tests {
             // When the appointment are deleted, the user will get back slots based on the deleted data.
             // Here we can just make a number up to make sure it matches.
             user.available_slots = i as u32;
-            let updated_users = HashMap::from_iter([(user_id, user)]);
+            let updated_users = HashMap::from_iter([(user_id, user.clone())]);
 
             // Check that the db transaction had i queries on it
             assert_eq!(
@@ -1286,7 +2991,10 @@ mod tests {
             Ok { .. }
         ));
 
-        dbm.batch_remove_appointments(&[uuid], &HashMap::from_iter([(appointment.user_id, info)]));
+        dbm.batch_remove_appointments(
+            &[uuid],
+            &HashMap::from_iter([(appointment.user_id, info.clone())]),
+        );
         assert!(dbm.load_appointment(uuid).is_none());
 
         // Appointment + Tracker
@@ -1296,7 +3004,10 @@ mod tests {
         ));
         assert!(matches!(dbm.store_tracker(uuid, &tracker), Ok { .. }));
 
-        dbm.batch_remove_appointments(&[uuid], &HashMap::from_iter([(appointment.user_id, info)]));
+        dbm.batch_remove_appointments(
+            &[uuid],
+            &HashMap::from_iter([(appointment.user_id, info.clone())]),
+        );
         assert!(dbm.load_appointment(uuid).is_none());
         assert!(dbm.load_tracker(uuid).is_none());
     }
@@ -1312,7 +3023,7 @@ mod tests {
 
     #[test]
     fn test_load_uuids() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
         let dispute_tx = get_random_tx();
@@ -1350,7 +3061,7 @@ mod tests {
 
     #[test]
     fn test_batch_check_locators_exist() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         // Generate `n_app` appointments which we will store in the DB.
         let n_app = 100;
         let appointments: Vec<_> = (0..n_app)
@@ -1388,9 +3099,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_batch_check_locators_exist_after_removal() {
+        // The locator filter must be kept in sync with the appointments removed from the database
+        let mut dbm = DBM::in_memory().unwrap();
+        let info = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+
+        // Two users sharing the same locator, plus a third one with a different locator
+        let (uuid1, appointment1) =
+            generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let (_, mut appointment2) =
+            generate_dummy_appointment_with_user(get_random_user_id(), None);
+        appointment2.inner.locator = appointment1.locator();
+        let uuid2 = UUID::new(appointment2.locator(), appointment2.user_id);
+        let (uuid3, appointment3) =
+            generate_dummy_appointment_with_user(get_random_user_id(), None);
+        for (uuid, appointment) in [
+            (uuid1, &appointment1),
+            (uuid2, &appointment2),
+            (uuid3, &appointment3),
+        ] {
+            dbm.store_user(appointment.user_id, &info).unwrap();
+            dbm.store_appointment(uuid, appointment).unwrap();
+        }
+        let locator = appointment1.locator();
+        let locator3 = appointment3.locator();
+
+        // Removing one of the appointments sharing a locator keeps the locator around
+        dbm.batch_remove_appointments(&[uuid1], &HashMap::new());
+        assert_eq!(
+            dbm.batch_check_locators_exist(vec![&locator]),
+            vec![locator]
+        );
+
+        // Until the other one is gone too (here by deleting its user)
+        dbm.batch_remove_users(&[appointment2.user_id]);
+        assert!(dbm.batch_check_locators_exist(vec![&locator]).is_empty());
+
+        // Single removals are also tracked
+        assert_eq!(
+            dbm.batch_check_locators_exist(vec![&locator3]),
+            vec![locator3]
+        );
+        dbm.remove_appointment(uuid3);
+        assert!(dbm.batch_check_locators_exist(vec![&locator3]).is_empty());
+    }
+
+    #[test]
+    fn test_locator_filter_loaded_on_restart() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let db_path = tmp_path.path().join("teos_db.sql3");
+        let appointment = generate_dummy_appointment(None);
+
+        let mut dbm = DBM::new(db_path.clone()).unwrap();
+        let info = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &info).unwrap();
+        dbm.store_appointment(appointment.uuid(), &appointment)
+            .unwrap();
+        drop(dbm);
+
+        let dbm = DBM::new(db_path).unwrap();
+        assert_eq!(
+            dbm.batch_check_locators_exist(vec![&appointment.locator()]),
+            vec![appointment.locator()]
+        );
+    }
+
     #[test]
     fn test_store_load_tracker() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         // In order to add a tracker we need the associated appointment to be present (which
         // at the same time requires an associated user to be present)
@@ -1409,7 +3186,7 @@ mod tests {
 
     #[test]
     fn test_store_duplicate_tracker() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1447,7 +3224,7 @@ mod tests {
 
     #[test]
     fn test_update_tracker_status() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
 
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
@@ -1487,7 +3264,7 @@ mod tests {
 
     #[test]
     fn test_load_all_trackers() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let mut trackers = HashMap::new();
 
         for i in 1..11 {
@@ -1513,7 +3290,7 @@ mod tests {
 
     #[test]
     fn test_load_trackers_with_locator() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let mut trackers = HashMap::new();
         let dispute_tx = get_random_tx();
         let dispute_txid = dispute_tx.txid();
@@ -1549,7 +3326,7 @@ mod tests {
 
     #[test]
     fn test_load_trackers_with_confirmation_status_in_mempool() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let n_trackers = 100;
         let mut tracker_statuses = HashMap::new();
 
@@ -1603,7 +3380,7 @@ mod tests {
 
     #[test]
     fn test_load_trackers_with_confirmation_status_confirmed() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let n_blocks = 100;
         let n_trackers = 30;
         let mut tracker_statuses = HashMap::new();
@@ -1675,7 +3452,7 @@ mod tests {
 
     #[test]
     fn test_load_penalties_summaries() {
-        let dbm = DBM::in_memory().unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
         let n_trackers = 100;
         let mut penalties_summaries = HashMap::new();
 
@@ -1700,13 +3477,292 @@ mod tests {
             let tracker = get_random_tracker(user_id, status);
             dbm.store_tracker(uuid, &tracker).unwrap();
 
-            penalties_summaries
-                .insert(uuid, PenaltySummary::new(tracker.penalty_tx.txid(), status));
+            penalties_summaries.insert(
+                uuid,
+                PenaltySummary::new(tracker.penalty_tx.txid(), status, user_id),
+            );
         }
 
         assert_eq!(dbm.load_penalties_summaries(), penalties_summaries);
     }
 
+    #[test]
+    fn test_store_load_missed_breaches() {
+        let mut dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_missed_breaches().is_empty());
+
+        let appointment = generate_dummy_appointment(None);
+        let breaches = vec![
+            MissedBreach::new(
+                appointment.uuid(),
+                appointment.locator(),
+                appointment.user_id,
+                MissedBreachKind::Breached,
+                42,
+                Some(get_random_tx().txid()),
+            ),
+            MissedBreach::new(
+                appointment.uuid(),
+                appointment.locator(),
+                appointment.user_id,
+                MissedBreachKind::Unverified,
+                43,
+                None,
+            ),
+        ];
+        assert_eq!(dbm.store_missed_breaches(&breaches), 2);
+        assert_eq!(dbm.load_missed_breaches(), breaches);
+
+        // Storing the same findings again is a no-op
+        assert_eq!(dbm.store_missed_breaches(&breaches[1..]), 0);
+        assert_eq!(dbm.load_missed_breaches(), breaches);
+    }
+
+    #[test]
+    fn test_store_load_appointment_outcomes() {
+        let mut dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_appointment_outcomes(None).is_empty());
+
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        let appointment = generate_dummy_appointment(None);
+        let uuid = UUID::new(appointment.locator(), user_id);
+        let dispute_txid = get_random_tx().txid();
+        let outcomes = vec![
+            AppointmentOutcome::new(
+                uuid,
+                appointment.locator(),
+                user_id,
+                dispute_txid,
+                FailureKind::Decryption,
+                None,
+                42,
+            ),
+            AppointmentOutcome::new(
+                generate_uuid(),
+                get_random_locator(),
+                user_id,
+                get_random_tx().txid(),
+                FailureKind::Deserialization,
+                None,
+                43,
+            ),
+            AppointmentOutcome::new(
+                uuid,
+                appointment.locator(),
+                user_id,
+                dispute_txid,
+                FailureKind::Rejected,
+                Some(-26),
+                44,
+            ),
+        ];
+        dbm.store_appointment_outcomes(&outcomes);
+
+        assert_eq!(dbm.load_appointment_outcomes(None), outcomes);
+        assert_eq!(
+            dbm.load_appointment_outcomes(Some(uuid)),
+            vec![outcomes[0].clone(), outcomes[2].clone()]
+        );
+        assert!(dbm
+            .load_appointment_outcomes(Some(generate_uuid()))
+            .is_empty());
+
+        // Outcomes outlive the appointments, but not the user
+        dbm.batch_remove_users(&[user_id]);
+        assert!(dbm.load_appointment_outcomes(None).is_empty());
+    }
+
+    #[test]
+    fn test_store_load_backup() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        assert_eq!(dbm.load_backup(user_id), None);
+        assert_eq!(dbm.get_backup_version_and_length(user_id), None);
+
+        let backup = Backup::new(get_random_bytes(100), 1);
+        dbm.store_backup(user_id, &backup).unwrap();
+        assert_eq!(dbm.load_backup(user_id), Some(backup));
+        assert_eq!(dbm.get_backup_version_and_length(user_id), Some((1, 100)));
+
+        // Storing a new backup replaces the old one
+        let backup = Backup::new(get_random_bytes(50), 2);
+        dbm.store_backup(user_id, &backup).unwrap();
+        assert_eq!(dbm.load_backup(user_id), Some(backup));
+        assert_eq!(dbm.get_backup_version_and_length(user_id), Some((2, 50)));
+    }
+
+    #[test]
+    fn test_store_backup_missing_user() {
+        let dbm = DBM::in_memory().unwrap();
+        let backup = Backup::new(get_random_bytes(100), 1);
+
+        assert!(matches!(
+            dbm.store_backup(get_random_user_id(), &backup),
+            Err(Error::MissingForeignKey)
+        ));
+    }
+
+    #[test]
+    fn test_batch_remove_users_removes_backups() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        dbm.store_backup(user_id, &Backup::new(get_random_bytes(100), 1))
+            .unwrap();
+
+        dbm.batch_remove_users(&[user_id]);
+        assert_eq!(dbm.load_backup(user_id), None);
+    }
+
+    #[test]
+    fn test_rebroadcast_attempts() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let uuid = generate_uuid();
+        assert_eq!(dbm.load_rebroadcast_attempts(uuid), 0);
+
+        // Attempts can only be recorded for existing trackers
+        assert!(matches!(
+            dbm.increase_rebroadcast_attempts(uuid),
+            Err(Error::MissingForeignKey)
+        ));
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &user).unwrap();
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        dbm.store_tracker(
+            uuid,
+            &get_random_tracker(appointment.user_id, ConfirmationStatus::InMempoolSince(42)),
+        )
+        .unwrap();
+
+        for i in 1..=3 {
+            dbm.increase_rebroadcast_attempts(uuid).unwrap();
+            assert_eq!(dbm.load_rebroadcast_attempts(uuid), i);
+        }
+
+        // Attempts are removed alongside the tracker
+        dbm.remove_appointment(uuid);
+        assert_eq!(dbm.load_rebroadcast_attempts(uuid), 0);
+    }
+
+    #[test]
+    fn test_store_load_response_receipt() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &user).unwrap();
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        assert_eq!(dbm.load_response_receipt(uuid), None);
+
+        let (tower_sk, _) = get_random_keypair();
+        let mut receipt = get_random_response_receipt(tower_sk);
+        dbm.store_response_receipt(uuid, appointment.user_id, &receipt)
+            .unwrap();
+        assert_eq!(dbm.load_response_receipt(uuid), Some(receipt.clone()));
+        assert_eq!(
+            dbm.load_response_receipts(None),
+            HashMap::from_iter([(uuid, (appointment.user_id, receipt.clone()))])
+        );
+
+        // Storing a new receipt for the same appointment replaces the old one
+        receipt.set_confirmation_height(100);
+        receipt.sign(&tower_sk);
+        dbm.store_response_receipt(uuid, appointment.user_id, &receipt)
+            .unwrap();
+        assert_eq!(dbm.load_response_receipt(uuid), Some(receipt.clone()));
+
+        // Receipts outlive their appointment, but not their user
+        dbm.remove_appointment(uuid);
+        assert_eq!(dbm.load_response_receipt(uuid), Some(receipt));
+        dbm.batch_remove_users(&[appointment.user_id]);
+        assert_eq!(dbm.load_response_receipt(uuid), None);
+    }
+
+    #[test]
+    fn test_store_load_remove_headers() {
+        let dbm = DBM::in_memory().unwrap();
+        let chain = Blockchain::default().with_height(10);
+
+        for height in 1..=10 {
+            dbm.store_header(&chain.at_height(height)).unwrap();
+        }
+        // Storing the same header twice is fine
+        dbm.store_header(&chain.tip()).unwrap();
+
+        let mut headers = dbm.load_headers();
+        headers.sort_by_key(|h| h.height);
+        assert_eq!(
+            headers,
+            (1..=10).map(|h| chain.at_height(h)).collect::<Vec<_>>()
+        );
+
+        dbm.remove_header(&chain.tip().header.block_hash()).unwrap();
+        assert!(matches!(
+            dbm.remove_header(&chain.tip().header.block_hash()),
+            Err(Error::NotFound)
+        ));
+        dbm.remove_headers_up_to(5);
+
+        let mut headers = dbm.load_headers();
+        headers.sort_by_key(|h| h.height);
+        assert_eq!(
+            headers,
+            (6..10).map(|h| chain.at_height(h)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_store_load_remove_block_txids() {
+        let dbm = DBM::in_memory().unwrap();
+        let mut chain = Blockchain::default().with_height(10);
+
+        let mut blocks = Vec::new();
+        for height in 1..=10 {
+            let block = BlockTxids {
+                header: chain.at_height(height).header,
+                txids: (0..3).map(|_| get_random_tx().txid()).collect(),
+            };
+            dbm.store_block_txids(&block, height as u32).unwrap();
+            blocks.push(block);
+        }
+        // Blocks with no transactions can be stored as well
+        let empty_block = BlockTxids {
+            header: chain.generate(None).header,
+            txids: Vec::new(),
+        };
+        dbm.store_block_txids(&empty_block, 11).unwrap();
+
+        for block in blocks.iter().chain([&empty_block]) {
+            assert_eq!(
+                dbm.load_block_txids(&block.header.block_hash()),
+                Some(block.txids.clone())
+            );
+        }
+
+        let tip_hash = empty_block.header.block_hash();
+        dbm.remove_block_txids(&tip_hash).unwrap();
+        assert_eq!(dbm.load_block_txids(&tip_hash), None);
+        assert!(matches!(
+            dbm.remove_block_txids(&tip_hash),
+            Err(Error::NotFound)
+        ));
+
+        dbm.remove_block_txids_up_to(5);
+        for (height, block) in (1..=10).zip(blocks.iter()) {
+            assert_eq!(
+                dbm.load_block_txids(&block.header.block_hash()).is_some(),
+                height > 5
+            );
+        }
+    }
+
     #[test]
     fn test_store_load_last_known_block() {
         let dbm = DBM::in_memory().unwrap();
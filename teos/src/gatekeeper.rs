@@ -1,21 +1,55 @@
 //! Logic related to the Gatekeeper, the component in charge of managing access to the tower resources.
 
 use lightning::chain;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicU32, Ordering};
+use lightning::ln::PaymentHash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
 use teos_common::appointment::{compute_appointment_slots, Locator};
+use teos_common::backup::Backup;
 use teos_common::constants::ENCRYPTED_BLOB_MAX_SIZE;
 use teos_common::cryptography;
 use teos_common::receipts::RegistrationReceipt;
 use teos_common::UserId;
 
-use crate::dbm::DBM;
+use crate::dbm::{DBReader, DBM};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
 
+/// Name of the plan built from the tower `subscription_slots` and `subscription_duration`. Users that do not pick a
+/// plan when registering are subscribed to this one.
+pub const DEFAULT_PLAN: &str = "default";
+
+/// A subscription plan offered by the tower.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionPlan {
+    /// Number of slots a subscription to the plan is given.
+    pub slots: u32,
+    /// Duration of a subscription to the plan, in blocks.
+    pub duration: u32,
+    /// Maximum size of the appointment blobs accepted under the plan, in bytes. Zero means there is no limit.
+    #[serde(default)]
+    pub max_blob_size: u32,
+    /// Price of a subscription to the plan, in millisatoshis.
+    #[serde(default)]
+    pub price_msat: u64,
+}
+
+impl SubscriptionPlan {
+    /// Creates a new [SubscriptionPlan] instance.
+    pub fn new(slots: u32, duration: u32, max_blob_size: u32, price_msat: u64) -> Self {
+        SubscriptionPlan {
+            slots,
+            duration,
+            max_blob_size,
+            price_msat,
+        }
+    }
+}
+
 /// Data regarding a user subscription with the tower.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct UserInfo {
     /// Number of appointment slots available for a given user.
     pub(crate) available_slots: u32,
@@ -23,17 +57,26 @@ pub(crate) struct UserInfo {
     pub(crate) subscription_start: u32,
     /// Block height where the user subscription expires.
     pub(crate) subscription_expiry: u32,
+    /// Name of the plan the user is subscribed to.
+    pub(crate) plan: String,
 }
 
 impl UserInfo {
-    /// Creates a new [UserInfo] instance.
+    /// Creates a new [UserInfo] instance subscribed to the [DEFAULT_PLAN].
     pub fn new(available_slots: u32, subscription_start: u32, subscription_expiry: u32) -> Self {
         UserInfo {
             available_slots,
             subscription_start,
             subscription_expiry,
+            plan: DEFAULT_PLAN.to_owned(),
         }
     }
+
+    /// Sets the plan the user is subscribed to.
+    pub fn with_plan(mut self, plan: String) -> Self {
+        self.plan = plan;
+        self
+    }
 }
 
 /// Error raised if the user cannot be authenticated.
@@ -44,11 +87,24 @@ pub(crate) struct AuthenticationFailure<'a>(&'a str);
 #[derive(Debug, PartialEq)]
 pub(crate) struct NotEnoughSlots;
 
-/// Error raised if the user subscription slots limit has been reached.
-///
-/// This is currently set to [u32::MAX].
-#[derive(Debug, PartialEq)]
-pub(crate) struct MaxSlotsReached;
+/// Packs the reasons why a user may not be able to register with the tower (or renew their subscription).
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RegistrationFailure {
+    /// The user subscription slots limit has been reached. This is currently set to [u32::MAX].
+    MaxSlotsReached,
+    /// The tower is running in private mode and the user is not in the allow-list.
+    NotAllowed,
+    /// The user has been banned from the tower.
+    Banned,
+    /// The requested subscription plan is not offered by the tower.
+    UnknownPlan,
+    /// The tower requires a proof of work to register and the user did not provide a valid one.
+    InvalidProofOfWork,
+    /// The tower requires a registration token to register and the user did not provide a valid one.
+    InvalidToken,
+    /// The payment the user is registering with cannot be found (e.g. it has already been redeemed).
+    PaymentNotFound,
+}
 
 /// Component in charge of managing access to the tower resources.
 ///
@@ -57,42 +113,90 @@ pub(crate) struct MaxSlotsReached;
 /// available slots.
 /// This is the only component in the system that has some knowledge regarding users, all other components do query the
 /// [Gatekeeper] for such information.
+///
+/// Towers can also be run in private mode, in which case only the users in the allow-list (managed by the tower admin)
+/// can register. Banned users are refused no matter the mode.
 #[derive(Debug)]
 pub struct Gatekeeper {
     /// last known block header by the [Gatekeeper].
     last_known_block_height: AtomicU32,
-    /// Number of slots new subscriptions get by default.
-    subscription_slots: u32,
-    /// Expiry time new subscription get by default, in blocks (starting from the block the subscription is requested).
-    subscription_duration: u32,
+    /// Subscription plans offered by the tower, by name. Always contains the [DEFAULT_PLAN].
+    plans: BTreeMap<String, SubscriptionPlan>,
     /// Grace period given to renew subscriptions, in blocks.
     expiry_delta: u32,
+    /// Whether only the users in the allow-list can register with the tower.
+    private_mode: bool,
     /// Map of users registered within the tower.
     registered_users: Mutex<HashMap<UserId, UserInfo>>,
+    /// The users allowed to register with the tower when running in private mode.
+    allowed_users: Mutex<HashSet<UserId>>,
+    /// The users banned from the tower.
+    banned_users: Mutex<HashSet<UserId>>,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
     dbm: Arc<Mutex<DBM>>,
+    /// A pool of read-only connections to the database. Used by read-only paths so they don't contend with the [DBM].
+    db_reader: DBReader,
+    /// Whether the tower is a standby following its primary. Standbys leave all deletions to the primary.
+    standby: AtomicBool,
 }
 
 impl Gatekeeper {
     /// Creates a new [Gatekeeper] instance.
+    ///
+    /// `subscription_slots` and `subscription_duration` define the [DEFAULT_PLAN], which is offered alongside `plans`.
     pub fn new(
         last_known_block_height: u32,
         subscription_slots: u32,
         subscription_duration: u32,
         expiry_delta: u32,
+        mut plans: BTreeMap<String, SubscriptionPlan>,
+        private_mode: bool,
         dbm: Arc<Mutex<DBM>>,
     ) -> Self {
-        let registered_users = dbm.lock().unwrap().load_all_users();
+        plans.insert(
+            DEFAULT_PLAN.to_owned(),
+            SubscriptionPlan::new(subscription_slots, subscription_duration, 0, 0),
+        );
+        let (registered_users, allowed_users, banned_users, db_reader) = {
+            let dbm = dbm.lock().unwrap();
+            (
+                dbm.load_all_users(),
+                dbm.load_allowed_users(),
+                dbm.load_banned_users(),
+                dbm.reader(),
+            )
+        };
         Gatekeeper {
             last_known_block_height: AtomicU32::new(last_known_block_height),
-            subscription_slots,
-            subscription_duration,
+            plans,
             expiry_delta,
+            private_mode,
             registered_users: Mutex::new(registered_users),
+            allowed_users: Mutex::new(allowed_users),
+            banned_users: Mutex::new(banned_users),
             dbm,
+            db_reader,
+            standby: AtomicBool::new(false),
         }
     }
 
+    /// Reloads the users (including the allow-list and the ban list) from the database. Used by standby towers when
+    /// taking over, given their database has been updated by the primary in the meantime.
+    pub fn reload(&self) {
+        let dbm = self.dbm.lock().unwrap();
+        *self.registered_users.lock().unwrap() = dbm.load_all_users();
+        *self.allowed_users.lock().unwrap() = dbm.load_allowed_users();
+        *self.banned_users.lock().unwrap() = dbm.load_banned_users();
+    }
+
+    /// Sets whether the tower is a standby following its primary.
+    ///
+    /// Standbys neither expire users nor delete appointments on their own. Their in-memory users are only loaded when
+    /// taking over, so they may be outdated until then, and the primary replicates its own deletions anyway.
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Release)
+    }
+
     /// Returns whether the [Gatekeeper] has been created from scratch (fresh) or from backed-up data.
     pub fn is_fresh(&self) -> bool {
         self.registered_users.lock().unwrap().is_empty()
@@ -103,6 +207,11 @@ impl Gatekeeper {
         self.registered_users.lock().unwrap().len()
     }
 
+    /// Checks whether a user is registered with the tower.
+    pub(crate) fn is_registered(&self, user_id: UserId) -> bool {
+        self.registered_users.lock().unwrap().contains_key(&user_id)
+    }
+
     /// Gets the list of all registered user ids.
     pub(crate) fn get_user_ids(&self) -> Vec<UserId> {
         self.registered_users
@@ -113,10 +222,29 @@ impl Gatekeeper {
             .collect()
     }
 
+    /// Gets the subscription plans offered by the tower, sorted by name.
+    pub(crate) fn get_subscription_plans(&self) -> Vec<(String, SubscriptionPlan)> {
+        self.plans
+            .iter()
+            .map(|(name, plan)| (name.clone(), plan.clone()))
+            .collect()
+    }
+
+    /// Gets the maximum appointment blob size allowed by the plan of a given user, if limited.
+    pub(crate) fn get_max_blob_size(&self, user_id: UserId) -> Option<u32> {
+        let registered_users = self.registered_users.lock().unwrap();
+        let plan = self.plans.get(&registered_users.get(&user_id)?.plan)?;
+        if plan.max_blob_size > 0 {
+            Some(plan.max_blob_size)
+        } else {
+            None
+        }
+    }
+
     /// Gets the data held by the tower about a given user.
     pub(crate) fn get_user_info(&self, user_id: UserId) -> Option<(UserInfo, Vec<Locator>)> {
         let info = self.registered_users.lock().unwrap().get(&user_id).cloned();
-        info.map(|info| (info, self.dbm.lock().unwrap().load_user_locators(user_id)))
+        info.map(|info| (info, self.db_reader.get().load_user_locators(user_id)))
     }
 
     /// Authenticates a user.
@@ -133,53 +261,118 @@ impl Gatekeeper {
                 .map_err(|_| AuthenticationFailure("Wrong message or signature."))?,
         );
 
-        if self.registered_users.lock().unwrap().contains_key(&user_id) {
+        if !self.registered_users.lock().unwrap().contains_key(&user_id) {
+            Err(AuthenticationFailure("User not found."))
+        } else if let Err(e) = self.check_access(user_id) {
+            log::info!("Refusing request from {user_id}: {e:?}");
+            Err(AuthenticationFailure("User not allowed."))
+        } else {
             Ok(user_id)
+        }
+    }
+
+    /// Checks whether a user is allowed to interact with the tower, that is, the user is not banned and, if the tower
+    /// is running in private mode, the user is in the allow-list.
+    fn check_access(&self, user_id: UserId) -> Result<(), RegistrationFailure> {
+        if self.banned_users.lock().unwrap().contains(&user_id) {
+            Err(RegistrationFailure::Banned)
+        } else if self.private_mode && !self.allowed_users.lock().unwrap().contains(&user_id) {
+            Err(RegistrationFailure::NotAllowed)
         } else {
-            Err(AuthenticationFailure("User not found."))
+            Ok(())
         }
     }
 
-    /// Adds a new user to the tower (or updates its subscription if already registered).
+    /// Gets the plan a user is asking to register for (alongside its name), provided the user is allowed to register.
+    ///
+    /// An empty `plan` name stands for the [DEFAULT_PLAN].
+    pub(crate) fn get_registration_plan(
+        &self,
+        user_id: UserId,
+        plan: &str,
+    ) -> Result<(String, SubscriptionPlan), RegistrationFailure> {
+        self.check_access(user_id)?;
+        let plan_name = if plan.is_empty() { DEFAULT_PLAN } else { plan };
+        self.plans
+            .get(plan_name)
+            .map(|plan| (plan_name.to_owned(), plan.clone()))
+            .ok_or(RegistrationFailure::UnknownPlan)
+    }
+
+    /// Adds a new user to the tower (or updates its subscription if already registered) under a given plan.
+    ///
+    /// An empty `plan` name stands for the [DEFAULT_PLAN].
     pub(crate) fn add_update_user(
         &self,
         user_id: UserId,
-    ) -> Result<RegistrationReceipt, MaxSlotsReached> {
+        plan: &str,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        self.add_update_user_with_payment(user_id, plan, None)
+    }
+
+    /// Same as [Gatekeeper::add_update_user], but for users that have paid for the plan. The pending payment identified
+    /// by `payment_hash` is redeemed alongside the subscription update, so a payment cannot activate the
+    /// subscription twice.
+    pub(crate) fn add_update_paid_user(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        payment_hash: &PaymentHash,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        self.add_update_user_with_payment(user_id, plan, Some(payment_hash))
+    }
+
+    fn add_update_user_with_payment(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        payment_hash: Option<&PaymentHash>,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        let (plan_name, plan) = self.get_registration_plan(user_id, plan)?;
         let block_count = self.last_known_block_height.load(Ordering::Acquire);
 
-        // TODO: For now, new calls to `add_update_user` add subscription_slots to the current count and reset the expiry time
+        // TODO: For now, new calls to `add_update_user` add the plan slots to the current count and reset the expiry time.
+        // The user is moved to the latest plan they registered for.
         let mut registered_users = self.registered_users.lock().unwrap();
-        let user_info = match registered_users.get_mut(&user_id) {
+        let (user_info, is_new) = match registered_users.get(&user_id) {
             // User already exists, updating the info
             Some(user_info) => {
+                let mut user_info = user_info.clone();
                 user_info.available_slots = user_info
                     .available_slots
-                    .checked_add(self.subscription_slots)
-                    .ok_or(MaxSlotsReached)?;
+                    .checked_add(plan.slots)
+                    .ok_or(RegistrationFailure::MaxSlotsReached)?;
                 user_info.subscription_expiry = user_info
                     .subscription_expiry
-                    .checked_add(self.subscription_duration)
+                    .checked_add(plan.duration)
                     .unwrap_or(u32::MAX);
-                self.dbm.lock().unwrap().update_user(user_id, user_info);
-
-                user_info
+                user_info.plan = plan_name;
+                (user_info, false)
             }
             // New user
-            None => {
-                let user_info = UserInfo::new(
-                    self.subscription_slots,
-                    block_count,
-                    block_count + self.subscription_duration,
-                );
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .store_user(user_id, &user_info)
-                    .unwrap();
+            None => (
+                UserInfo::new(plan.slots, block_count, block_count + plan.duration)
+                    .with_plan(plan_name),
+                true,
+            ),
+        };
 
-                registered_users.insert(user_id, user_info);
-                registered_users.get_mut(&user_id).unwrap()
-            }
+        let mut dbm = self.dbm.lock().unwrap();
+        match payment_hash {
+            Some(payment_hash) => dbm
+                .store_paid_user(user_id, &user_info, payment_hash)
+                .map_err(|_| RegistrationFailure::PaymentNotFound)?,
+            None if is_new => dbm.store_user(user_id, &user_info).unwrap(),
+            None => dbm.update_user(user_id, &user_info),
+        }
+        registered_users.insert(user_id, user_info);
+        let user_info = &registered_users[&user_id];
+
+        // Default plan receipts commit to no plan so they serialize exactly as they did before plans were introduced.
+        let receipt_plan = if user_info.plan == DEFAULT_PLAN {
+            String::new()
+        } else {
+            user_info.plan.clone()
         };
 
         Ok(RegistrationReceipt::new(
@@ -187,9 +380,108 @@ impl Gatekeeper {
             user_info.available_slots,
             user_info.subscription_start,
             user_info.subscription_expiry,
+            receipt_plan,
         ))
     }
 
+    /// Adds a user to the allow-list. Only meaningful if the tower is running in private mode.
+    pub(crate) fn add_allowed_user(&self, user_id: UserId) {
+        let mut allowed_users = self.allowed_users.lock().unwrap();
+        self.dbm
+            .lock()
+            .unwrap()
+            .store_allowed_user(user_id)
+            .unwrap();
+        allowed_users.insert(user_id);
+    }
+
+    /// Removes a user from the allow-list. Returns whether the user was in the list.
+    ///
+    /// The user data is kept, but the user will not be able to interact with the tower if running in private mode.
+    pub(crate) fn remove_allowed_user(&self, user_id: UserId) -> bool {
+        let mut allowed_users = self.allowed_users.lock().unwrap();
+        if allowed_users.remove(&user_id) {
+            self.dbm
+                .lock()
+                .unwrap()
+                .remove_allowed_user(user_id)
+                .unwrap();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gets the list of all allowed user ids.
+    pub(crate) fn get_allowed_users(&self) -> Vec<UserId> {
+        self.allowed_users.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Extends the subscription of a registered user by a given number of blocks.
+    ///
+    /// Returns the updated [UserInfo], or [None] if the user cannot be found.
+    pub(crate) fn extend_subscription(&self, user_id: UserId, blocks: u32) -> Option<UserInfo> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        let user_info = registered_users.get_mut(&user_id)?;
+        user_info.subscription_expiry = user_info.subscription_expiry.saturating_add(blocks);
+        self.dbm.lock().unwrap().update_user(user_id, user_info);
+
+        Some(user_info.clone())
+    }
+
+    /// Sets the number of available slots of a registered user.
+    ///
+    /// Returns the updated [UserInfo], or [None] if the user cannot be found.
+    pub(crate) fn set_available_slots(&self, user_id: UserId, slots: u32) -> Option<UserInfo> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        let user_info = registered_users.get_mut(&user_id)?;
+        user_info.available_slots = slots;
+        self.dbm.lock().unwrap().update_user(user_id, user_info);
+
+        Some(user_info.clone())
+    }
+
+    /// Bans a user from the tower. Banned users cannot register nor interact with the tower, and their data is deleted.
+    pub(crate) fn ban_user(&self, user_id: UserId) {
+        self.banned_users.lock().unwrap().insert(user_id);
+        self.dbm.lock().unwrap().store_banned_user(user_id).unwrap();
+        self.delete_user(user_id);
+    }
+
+    /// Lifts the ban of a user. Returns whether the user was banned.
+    pub(crate) fn unban_user(&self, user_id: UserId) -> bool {
+        let mut banned_users = self.banned_users.lock().unwrap();
+        if banned_users.remove(&user_id) {
+            self.dbm
+                .lock()
+                .unwrap()
+                .remove_banned_user(user_id)
+                .unwrap();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gets the list of all banned user ids.
+    pub(crate) fn get_banned_users(&self) -> Vec<UserId> {
+        self.banned_users.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Deletes a user alongside all their data (appointments, trackers and backups). Returns whether the user was
+    /// registered.
+    ///
+    /// The user is free to register again afterwards (modulo being banned or not allowed).
+    pub(crate) fn delete_user(&self, user_id: UserId) -> bool {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        if registered_users.remove(&user_id).is_some() {
+            self.dbm.lock().unwrap().batch_remove_users(&[user_id]);
+            true
+        } else {
+            false
+        }
+    }
+
     /// Adds an appointment to a given user, or updates it if already present in the system (and belonging to the requester).
     pub(crate) fn add_update_appointment(
         &self,
@@ -225,6 +517,37 @@ impl Gatekeeper {
         }
     }
 
+    /// Adds a backup to a given user, or updates it if the user already has one.
+    ///
+    /// Backups take slots the same way appointments do, so updates only fill (or free) the difference with the old backup.
+    pub(crate) fn add_update_backup(
+        &self,
+        user_id: UserId,
+        backup: &Backup,
+    ) -> Result<u32, NotEnoughSlots> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        let user_info = registered_users.get_mut(&user_id).unwrap();
+        let used_size = self
+            .dbm
+            .lock()
+            .unwrap()
+            .get_backup_version_and_length(user_id)
+            .map_or(0, |(_, length)| length);
+        let used_slots = compute_appointment_slots(used_size, ENCRYPTED_BLOB_MAX_SIZE);
+        let required_slots = compute_appointment_slots(backup.data.len(), ENCRYPTED_BLOB_MAX_SIZE);
+
+        let diff = required_slots as i64 - used_slots as i64;
+        if diff <= user_info.available_slots as i64 {
+            user_info.available_slots = (user_info.available_slots as i64 - diff) as u32;
+
+            self.dbm.lock().unwrap().update_user(user_id, user_info);
+
+            Ok(user_info.available_slots)
+        } else {
+            Err(NotEnoughSlots)
+        }
+    }
+
     /// Checks whether a subscription has expired.
     pub(crate) fn has_subscription_expired(
         &self,
@@ -262,7 +585,12 @@ impl Gatekeeper {
     ///
     /// DISCUSS: When `refund` is `false` we don't give back the slots to the user for the deleted appointments.
     /// This is to discourage misbehavior (sending bad appointments, either non-decryptable or rejected by the network).
+    ///
+    /// Nothing is deleted while the tower is a standby (see [Gatekeeper::set_standby]).
     pub(crate) fn delete_appointments(&self, appointments: Vec<UUID>, refund: bool) {
+        if self.standby.load(Ordering::Acquire) {
+            return;
+        }
         let mut dbm = self.dbm.lock().unwrap();
 
         let updated_users = if refund {
@@ -273,7 +601,7 @@ impl Gatekeeper {
                 let (user_id, blob_size) = dbm.get_appointment_user_and_length(*uuid).unwrap();
                 registered_users.get_mut(&user_id).unwrap().available_slots +=
                     compute_appointment_slots(blob_size, ENCRYPTED_BLOB_MAX_SIZE);
-                updated_users.insert(user_id, registered_users[&user_id]);
+                updated_users.insert(user_id, registered_users[&user_id].clone());
             }
             updated_users
         } else {
@@ -304,7 +632,12 @@ impl chain::Listen for Gatekeeper {
         log::info!("New block received: {}", header.block_hash());
 
         // Expired user deletion is delayed. Users are deleted when their subscription is outdated, not expired.
-        let outdated_users = self.get_outdated_users(height);
+        // Standbys leave it to their primary, given they may not know about the latest renewals.
+        let outdated_users = if self.standby.load(Ordering::Acquire) {
+            Vec::new()
+        } else {
+            self.get_outdated_users(height)
+        };
         if !outdated_users.is_empty() {
             // Remove the outdated users from memory first.
             {
@@ -350,10 +683,12 @@ mod tests {
 
     impl PartialEq for Gatekeeper {
         fn eq(&self, other: &Self) -> bool {
-            self.subscription_slots == other.subscription_slots
-                && self.subscription_duration == other.subscription_duration
+            self.plans == other.plans
                 && self.expiry_delta == other.expiry_delta
+                && self.private_mode == other.private_mode
                 && *self.registered_users.lock().unwrap() == *other.registered_users.lock().unwrap()
+                && *self.allowed_users.lock().unwrap() == *other.allowed_users.lock().unwrap()
+                && *self.banned_users.lock().unwrap() == *other.banned_users.lock().unwrap()
                 && self.last_known_block_height.load(Ordering::Relaxed)
                     == other.last_known_block_height.load(Ordering::Relaxed)
         }
@@ -366,7 +701,7 @@ mod tests {
         }
 
         pub(crate) fn add_outdated_user(&self, user_id: UserId, outdates_at: u32) {
-            self.add_update_user(user_id).unwrap();
+            self.add_update_user(user_id, DEFAULT_PLAN).unwrap();
             let mut registered_users = self.registered_users.lock().unwrap();
             let user = registered_users.get_mut(&user_id).unwrap();
             user.subscription_expiry = outdates_at - self.expiry_delta;
@@ -375,7 +710,15 @@ mod tests {
 
     fn init_gatekeeper(chain: &Blockchain) -> Gatekeeper {
         let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
-        Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm)
+        Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
+            dbm,
+        )
     }
 
     #[test]
@@ -389,6 +732,8 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
             dbm.clone(),
         );
         assert!(gatekeeper.is_fresh());
@@ -397,7 +742,7 @@ mod tests {
         // (as if simulating a bootstrap from existing data), the data should be properly loaded.
         for _ in 0..10 {
             let user_id = get_random_user_id();
-            gatekeeper.add_update_user(user_id).unwrap();
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
 
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
             gatekeeper
@@ -413,12 +758,51 @@ mod tests {
         }
 
         // Create a new GK reusing the same DB and check that the data is loaded
-        let another_gk =
-            Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm);
+        let another_gk = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
+            dbm,
+        );
         assert!(!another_gk.is_fresh());
         assert_eq!(gatekeeper, another_gk);
     }
 
+    #[test]
+    fn test_reload() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        // Users added to the database behind the back of the Gatekeeper are not known until it is reloaded
+        let (user_id, allowed_id, banned_id) = (
+            get_random_user_id(),
+            get_random_user_id(),
+            get_random_user_id(),
+        );
+        {
+            let dbm = gatekeeper.dbm.lock().unwrap();
+            dbm.store_user(
+                user_id,
+                &UserInfo::new(SLOTS, START_HEIGHT as u32, START_HEIGHT as u32 + DURATION),
+            )
+            .unwrap();
+            dbm.store_allowed_user(allowed_id).unwrap();
+            dbm.store_banned_user(banned_id).unwrap();
+        }
+        assert!(!gatekeeper.is_registered(user_id));
+
+        gatekeeper.reload();
+        assert!(gatekeeper.is_registered(user_id));
+        assert!(gatekeeper
+            .allowed_users
+            .lock()
+            .unwrap()
+            .contains(&allowed_id));
+        assert!(gatekeeper.banned_users.lock().unwrap().contains(&banned_id));
+    }
+
     #[test]
     fn test_authenticate_user() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -443,7 +827,7 @@ mod tests {
 
         // Last, let's add the user to the Gatekeeper and try again.
         let user_id = UserId(user_pk);
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
         assert_eq!(
             gatekeeper.authenticate_user(message, &signature),
             Ok(user_id)
@@ -460,7 +844,7 @@ mod tests {
 
         // Let's start by adding new user
         let user_id = get_random_user_id();
-        let receipt = gatekeeper.add_update_user(user_id).unwrap();
+        let receipt = gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
         // The data should have been also added to the database
         assert_eq!(
             gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
@@ -476,7 +860,7 @@ mod tests {
         gatekeeper
             .last_known_block_height
             .store(chain.get_block_count(), Ordering::Relaxed);
-        let updated_receipt = gatekeeper.add_update_user(user_id).unwrap();
+        let updated_receipt = gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
 
         assert_eq!(updated_receipt.available_slots(), SLOTS * 2);
         assert_eq!(
@@ -504,8 +888,8 @@ mod tests {
             .available_slots = u32::MAX;
 
         assert!(matches!(
-            gatekeeper.add_update_user(user_id),
-            Err(MaxSlotsReached)
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN),
+            Err(RegistrationFailure::MaxSlotsReached)
         ));
 
         // Data in the database remains untouched
@@ -519,6 +903,339 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_add_update_user_default_plan_receipt() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let gatekeeper = init_gatekeeper(&chain);
+
+        // Default plan receipts commit to no plan, so they serialize as they did before plans were introduced:
+        // user_id | available_slots | subscription_start | subscription_expiry
+        let user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(user_id, "").unwrap();
+        assert_eq!(receipt.plan(), "");
+
+        let mut baseline = user_id.to_vec();
+        baseline.extend_from_slice(&SLOTS.to_be_bytes());
+        baseline.extend_from_slice(&(START_HEIGHT as u32).to_be_bytes());
+        baseline.extend_from_slice(&(START_HEIGHT as u32 + DURATION).to_be_bytes());
+        assert_eq!(receipt.to_vec(), baseline);
+
+        // Naming the default plan explicitly makes no difference
+        let user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        assert_eq!(receipt.plan(), "");
+        assert_eq!(receipt.to_vec().len(), baseline.len());
+    }
+
+    #[test]
+    fn test_add_update_user_with_plan() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let premium = SubscriptionPlan::new(SLOTS * 10, DURATION * 2, 1024, 1000);
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::from([("premium".to_owned(), premium.clone())]),
+            false,
+            dbm,
+        );
+
+        // Users can only register for plans offered by the tower
+        let user_id = get_random_user_id();
+        assert_eq!(
+            gatekeeper.add_update_user(user_id, "gold"),
+            Err(RegistrationFailure::UnknownPlan)
+        );
+        assert!(gatekeeper.get_user_info(user_id).is_none());
+
+        // Registering for a plan sets the plan slots and duration, and the plan is committed in the receipt
+        let receipt = gatekeeper.add_update_user(user_id, "premium").unwrap();
+        assert_eq!(receipt.available_slots(), premium.slots);
+        assert_eq!(
+            receipt.subscription_expiry(),
+            START_HEIGHT as u32 + premium.duration
+        );
+        assert_eq!(receipt.plan(), "premium");
+        assert_eq!(
+            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            UserInfo::new(
+                premium.slots,
+                receipt.subscription_start(),
+                receipt.subscription_expiry()
+            )
+            .with_plan("premium".to_owned())
+        );
+
+        // Re-registering under a different plan adds up the new plan slots and duration, and moves the user to it.
+        // An empty plan name stands for the default plan.
+        let receipt = gatekeeper.add_update_user(user_id, "").unwrap();
+        assert_eq!(receipt.available_slots(), premium.slots + SLOTS);
+        assert_eq!(
+            receipt.subscription_expiry(),
+            START_HEIGHT as u32 + premium.duration + DURATION
+        );
+        assert_eq!(receipt.plan(), "");
+        assert_eq!(
+            gatekeeper
+                .dbm
+                .lock()
+                .unwrap()
+                .load_user(user_id)
+                .unwrap()
+                .plan,
+            DEFAULT_PLAN
+        );
+    }
+
+    #[test]
+    fn test_get_subscription_plans() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let premium = SubscriptionPlan::new(SLOTS * 10, DURATION * 2, 1024, 1000);
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::from([("premium".to_owned(), premium.clone())]),
+            false,
+            dbm,
+        );
+
+        // The default plan is always offered, and plans are sorted by name
+        assert_eq!(
+            gatekeeper.get_subscription_plans(),
+            vec![
+                (
+                    DEFAULT_PLAN.to_owned(),
+                    SubscriptionPlan::new(SLOTS, DURATION, 0, 0)
+                ),
+                ("premium".to_owned(), premium)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_max_blob_size() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::from([(
+                "small".to_owned(),
+                SubscriptionPlan::new(SLOTS, DURATION, 512, 0),
+            )]),
+            false,
+            dbm,
+        );
+
+        // Unknown users have no limit
+        let user_id = get_random_user_id();
+        assert_eq!(gatekeeper.get_max_blob_size(user_id), None);
+
+        // Neither have users of plans with no max_blob_size
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        assert_eq!(gatekeeper.get_max_blob_size(user_id), None);
+
+        gatekeeper.add_update_user(user_id, "small").unwrap();
+        assert_eq!(gatekeeper.get_max_blob_size(user_id), Some(512));
+    }
+
+    #[test]
+    fn test_add_update_user_private_mode() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            true,
+            dbm,
+        );
+
+        // If the tower is running in private mode, only users in the allow-list can register
+        let user_id = get_random_user_id();
+        assert!(matches!(
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN),
+            Err(RegistrationFailure::NotAllowed)
+        ));
+        assert!(gatekeeper.get_user_info(user_id).is_none());
+
+        gatekeeper.add_allowed_user(user_id);
+        assert_eq!(gatekeeper.get_allowed_users(), vec![user_id]);
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        assert!(gatekeeper.get_user_info(user_id).is_some());
+    }
+
+    #[test]
+    fn test_allowed_users() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            true,
+            dbm.clone(),
+        );
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        gatekeeper.add_allowed_user(user_id);
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+
+        let message = "message".as_bytes();
+        let signature = cryptography::sign(message, &user_sk).unwrap();
+        assert_eq!(
+            gatekeeper.authenticate_user(message, &signature),
+            Ok(user_id)
+        );
+
+        // The allow-list is persisted, so a new Gatekeeper reusing the same db should load it
+        let another_gk = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            true,
+            dbm.clone(),
+        );
+        assert_eq!(gatekeeper, another_gk);
+
+        // Once removed from the list, the user data is kept but the user can no longer interact with the tower
+        assert!(gatekeeper.remove_allowed_user(user_id));
+        assert!(!gatekeeper.remove_allowed_user(user_id));
+        assert!(gatekeeper.get_allowed_users().is_empty());
+        assert!(dbm.lock().unwrap().load_allowed_users().is_empty());
+        assert!(gatekeeper.get_user_info(user_id).is_some());
+        assert_eq!(
+            gatekeeper.authenticate_user(message, &signature),
+            Err(AuthenticationFailure("User not allowed."))
+        );
+    }
+
+    #[test]
+    fn test_ban_unban_user() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
+            dbm.clone(),
+        );
+
+        // Banning a user deletes all their data and prevents them from registering again
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        gatekeeper
+            .add_update_appointment(user_id, uuid, &appointment)
+            .unwrap();
+        dbm.lock()
+            .unwrap()
+            .store_appointment(uuid, &appointment)
+            .unwrap();
+
+        gatekeeper.ban_user(user_id);
+        assert_eq!(gatekeeper.get_banned_users(), vec![user_id]);
+        assert!(gatekeeper.get_user_info(user_id).is_none());
+        assert!(dbm.lock().unwrap().load_user(user_id).is_none());
+        assert!(dbm.lock().unwrap().load_appointment(uuid).is_none());
+        assert!(matches!(
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN),
+            Err(RegistrationFailure::Banned)
+        ));
+
+        // Bans are persisted
+        let another_gk = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
+            dbm.clone(),
+        );
+        assert_eq!(gatekeeper, another_gk);
+
+        // Once unbanned, the user can register again
+        assert!(gatekeeper.unban_user(user_id));
+        assert!(!gatekeeper.unban_user(user_id));
+        assert!(gatekeeper.get_banned_users().is_empty());
+        assert!(dbm.lock().unwrap().load_banned_users().is_empty());
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+    }
+
+    #[test]
+    fn test_extend_subscription() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        // Unknown users cannot be extended
+        let user_id = get_random_user_id();
+        assert!(gatekeeper.extend_subscription(user_id, 10).is_none());
+
+        let receipt = gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        let user_info = gatekeeper.extend_subscription(user_id, 10).unwrap();
+        assert_eq!(
+            user_info.subscription_expiry,
+            receipt.subscription_expiry() + 10
+        );
+        assert_eq!(
+            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            user_info
+        );
+
+        // The expiry saturates instead of overflowing
+        let user_info = gatekeeper.extend_subscription(user_id, u32::MAX).unwrap();
+        assert_eq!(user_info.subscription_expiry, u32::MAX);
+    }
+
+    #[test]
+    fn test_set_available_slots() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        let user_id = get_random_user_id();
+        assert!(gatekeeper.set_available_slots(user_id, 42).is_none());
+
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        let user_info = gatekeeper.set_available_slots(user_id, 42).unwrap();
+        assert_eq!(user_info.available_slots, 42);
+        assert_eq!(
+            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            user_info
+        );
+    }
+
+    #[test]
+    fn test_delete_user() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        let user_id = get_random_user_id();
+        assert!(!gatekeeper.delete_user(user_id));
+
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        assert!(gatekeeper.delete_user(user_id));
+        assert!(gatekeeper.get_user_info(user_id).is_none());
+        assert!(gatekeeper.dbm.lock().unwrap().load_user(user_id).is_none());
+
+        // Deleted users are free to register again
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+    }
+
     #[test]
     fn test_add_update_appointment() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -528,7 +1245,7 @@ mod tests {
 
         // Let's first add the a user to the Gatekeeper (inputs are always sanitized here, so we don't need tests for non-registered users)
         let user_id = get_random_user_id();
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
 
         // Now let's add a new appointment
         let slots_before = gatekeeper
@@ -650,6 +1367,69 @@ mod tests {
         assert_eq!(loaded_user.available_slots, updated_slot_count);
     }
 
+    #[test]
+    fn test_add_update_backup() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
+        let slots_before = gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .unwrap()
+            .available_slots;
+
+        // A new backup takes as many slots as an appointment of the same size would
+        let backup = Backup::new(get_random_bytes(ENCRYPTED_BLOB_MAX_SIZE + 1), 1);
+        let available_slots = gatekeeper.add_update_backup(user_id, &backup).unwrap();
+        assert_eq!(slots_before, available_slots + 2);
+        // Simulate the watcher adding the backup in the database.
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_backup(user_id, &backup)
+            .unwrap();
+        let loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        assert_eq!(loaded_user.available_slots, available_slots);
+
+        // Updating it with a smaller backup frees the difference
+        let smaller_backup = Backup::new(get_random_bytes(ENCRYPTED_BLOB_MAX_SIZE), 2);
+        assert_eq!(
+            gatekeeper
+                .add_update_backup(user_id, &smaller_backup)
+                .unwrap(),
+            available_slots + 1
+        );
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_backup(user_id, &smaller_backup)
+            .unwrap();
+
+        // Backups that do not fit in the available slots are rejected
+        gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .get_mut(&user_id)
+            .unwrap()
+            .available_slots = 0;
+        assert!(matches!(
+            gatekeeper.add_update_backup(user_id, &backup),
+            Err(NotEnoughSlots)
+        ));
+        // But same-size updates are fine
+        assert_eq!(
+            gatekeeper
+                .add_update_backup(user_id, &smaller_backup)
+                .unwrap(),
+            0
+        );
+    }
+
     #[test]
     fn test_has_subscription_expired() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -662,7 +1442,7 @@ mod tests {
         ));
 
         // If the user is registered and the subscription is active we should get (false, expiry)
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
         assert_eq!(
             gatekeeper.has_subscription_expired(user_id),
             Ok((false, DURATION + START_HEIGHT as u32))
@@ -695,7 +1475,7 @@ mod tests {
 
         // Adding a user whose subscription is outdated should return an entry
         let user_id = get_random_user_id();
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
 
         // Check that data is not yet outdated
         assert_eq!(gatekeeper.get_outdated_users(start_height), vec![]);
@@ -717,7 +1497,7 @@ mod tests {
 
         for _ in 0..n_users {
             let user_id = get_random_user_id();
-            gatekeeper.add_update_user(user_id).unwrap();
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
             for i in 0..n_apps {
                 let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
                 gatekeeper
@@ -789,7 +1569,7 @@ mod tests {
 
         for _ in 0..n_users {
             let user_id = get_random_user_id();
-            gatekeeper.add_update_user(user_id).unwrap();
+            gatekeeper.add_update_user(user_id, DEFAULT_PLAN).unwrap();
             let mut user_remaining_slots =
                 gatekeeper.get_user_info(user_id).unwrap().0.available_slots;
             for i in 0..n_apps {
@@ -897,6 +1677,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_filtered_block_connected_standby() {
+        // Standbys do not delete anything on their own, they leave it to their primary
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let gatekeeper = init_gatekeeper(&chain);
+        gatekeeper.set_standby(true);
+
+        let user_id = get_random_user_id();
+        gatekeeper.add_outdated_user(user_id, chain.tip().height + 1);
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_appointment(uuid, &appointment)
+            .unwrap();
+
+        gatekeeper.block_connected(&chain.generate(None), chain.get_block_count());
+        gatekeeper.delete_appointments(vec![uuid], false);
+        assert!(gatekeeper.dbm.lock().unwrap().load_user(user_id).is_some());
+        assert!(gatekeeper.dbm.lock().unwrap().appointment_exists(uuid));
+        assert_eq!(
+            gatekeeper.last_known_block_height.load(Ordering::Relaxed),
+            chain.get_block_count()
+        );
+
+        // Once it takes over, it deletes data as usual
+        gatekeeper.set_standby(false);
+        gatekeeper.block_connected(&chain.generate(None), chain.get_block_count());
+        assert!(gatekeeper.dbm.lock().unwrap().load_user(user_id).is_none());
+        assert!(!gatekeeper.dbm.lock().unwrap().appointment_exists(uuid));
+    }
+
     #[test]
     fn test_block_disconnected() {
         // Block disconnected simply updates the last known block
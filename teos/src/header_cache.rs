@@ -0,0 +1,292 @@
+//! Logic related to the HeaderCache, a bounded (and optionally persistent) cache of block headers used by the [ChainMonitor](crate::chain_monitor::ChainMonitor),
+//! and the BlockTxidsStore, which persists the transaction ids of the latest blocks alongside it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bitcoin::{BlockHash, BlockHeader, Txid};
+use lightning::chain;
+use lightning_block_sync::poll::ValidatedBlockHeader;
+use lightning_block_sync::Cache;
+
+use crate::dbm::DBM;
+use crate::tx_index::BlockTxids;
+
+/// A cache of block headers that only keeps the most recent ones.
+///
+/// Headers deeper than `depth` blocks (counting from the highest header in the cache) are dropped, so the cache does not
+/// grow for as long as the tower is running. `depth` is therefore the deepest reorg the cache can serve.
+///
+/// If built with a [DBM], the headers are also persisted so they can be loaded back after a restart.
+#[derive(Debug)]
+pub struct HeaderCache {
+    /// The cached headers, indexed by block hash.
+    headers: HashMap<BlockHash, ValidatedBlockHeader>,
+    /// The number of headers to keep.
+    depth: u32,
+    /// A [DBM] (database manager) instance. Used to persist the headers, if set.
+    dbm: Option<Arc<Mutex<DBM>>>,
+}
+
+impl HeaderCache {
+    /// Creates a new, in-memory, [HeaderCache] instance.
+    pub fn new(depth: u32) -> Self {
+        HeaderCache {
+            headers: HashMap::new(),
+            depth,
+            dbm: None,
+        }
+    }
+
+    /// Creates a new persistent [HeaderCache] instance, loading the headers stored in the database (if any).
+    pub fn persistent(depth: u32, dbm: Arc<Mutex<DBM>>) -> Self {
+        let headers = dbm
+            .lock()
+            .unwrap()
+            .load_headers()
+            .into_iter()
+            .map(|header| (header.header.block_hash(), header))
+            .collect();
+
+        let mut cache = HeaderCache {
+            headers,
+            depth,
+            dbm: Some(dbm),
+        };
+        // The depth may have been reduced since the headers were stored.
+        cache.prune();
+        log::info!("Loaded {} headers from the database", cache.len());
+
+        cache
+    }
+
+    /// Gets the number of headers in the cache.
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Checks whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Removes the headers that are deeper than `depth`.
+    fn prune(&mut self) {
+        let tip_height = match self.headers.values().map(|h| h.height).max() {
+            Some(height) if height >= self.depth => height,
+            _ => return,
+        };
+        let prune_height = tip_height - self.depth;
+
+        self.headers
+            .retain(|_, header| header.height > prune_height);
+        if let Some(dbm) = &self.dbm {
+            dbm.lock().unwrap().remove_headers_up_to(prune_height);
+        }
+    }
+}
+
+impl Cache for HeaderCache {
+    fn look_up(&self, block_hash: &BlockHash) -> Option<&ValidatedBlockHeader> {
+        self.headers.get(block_hash)
+    }
+
+    fn block_connected(&mut self, block_hash: BlockHash, block_header: ValidatedBlockHeader) {
+        if let Some(dbm) = &self.dbm {
+            if let Err(e) = dbm.lock().unwrap().store_header(&block_header) {
+                log::error!("Couldn't store header {block_hash}. Error: {e:?}");
+            }
+        }
+        self.headers.insert(block_hash, block_header);
+        self.prune();
+    }
+
+    fn block_disconnected(&mut self, block_hash: &BlockHash) -> Option<ValidatedBlockHeader> {
+        if let Some(dbm) = &self.dbm {
+            dbm.lock().unwrap().remove_header(block_hash).ok();
+        }
+        self.headers.remove(block_hash)
+    }
+}
+
+/// A store of the transaction ids of the most recent blocks.
+///
+/// Lets the [Responder](crate::responder::Responder) rebuild its index after a restart without fetching the blocks
+/// again. Blocks deeper than `depth` are dropped. Nothing is stored if built without a [DBM].
+#[derive(Debug)]
+pub struct BlockTxidsStore {
+    /// The number of blocks to keep.
+    depth: u32,
+    /// A [DBM] (database manager) instance. Used to persist the transaction ids, if set.
+    dbm: Option<Arc<Mutex<DBM>>>,
+}
+
+impl BlockTxidsStore {
+    /// Creates a new [BlockTxidsStore] instance.
+    pub fn new(depth: u32, dbm: Option<Arc<Mutex<DBM>>>) -> Self {
+        BlockTxidsStore { depth, dbm }
+    }
+
+    /// Loads the transaction ids of a given block, if stored.
+    pub fn load(&self, block_hash: &BlockHash) -> Option<Vec<Txid>> {
+        self.dbm
+            .as_ref()
+            .and_then(|dbm| dbm.lock().unwrap().load_block_txids(block_hash))
+    }
+
+    /// Stores the transaction ids of a block found at a given height, dropping the ones deeper than `depth`.
+    pub fn store(&self, block: &BlockTxids, height: u32) {
+        if let Some(dbm) = &self.dbm {
+            let dbm = dbm.lock().unwrap();
+            if let Err(e) = dbm.store_block_txids(block, height) {
+                log::error!(
+                    "Couldn't store the transaction ids of block {}. Error: {e:?}",
+                    block.header.block_hash()
+                );
+            }
+            if height >= self.depth {
+                dbm.remove_block_txids_up_to(height - self.depth);
+            }
+        }
+    }
+}
+
+impl chain::Listen for BlockTxidsStore {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &chain::transaction::TransactionData,
+        height: u32,
+    ) {
+        let block = BlockTxids {
+            header: *header,
+            txids: txdata.iter().map(|(_, tx)| tx.txid()).collect(),
+        };
+        self.store(&block, height);
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, _: u32) {
+        if let Some(dbm) = &self.dbm {
+            dbm.lock()
+                .unwrap()
+                .remove_block_txids(&header.block_hash())
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lightning::chain::Listen;
+
+    use crate::test_utils::{Blockchain, START_HEIGHT};
+
+    const DEPTH: u32 = 10;
+
+    #[test]
+    fn test_block_connected() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let mut cache = HeaderCache::new(DEPTH);
+
+        // Headers are kept until the cache is full
+        for height in 1..=DEPTH as usize {
+            let header = chain.at_height(height);
+            cache.block_connected(header.header.block_hash(), header);
+            assert_eq!(cache.len(), height);
+        }
+
+        // From there on, the deepest headers are dropped
+        for height in DEPTH as usize + 1..=START_HEIGHT {
+            let header = chain.at_height(height);
+            cache.block_connected(header.header.block_hash(), header);
+            assert_eq!(cache.len(), DEPTH as usize);
+            assert!(cache
+                .look_up(&chain.at_height(height - DEPTH as usize).header.block_hash())
+                .is_none());
+            for h in height - DEPTH as usize + 1..=height {
+                let header = chain.at_height(h);
+                assert_eq!(cache.look_up(&header.header.block_hash()), Some(&header));
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_disconnected() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let mut cache = HeaderCache::new(DEPTH);
+
+        let tip = chain.tip();
+        cache.block_connected(tip.header.block_hash(), tip);
+        assert_eq!(
+            cache.block_disconnected(&tip.header.block_hash()),
+            Some(tip)
+        );
+        assert!(cache.is_empty());
+        assert_eq!(cache.block_disconnected(&tip.header.block_hash()), None);
+    }
+
+    #[test]
+    fn test_persistent() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let mut cache = HeaderCache::persistent(DEPTH, dbm.clone());
+        assert!(cache.is_empty());
+
+        for height in 1..=START_HEIGHT {
+            let header = chain.at_height(height);
+            cache.block_connected(header.header.block_hash(), header);
+        }
+        cache.block_disconnected(&chain.tip().header.block_hash());
+
+        // Only the headers in the cache are kept in the database
+        let mut stored = dbm.lock().unwrap().load_headers();
+        stored.sort_by_key(|h| h.height);
+        assert_eq!(
+            stored,
+            (START_HEIGHT - DEPTH as usize + 1..START_HEIGHT)
+                .map(|h| chain.at_height(h))
+                .collect::<Vec<_>>()
+        );
+
+        // A new cache picks up where the old one left, pruning the headers if the depth is reduced
+        let cache = HeaderCache::persistent(DEPTH, dbm.clone());
+        assert_eq!(cache.len(), DEPTH as usize - 1);
+        let cache = HeaderCache::persistent(DEPTH / 2, dbm.clone());
+        assert_eq!(cache.len(), DEPTH as usize / 2);
+        assert_eq!(dbm.lock().unwrap().load_headers().len(), DEPTH as usize / 2);
+    }
+
+    #[test]
+    fn test_block_txids_store() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let store = BlockTxidsStore::new(DEPTH, Some(dbm));
+
+        let mut blocks = Vec::new();
+        for height in START_HEIGHT + 1..=START_HEIGHT + DEPTH as usize + 1 {
+            let block = chain.generate(None);
+            store.block_connected(&block, height as u32);
+            blocks.push(block);
+        }
+
+        // Only the latest `DEPTH` blocks are kept
+        assert_eq!(store.load(&blocks[0].block_hash()), None);
+        for block in blocks[1..].iter() {
+            assert_eq!(
+                store.load(&block.block_hash()),
+                Some(block.txdata.iter().map(|tx| tx.txid()).collect())
+            );
+        }
+
+        let tip = blocks.last().unwrap();
+        store.block_disconnected(&tip.header, chain.tip().height);
+        assert_eq!(store.load(&tip.block_hash()), None);
+
+        // A store with no database keeps nothing
+        let store = BlockTxidsStore::new(DEPTH, None);
+        store.block_connected(tip, chain.tip().height);
+        assert_eq!(store.load(&tip.block_hash()), None);
+    }
+}
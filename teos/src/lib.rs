@@ -4,10 +4,13 @@
 
 // FIXME: This is a temporary fix. See https://github.com/tokio-rs/prost/issues/661
 #[allow(clippy::derive_partial_eq_without_eq)]
+// Streaming RPCs get their associated type named after the (snake case) method.
+#[allow(non_camel_case_types)]
 pub mod protos {
     tonic::include_proto!("teos.v2");
 }
 pub mod api;
+pub mod audit;
 pub mod bitcoin_cli;
 pub mod carrier;
 pub mod chain_monitor;
@@ -18,11 +21,17 @@ pub mod dbm;
 mod errors;
 mod extended_appointment;
 pub mod gatekeeper;
+pub mod header_cache;
+mod locator_filter;
+pub mod payments;
+pub mod recovery;
+pub mod replication;
 pub mod responder;
 #[doc(hidden)]
 mod rpc_errors;
+pub mod snapshot;
 pub mod tls;
-mod tx_index;
+pub mod tx_index;
 pub mod watcher;
 
 #[cfg(test)]
@@ -0,0 +1,103 @@
+//! Logic related to the LocatorFilter, an in-memory index of the locators being watched by the tower.
+
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+use teos_common::appointment::Locator;
+
+/// An exact, in-memory, set of the [Locator]s of the appointments held by the tower.
+///
+/// Every block brings thousands of potential locators, most of which do not match any appointment. The filter is used
+/// to screen them out before querying the database, so only the (likely) triggered ones are looked up.
+///
+/// Locators are reference counted, given the same locator can be shared by appointments from different users.
+#[derive(Debug, Default)]
+pub(crate) struct LocatorFilter {
+    /// The watched locators, alongside the number of appointments using them.
+    locators: HashMap<Locator, usize>,
+}
+
+impl LocatorFilter {
+    /// Creates a new, empty, [LocatorFilter] instance.
+    pub fn new() -> Self {
+        LocatorFilter::default()
+    }
+
+    /// Adds a locator to the filter.
+    pub fn insert(&mut self, locator: Locator) {
+        *self.locators.entry(locator).or_insert(0) += 1;
+    }
+
+    /// Removes a locator from the filter. The locator is only dropped once no appointment uses it.
+    pub fn remove(&mut self, locator: &Locator) {
+        if let Some(count) = self.locators.get_mut(locator) {
+            *count -= 1;
+            if *count == 0 {
+                self.locators.remove(locator);
+            }
+        }
+    }
+
+    /// Checks whether a locator is in the filter.
+    pub fn contains(&self, locator: &Locator) -> bool {
+        self.locators.contains_key(locator)
+    }
+
+    /// Gets the number of distinct locators in the filter.
+    pub fn len(&self) -> usize {
+        self.locators.len()
+    }
+}
+
+impl FromIterator<Locator> for LocatorFilter {
+    fn from_iter<I: IntoIterator<Item = Locator>>(iter: I) -> Self {
+        let mut filter = LocatorFilter::new();
+        for locator in iter {
+            filter.insert(locator);
+        }
+        filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::get_random_tx;
+
+    #[test]
+    fn test_insert_remove() {
+        let mut filter = LocatorFilter::new();
+        let locator = Locator::new(get_random_tx().txid());
+        assert!(!filter.contains(&locator));
+
+        // A locator shared by two appointments is kept until both are removed
+        filter.insert(locator);
+        filter.insert(locator);
+        assert!(filter.contains(&locator));
+        assert_eq!(filter.len(), 1);
+
+        filter.remove(&locator);
+        assert!(filter.contains(&locator));
+        filter.remove(&locator);
+        assert!(!filter.contains(&locator));
+        assert_eq!(filter.len(), 0);
+
+        // Removing an unknown locator is a noop
+        filter.remove(&locator);
+        assert_eq!(filter.len(), 0);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let locators: Vec<Locator> = (0..10)
+            .map(|_| Locator::new(get_random_tx().txid()))
+            .collect();
+        let filter: LocatorFilter = locators.iter().cloned().collect();
+
+        assert_eq!(filter.len(), locators.len());
+        for locator in locators.iter() {
+            assert!(filter.contains(locator));
+        }
+    }
+}
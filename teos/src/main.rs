@@ -1,13 +1,14 @@
 use log::LevelFilter;
 use simple_logger::SimpleLogger;
 use std::fs;
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::task;
-use tonic::transport::{Certificate, Server, ServerTlsConfig};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig};
 
 use bitcoin::network::constants::Network;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
@@ -16,45 +17,126 @@ use lightning_block_sync::init::validate_best_block_header;
 use lightning_block_sync::poll::{
     ChainPoller, Poll, Validate, ValidatedBlock, ValidatedBlockHeader,
 };
-use lightning_block_sync::{BlockSource, BlockSourceError, SpvClient, UnboundedCache};
+use lightning_block_sync::{BlockSource, BlockSourceError, Cache, SpvClient};
 
+use teos::api::grpc::{self, PublicGrpcAPI};
+use teos::api::http;
 use teos::api::internal::InternalAPI;
-use teos::api::{http, tor::TorAPI};
+use teos::api::rate_limit::RateLimiter;
+use teos::api::tor::{TorAPI, TorControlAuth};
+use teos::audit::AuditLog;
 use teos::bitcoin_cli::BitcoindClient;
 use teos::carrier::Carrier;
 use teos::chain_monitor::ChainMonitor;
-use teos::config::{self, AuthMethod, Config, Opt};
+use teos::config::{self, AuthMethod, Command, Config, Opt};
 use teos::dbm::DBM;
 use teos::gatekeeper::Gatekeeper;
+use teos::header_cache::{BlockTxidsStore, HeaderCache};
+use teos::payments::{ClnInvoiceBackend, InvoiceBackend, LndInvoiceBackend, Payments};
 use teos::protos as msgs;
 use teos::protos::private_tower_services_server::PrivateTowerServicesServer;
 use teos::protos::public_tower_services_server::PublicTowerServicesServer;
+use teos::recovery::RecoveryScanner;
+use teos::replication::{ReplicationSource, Standby};
 use teos::responder::Responder;
-use teos::tls::tls_init;
-use teos::watcher::Watcher;
+use teos::snapshot::{self, SnapshotError};
+use teos::tls::{tls_init, ClientAuthenticator, ClientCertificates, ReloadableCertificate};
+use teos::tx_index::BlockTxids;
+use teos::watcher::{RegistrationGate, Watcher, WatcherConfig};
 
-use teos_common::constants::IRREVOCABLY_RESOLVED;
 use teos_common::cryptography::get_random_keypair;
-use teos_common::TowerId;
 
-async fn get_last_n_blocks<B, T>(
+/// Enables (or disables) the replication log. Both primaries and standbys (that have taken over) record their changes,
+/// so the other tower can follow them.
+fn set_replication_log(dbm: &Mutex<DBM>, enabled: bool) {
+    if let Err(e) = dbm.lock().unwrap().set_replication_log(enabled) {
+        log::error!("Cannot set up the replication log. {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Gets the latest `n` blocks, starting at `last_known_block`, sorted from the most recent to the oldest.
+///
+/// Only the latest `n_full` blocks are returned in full. For the rest, just their transaction ids are returned, which are
+/// loaded from the `txids_store` if possible instead of fetching the blocks again.
+async fn get_last_n_blocks<B, T, C>(
     poller: &mut ChainPoller<B, T>,
     mut last_known_block: ValidatedBlockHeader,
     n: usize,
-) -> Result<Vec<ValidatedBlock>, BlockSourceError>
+    n_full: usize,
+    cache: &mut C,
+    txids_store: &BlockTxidsStore,
+) -> Result<(Vec<ValidatedBlock>, Vec<BlockTxids>), BlockSourceError>
 where
     B: DerefMut<Target = T> + Sized + Send + Sync,
     T: BlockSource,
+    C: Cache,
 {
-    let mut last_n_blocks = Vec::with_capacity(n);
-    for _ in 0..n {
-        log::debug!("Fetching block #{}", last_known_block.height);
-        let block = poller.fetch_block(&last_known_block).await?;
-        last_known_block = poller.look_up_previous_header(&last_known_block).await?;
-        last_n_blocks.push(block);
+    let mut last_n_blocks = Vec::with_capacity(n_full);
+    let mut last_n_txids = Vec::with_capacity(n);
+    for i in 0..n {
+        let block_hash = last_known_block.header.block_hash();
+        let stored_txids = if i < n_full {
+            None
+        } else {
+            txids_store.load(&block_hash)
+        };
+        let block_txids = match stored_txids {
+            Some(txids) => BlockTxids {
+                header: last_known_block.header,
+                txids,
+            },
+            None => {
+                log::debug!("Fetching block #{}", last_known_block.height);
+                let block = poller.fetch_block(&last_known_block).await?;
+                let block_txids = BlockTxids::from(&block);
+                txids_store.store(&block_txids, last_known_block.height);
+                if i < n_full {
+                    last_n_blocks.push(block);
+                }
+                block_txids
+            }
+        };
+        last_n_txids.push(block_txids);
+        cache.block_connected(block_hash, last_known_block);
+
+        // The previous header is not needed for the last block (which may well be the genesis block).
+        if i + 1 < n {
+            // Headers are only pulled from the backend if they are not cached already.
+            last_known_block = match cache.look_up(&last_known_block.header.prev_blockhash) {
+                Some(header) => *header,
+                None => poller.look_up_previous_header(&last_known_block).await?,
+            };
+        }
     }
 
-    Ok(last_n_blocks)
+    Ok((last_n_blocks, last_n_txids))
+}
+
+/// Builds the endpoint of the private API of the primary tower, authenticated using the replication certificates.
+fn replication_endpoint(conf: &Config) -> Endpoint {
+    let read = |path: &str| {
+        fs::read(config::data_dir_absolute_path(path.to_owned())).unwrap_or_else(|e| {
+            eprintln!("Unable to read {path}: {e}");
+            std::process::exit(1);
+        })
+    };
+    let tls = ClientTlsConfig::new()
+        .domain_name(conf.replication_tls_domain.clone())
+        .ca_certificate(Certificate::from_pem(read(&conf.replication_ca_cert_path)))
+        .identity(Identity::from_pem(
+            read(&conf.replication_cert_path),
+            read(&conf.replication_key_path),
+        ));
+
+    Endpoint::from_shared(format!("https://{}", conf.replication_primary))
+        .map_err(|e| e.to_string())
+        .and_then(|endpoint| endpoint.tls_config(tls).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid replication_primary: {e}");
+            std::process::exit(1);
+        })
+        .connect_timeout(Duration::from_secs(conf.replication_heartbeat_interval))
 }
 
 fn create_new_tower_keypair(db: &DBM) -> (SecretKey, PublicKey) {
@@ -65,7 +147,8 @@ fn create_new_tower_keypair(db: &DBM) -> (SecretKey, PublicKey) {
 
 #[tokio::main]
 async fn main() {
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
+    let command = opt.command.take();
     let path = config::data_dir_absolute_path(opt.data_dir.clone());
     let conf_file_path = path.join("teos.toml");
     // Create data dir if it does not exist
@@ -126,21 +209,40 @@ async fn main() {
         DBM::new(path_network.join("teos_db.sql3")).unwrap(),
     ));
 
-    // Load tower secret key or create a fresh one if none is found. If overwrite key is set, create a new
-    // key straightaway
-    let (tower_sk, tower_pk) = {
-        let locked_db = dbm.lock().unwrap();
-        if conf.overwrite_key {
-            log::info!("Overwriting tower keys");
-            create_new_tower_keypair(&locked_db)
-        } else if let Some(sk) = locked_db.load_tower_key() {
-            (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))
-        } else {
-            log::info!("Tower keys not found. Creating a fresh set");
-            create_new_tower_keypair(&locked_db)
+    match &command {
+        Some(Command::Export(data)) => {
+            let exported = fs::File::create(&data.path)
+                .map_err(SnapshotError::from)
+                .and_then(|file| snapshot::export(&dbm.lock().unwrap(), io::BufWriter::new(file)));
+            match exported {
+                Ok(n) => {
+                    log::info!("{n} records exported to {}", data.path.display());
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    log::error!("Cannot export the tower data. {e}");
+                    std::process::exit(1);
+                }
+            }
         }
-    };
-    log::info!("tower_id: {tower_pk}");
+        Some(Command::Import(data)) => {
+            let imported = fs::File::open(&data.path)
+                .map_err(SnapshotError::from)
+                .and_then(|file| {
+                    snapshot::import(&mut dbm.lock().unwrap(), io::BufReader::new(file))
+                });
+            match imported {
+                Ok(n) => log::info!("{n} records imported from {}", data.path.display()),
+                Err(e) => {
+                    log::error!("Cannot import the tower data. {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => (),
+    }
+
+    let replication_primary = (conf.replication == "standby").then(|| replication_endpoint(&conf));
 
     let btc_rpc_auth = match conf.get_auth_method() {
         AuthMethod::CookieFile => {
@@ -188,7 +290,62 @@ async fn main() {
         )
         .unwrap(),
     );
+    // Snapshots can only be restored once bitcoind is reachable, given their last known block must be checked
+    if let Some(Command::Restore(data)) = &command {
+        let restored = snapshot::restore(
+            &mut dbm.lock().unwrap(),
+            &data.path,
+            |block_hash| matches!(rpc.get_block_header_info(block_hash), Ok(header) if header.confirmations >= 0),
+        );
+        match restored {
+            Ok(()) => log::info!("Tower database restored from {}", data.path.display()),
+            Err(e) => {
+                log::error!("Cannot restore the tower database. {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Standbys follow their primary until it is gone, and then run as a regular tower on top of the replicated data
+    // (and tower key). The components are built once the standby is in sync, so they use the tower key of the primary
+    let standby = if let Some(primary) = replication_primary {
+        log::info!("Running as a standby of {}", conf.replication_primary);
+        let (in_sync_trigger, in_sync) = triggered::trigger();
+        let follower = task::spawn(
+            Standby::new(
+                dbm.clone(),
+                primary,
+                Duration::from_secs(conf.replication_failover_timeout),
+                in_sync_trigger,
+            )
+            .follow(),
+        );
+        in_sync.await;
+        Some(follower)
+    } else {
+        set_replication_log(&dbm, !conf.replication.is_empty());
+        None
+    };
+
+    // Load tower secret key or create a fresh one if none is found. If overwrite key is set, create a new
+    // key straightaway
+    let (tower_sk, tower_pk) = {
+        let locked_db = dbm.lock().unwrap();
+        if conf.overwrite_key {
+            log::info!("Overwriting tower keys");
+            create_new_tower_keypair(&locked_db)
+        } else if let Some(sk) = locked_db.load_tower_key() {
+            (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))
+        } else {
+            log::info!("Tower keys not found. Creating a fresh set");
+            create_new_tower_keypair(&locked_db)
+        }
+    };
+    log::info!("tower_id: {tower_pk}");
+
     let mut derefed = bitcoin_cli.deref();
+    // Number of blocks needed to populate both the locator cache and the transaction index.
+    let bootstrap_depth = std::cmp::max(conf.locator_cache_depth, conf.tx_index_depth);
     // Load last known block from DB if found. Poll it from Bitcoind otherwise.
     let last_known_block = dbm.lock().unwrap().load_last_known_block();
     let tip = if let Some(block_hash) = last_known_block {
@@ -207,19 +364,20 @@ async fn main() {
 
         // If we are running in pruned mode some data may be missing (if we happen to have been offline for a while)
         if let Some(prune_height) = rpc.get_blockchain_info().unwrap().prune_height {
-            if last_known_header.height - IRREVOCABLY_RESOLVED + 1 < prune_height as u32 {
+            let first_needed = (last_known_header.height + 1).saturating_sub(bootstrap_depth);
+            if first_needed < prune_height as u32 {
                 log::warn!(
                     "Cannot load blocks in the range {}-{}. Chain has gone too far out of sync",
-                    last_known_header.height - IRREVOCABLY_RESOLVED + 1,
+                    first_needed,
                     last_known_header.height
                 );
                 if conf.force_update {
                     log::info!("Forcing a backend update");
-                    // We want to grab the first IRREVOCABLY_RESOLVED we know about for the initial cache
+                    // We want to grab the first bootstrap_depth blocks we know about for the initial caches
                     // So we can perform transitions from there onwards.
-                    let target_height = prune_height + IRREVOCABLY_RESOLVED as u64;
+                    let target_height = prune_height + bootstrap_depth as u64;
                     let target_hash = rpc.get_block_hash(target_height).unwrap();
-                    last_known_header = derefed
+                    let target_header = derefed
                         .get_header(
                             &rpc.get_block_hash(target_height).unwrap(),
                             Some(target_height as u32),
@@ -228,9 +386,29 @@ async fn main() {
                         .unwrap()
                         .validate(target_hash)
                         .unwrap();
+
+                    // Check whatever is still available in the skipped range so potential breaches can be reported.
+                    let scanner = RecoveryScanner::new(
+                        dbm.clone(),
+                        conf.use_block_filters.then(|| rpc.clone()),
+                    );
+                    match scanner
+                        .scan(&derefed, last_known_header.height, target_header)
+                        .await
+                    {
+                        Ok(0) => log::info!("No missed breaches found while recovering"),
+                        Ok(n) => log::warn!(
+                            "{n} appointments may have been breached while offline. Check them using getmissedbreaches"
+                        ),
+                        Err(e) => log::error!(
+                            "Recovery scan could not be completed. Error: {}",
+                            e.into_inner()
+                        ),
+                    }
+                    last_known_header = target_header;
                 } else {
                     log::error!(
-                        "The underlying chain has gone too far out of sync. The tower block cache cannot be initialized. Run with --forceupdate to force update. THIS WILL, POTENTIALLY, MAKE THE TOWER MISS SOME OF ITS APPOINTMENTS"
+                        "The underlying chain has gone too far out of sync. The tower block cache cannot be initialized. Rerun with --forceupdate to force update and run a recovery scan over the skipped blocks. THIS WILL, POTENTIALLY, MAKE THE TOWER MISS SOME OF ITS APPOINTMENTS. The recovery scan is best-effort, and its findings can be checked using getmissedbreaches"
                     );
                     std::process::exit(1);
                 }
@@ -241,18 +419,6 @@ async fn main() {
         validate_best_block_header(&derefed).await.unwrap()
     };
 
-    // DISCUSS: This is not really required (and only triggered in regtest). This is only in place so the caches can be
-    // populated with enough blocks mainly because the size of the cache is based on the amount of blocks passed when initializing.
-    // However, we could add an additional parameter to specify the size of the cache, and initialize with however may blocks we
-    // could pull from the backend. Adding this functionality just for regtest seemed unnecessary though, hence the check.
-    if tip.height < IRREVOCABLY_RESOLVED {
-        log::error!(
-            "Not enough blocks to start teosd (required: {IRREVOCABLY_RESOLVED}). Mine at least {} more",
-            IRREVOCABLY_RESOLVED - tip.height
-        );
-        std::process::exit(1);
-    }
-
     log::info!(
         "Current chain tip: {} (height: {})",
         tip.header.block_hash(),
@@ -272,34 +438,69 @@ async fn main() {
         conf.subscription_slots,
         conf.subscription_duration,
         conf.expiry_delta,
+        conf.plans.clone(),
+        conf.private_mode,
         dbm.clone(),
     ));
+    // Standbys leave expiring users and deleting appointments to their primary until they take over
+    gatekeeper.set_standby(standby.is_some());
 
+    let mut cache = if conf.persist_header_cache {
+        HeaderCache::persistent(conf.header_cache_depth, dbm.clone())
+    } else {
+        HeaderCache::new(conf.header_cache_depth)
+    };
+    let txids_store = BlockTxidsStore::new(
+        conf.tx_index_depth,
+        conf.persist_header_cache.then(|| dbm.clone()),
+    );
     let mut poller = ChainPoller::new(&mut derefed, Network::from_str(btc_network).unwrap());
     let (responder, watcher) = {
-        let last_n_blocks = get_last_n_blocks(&mut poller, tip, IRREVOCABLY_RESOLVED as usize)
-            .await.unwrap_or_else(|e| {
-                // I'm pretty sure this can only happen if we are pulling blocks from the target to the prune height, and by the time we get to
-                // the end at least one has been pruned.
-                log::error!("Couldn't load the latest {IRREVOCABLY_RESOLVED} blocks. Please try again (Error: {})", e.into_inner());
-                std::process::exit(1);
-            }
-        );
+        // Young chains (e.g. a fresh regtest) may have fewer blocks than the caches can hold, so the caches start partially filled.
+        let n_blocks = std::cmp::min(bootstrap_depth, tip.height + 1) as usize;
+        // Only the Watcher needs the full blocks. The Responder can do with their transaction ids
+        let n_full_blocks = std::cmp::min(conf.locator_cache_depth as usize, n_blocks);
+        let (last_n_blocks, last_n_txids) = get_last_n_blocks(
+            &mut poller,
+            tip,
+            n_blocks,
+            n_full_blocks,
+            &mut cache,
+            &txids_store,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            // I'm pretty sure this can only happen if we are pulling blocks from the target to the prune height, and by the time we get to
+            // the end at least one has been pruned.
+            log::error!(
+                "Couldn't load the latest {n_blocks} blocks. Please try again (Error: {})",
+                e.into_inner()
+            );
+            std::process::exit(1);
+        });
 
+        // Standbys follow the chain without broadcasting anything until they take over
+        let mut carrier = Carrier::new(rpc, bitcoind_reachable.clone(), tip.height);
+        carrier.set_broadcasting(standby.is_none());
         let responder = Arc::new(Responder::new(
-            &last_n_blocks,
+            &last_n_txids[..std::cmp::min(conf.tx_index_depth as usize, n_blocks)],
             tip.height,
-            Carrier::new(rpc, bitcoind_reachable.clone(), tip.height),
+            conf.tx_index_depth as usize,
+            carrier,
             gatekeeper.clone(),
+            tower_sk,
             dbm.clone(),
         ));
         let watcher = Arc::new(Watcher::new(
             gatekeeper.clone(),
             responder.clone(),
-            &last_n_blocks[0..6],
+            &last_n_blocks,
             tip.height,
+            WatcherConfig::new(
+                conf.locator_cache_depth as usize,
+                RegistrationGate::new(conf.registration_pow_difficulty, conf.registration_tokens),
+            ),
             tower_sk,
-            TowerId(tower_pk),
             dbm.clone(),
         ));
         (responder, watcher)
@@ -311,21 +512,68 @@ async fn main() {
         log::info!("Bootstrapping from backed up data");
     }
 
+    // Set up the invoice backend if paid plans are offered
+    let invoice_backend: Option<Arc<dyn InvoiceBackend>> = match conf.invoice_backend.as_str() {
+        "cln" => Some(Arc::new(ClnInvoiceBackend::new(
+            config::data_dir_absolute_path(conf.cln_rpc_path.clone())
+                .to_str()
+                .unwrap(),
+        ))),
+        "lnd" => match LndInvoiceBackend::new(
+            &conf.lnd_rest_address,
+            config::data_dir_absolute_path(conf.lnd_macaroon_path.clone())
+                .to_str()
+                .unwrap(),
+            config::data_dir_absolute_path(conf.lnd_cert_path.clone())
+                .to_str()
+                .unwrap(),
+        ) {
+            Ok(backend) => Some(Arc::new(backend)),
+            Err(e) => {
+                log::error!("{e}");
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let invoice_expiry = conf.invoice_expiry;
+    let payments =
+        invoice_backend.map(|backend| Payments::new(backend, invoice_expiry, dbm.clone()));
+    if payments.is_some() {
+        log::info!(
+            "Paid plans enabled using the {} invoice backend",
+            conf.invoice_backend
+        );
+    }
+    let audit_log = AuditLog::new(dbm.clone(), conf.audit_log_retention_days);
+    let replication = if conf.replication.is_empty() {
+        None
+    } else {
+        Some(ReplicationSource::new(
+            dbm.lock().unwrap().reader(),
+            tower_sk,
+            Duration::from_secs(conf.replication_heartbeat_interval),
+        ))
+    };
+
     let (shutdown_trigger, shutdown_signal_rpc_api) = triggered::trigger();
     let shutdown_signal_internal_api = shutdown_signal_rpc_api.clone();
     let shutdown_signal_http = shutdown_signal_rpc_api.clone();
+    let shutdown_signal_public_grpc = shutdown_signal_rpc_api.clone();
     let shutdown_signal_cm = shutdown_signal_rpc_api.clone();
     let shutdown_signal_tor = shutdown_signal_rpc_api.clone();
 
     // The ordering here actually matters. Listeners are called by order, and we want the gatekeeper to be called
     // first so it updates the users' states and both the Watcher and the Responder operate only on registered users.
-    let listener = &(gatekeeper, &(watcher.clone(), responder));
-    let cache = &mut UnboundedCache::new();
-    let spv_client = SpvClient::new(tip, poller, cache, listener);
+    let listener = &(
+        gatekeeper.clone(),
+        &(watcher.clone(), &(responder.clone(), &txids_store)),
+    );
+    let spv_client = SpvClient::new(tip, poller, &mut cache, listener);
     let mut chain_monitor = ChainMonitor::new(
         spv_client,
         tip,
-        dbm,
+        dbm.clone(),
         conf.polling_delta,
         shutdown_signal_cm,
         bitcoind_reachable.clone(),
@@ -334,23 +582,72 @@ async fn main() {
 
     // Get all the components up to date if there's a backlog of blocks
     chain_monitor.poll_best_tip().await;
+
+    // Standbys keep monitoring the chain while following their primary. Once it is gone, the components pick up the data
+    // replicated in the meantime and start broadcasting
+    if let Some(follower) = standby {
+        log::info!(
+            "Monitoring the chain with broadcasting disabled until the primary tower is gone"
+        );
+        let (took_over_trigger, took_over) = triggered::trigger();
+        tokio::join!(chain_monitor.monitor_chain_until(took_over), async {
+            follower.await.unwrap();
+            took_over_trigger.trigger();
+        });
+
+        log::warn!("The primary tower is gone. Taking over");
+        {
+            let dbm = dbm.lock().unwrap();
+            dbm.remove_replication_seq().unwrap();
+            if dbm.load_tower_key() != Some(tower_sk) {
+                log::error!("The primary tower changed its key while being followed. Restart the tower to take over with the new key");
+                std::process::exit(1);
+            }
+        }
+        set_replication_log(&dbm, true);
+        gatekeeper.reload();
+        gatekeeper.set_standby(false);
+        responder.reload();
+        responder.set_broadcasting(true);
+    }
     log::info!("Bootstrap completed. Turning on interfaces");
 
     // Build interfaces
-    let http_api_addr = format!("{}:{}", conf.api_bind, conf.api_port)
-        .parse()
-        .unwrap();
-    let mut addresses = vec![msgs::NetworkAddress::from_ipv4(
-        conf.api_bind.clone(),
-        conf.api_port,
-    )];
+    // In Tor-only mode the API is bound to loopback, so it can only be reached through the onion service
+    let api_bind = if conf.tor_only {
+        "127.0.0.1".to_owned()
+    } else {
+        conf.api_bind.clone()
+    };
+    let http_api_addr = config::resolve_bind_addr(&api_bind, conf.api_port).unwrap_or_else(|e| {
+        eprintln!("Cannot resolve api_bind: {e}");
+        std::process::exit(1);
+    });
+    let mut addresses = Vec::new();
+    if !conf.tor_only {
+        addresses.push(msgs::NetworkAddress::from_clearnet(
+            conf.api_bind.clone(),
+            conf.api_port,
+        ));
+    }
 
     // Create Tor endpoint if required
     let tor_api = if conf.tor_support {
+        let tor_control_auth = if !conf.tor_control_cookie_path.is_empty() {
+            TorControlAuth::Cookie(config::data_dir_absolute_path(
+                conf.tor_control_cookie_path.clone(),
+            ))
+        } else if !conf.tor_control_password.is_empty() {
+            TorControlAuth::Password(conf.tor_control_password.clone())
+        } else {
+            TorControlAuth::Auto
+        };
         let tor_api = TorAPI::new(
             http_api_addr,
             conf.onion_hidden_service_port,
             conf.tor_control_port,
+            tor_control_auth,
+            conf.tor_client_auth_keys.clone(),
             path_network,
         )
         .await;
@@ -364,13 +661,25 @@ async fn main() {
         None
     };
 
+    let rate_limiter = Arc::new(RateLimiter::new(conf.rate_limits));
+    let client_certificates = Arc::new(ClientCertificates::new(path.clone()).unwrap_or_else(|e| {
+        eprintln!("Couldn't load the client certificates revocation list: {e}");
+        std::process::exit(1);
+    }));
     let internal_api = Arc::new(InternalAPI::new(
         watcher,
+        Arc::new(bitcoin_cli.get_new_rpc_client().unwrap()),
         addresses,
         bitcoind_reachable.clone(),
         shutdown_trigger,
+        payments,
+        rate_limiter.clone(),
+        client_certificates.clone(),
+        audit_log,
+        replication,
     ));
     let internal_api_cloned = internal_api.clone();
+    let internal_api_public_grpc = internal_api.clone();
 
     let rpc_api_addr = format!("{}:{}", conf.rpc_bind, conf.rpc_port)
         .parse()
@@ -381,7 +690,7 @@ async fn main() {
 
     // Generate mtls certificates to data directory so the admin can securely connect
     // to the server to perform administrative tasks.
-    let (identity, ca_cert) = tls_init(&path).unwrap_or_else(|e| {
+    let (identity, ca_cert) = tls_init(&path, &conf.rpc_tls_alt_names).unwrap_or_else(|e| {
         eprintln!("Couldn't generate tls certificates: {e:?}");
         std::process::exit(1);
     });
@@ -395,7 +704,10 @@ async fn main() {
         Server::builder()
             .tls_config(tls)
             .expect("couldn't configure tls")
-            .add_service(PrivateTowerServicesServer::new(internal_api))
+            .add_service(PrivateTowerServicesServer::with_interceptor(
+                internal_api,
+                ClientAuthenticator::new(client_certificates),
+            ))
             .serve_with_shutdown(rpc_api_addr, shutdown_signal_rpc_api)
             .await
             .unwrap();
@@ -409,15 +721,50 @@ async fn main() {
             .unwrap();
     });
 
+    let api_certificate = if conf.api_tls_cert_path.is_empty() {
+        None
+    } else {
+        let certificate = ReloadableCertificate::new(
+            config::data_dir_absolute_path(conf.api_tls_cert_path.clone()),
+            config::data_dir_absolute_path(conf.api_tls_key_path.clone()),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Couldn't load the API TLS certificate: {e}");
+            std::process::exit(1);
+        });
+        Some(Arc::new(certificate))
+    };
+
     let (http_service_ready, ready_signal_http) = triggered::trigger();
     let http_api_task = task::spawn(http::serve(
         http_api_addr,
         internal_api_addr,
+        rate_limiter.clone(),
+        api_certificate.clone(),
         http_service_ready,
         shutdown_signal_http,
     ));
     ready_signal_http.await;
 
+    // Serve the public gRPC interface if required. The certificate is guaranteed to be set by Config::verify
+    let mut public_grpc_task = None;
+    if conf.public_grpc {
+        let public_grpc_addr = config::resolve_bind_addr(&api_bind, conf.public_grpc_port)
+            .unwrap_or_else(|e| {
+                eprintln!("Cannot resolve api_bind: {e}");
+                std::process::exit(1);
+            });
+        let (grpc_service_ready, ready_signal_grpc) = triggered::trigger();
+        public_grpc_task = Some(task::spawn(grpc::serve(
+            public_grpc_addr,
+            PublicGrpcAPI::new(internal_api_public_grpc, rate_limiter),
+            api_certificate.unwrap(),
+            grpc_service_ready,
+            shutdown_signal_public_grpc,
+        )));
+        ready_signal_grpc.await;
+    }
+
     // Add Tor Onion Service for public API
     let mut tor_task = Option::None;
     let (tor_service_ready, ready_signal_tor) = triggered::trigger();
@@ -444,6 +791,9 @@ async fn main() {
     http_api_task.await.unwrap();
     private_api_task.await.unwrap();
     public_api_task.await.unwrap();
+    if let Some(public_grpc_task) = public_grpc_task {
+        public_grpc_task.await.unwrap();
+    }
     if let Some(tor_task) = tor_task {
         tor_task.await.unwrap();
     }
@@ -0,0 +1,593 @@
+//! Logic related to paid subscriptions.
+//!
+//! Users registering for a plan with a price are handed a BOLT11 invoice issued by the Lightning node backing the
+//! tower. The subscription is only activated once the invoice has been paid, which is checked the next time the user
+//! tries to register. The payment is only redeemed once the subscription has been activated.
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use bitcoin::base64;
+use lightning::ln::PaymentHash;
+
+use teos_common::cryptography::get_random_bytes;
+use teos_common::net::cln_rpc::ClnRpc;
+use teos_common::UserId;
+
+use crate::dbm::DBM;
+
+/// Error raised if the invoice backend cannot fulfil a request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvoiceBackendError(pub String);
+
+impl std::fmt::Display for InvoiceBackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invoice backend error: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvoiceBackendError {}
+
+/// An invoice issued by an [InvoiceBackend].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    /// The BOLT11 encoded invoice.
+    pub bolt11: String,
+    /// The hash of the invoice preimage, used to check whether it has been paid.
+    pub payment_hash: PaymentHash,
+}
+
+/// Interface of the Lightning nodes the tower can use to get paid.
+#[tonic::async_trait]
+pub trait InvoiceBackend: Send + Sync {
+    /// Creates an invoice for `amount_msat` that expires after `expiry` seconds.
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        expiry: u32,
+    ) -> Result<Invoice, InvoiceBackendError>;
+
+    /// Checks whether the invoice identified by `payment_hash` has been paid.
+    async fn is_settled(&self, payment_hash: &PaymentHash) -> Result<bool, InvoiceBackendError>;
+}
+
+/// [InvoiceBackend] backed by Core Lightning, reached through its RPC socket.
+pub struct ClnInvoiceBackend {
+    rpc: ClnRpc,
+}
+
+impl ClnInvoiceBackend {
+    /// Creates a new [ClnInvoiceBackend] instance given the path to the CLN RPC socket.
+    pub fn new(rpc_path: &str) -> Self {
+        ClnInvoiceBackend {
+            rpc: ClnRpc::new(rpc_path),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl InvoiceBackend for ClnInvoiceBackend {
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        expiry: u32,
+    ) -> Result<Invoice, InvoiceBackendError> {
+        // Labels must be unique within CLN
+        let label = format!("teos-{}", hex::encode(get_random_bytes(16)));
+        let result = self
+            .rpc
+            .call(
+                "invoice",
+                json!({"amount_msat": amount_msat, "label": label, "description": description, "expiry": expiry}),
+            )
+            .await
+            .map_err(|e| InvoiceBackendError(e.to_string()))?;
+
+        Ok(Invoice {
+            bolt11: get_str(&result, "bolt11")?.to_owned(),
+            payment_hash: parse_payment_hash(&hex::decode(get_str(&result, "payment_hash")?).ok())?,
+        })
+    }
+
+    async fn is_settled(&self, payment_hash: &PaymentHash) -> Result<bool, InvoiceBackendError> {
+        let result = self
+            .rpc
+            .call(
+                "listinvoices",
+                json!({"payment_hash": hex::encode(payment_hash.0)}),
+            )
+            .await
+            .map_err(|e| InvoiceBackendError(e.to_string()))?;
+
+        Ok(matches!(
+            result["invoices"].as_array().and_then(|invoices| invoices.first()),
+            Some(invoice) if invoice["status"] == "paid"
+        ))
+    }
+}
+
+/// [InvoiceBackend] backed by LND, reached through its REST interface.
+pub struct LndInvoiceBackend {
+    rest_address: String,
+    macaroon: String,
+    client: reqwest::Client,
+}
+
+impl LndInvoiceBackend {
+    /// Creates a new [LndInvoiceBackend] instance.
+    ///
+    /// The macaroon is read from `macaroon_path`. If `cert_path` is not empty, the certificate it points to is trusted
+    /// on top of the system ones (LND uses a self-signed certificate by default).
+    pub fn new(
+        rest_address: &str,
+        macaroon_path: &str,
+        cert_path: &str,
+    ) -> Result<Self, InvoiceBackendError> {
+        let macaroon = std::fs::read(macaroon_path).map_err(|e| {
+            InvoiceBackendError(format!("Cannot read macaroon ({macaroon_path}): {e}"))
+        })?;
+
+        let mut builder = reqwest::Client::builder();
+        if !cert_path.is_empty() {
+            let cert = std::fs::read(cert_path)
+                .ok()
+                .and_then(|cert| reqwest::Certificate::from_pem(&cert).ok())
+                .ok_or_else(|| {
+                    InvoiceBackendError(format!("Cannot load TLS certificate ({cert_path})"))
+                })?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        Ok(LndInvoiceBackend {
+            rest_address: rest_address.trim_end_matches('/').to_owned(),
+            macaroon: hex::encode(macaroon),
+            client: builder
+                .build()
+                .map_err(|e| InvoiceBackendError(e.to_string()))?,
+        })
+    }
+
+    /// Sends a request to the LND REST interface and returns the parsed response.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value, InvoiceBackendError> {
+        let response = request
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+            .send()
+            .await
+            .map_err(|e| InvoiceBackendError(e.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .json::<Value>()
+            .await
+            .map_err(|e| InvoiceBackendError(e.to_string()))?;
+
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(InvoiceBackendError(format!(
+                "LND replied with {status}: {}",
+                body["message"].as_str().unwrap_or_default()
+            )))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl InvoiceBackend for LndInvoiceBackend {
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        expiry: u32,
+    ) -> Result<Invoice, InvoiceBackendError> {
+        // 64-bit integers are encoded as strings by the LND REST interface
+        let result = self
+            .send(
+                self.client
+                    .post(format!("{}/v1/invoices", self.rest_address))
+                    .json(&json!({"value_msat": amount_msat.to_string(), "memo": description, "expiry": expiry.to_string()})),
+            )
+            .await?;
+
+        Ok(Invoice {
+            bolt11: get_str(&result, "payment_request")?.to_owned(),
+            payment_hash: parse_payment_hash(&base64::decode(get_str(&result, "r_hash")?).ok())?,
+        })
+    }
+
+    async fn is_settled(&self, payment_hash: &PaymentHash) -> Result<bool, InvoiceBackendError> {
+        let result = self
+            .send(self.client.get(format!(
+                "{}/v1/invoice/{}",
+                self.rest_address,
+                hex::encode(payment_hash.0)
+            )))
+            .await?;
+
+        Ok(result["state"] == "SETTLED")
+    }
+}
+
+/// Gets a string field from an invoice backend response.
+fn get_str<'a>(response: &'a Value, field: &str) -> Result<&'a str, InvoiceBackendError> {
+    response[field]
+        .as_str()
+        .ok_or_else(|| InvoiceBackendError(format!("Missing {field} in response")))
+}
+
+/// Builds a [PaymentHash] out of the raw bytes returned by an invoice backend.
+fn parse_payment_hash(raw: &Option<Vec<u8>>) -> Result<PaymentHash, InvoiceBackendError> {
+    raw.as_deref()
+        .and_then(|raw| <[u8; 32]>::try_from(raw).ok())
+        .map(PaymentHash)
+        .ok_or_else(|| InvoiceBackendError("Wrong payment hash format".to_owned()))
+}
+
+/// Current UNIX time, in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// An invoice a user has been handed to pay for a subscription plan, pending to be paid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PendingPayment {
+    /// The name of the plan the user is paying for.
+    pub plan: String,
+    /// The invoice the user has to pay.
+    pub invoice: Invoice,
+    /// UNIX time (in seconds) when the invoice expires.
+    pub expires_at: u64,
+}
+
+impl PendingPayment {
+    /// Creates a new [PendingPayment] instance.
+    pub fn new(plan: String, invoice: Invoice, expires_at: u64) -> Self {
+        PendingPayment {
+            plan,
+            invoice,
+            expires_at,
+        }
+    }
+}
+
+/// The state of the payment for a subscription plan.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PaymentStatus {
+    /// The user has paid for the plan, so the subscription can be activated. The payment is redeemed on activation.
+    Settled(PendingPayment),
+    /// The user still has to pay the given invoice.
+    Pending(Invoice),
+}
+
+/// Component in charge of billing users for paid subscription plans.
+///
+/// There is at most one pending payment per user, which is persisted so invoices survive restarts.
+pub struct Payments {
+    /// The Lightning node issuing the invoices.
+    backend: Arc<dyn InvoiceBackend>,
+    /// For how long (in seconds) invoices are valid.
+    invoice_expiry: u32,
+    /// A [DBM] (database manager) instance. Used to persist pending payments.
+    dbm: Arc<Mutex<DBM>>,
+}
+
+impl Payments {
+    /// Creates a new [Payments] instance.
+    pub fn new(
+        backend: Arc<dyn InvoiceBackend>,
+        invoice_expiry: u32,
+        dbm: Arc<Mutex<DBM>>,
+    ) -> Self {
+        Payments {
+            backend,
+            invoice_expiry,
+            dbm,
+        }
+    }
+
+    /// Checks the payment of a given user for a given plan.
+    ///
+    /// If the user has already paid the invoice they were given, [PaymentStatus::Settled] is returned alongside the
+    /// pending payment, no matter the plan the user is asking for now: the paid plan is the one to be activated. The
+    /// pending payment is kept until the subscription is activated (see [Gatekeeper::add_update_paid_user]), so it is
+    /// not lost if the activation fails. Otherwise, the still valid invoice is returned, or a new one is issued if
+    /// there was none, it has expired or it was issued for a different plan.
+    ///
+    /// [Gatekeeper::add_update_paid_user]: crate::gatekeeper::Gatekeeper::add_update_paid_user
+    pub(crate) async fn check_payment(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        price_msat: u64,
+    ) -> Result<PaymentStatus, InvoiceBackendError> {
+        let pending = self.dbm.lock().unwrap().load_pending_payment(user_id);
+        if let Some(pending) = pending {
+            if self
+                .backend
+                .is_settled(&pending.invoice.payment_hash)
+                .await?
+            {
+                log::info!(
+                    "Payment for the {} plan received from {user_id}",
+                    pending.plan
+                );
+                return Ok(PaymentStatus::Settled(pending));
+            } else if pending.plan == plan && pending.expires_at > now() {
+                return Ok(PaymentStatus::Pending(pending.invoice));
+            }
+        }
+
+        let invoice = self
+            .backend
+            .create_invoice(
+                price_msat,
+                &format!("Watchtower subscription ({plan} plan)"),
+                self.invoice_expiry,
+            )
+            .await?;
+        let pending = PendingPayment::new(
+            plan.to_owned(),
+            invoice.clone(),
+            now() + self.invoice_expiry as u64,
+        );
+        self.dbm
+            .lock()
+            .unwrap()
+            .store_pending_payment(user_id, &pending)
+            .map_err(|e| InvoiceBackendError(format!("Cannot store pending payment: {e:?}")))?;
+
+        Ok(PaymentStatus::Pending(invoice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::test_utils::{get_random_user_id, run_cln_rpc_mock};
+
+    use crate::gatekeeper::UserInfo;
+    use crate::test_utils::MockInvoiceBackend;
+
+    const PRICE: u64 = 1000;
+    const INVOICE_EXPIRY: u32 = 3600;
+
+    fn init_payments(backend: Arc<MockInvoiceBackend>) -> Payments {
+        Payments::new(
+            backend,
+            INVOICE_EXPIRY,
+            Arc::new(Mutex::new(DBM::in_memory().unwrap())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_check_payment() {
+        let backend = Arc::new(MockInvoiceBackend::new());
+        let payments = init_payments(backend.clone());
+        let user_id = get_random_user_id();
+
+        // The first request gets an invoice, which is persisted
+        let invoice = match payments.check_payment(user_id, "premium", PRICE).await {
+            Ok(PaymentStatus::Pending(invoice)) => invoice,
+            x => panic!("Unexpected payment status: {:?}", x),
+        };
+        assert_eq!(backend.get_amount(&invoice.payment_hash), Some(PRICE));
+        let pending = payments
+            .dbm
+            .lock()
+            .unwrap()
+            .load_pending_payment(user_id)
+            .unwrap();
+        assert_eq!(pending.plan, "premium");
+        assert_eq!(pending.invoice, invoice);
+
+        // Asking again before paying returns the same invoice
+        assert_eq!(
+            payments.check_payment(user_id, "premium", PRICE).await,
+            Ok(PaymentStatus::Pending(invoice.clone()))
+        );
+
+        // Once paid, the payment is settled. The pending payment is kept until it is redeemed
+        backend.settle(&invoice.payment_hash);
+        assert_eq!(
+            payments.check_payment(user_id, "premium", PRICE).await,
+            Ok(PaymentStatus::Settled(pending.clone()))
+        );
+        assert_eq!(
+            payments.dbm.lock().unwrap().load_pending_payment(user_id),
+            Some(pending.clone())
+        );
+
+        // A redeemed payment cannot be used twice
+        payments
+            .dbm
+            .lock()
+            .unwrap()
+            .store_paid_user(
+                user_id,
+                &UserInfo::new(PRICE as u32, 0, 1),
+                &invoice.payment_hash,
+            )
+            .unwrap();
+        assert!(matches!(
+            payments.check_payment(user_id, "premium", PRICE).await,
+            Ok(PaymentStatus::Pending(new_invoice)) if new_invoice != invoice
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_payment_different_plan() {
+        let backend = Arc::new(MockInvoiceBackend::new());
+        let payments = init_payments(backend.clone());
+        let user_id = get_random_user_id();
+
+        let invoice = match payments.check_payment(user_id, "premium", PRICE).await {
+            Ok(PaymentStatus::Pending(invoice)) => invoice,
+            x => panic!("Unexpected payment status: {:?}", x),
+        };
+
+        // Paying for a plan and asking for a different one settles the paid plan, which is not overwritten
+        backend.settle(&invoice.payment_hash);
+        match payments.check_payment(user_id, "gold", PRICE * 2).await {
+            Ok(PaymentStatus::Settled(pending)) => {
+                assert_eq!(pending.plan, "premium");
+                assert_eq!(pending.invoice, invoice);
+            }
+            x => panic!("Unexpected payment status: {:?}", x),
+        }
+
+        // An unpaid invoice for a different plan is replaced though
+        let user_id = get_random_user_id();
+        let invoice = match payments.check_payment(user_id, "premium", PRICE).await {
+            Ok(PaymentStatus::Pending(invoice)) => invoice,
+            x => panic!("Unexpected payment status: {:?}", x),
+        };
+        match payments.check_payment(user_id, "gold", PRICE * 2).await {
+            Ok(PaymentStatus::Pending(new_invoice)) => {
+                assert_ne!(new_invoice, invoice);
+                assert_eq!(
+                    backend.get_amount(&new_invoice.payment_hash),
+                    Some(PRICE * 2)
+                );
+            }
+            x => panic!("Unexpected payment status: {:?}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_payment_expired_invoice() {
+        let backend = Arc::new(MockInvoiceBackend::new());
+        let payments = init_payments(backend.clone());
+        let user_id = get_random_user_id();
+
+        let invoice = match payments.check_payment(user_id, "premium", PRICE).await {
+            Ok(PaymentStatus::Pending(invoice)) => invoice,
+            x => panic!("Unexpected payment status: {:?}", x),
+        };
+
+        // Expire the pending payment. A new invoice is issued
+        let mut pending = payments
+            .dbm
+            .lock()
+            .unwrap()
+            .load_pending_payment(user_id)
+            .unwrap();
+        pending.expires_at = now() - 1;
+        payments
+            .dbm
+            .lock()
+            .unwrap()
+            .store_pending_payment(user_id, &pending)
+            .unwrap();
+
+        assert!(matches!(
+            payments.check_payment(user_id, "premium", PRICE).await,
+            Ok(PaymentStatus::Pending(new_invoice)) if new_invoice != invoice
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_payment_backend_error() {
+        let backend = Arc::new(MockInvoiceBackend::new());
+        let payments = init_payments(backend.clone());
+        let user_id = get_random_user_id();
+
+        backend.set_unreachable();
+        assert!(matches!(
+            payments.check_payment(user_id, "premium", PRICE).await,
+            Err(InvoiceBackendError(_))
+        ));
+        assert_eq!(
+            payments.dbm.lock().unwrap().load_pending_payment(user_id),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cln_invoice_backend() {
+        let payment_hash = [7; 32];
+        let path = run_cln_rpc_mock(move |method, params| match method {
+            "invoice" => {
+                assert_eq!(params["amount_msat"], PRICE);
+                assert_eq!(params["expiry"], INVOICE_EXPIRY);
+                json!({"result": {"bolt11": "lnbcrt10n1", "payment_hash": hex::encode(payment_hash)}})
+            }
+            "listinvoices" => {
+                assert_eq!(params["payment_hash"], hex::encode(payment_hash));
+                json!({"result": {"invoices": [{"status": "paid"}]}})
+            }
+            _ => json!({"error": {"code": -32601, "message": "Unknown command"}}),
+        })
+        .await;
+        let backend = ClnInvoiceBackend::new(path.to_str().unwrap());
+
+        let invoice = backend
+            .create_invoice(PRICE, "description", INVOICE_EXPIRY)
+            .await
+            .unwrap();
+        assert_eq!(
+            invoice,
+            Invoice {
+                bolt11: "lnbcrt10n1".to_owned(),
+                payment_hash: PaymentHash(payment_hash)
+            }
+        );
+        assert!(backend.is_settled(&invoice.payment_hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lnd_invoice_backend() {
+        let payment_hash = [7; 32];
+        let macaroon = tempdir::TempDir::new("lnd").unwrap();
+        let macaroon_path = macaroon.path().join("invoice.macaroon");
+        std::fs::write(&macaroon_path, [1, 2, 3]).unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        let create_mock = server
+            .mock("POST", "/v1/invoices")
+            .match_header("Grpc-Metadata-macaroon", "010203")
+            .match_body(mockito::Matcher::PartialJson(
+                json!({"value_msat": PRICE.to_string()}),
+            ))
+            .with_body(
+                json!({"payment_request": "lnbcrt10n1", "r_hash": base64::encode(&payment_hash)})
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+        let lookup_mock = server
+            .mock(
+                "GET",
+                format!("/v1/invoice/{}", hex::encode(payment_hash)).as_str(),
+            )
+            .with_body(json!({"state": "OPEN"}).to_string())
+            .create_async()
+            .await;
+
+        let backend =
+            LndInvoiceBackend::new(&server.url(), macaroon_path.to_str().unwrap(), "").unwrap();
+        let invoice = backend
+            .create_invoice(PRICE, "description", INVOICE_EXPIRY)
+            .await
+            .unwrap();
+        assert_eq!(
+            invoice,
+            Invoice {
+                bolt11: "lnbcrt10n1".to_owned(),
+                payment_hash: PaymentHash(payment_hash)
+            }
+        );
+        assert!(!backend.is_settled(&invoice.payment_hash).await.unwrap());
+
+        create_mock.assert_async().await;
+        lookup_mock.assert_async().await;
+    }
+}
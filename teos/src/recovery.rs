@@ -0,0 +1,462 @@
+//! Logic related to the RecoveryScanner, the component in charge of looking for breaches the tower may have missed while offline.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+use bitcoin::{BlockHash, Txid};
+use bitcoincore_rpc::{Client as BitcoindClient, RpcApi};
+use lightning_block_sync::poll::{Validate, ValidatedBlock, ValidatedBlockHeader};
+use lightning_block_sync::{BlockSource, BlockSourceError};
+
+use teos_common::appointment::Locator;
+use teos_common::UserId;
+
+use crate::dbm::DBM;
+use crate::extended_appointment::UUID;
+use crate::protos as msgs;
+
+/// The kind of evidence backing a [MissedBreach].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MissedBreachKind {
+    /// The appointment locator was found in a block the tower did not get to process.
+    Breached = 0,
+    /// The penalty transaction of a tracker matched the compact filter of a block that could not be fetched.
+    /// The penalty may have been confirmed at that height.
+    PenaltyFilterMatch = 1,
+    /// Some blocks could not be checked, so the appointment may have been triggered without the tower noticing.
+    Unverified = 2,
+}
+
+impl TryFrom<i32> for MissedBreachKind {
+    type Error = String;
+
+    fn try_from(x: i32) -> Result<Self, Self::Error> {
+        match x {
+            0 => Ok(MissedBreachKind::Breached),
+            1 => Ok(MissedBreachKind::PenaltyFilterMatch),
+            2 => Ok(MissedBreachKind::Unverified),
+            x => Err(format!("Unknown missed breach kind: {x}")),
+        }
+    }
+}
+
+impl std::str::FromStr for MissedBreachKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "breached" => Ok(MissedBreachKind::Breached),
+            "penalty_filter_match" => Ok(MissedBreachKind::PenaltyFilterMatch),
+            "unverified" => Ok(MissedBreachKind::Unverified),
+            _ => Err(format!("Unknown missed breach kind: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for MissedBreachKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            MissedBreachKind::Breached => "breached",
+            MissedBreachKind::PenaltyFilterMatch => "penalty_filter_match",
+            MissedBreachKind::Unverified => "unverified",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// An entry of the recovery report. Flags an appointment that may have been breached while the tower was offline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MissedBreach {
+    /// The identifier of the affected appointment.
+    pub uuid: UUID,
+    /// The locator of the affected appointment.
+    pub locator: Locator,
+    /// The [UserId] the appointment belongs to.
+    pub user_id: UserId,
+    /// What the tower found out about the appointment.
+    pub kind: MissedBreachKind,
+    /// The height of the block the evidence was found in. For [MissedBreachKind::Unverified] this is the highest
+    /// block that could not be checked.
+    pub height: u32,
+    /// The id of the dispute transaction, if known.
+    pub dispute_txid: Option<Txid>,
+}
+
+impl From<MissedBreach> for msgs::MissedBreach {
+    fn from(breach: MissedBreach) -> Self {
+        msgs::MissedBreach {
+            uuid: breach.uuid.to_vec(),
+            locator: breach.locator.to_vec(),
+            user_id: breach.user_id.to_vec(),
+            kind: breach.kind as i32,
+            height: breach.height,
+            dispute_txid: breach
+                .dispute_txid
+                .map(|txid| txid.to_vec())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl MissedBreach {
+    /// Creates a new [MissedBreach] instance.
+    pub fn new(
+        uuid: UUID,
+        locator: Locator,
+        user_id: UserId,
+        kind: MissedBreachKind,
+        height: u32,
+        dispute_txid: Option<Txid>,
+    ) -> Self {
+        MissedBreach {
+            uuid,
+            locator,
+            user_id,
+            kind,
+            height,
+            dispute_txid,
+        }
+    }
+}
+
+/// Component in charge of looking for breaches in blocks the tower skipped.
+///
+/// This happens if the tower has been offline past the prune height of its backend and it is forced to jump ahead
+/// (`--forceupdate`). Every block in the skipped range that can still be fetched is checked against the stored locators.
+/// Blocks that cannot be fetched are, if enabled, checked using their compact block filters (`getblockfilter`).
+///
+/// Notice filters only commit to scripts, while locators are derived from transaction ids. Therefore, filters can only be
+/// matched against the penalties held by the [Responder](crate::responder::Responder), and any appointment that was
+/// being watched while a block was missing is reported as [MissedBreachKind::Unverified].
+///
+/// The findings are persisted so they can be queried later on.
+pub struct RecoveryScanner {
+    /// A [DBM] (database manager) instance. Used to load the stored locators and persist the report.
+    dbm: Arc<Mutex<DBM>>,
+    /// A `bitcoind` RPC client used to pull compact block filters. [None] if filters are not to be used.
+    filter_source: Option<Arc<BitcoindClient>>,
+}
+
+impl RecoveryScanner {
+    /// Creates a new [RecoveryScanner] instance.
+    pub fn new(dbm: Arc<Mutex<DBM>>, filter_source: Option<Arc<BitcoindClient>>) -> Self {
+        RecoveryScanner { dbm, filter_source }
+    }
+
+    /// Scans the blocks in the range `(last_known_height, target]`, going backwards from `target`.
+    ///
+    /// The findings are persisted to the database. Returns how many new findings were stored (the ones reported by a
+    /// previous scan are not counted again), or an error if the headers of the range cannot be pulled from the
+    /// `block_source`.
+    pub async fn scan<B: BlockSource>(
+        &self,
+        block_source: &B,
+        last_known_height: u32,
+        target: ValidatedBlockHeader,
+    ) -> Result<usize, BlockSourceError> {
+        let mut report = Vec::new();
+        let mut missing_blocks = Vec::new();
+        let mut header = target;
+
+        while header.height > last_known_height {
+            let block_hash = header.header.block_hash();
+            match block_source
+                .get_block(&block_hash)
+                .await
+                .and_then(|block| block.validate(block_hash))
+            {
+                Ok(block) => report.extend(self.check_block(&block, header.height)),
+                Err(e) => {
+                    log::warn!(
+                        "Cannot fetch block {block_hash} (height: {}). Error: {}",
+                        header.height,
+                        e.into_inner()
+                    );
+                    missing_blocks.push((block_hash, header.height));
+                }
+            }
+
+            let prev_hash = header.header.prev_blockhash;
+            header = block_source
+                .get_header(&prev_hash, Some(header.height - 1))
+                .await?
+                .validate(prev_hash)?;
+        }
+
+        if let Some(&(_, highest_missing)) = missing_blocks.first() {
+            if self.filter_source.is_some() {
+                report.extend(self.check_filters(&missing_blocks));
+            }
+
+            // Appointments that have already been found in a block are not flagged again.
+            let breached: HashSet<UUID> = report
+                .iter()
+                .filter(|b| b.kind == MissedBreachKind::Breached)
+                .map(|b| b.uuid)
+                .collect();
+
+            let appointments = self.dbm.lock().unwrap().load_appointments(None);
+            for (uuid, appointment) in appointments {
+                if appointment.start_block <= highest_missing && !breached.contains(&uuid) {
+                    report.push(MissedBreach::new(
+                        uuid,
+                        appointment.locator(),
+                        appointment.user_id,
+                        MissedBreachKind::Unverified,
+                        highest_missing,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        let stored = self.dbm.lock().unwrap().store_missed_breaches(&report);
+        log::info!(
+            "Recovery scan completed. Blocks not available: {}. Findings: {} ({} new)",
+            missing_blocks.len(),
+            report.len(),
+            stored
+        );
+
+        Ok(stored)
+    }
+
+    /// Checks the transactions of a given block against the locators stored in the database.
+    fn check_block(&self, block: &ValidatedBlock, height: u32) -> Vec<MissedBreach> {
+        let locator_txid_map: HashMap<Locator, Txid> = block
+            .txdata
+            .iter()
+            .map(|tx| (Locator::new(tx.txid()), tx.txid()))
+            .collect();
+
+        let dbm = self.dbm.lock().unwrap();
+        let mut findings = Vec::new();
+        for locator in dbm.batch_check_locators_exist(locator_txid_map.keys().collect()) {
+            for uuid in dbm.load_uuids(locator) {
+                // Appointments that already made it to the Responder do not need any further action.
+                if dbm.tracker_exists(uuid) {
+                    continue;
+                }
+                let appointment = dbm.load_appointment(uuid).unwrap();
+                log::warn!("Missed breach found at height {height} (uuid={uuid})");
+                findings.push(MissedBreach::new(
+                    uuid,
+                    locator,
+                    appointment.user_id,
+                    MissedBreachKind::Breached,
+                    height,
+                    Some(locator_txid_map[&locator]),
+                ));
+            }
+        }
+
+        findings
+    }
+
+    /// Checks the compact filters of the given blocks against the output scripts of the penalties being tracked.
+    fn check_filters(&self, blocks: &[(BlockHash, u32)]) -> Vec<MissedBreach> {
+        let rpc = self.filter_source.as_ref().unwrap();
+        let trackers = self.dbm.lock().unwrap().load_trackers(None);
+        let mut findings = Vec::new();
+
+        if trackers.is_empty() {
+            return findings;
+        }
+
+        for (block_hash, height) in blocks {
+            let filter = match rpc.get_block_filter(block_hash) {
+                Ok(r) => r.into_filter(),
+                Err(e) => {
+                    log::warn!("Cannot get the compact filter of block {block_hash}. Error: {e:?}");
+                    continue;
+                }
+            };
+
+            for (uuid, tracker) in trackers.iter() {
+                let mut scripts = tracker
+                    .penalty_tx
+                    .output
+                    .iter()
+                    .map(|o| o.script_pubkey.as_bytes());
+                if filter.match_any(block_hash, &mut scripts).unwrap_or(false) {
+                    log::info!("Penalty of {uuid} may have been confirmed at height {height}");
+                    findings.push(MissedBreach::new(
+                        *uuid,
+                        Locator::new(tracker.dispute_tx.txid()),
+                        tracker.user_id,
+                        MissedBreachKind::PenaltyFilterMatch,
+                        *height,
+                        Some(tracker.dispute_tx.txid()),
+                    ));
+                }
+            }
+        }
+
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::blockdata::script::{Builder, Script};
+    use bitcoin::util::bip158::BlockFilter;
+    use bitcoincore_rpc::Auth;
+
+    use teos_common::cryptography::get_random_bytes;
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::responder::ConfirmationStatus;
+    use crate::test_utils::{
+        generate_dummy_appointment, generate_dummy_appointment_with_user, get_random_tracker,
+        start_server, store_appointment_and_its_user, BitcoindMock, Blockchain, MockOptions,
+        START_HEIGHT,
+    };
+
+    const LAST_KNOWN_HEIGHT: u32 = START_HEIGHT as u32 - 10;
+
+    fn init_scanner(
+        filter_source: Option<Arc<BitcoindClient>>,
+    ) -> (RecoveryScanner, Arc<Mutex<DBM>>) {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        (RecoveryScanner::new(dbm.clone(), filter_source), dbm)
+    }
+
+    #[tokio::test]
+    async fn test_scan_breached() {
+        let chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 3);
+        let (scanner, dbm) = init_scanner(None);
+
+        // Add an appointment triggered in the skipped range, one triggered before it and one not triggered at all
+        let breach_height = LAST_KNOWN_HEIGHT as usize + 5;
+        let dispute_txid = chain.blocks[breach_height].txdata[1].txid();
+        let breached = generate_dummy_appointment(Some(&dispute_txid));
+        let old_dispute_txid = chain.blocks[LAST_KNOWN_HEIGHT as usize].txdata[0].txid();
+        let old = generate_dummy_appointment(Some(&old_dispute_txid));
+        let not_breached = generate_dummy_appointment(None);
+        for appointment in [&breached, &old, &not_breached] {
+            store_appointment_and_its_user(&mut dbm.lock().unwrap(), appointment);
+        }
+
+        assert_eq!(
+            scanner
+                .scan(&chain, LAST_KNOWN_HEIGHT, chain.tip())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            dbm.lock().unwrap().load_missed_breaches(),
+            vec![MissedBreach::new(
+                breached.uuid(),
+                breached.locator(),
+                breached.user_id,
+                MissedBreachKind::Breached,
+                breach_height as u32,
+                Some(dispute_txid)
+            )]
+        );
+
+        // Scanning again does not duplicate the report, nor counts the findings twice
+        assert_eq!(
+            scanner
+                .scan(&chain, LAST_KNOWN_HEIGHT, chain.tip())
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(dbm.lock().unwrap().load_missed_breaches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan_missing_blocks() {
+        let missing_from = START_HEIGHT - 3;
+        let chain = Blockchain::default()
+            .with_height_and_txs(START_HEIGHT, 3)
+            .without_blocks(missing_from..);
+        let (scanner, dbm) = init_scanner(None);
+
+        // An appointment triggered in an available block is reported as breached. The rest of appointments
+        // that were already being watched when the blocks went missing are reported as unverified.
+        let user_id = get_random_user_id();
+        let dispute_txid = chain.blocks[missing_from - 1].txdata[0].txid();
+        let breached = generate_dummy_appointment_with_user(user_id, Some(&dispute_txid)).1;
+        let mut unverified = generate_dummy_appointment_with_user(user_id, None).1;
+        unverified.start_block = LAST_KNOWN_HEIGHT;
+        let mut too_recent = generate_dummy_appointment_with_user(user_id, None).1;
+        too_recent.start_block = START_HEIGHT as u32 + 1;
+        for appointment in [&breached, &unverified, &too_recent] {
+            store_appointment_and_its_user(&mut dbm.lock().unwrap(), appointment);
+        }
+
+        assert_eq!(
+            scanner
+                .scan(&chain, LAST_KNOWN_HEIGHT, chain.tip())
+                .await
+                .unwrap(),
+            2
+        );
+        let report = dbm.lock().unwrap().load_missed_breaches();
+        assert!(report.contains(&MissedBreach::new(
+            breached.uuid(),
+            breached.locator(),
+            user_id,
+            MissedBreachKind::Breached,
+            missing_from as u32 - 1,
+            Some(dispute_txid)
+        )));
+        assert!(report.contains(&MissedBreach::new(
+            unverified.uuid(),
+            unverified.locator(),
+            user_id,
+            MissedBreachKind::Unverified,
+            START_HEIGHT as u32,
+            None
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_scan_block_filters() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+
+        // Store a tracker and mine its penalty in a block that will not be available. The penalty is given a unique
+        // script so it does not match the rest of the blocks
+        let user_id = get_random_user_id();
+        let mut tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(100));
+        tracker.penalty_tx.output[0].script_pubkey = Builder::new()
+            .push_slice(&get_random_bytes(20))
+            .into_script();
+        let appointment = generate_dummy_appointment_with_user(user_id, None).1;
+        let uuid = appointment.uuid();
+
+        let block = chain.generate(Some(vec![tracker.penalty_tx.clone()]));
+        let chain = chain.without_blocks(START_HEIGHT + 1..);
+        let filter = BlockFilter::new_script_filter(&block, |_| Ok(Script::new())).unwrap();
+
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_block_filter(&filter));
+        let rpc = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock.server);
+
+        let (scanner, dbm) = init_scanner(Some(rpc));
+        store_appointment_and_its_user(&mut dbm.lock().unwrap(), &appointment);
+        dbm.lock().unwrap().store_tracker(uuid, &tracker).unwrap();
+
+        scanner
+            .scan(&chain, START_HEIGHT as u32, chain.tip())
+            .await
+            .unwrap();
+        assert_eq!(
+            dbm.lock().unwrap().load_missed_breaches(),
+            vec![MissedBreach::new(
+                uuid,
+                Locator::new(tracker.dispute_tx.txid()),
+                user_id,
+                MissedBreachKind::PenaltyFilterMatch,
+                START_HEIGHT as u32 + 1,
+                Some(tracker.dispute_tx.txid())
+            )]
+        );
+    }
+}
@@ -0,0 +1,899 @@
+//! Logic related to hot-standby replication.
+//!
+//! A primary tower records the changes made to its users (alongside their access lists, backups, pending payments and
+//! response receipts), registration tokens, appointments and trackers in a replication log (see
+//! [DBM::set_replication_log]) and streams them to its standbys through the private API. A standby applies the changes
+//! to its own database and, once the primary has not been heard of for a while, takes over using the same tower key.
+//! Standbys monitor the chain on their own (with broadcasting disabled) as soon as they are in sync, so they are up to
+//! date when taking over.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tonic::transport::Endpoint;
+use tonic::{Code, Status};
+use triggered::Trigger;
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::BlockHash;
+
+use teos_common::dbm::DatabaseConnection;
+use teos_common::UserId;
+
+use crate::dbm::{DBReader, DBM};
+use crate::extended_appointment::UUID;
+use crate::protos as msgs;
+use crate::protos::private_tower_services_client::PrivateTowerServicesClient;
+use crate::protos::replication_event::Event;
+use crate::snapshot::{self, ExportRecord};
+
+/// Maximum number of changes loaded from the replication log at once.
+const CHANGES_BATCH_SIZE: u32 = 1000;
+
+/// Maximum time between two checks of the replication log.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time between two attempts to reach the primary tower.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Packs the reasons why following a primary tower may fail.
+#[derive(Debug)]
+pub enum ReplicationError {
+    Unreachable(String),
+    Refused(Code, String),
+    Timeout,
+    StreamClosed,
+    InvalidEvent(String),
+    DatabaseError(String),
+}
+
+impl fmt::Display for ReplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplicationError::Unreachable(e) => write!(f, "The primary tower is unreachable: {e}"),
+            ReplicationError::Refused(code, e) => {
+                write!(f, "The primary tower refused to replicate ({code:?}): {e}")
+            }
+            ReplicationError::Timeout => write!(f, "The primary tower stopped sending heartbeats"),
+            ReplicationError::StreamClosed => write!(f, "The primary tower closed the stream"),
+            ReplicationError::InvalidEvent(e) => write!(f, "Invalid replication event: {e}"),
+            ReplicationError::DatabaseError(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+/// Streams the changes of the tower database to standby towers. Served through the private API.
+#[derive(Clone)]
+pub struct ReplicationSource {
+    /// Read-only access to the database, so streaming does not contend with the writer.
+    db_reader: DBReader,
+    /// The tower secret key. Handed to standbys on resync so they can take over.
+    tower_sk: SecretKey,
+    /// How often a heartbeat is sent while there are no changes.
+    heartbeat_interval: Duration,
+}
+
+impl ReplicationSource {
+    /// Creates a new [ReplicationSource] instance.
+    pub fn new(db_reader: DBReader, tower_sk: SecretKey, heartbeat_interval: Duration) -> Self {
+        Self {
+            db_reader,
+            tower_sk,
+            heartbeat_interval,
+        }
+    }
+
+    /// Builds the events of a full resync: the resync itself followed by every row of the database. Returns them
+    /// alongside the sequence number they are up to date with.
+    fn resync_events(&self, dbm: &DBM) -> (u64, Vec<msgs::ReplicationEvent>) {
+        let (_, seq) = dbm.get_replication_log_bounds();
+        let mut events = vec![msgs::ReplicationEvent {
+            event: Some(Event::Resync(msgs::ReplicationResync {
+                tower_key: self.tower_sk.secret_bytes().to_vec(),
+                seq,
+            })),
+        }];
+        events.extend(snapshot::load_records(dbm).into_iter().map(|record| {
+            let (table, key) = record.key();
+            change_event(seq, table.to_owned(), key.to_vec(), Some(record))
+        }));
+
+        (seq, events)
+    }
+
+    /// Builds the events of (at most [CHANGES_BATCH_SIZE]) changes made after `after_seq`. Returns them alongside the
+    /// sequence number of the last one, or `None` if some of them are no longer in the replication log.
+    fn change_events(dbm: &DBM, after_seq: u64) -> Option<(u64, Vec<msgs::ReplicationEvent>)> {
+        let (oldest, latest) = dbm.get_replication_log_bounds();
+        if after_seq > latest || after_seq + 1 < oldest {
+            return None;
+        }
+
+        let mut seq = after_seq;
+        let events = dbm
+            .load_replication_log(after_seq, CHANGES_BATCH_SIZE)
+            .into_iter()
+            .map(|(change_seq, table, key)| {
+                seq = change_seq;
+                let record = load_record(dbm, &table, &key);
+                change_event(change_seq, table, key, record)
+            })
+            .collect();
+
+        Some((seq, events))
+    }
+
+    /// Streams the changes made to the database after `after_seq` through `tx` (preceded by a full resync if the
+    /// standby is new or too far behind), until either the standby is gone or the tower shuts down.
+    pub(crate) async fn stream(
+        self,
+        after_seq: u64,
+        tx: mpsc::Sender<Result<msgs::ReplicationEvent, Status>>,
+        shutdown: Trigger,
+    ) {
+        let poll_interval = std::cmp::min(self.heartbeat_interval, MAX_POLL_INTERVAL);
+        let mut seq = after_seq;
+        let mut resync = after_seq == 0;
+        let mut last_heartbeat: Option<Instant> = None;
+
+        while !shutdown.is_triggered() {
+            let events = {
+                let dbm = self.db_reader.get();
+                // The log and the rows are read within a transaction so they are consistent with each other
+                let events = dbm.get_connection().execute_batch("BEGIN").map(|_| {
+                    let changes = if resync {
+                        None
+                    } else {
+                        Self::change_events(&dbm, seq)
+                    };
+                    match changes {
+                        Some((change_seq, events)) => {
+                            seq = change_seq;
+                            events
+                        }
+                        None => {
+                            log::info!("Resyncing standby tower");
+                            let (resync_seq, events) = self.resync_events(&dbm);
+                            seq = resync_seq;
+                            resync = false;
+                            // Letting the standby know the resync is over straightaway
+                            last_heartbeat = None;
+                            events
+                        }
+                    }
+                });
+                dbm.get_connection().execute_batch("COMMIT").ok();
+                events
+            };
+
+            let mut events = match events {
+                Ok(events) => events,
+                Err(e) => {
+                    log::error!("Cannot read the replication log. Error: {e}");
+                    tx.send(Err(Status::new(
+                        Code::Internal,
+                        "Cannot read the replication log",
+                    )))
+                    .await
+                    .ok();
+                    return;
+                }
+            };
+
+            if events.is_empty() {
+                if matches!(last_heartbeat, Some(sent) if sent.elapsed() < self.heartbeat_interval)
+                {
+                    tokio::time::sleep(poll_interval).await;
+                    continue;
+                }
+                last_heartbeat = Some(Instant::now());
+                events.push(msgs::ReplicationEvent {
+                    event: Some(Event::Heartbeat(msgs::ReplicationHeartbeat {
+                        seq,
+                        last_known_block: self
+                            .db_reader
+                            .get()
+                            .load_last_known_block()
+                            .map(|block_hash| block_hash.to_vec())
+                            .unwrap_or_default(),
+                    })),
+                });
+            }
+
+            for event in events {
+                if tx.send(Ok(event)).await.is_err() {
+                    log::info!("Replication stopped. The standby tower is gone");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Builds the event of a change of a row. Rows with no record have been deleted.
+fn change_event(
+    seq: u64,
+    table: String,
+    key: Vec<u8>,
+    record: Option<ExportRecord>,
+) -> msgs::ReplicationEvent {
+    msgs::ReplicationEvent {
+        event: Some(Event::Change(msgs::ReplicationChange {
+            seq,
+            table,
+            key,
+            record: record
+                .map(|record| serde_json::to_string(&record).unwrap())
+                .unwrap_or_default(),
+        })),
+    }
+}
+
+/// Loads the current version of a row of one of the replicated tables, if it (still) exists.
+fn load_record(dbm: &DBM, table: &str, key: &[u8]) -> Option<ExportRecord> {
+    match table {
+        "users" => {
+            let user_id = UserId::from_slice(key).ok()?;
+            dbm.load_user(user_id)
+                .map(|user_info| ExportRecord::user(user_id, user_info))
+        }
+        "appointments" => {
+            let uuid = UUID::from_slice(key).ok()?;
+            dbm.load_appointment(uuid)
+                .map(|appointment| ExportRecord::appointment(uuid, appointment))
+        }
+        "trackers" => {
+            let uuid = UUID::from_slice(key).ok()?;
+            dbm.load_tracker(uuid)
+                .and_then(|tracker| ExportRecord::tracker(uuid, tracker))
+        }
+        "allowed_users" => {
+            let user_id = UserId::from_slice(key).ok()?;
+            dbm.load_allowed_users()
+                .contains(&user_id)
+                .then(|| ExportRecord::AllowedUser {
+                    user_id: key.to_vec(),
+                })
+        }
+        "banned_users" => {
+            let user_id = UserId::from_slice(key).ok()?;
+            dbm.load_banned_users()
+                .contains(&user_id)
+                .then(|| ExportRecord::BannedUser {
+                    user_id: key.to_vec(),
+                })
+        }
+        "registration_tokens" => {
+            let token = String::from_utf8(key.to_vec()).ok()?;
+            dbm.has_registration_token(&token)
+                .then_some(ExportRecord::RegistrationToken { token })
+        }
+        "pending_payments" => {
+            let user_id = UserId::from_slice(key).ok()?;
+            dbm.load_pending_payment(user_id)
+                .map(|pending| ExportRecord::pending_payment(user_id, pending))
+        }
+        "backups" => {
+            let user_id = UserId::from_slice(key).ok()?;
+            dbm.load_backup(user_id)
+                .map(|backup| ExportRecord::backup(user_id, backup))
+        }
+        "response_receipts" => {
+            let uuid = UUID::from_slice(key).ok()?;
+            let (user_id, receipt) = dbm.load_response_receipts(Some(uuid)).remove(&uuid)?;
+            ExportRecord::response_receipt(uuid, user_id, receipt)
+        }
+        _ => None,
+    }
+}
+
+/// Maps a database error to a [ReplicationError].
+fn database_error<E: fmt::Debug>(e: E) -> ReplicationError {
+    ReplicationError::DatabaseError(format!("{e:?}"))
+}
+
+/// Removes a row of one of the replicated tables. Rows that are already gone (e.g. deleted on cascade) are skipped.
+fn remove_row(dbm: &mut DBM, table: &str, key: &[u8]) -> Result<(), ReplicationError> {
+    let invalid_key = || ReplicationError::InvalidEvent(format!("invalid {table} key"));
+    match table {
+        "users" => {
+            dbm.batch_remove_users(&[UserId::from_slice(key).map_err(|_| invalid_key())?]);
+        }
+        "appointments" => {
+            let uuid = UUID::from_slice(key).map_err(|_| invalid_key())?;
+            if dbm.appointment_exists(uuid) {
+                dbm.remove_appointment(uuid);
+            }
+        }
+        "trackers" => {
+            let uuid = UUID::from_slice(key).map_err(|_| invalid_key())?;
+            if dbm.load_tracker(uuid).is_some() {
+                dbm.remove_tracker(uuid);
+            }
+        }
+        "allowed_users" => {
+            dbm.remove_allowed_user(UserId::from_slice(key).map_err(|_| invalid_key())?)
+                .ok();
+        }
+        "banned_users" => {
+            dbm.remove_banned_user(UserId::from_slice(key).map_err(|_| invalid_key())?)
+                .ok();
+        }
+        "registration_tokens" => {
+            let token = String::from_utf8(key.to_vec()).map_err(|_| invalid_key())?;
+            dbm.remove_registration_token(&token).ok();
+        }
+        "pending_payments" => {
+            dbm.remove_pending_payment(UserId::from_slice(key).map_err(|_| invalid_key())?)
+                .ok();
+        }
+        "backups" => {
+            dbm.remove_backup(UserId::from_slice(key).map_err(|_| invalid_key())?)
+                .ok();
+        }
+        "response_receipts" => {
+            dbm.remove_response_receipt(UUID::from_slice(key).map_err(|_| invalid_key())?)
+                .ok();
+        }
+        _ => {
+            return Err(ReplicationError::InvalidEvent(format!(
+                "unknown table {table}"
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// A tower following a primary. See [Standby::follow].
+pub struct Standby {
+    /// The standby database, where the changes of the primary are applied.
+    dbm: Arc<Mutex<DBM>>,
+    /// The private API endpoint of the primary tower.
+    primary: Endpoint,
+    /// How long the primary can go unheard of before the standby takes over.
+    failover_timeout: Duration,
+    /// When the primary was last heard of.
+    last_contact: Instant,
+    /// The sequence number of the ongoing resync, if any.
+    resync_seq: Option<u64>,
+    /// Triggered once the standby is in sync with the primary, so the tower can start monitoring the chain.
+    in_sync: Trigger,
+}
+
+impl Standby {
+    /// Creates a new [Standby] instance. `in_sync` is triggered once the standby is in sync with the primary.
+    pub fn new(
+        dbm: Arc<Mutex<DBM>>,
+        primary: Endpoint,
+        failover_timeout: Duration,
+        in_sync: Trigger,
+    ) -> Self {
+        Self {
+            dbm,
+            primary,
+            failover_timeout,
+            last_contact: Instant::now(),
+            resync_seq: None,
+            in_sync,
+        }
+    }
+
+    /// Follows the primary tower, applying the changes it streams, until it has been unreachable (or silent) for
+    /// longer than the failover timeout. Returns once the standby has to take over.
+    ///
+    /// A standby that is not in sync with its primary (e.g. it has never completed a resync) has no data to take over
+    /// with, so it keeps trying to reach the primary instead.
+    pub async fn follow(mut self) {
+        let retry_interval = std::cmp::min(self.failover_timeout / 4, MAX_RETRY_INTERVAL);
+        let mut reported_out_of_sync = false;
+        // A standby that was in sync when it was stopped has all the data it needs to monitor the chain
+        if self.dbm.lock().unwrap().load_replication_seq().is_some() {
+            self.in_sync.trigger();
+        }
+
+        loop {
+            if let Err(e) = self.follow_stream().await {
+                log::warn!("Lost track of the primary tower. {e}");
+            }
+
+            if self.last_contact.elapsed() >= self.failover_timeout {
+                if self.dbm.lock().unwrap().load_replication_seq().is_some() {
+                    return;
+                } else if !reported_out_of_sync {
+                    log::error!("The primary tower is gone, but this tower is not in sync with it so it cannot take over. Retrying");
+                    reported_out_of_sync = true;
+                }
+            }
+            tokio::time::sleep(retry_interval).await;
+        }
+    }
+
+    /// Requests the changes of the primary tower and applies them until the stream breaks.
+    async fn follow_stream(&mut self) -> Result<(), ReplicationError> {
+        let channel = tokio::time::timeout(self.failover_timeout, self.primary.connect())
+            .await
+            .map_err(|_| ReplicationError::Timeout)?
+            .map_err(|e| ReplicationError::Unreachable(e.to_string()))?;
+
+        let after_seq = self.dbm.lock().unwrap().load_replication_seq().unwrap_or(0);
+        let mut events = match PrivateTowerServicesClient::new(channel)
+            .replicate(msgs::ReplicateRequest { after_seq })
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(status) if status.code() == Code::Unavailable => {
+                return Err(ReplicationError::Unreachable(status.message().to_owned()))
+            }
+            // A primary that refuses to replicate is still alive, so the standby must not take over
+            Err(status) => {
+                self.last_contact = Instant::now();
+                return Err(ReplicationError::Refused(
+                    status.code(),
+                    status.message().to_owned(),
+                ));
+            }
+        };
+        self.last_contact = Instant::now();
+        log::info!("Following the primary tower (after change #{after_seq})");
+
+        loop {
+            match tokio::time::timeout(self.failover_timeout, events.message()).await {
+                Ok(Ok(Some(event))) => {
+                    self.last_contact = Instant::now();
+                    self.apply(event)?;
+                }
+                Ok(Ok(None)) => return Err(ReplicationError::StreamClosed),
+                Ok(Err(status)) => {
+                    return Err(ReplicationError::Unreachable(status.message().to_owned()))
+                }
+                Err(_) => return Err(ReplicationError::Timeout),
+            }
+        }
+    }
+
+    /// Applies an event streamed by the primary tower to the standby database.
+    ///
+    /// The replication sequence number is only stored once the standby is in sync, so an interrupted resync is started
+    /// over when reconnecting. The last block processed by the primary is only adopted until then, given the standby
+    /// follows the chain on its own from there on.
+    fn apply(&mut self, event: msgs::ReplicationEvent) -> Result<(), ReplicationError> {
+        let mut dbm = self.dbm.lock().unwrap();
+
+        match event.event {
+            Some(Event::Resync(resync)) => {
+                let tower_sk = SecretKey::from_slice(&resync.tower_key)
+                    .map_err(|_| ReplicationError::InvalidEvent("invalid tower key".to_owned()))?;
+                log::info!("Resyncing with the primary tower");
+                dbm.remove_replication_seq().map_err(database_error)?;
+                dbm.clear_replicated_data().map_err(database_error)?;
+                if dbm.load_tower_key() != Some(tower_sk) {
+                    dbm.store_tower_key(&tower_sk).map_err(database_error)?;
+                }
+                self.resync_seq = Some(resync.seq);
+            }
+            Some(Event::Change(change)) => {
+                if change.record.is_empty() {
+                    remove_row(&mut dbm, &change.table, &change.key)?;
+                } else {
+                    serde_json::from_str::<ExportRecord>(&change.record)
+                        .map_err(|e| e.to_string())
+                        .and_then(|record| record.store_or_update(&mut dbm))
+                        .map_err(ReplicationError::InvalidEvent)?;
+                }
+                // Changes coming with a resync are not enough to be in sync, given the resync may be interrupted
+                if !matches!(self.resync_seq, Some(seq) if change.seq <= seq) {
+                    dbm.store_replication_seq(change.seq)
+                        .map_err(database_error)?;
+                    self.resync_seq = None;
+                    self.in_sync.trigger();
+                }
+            }
+            Some(Event::Heartbeat(heartbeat)) => {
+                if !self.in_sync.is_triggered() {
+                    if let Ok(block_hash) = BlockHash::from_slice(&heartbeat.last_known_block) {
+                        dbm.store_last_known_block(&block_hash)
+                            .map_err(database_error)?;
+                    }
+                }
+                dbm.store_replication_seq(heartbeat.seq)
+                    .map_err(database_error)?;
+                if self.resync_seq.take().is_some() {
+                    log::info!("In sync with the primary tower");
+                }
+                self.in_sync.trigger();
+            }
+            None => return Err(ReplicationError::InvalidEvent("empty event".to_owned())),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{
+        Certificate, Channel, ClientTlsConfig, Identity, Server, ServerTlsConfig,
+    };
+    use tonic::Request;
+
+    use teos_common::cryptography::get_random_keypair;
+    use teos_common::protos as common_msgs;
+    use teos_common::test_utils::{get_random_response_receipt, get_random_user_id};
+
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use lightning::ln::PaymentHash;
+
+    use teos_common::backup::Backup;
+
+    use crate::gatekeeper::UserInfo;
+    use crate::payments::{Invoice, PendingPayment};
+    use crate::protos::private_tower_services_server::PrivateTowerServicesServer;
+    use crate::protos::public_tower_services_server::PublicTowerServices;
+    use crate::responder::ConfirmationStatus;
+    use crate::test_utils::{
+        create_api_with_config, generate_dummy_appointment_with_user, get_random_tracker, ApiConfig,
+    };
+    use crate::tls::{tls_init, ClientAuthenticator, ClientCertificates, ClientRole};
+
+    /// Creates a primary database (with the replication log enabled) holding a user, an appointment and a tracker.
+    fn populated_primary() -> DBM {
+        let mut dbm = DBM::in_memory().unwrap();
+        dbm.set_replication_log(true).unwrap();
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42, 100))
+            .unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        dbm.store_tracker(
+            uuid,
+            &get_random_tracker(user_id, ConfirmationStatus::ConfirmedIn(100)),
+        )
+        .unwrap();
+
+        dbm
+    }
+
+    fn create_standby(dbm: Arc<Mutex<DBM>>) -> Standby {
+        Standby::new(
+            dbm,
+            Endpoint::from_static("https://localhost:1"),
+            Duration::from_secs(1),
+            triggered::trigger().0,
+        )
+    }
+
+    fn heartbeat(seq: u64, last_known_block: BlockHash) -> msgs::ReplicationEvent {
+        msgs::ReplicationEvent {
+            event: Some(Event::Heartbeat(msgs::ReplicationHeartbeat {
+                seq,
+                last_known_block: last_known_block.to_vec(),
+            })),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut primary = populated_primary();
+        let (tower_sk, _) = get_random_keypair();
+        let source = ReplicationSource::new(primary.reader(), tower_sk, Duration::from_secs(1));
+
+        // Whatever the standby had is dropped on resync
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        dbm.lock()
+            .unwrap()
+            .store_user(get_random_user_id(), &UserInfo::new(1, 2, 3))
+            .unwrap();
+        let mut standby = create_standby(dbm.clone());
+        let (seq, events) = source.resync_events(&primary);
+        assert_eq!(seq, 3);
+        for event in events {
+            standby.apply(event).unwrap();
+        }
+        {
+            let dbm = dbm.lock().unwrap();
+            assert_eq!(dbm.load_tower_key(), Some(tower_sk));
+            assert_eq!(dbm.load_all_users(), primary.load_all_users());
+            assert_eq!(dbm.load_trackers(None), primary.load_trackers(None));
+            // The standby is not in sync until the resync is over
+            assert_eq!(dbm.load_replication_seq(), None);
+            assert!(!standby.in_sync.is_triggered());
+        }
+        standby
+            .apply(heartbeat(seq, BlockHash::hash(&[1, 2, 3])))
+            .unwrap();
+        assert_eq!(dbm.lock().unwrap().load_replication_seq(), Some(seq));
+        assert!(standby.in_sync.is_triggered());
+        assert_eq!(
+            dbm.lock().unwrap().load_last_known_block(),
+            Some(BlockHash::hash(&[1, 2, 3]))
+        );
+
+        // Once in sync, the standby follows the chain on its own
+        standby
+            .apply(heartbeat(seq, BlockHash::hash(&[4, 5, 6])))
+            .unwrap();
+        assert_eq!(
+            dbm.lock().unwrap().load_last_known_block(),
+            Some(BlockHash::hash(&[1, 2, 3]))
+        );
+
+        // Later changes (including deletions) are applied one by one
+        let (uuid, _) = primary.load_trackers(None).into_iter().next().unwrap();
+        primary.remove_appointment(uuid);
+        let user_id = get_random_user_id();
+        primary
+            .store_user(user_id, &UserInfo::new(1, 2, 3))
+            .unwrap();
+        primary.update_user(user_id, &UserInfo::new(4, 5, 6));
+        let (seq, events) = ReplicationSource::change_events(&primary, seq).unwrap();
+        for event in events {
+            standby.apply(event).unwrap();
+        }
+        let dbm = dbm.lock().unwrap();
+        assert_eq!(dbm.load_replication_seq(), Some(seq));
+        assert_eq!(dbm.load_all_users(), primary.load_all_users());
+        assert_eq!(dbm.load_appointments(None), primary.load_appointments(None));
+        assert!(dbm.load_trackers(None).is_empty());
+    }
+
+    #[test]
+    fn test_apply_user_data() {
+        // Access lists, registration tokens, payments, backups and receipts are replicated alongside the users
+        let mut primary = populated_primary();
+        let user_id = primary.load_all_users().into_keys().next().unwrap();
+        let uuid = primary.load_trackers(None).into_keys().next().unwrap();
+        let (tower_sk, _) = get_random_keypair();
+        primary.store_allowed_user(user_id).unwrap();
+        primary.store_banned_user(get_random_user_id()).unwrap();
+        // Tokens that look like numbers are not coerced into integers
+        primary.store_registration_token("1234").unwrap();
+        primary.store_registration_token("token").unwrap();
+        let payment_hash = PaymentHash([1; 32]);
+        primary
+            .store_pending_payment(
+                user_id,
+                &PendingPayment::new(
+                    "premium".to_owned(),
+                    Invoice {
+                        bolt11: "lnbcrt10n1".to_owned(),
+                        payment_hash,
+                    },
+                    42,
+                ),
+            )
+            .unwrap();
+        primary
+            .store_backup(user_id, &Backup::new(vec![1, 2, 3], 1))
+            .unwrap();
+        primary
+            .store_response_receipt(uuid, user_id, &get_random_response_receipt(tower_sk))
+            .unwrap();
+
+        let source = ReplicationSource::new(primary.reader(), tower_sk, Duration::from_secs(1));
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let mut standby = create_standby(dbm.clone());
+        let (seq, events) = source.resync_events(&primary);
+        for event in events {
+            standby.apply(event).unwrap();
+        }
+        let assert_replicated = |dbm: &DBM, primary: &DBM| {
+            assert_eq!(dbm.load_allowed_users(), primary.load_allowed_users());
+            assert_eq!(dbm.load_banned_users(), primary.load_banned_users());
+            assert_eq!(
+                HashSet::<String>::from_iter(dbm.load_registration_tokens()),
+                HashSet::from_iter(primary.load_registration_tokens())
+            );
+            assert_eq!(dbm.load_pending_payments(), primary.load_pending_payments());
+            assert_eq!(dbm.load_backups(), primary.load_backups());
+            assert_eq!(
+                dbm.load_response_receipts(None),
+                primary.load_response_receipts(None)
+            );
+        };
+        assert_replicated(&dbm.lock().unwrap(), &primary);
+
+        // Redemptions and removals are replicated too
+        primary.remove_allowed_user(user_id).unwrap();
+        primary.remove_registration_token("1234").unwrap();
+        primary
+            .store_paid_user(user_id, &UserInfo::new(1, 2, 3), &payment_hash)
+            .unwrap();
+        primary
+            .store_backup(user_id, &Backup::new(vec![4, 5, 6], 2))
+            .unwrap();
+        let (_, events) = ReplicationSource::change_events(&primary, seq).unwrap();
+        for event in events {
+            standby.apply(event).unwrap();
+        }
+        let dbm = dbm.lock().unwrap();
+        assert!(dbm.load_allowed_users().is_empty());
+        assert_eq!(dbm.load_registration_tokens(), vec!["token".to_owned()]);
+        assert!(dbm.load_pending_payments().is_empty());
+        assert_replicated(&dbm, &primary);
+    }
+
+    #[test]
+    fn test_change_events_out_of_log() {
+        let primary = populated_primary();
+        let (_, latest) = primary.get_replication_log_bounds();
+        assert!(ReplicationSource::change_events(&primary, latest).is_some());
+        assert!(ReplicationSource::change_events(&primary, latest + 1).is_none());
+
+        // Changes that are no longer in the log cannot be streamed
+        primary.set_replication_log(false).unwrap();
+        primary.set_replication_log(true).unwrap();
+        assert!(ReplicationSource::change_events(&primary, latest).is_some());
+        assert!(ReplicationSource::change_events(&primary, latest - 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let primary = populated_primary();
+        let source = ReplicationSource::new(
+            primary.reader(),
+            get_random_keypair().0,
+            Duration::from_millis(50),
+        );
+        let (tx, mut rx) = mpsc::channel(10);
+        let (shutdown_trigger, _) = triggered::trigger();
+        let task = tokio::spawn(source.stream(0, tx, shutdown_trigger.clone()));
+
+        // A new standby gets a resync (a user, an appointment and a tracker) followed by a heartbeat
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            events.push(rx.recv().await.unwrap().unwrap().event.unwrap());
+        }
+        assert!(matches!(
+            events[0],
+            Event::Resync(msgs::ReplicationResync { seq: 3, .. })
+        ));
+        assert!(events[1..4]
+            .iter()
+            .all(|event| matches!(event, Event::Change(change) if change.seq == 3)));
+        assert!(matches!(
+            events[4],
+            Event::Heartbeat(msgs::ReplicationHeartbeat { seq: 3, .. })
+        ));
+
+        // Changes are streamed as they happen
+        let user_id = get_random_user_id();
+        primary
+            .store_user(user_id, &UserInfo::new(1, 2, 3))
+            .unwrap();
+        loop {
+            match rx.recv().await.unwrap().unwrap().event.unwrap() {
+                Event::Change(change) => {
+                    assert_eq!(change.seq, 4);
+                    assert_eq!(change.key, user_id.to_vec());
+                    break;
+                }
+                event => assert!(matches!(event, Event::Heartbeat(_))),
+            }
+        }
+
+        // The stream stops with the tower
+        shutdown_trigger.trigger();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_follow_and_take_over() {
+        let tmp_dir = tempdir::TempDir::new("teos_replication").unwrap();
+        let (identity, ca_cert) = tls_init(tmp_dir.path(), &[]).unwrap();
+        let client_certificates =
+            Arc::new(ClientCertificates::new(tmp_dir.path().to_path_buf()).unwrap());
+        let (tower_sk, _) = get_random_keypair();
+        let (primary, _s) = create_api_with_config(
+            ApiConfig::default()
+                .client_certificates(client_certificates.clone())
+                .replication(tower_sk, Duration::from_millis(100)),
+        )
+        .await;
+        let standby_certificate = client_certificates
+            .issue("standby", ClientRole::Admin)
+            .unwrap();
+
+        // Serve the private API of the primary
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_port = listener.local_addr().unwrap().port();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let server = tokio::spawn(
+            Server::builder()
+                .tls_config(
+                    ServerTlsConfig::new()
+                        .identity(identity)
+                        .client_ca_root(Certificate::from_pem(&ca_cert)),
+                )
+                .unwrap()
+                .add_service(PrivateTowerServicesServer::with_interceptor(
+                    primary.clone(),
+                    ClientAuthenticator::new(client_certificates),
+                ))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown_signal),
+        );
+        let endpoint = Channel::from_shared(format!("https://localhost:{rpc_port}"))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .domain_name("localhost")
+                    .ca_certificate(Certificate::from_pem(&ca_cert))
+                    .identity(Identity::from_pem(
+                        standby_certificate.certificate,
+                        standby_certificate.key,
+                    )),
+            )
+            .unwrap();
+        let mut client = PrivateTowerServicesClient::new(endpoint.connect().await.unwrap());
+
+        // Follow the primary from a standby with its own database
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (in_sync_trigger, in_sync) = triggered::trigger();
+        let standby = tokio::spawn(
+            Standby::new(
+                dbm.clone(),
+                endpoint,
+                Duration::from_millis(500),
+                in_sync_trigger,
+            )
+            .follow(),
+        );
+        let wait_for = |condition: Box<dyn Fn(&DBM) -> bool + Send>| {
+            let dbm = dbm.clone();
+            tokio::time::timeout(Duration::from_secs(10), async move {
+                while !condition(&dbm.lock().unwrap()) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+        };
+
+        // The standby gets in sync with the primary
+        tokio::time::timeout(Duration::from_secs(10), in_sync)
+            .await
+            .unwrap();
+
+        // Users registered with the primary make it to the standby, and so do their deletions
+        let user_id = get_random_user_id();
+        primary
+            .register(Request::new(common_msgs::RegisterRequest {
+                user_id: user_id.to_vec(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        wait_for(Box::new(move |dbm| dbm.load_user(user_id).is_some()))
+            .await
+            .unwrap();
+        client
+            .delete_user(msgs::UserRequest {
+                user_id: user_id.to_vec(),
+            })
+            .await
+            .unwrap();
+        wait_for(Box::new(move |dbm| dbm.load_user(user_id).is_none()))
+            .await
+            .unwrap();
+        assert!(!standby.is_finished());
+
+        // Once the primary is gone, the standby takes over with the same tower key
+        client.stop(Request::new(())).await.unwrap();
+        shutdown_trigger.trigger();
+        server.await.unwrap().unwrap();
+        tokio::time::timeout(Duration::from_secs(10), standby)
+            .await
+            .unwrap()
+            .unwrap();
+        let dbm = dbm.lock().unwrap();
+        assert_eq!(dbm.load_tower_key(), Some(tower_sk));
+        assert!(dbm.load_replication_seq().is_some());
+    }
+}
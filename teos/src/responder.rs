@@ -1,23 +1,25 @@
 //! Logic related to the Responder, the components in charge of making sure breaches get properly punished.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
+use bitcoin::secp256k1::SecretKey;
 use bitcoin::{consensus, BlockHash};
 use bitcoin::{BlockHeader, Transaction, Txid};
 use lightning::chain;
-use lightning_block_sync::poll::ValidatedBlock;
 
+use teos_common::appointment::{FailureKind, Locator};
 use teos_common::constants;
 use teos_common::protos as common_msgs;
+use teos_common::receipts::ResponseReceipt;
 use teos_common::UserId;
 
 use crate::carrier::Carrier;
-use crate::dbm::DBM;
+use crate::dbm::{DBReader, DBM};
 use crate::extended_appointment::UUID;
 use crate::gatekeeper::Gatekeeper;
-use crate::tx_index::TxIndex;
-use crate::watcher::Breach;
+use crate::tx_index::{BlockTxids, TxIndex};
+use crate::watcher::{AppointmentOutcome, Breach};
 
 /// Number of missed confirmations to wait before rebroadcasting a transaction.
 const CONFIRMATIONS_BEFORE_RETRY: u8 = 6;
@@ -54,6 +56,14 @@ impl ConfirmationStatus {
         }
     }
 
+    /// Gets the height at which the transaction was confirmed or accepted to mempool (if any).
+    pub fn height(&self) -> Option<u32> {
+        match self {
+            ConfirmationStatus::ConfirmedIn(h) | ConfirmationStatus::InMempoolSince(h) => Some(*h),
+            _ => None,
+        }
+    }
+
     /// Whether the transaction was accepted by the underlying node.
     pub fn accepted(&self) -> bool {
         matches!(
@@ -100,18 +110,192 @@ impl From<TransactionTracker> for common_msgs::Tracker {
     }
 }
 
+/// The progress of a tracker's penalty transaction towards being irrevocably resolved, as reported to users.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PenaltyProgress {
+    /// The height since the penalty has been in mempool (if unconfirmed).
+    pub in_mempool_since: Option<u32>,
+    /// The number of confirmations of the penalty.
+    pub confirmations: u32,
+    /// The number of times the penalty has been rebroadcast.
+    pub rebroadcast_attempts: u32,
+}
+
+impl PenaltyProgress {
+    /// Builds the progress of a tracked penalty given its status and the current height.
+    pub fn new(status: ConfirmationStatus, rebroadcast_attempts: u32, current_height: u32) -> Self {
+        let (in_mempool_since, confirmations) = match status {
+            ConfirmationStatus::InMempoolSince(h) => (Some(h), 0),
+            ConfirmationStatus::ConfirmedIn(h) => (None, confirmations_at(h, current_height)),
+            _ => (None, 0),
+        };
+        PenaltyProgress {
+            in_mempool_since,
+            confirmations,
+            rebroadcast_attempts,
+        }
+    }
+
+    /// Builds the progress of a penalty that is not tracked anymore given the height it was confirmed in (if any).
+    /// The number of rebroadcast attempts is not kept once the tower stops tracking the penalty.
+    pub fn untracked(confirmation_height: Option<u32>, current_height: u32) -> Self {
+        PenaltyProgress {
+            in_mempool_since: None,
+            confirmations: confirmation_height.map_or(0, |h| confirmations_at(h, current_height)),
+            rebroadcast_attempts: 0,
+        }
+    }
+}
+
+/// Computes the number of confirmations of a transaction confirmed at `height`.
+fn confirmations_at(height: u32, current_height: u32) -> u32 {
+    (current_height + 1).saturating_sub(height)
+}
+
+impl From<PenaltyProgress> for common_msgs::PenaltyProgress {
+    fn from(p: PenaltyProgress) -> Self {
+        common_msgs::PenaltyProgress {
+            in_mempool_since: p.in_mempool_since.unwrap_or(0),
+            confirmations: p.confirmations,
+            rebroadcast_attempts: p.rebroadcast_attempts,
+        }
+    }
+}
+
 /// A struct that packages the summary of a tracker's penalty transaction.
 #[derive(Debug, PartialEq)]
 pub(crate) struct PenaltySummary {
     pub penalty_txid: Txid,
     pub status: ConfirmationStatus,
+    pub user_id: UserId,
 }
 
 impl PenaltySummary {
-    pub fn new(penalty_txid: Txid, status: ConfirmationStatus) -> Self {
+    pub fn new(penalty_txid: Txid, status: ConfirmationStatus, user_id: UserId) -> Self {
         PenaltySummary {
             penalty_txid,
             status,
+            user_id,
+        }
+    }
+}
+
+/// In-memory indexes over the trackers held by the [Responder].
+///
+/// They allow processing a block by only touching the trackers it affects (the ones whose penalty got confirmed and the
+/// ones reaching [IRREVOCABLY_RESOLVED](constants::IRREVOCABLY_RESOLVED) confirmations) instead of every tracker in the
+/// database. The indexes are built from the database on bootstrap.
+///
+/// Trackers deleted alongside their users (e.g. when a subscription is outdated) are dropped through
+/// [TrackerIndex::remove_users].
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TrackerIndex {
+    /// The trackers indexed by their penalty transaction id. A penalty may be shared by several trackers.
+    by_penalty: HashMap<Txid, HashSet<UUID>>,
+    /// The confirmed trackers indexed by the height of the block their penalty was confirmed in.
+    by_confirmation_height: HashMap<u32, HashSet<UUID>>,
+    /// The trackers indexed by the user they belong to.
+    by_user: HashMap<UserId, HashSet<UUID>>,
+    /// The owner, penalty transaction id and confirmation height (if confirmed) of every indexed tracker.
+    trackers: HashMap<UUID, (UserId, Txid, Option<u32>)>,
+}
+
+impl TrackerIndex {
+    /// Builds the indexes from the penalty summaries loaded from the database.
+    fn new(summaries: HashMap<UUID, PenaltySummary>) -> Self {
+        let mut index = TrackerIndex::default();
+        for (uuid, summary) in summaries {
+            index.insert(uuid, summary.user_id, summary.penalty_txid, summary.status);
+        }
+        index
+    }
+
+    /// Adds a tracker to the indexes.
+    fn insert(
+        &mut self,
+        uuid: UUID,
+        user_id: UserId,
+        penalty_txid: Txid,
+        status: ConfirmationStatus,
+    ) {
+        self.by_penalty
+            .entry(penalty_txid)
+            .or_default()
+            .insert(uuid);
+        self.by_user.entry(user_id).or_default().insert(uuid);
+        self.trackers.insert(uuid, (user_id, penalty_txid, None));
+        if let ConfirmationStatus::ConfirmedIn(h) = status {
+            self.set_confirmation_height(uuid, Some(h));
+        }
+    }
+
+    /// Updates the confirmation height of a tracker. [None] means the penalty is not confirmed.
+    fn set_confirmation_height(&mut self, uuid: UUID, height: Option<u32>) {
+        if let Some((_, _, current)) = self.trackers.get_mut(&uuid) {
+            if let Some(h) = current.take() {
+                Self::remove_from(&mut self.by_confirmation_height, &h, &uuid);
+            }
+            if let Some(h) = height {
+                self.by_confirmation_height
+                    .entry(h)
+                    .or_default()
+                    .insert(uuid);
+            }
+            *current = height;
+        }
+    }
+
+    /// Removes a tracker from the indexes.
+    fn remove(&mut self, uuid: &UUID) {
+        if let Some((user_id, penalty_txid, height)) = self.trackers.remove(uuid) {
+            Self::remove_from(&mut self.by_penalty, &penalty_txid, uuid);
+            Self::remove_from(&mut self.by_user, &user_id, uuid);
+            if let Some(h) = height {
+                Self::remove_from(&mut self.by_confirmation_height, &h, uuid);
+            }
+        }
+    }
+
+    /// Removes the trackers of the users not satisfying `is_kept` from the indexes.
+    fn remove_users<F: Fn(&UserId) -> bool>(&mut self, is_kept: F) {
+        let removed: Vec<UUID> = self
+            .by_user
+            .iter()
+            .filter(|(user_id, _)| !is_kept(user_id))
+            .flat_map(|(_, uuids)| uuids.iter().cloned())
+            .collect();
+        for uuid in removed.iter() {
+            self.remove(uuid);
+        }
+    }
+
+    /// Gets the trackers whose penalty is `penalty_txid`.
+    fn with_penalty(&self, penalty_txid: &Txid) -> Vec<UUID> {
+        self.by_penalty
+            .get(penalty_txid)
+            .map(|uuids| uuids.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Gets the trackers whose penalty was confirmed at `height`.
+    fn confirmed_at(&self, height: u32) -> Vec<UUID> {
+        self.by_confirmation_height
+            .get(&height)
+            .map(|uuids| uuids.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes `uuid` from the set at `key`, dropping the set if it ends up empty.
+    fn remove_from<K: std::hash::Hash + Eq>(
+        index: &mut HashMap<K, HashSet<UUID>>,
+        key: &K,
+        uuid: &UUID,
+    ) {
+        if let Some(uuids) = index.get_mut(key) {
+            uuids.remove(uuid);
+            if uuids.is_empty() {
+                index.remove(key);
+            }
         }
     }
 }
@@ -131,28 +315,63 @@ pub struct Responder {
     gatekeeper: Arc<Gatekeeper>,
     /// A [DBM] (database manager) instance. Used to persist tracker data into disk.
     dbm: Arc<Mutex<DBM>>,
+    /// A pool of read-only connections to the database. Used by read-only paths so they don't contend with the [DBM].
+    db_reader: DBReader,
     /// A list of all the reorged trackers that might need to be republished after reorg resolution.
     reorged_trackers: Mutex<HashSet<UUID>>,
+    /// In-memory indexes used to find the trackers affected by every new block.
+    tracker_index: Mutex<TrackerIndex>,
+    /// The tower signing key. Used to sign the [ResponseReceipt]s handed to users.
+    signing_key: SecretKey,
 }
 
 impl Responder {
     /// Creates a new [Responder] instance.
     pub fn new(
-        last_n_blocs: &[ValidatedBlock],
+        last_n_blocks: &[BlockTxids],
         last_known_block_height: u32,
+        tx_index_depth: usize,
         carrier: Carrier,
         gatekeeper: Arc<Gatekeeper>,
+        signing_key: SecretKey,
         dbm: Arc<Mutex<DBM>>,
     ) -> Self {
+        let (tracker_index, db_reader) = {
+            let dbm = dbm.lock().unwrap();
+            (
+                TrackerIndex::new(dbm.load_penalties_summaries()),
+                dbm.reader(),
+            )
+        };
         Responder {
             carrier: Mutex::new(carrier),
-            tx_index: Mutex::new(TxIndex::new(last_n_blocs, last_known_block_height)),
+            tx_index: Mutex::new(TxIndex::from_txids(
+                last_n_blocks,
+                last_known_block_height,
+                tx_index_depth,
+            )),
             dbm,
+            db_reader,
             gatekeeper,
             reorged_trackers: Mutex::new(HashSet::new()),
+            tracker_index: Mutex::new(tracker_index),
+            signing_key,
         }
     }
 
+    /// Reloads the tracker indexes from the database. Used by standby towers when taking over, given their database
+    /// has been updated by the primary in the meantime.
+    pub fn reload(&self) {
+        let summaries = self.dbm.lock().unwrap().load_penalties_summaries();
+        *self.tracker_index.lock().unwrap() = TrackerIndex::new(summaries);
+    }
+
+    /// Enables or disables broadcasting through the [Carrier]. Standby towers follow the chain with broadcasting
+    /// disabled until they take over.
+    pub fn set_broadcasting(&self, broadcasting: bool) {
+        self.carrier.lock().unwrap().set_broadcasting(broadcasting)
+    }
+
     /// Returns whether the [Responder] has been created from scratch (fresh) or from backed-up data.
     pub fn is_fresh(&self) -> bool {
         self.get_trackers_count() == 0
@@ -160,7 +379,7 @@ impl Responder {
 
     /// Gets the total number of trackers in the [Responder].
     pub(crate) fn get_trackers_count(&self) -> usize {
-        self.dbm.lock().unwrap().get_trackers_count()
+        self.db_reader.get().get_trackers_count()
     }
 
     /// Checks whether the [Responder] has gone through a reorg and some transactions should to be resent.
@@ -206,6 +425,8 @@ impl Responder {
     ///
     /// Some transaction may already be confirmed by the time the tower tries to send them to the network. If that's the case,
     /// the [Responder] will simply continue tracking the job until its completion.
+    ///
+    /// A [ResponseReceipt] is issued for every tracker added, so the user can prove the tower responded to the breach.
     pub(crate) fn add_tracker(
         &self,
         uuid: UUID,
@@ -213,14 +434,29 @@ impl Responder {
         user_id: UserId,
         status: ConfirmationStatus,
     ) {
-        if self
-            .dbm
-            .lock()
-            .unwrap()
+        let dispute_txid = breach.dispute_tx.txid();
+        let penalty_txid = breach.penalty_tx.txid();
+        let dbm = self.dbm.lock().unwrap();
+        if dbm
             .store_tracker(uuid, &TransactionTracker::new(breach, user_id, status))
             .is_ok()
         {
+            self.tracker_index
+                .lock()
+                .unwrap()
+                .insert(uuid, user_id, penalty_txid, status);
             log::info!("New tracker added (uuid={uuid})");
+
+            let mut receipt = ResponseReceipt::new(
+                Locator::new(dispute_txid),
+                dispute_txid,
+                penalty_txid,
+                status.height().unwrap(),
+                None,
+            );
+            receipt.sign(&self.signing_key);
+            // Errors are already logged by the DBM. Not having a receipt does not prevent the tower from responding.
+            let _ = dbm.store_response_receipt(uuid, user_id, &receipt);
         } else {
             log::error!(
                 "Failed to store tracker in database (uuid={uuid}). It might be already stored."
@@ -228,6 +464,15 @@ impl Responder {
         }
     }
 
+    /// Drops the trackers of the given users from the in-memory indexes. Meant to be called once the users have been
+    /// deleted, given their trackers are deleted from the database alongside them.
+    pub(crate) fn forget_users(&self, users: &[UserId]) {
+        self.tracker_index
+            .lock()
+            .unwrap()
+            .remove_users(|user_id| !users.contains(user_id));
+    }
+
     /// Checks whether a given tracker can be found in the [Responder].
     pub(crate) fn has_tracker(&self, uuid: UUID) -> bool {
         self.dbm.lock().unwrap().tracker_exists(uuid)
@@ -235,41 +480,63 @@ impl Responder {
 
     /// Checks the confirmation count for the [TransactionTracker]s.
     ///
-    /// For unconfirmed transactions, it checks whether they have been confirmed or keep missing confirmations.
-    /// For confirmed transactions, nothing is done until they are completed (confirmation count reaches [IRREVOCABLY_RESOLVED](constants::IRREVOCABLY_RESOLVED))
+    /// Only the trackers affected by the block are checked: the ones whose penalty transaction is in `txids` are flagged as
+    /// confirmed, and the ones confirmed [IRREVOCABLY_RESOLVED](constants::IRREVOCABLY_RESOLVED) blocks ago are completed.
+    /// Unconfirmed transactions that keep missing confirmations are handled by [Responder::rebroadcast_stale_txs].
+    /// A [ResponseReceipt] including the confirmation height is issued for every completed tracker.
     /// Returns the set of completed trackers or [None] if none were completed.
     fn check_confirmations(&self, txids: HashSet<Txid>, current_height: u32) -> Option<Vec<UUID>> {
         let mut completed_trackers = Vec::new();
         let mut reorged_trackers = self.reorged_trackers.lock().unwrap();
         let dbm = self.dbm.lock().unwrap();
+        let mut tracker_index = self.tracker_index.lock().unwrap();
 
-        for (uuid, penalty_summary) in dbm.load_penalties_summaries() {
-            if txids.contains(&penalty_summary.penalty_txid) {
-                // First confirmation was received
-                dbm.update_tracker_status(uuid, &ConfirmationStatus::ConfirmedIn(current_height))
-                    .unwrap();
+        let just_confirmed: Vec<UUID> = txids
+            .iter()
+            .flat_map(|txid| tracker_index.with_penalty(txid))
+            .collect();
+        for uuid in just_confirmed {
+            // First confirmation was received
+            if dbm
+                .update_tracker_status(uuid, &ConfirmationStatus::ConfirmedIn(current_height))
+                .is_ok()
+            {
+                tracker_index.set_confirmation_height(uuid, Some(current_height));
                 // Remove that uuid from reorged trackers if it was confirmed.
                 reorged_trackers.remove(&uuid);
-            // TODO: We won't need this check when we persist the correct tracker status
-            // in the DB after migrations are supported.
-            } else if reorged_trackers.contains(&uuid) {
-                // Don't consider reorged trackers since they have wrong DB status.
-                continue;
-            } else if let ConfirmationStatus::ConfirmedIn(h) = penalty_summary.status {
-                let confirmations = current_height - h;
-                if confirmations == constants::IRREVOCABLY_RESOLVED {
-                    // Tracker is deep enough in the chain, it can be deleted
+            } else {
+                // The tracker is not in the database anymore
+                tracker_index.remove(&uuid);
+            }
+        }
+
+        if let Some(h) = current_height.checked_sub(constants::IRREVOCABLY_RESOLVED) {
+            for uuid in tracker_index.confirmed_at(h) {
+                // TODO: We won't need this check when we persist the correct tracker status
+                // in the DB after migrations are supported.
+                if reorged_trackers.contains(&uuid) {
+                    // Don't consider reorged trackers since they have wrong DB status.
+                    continue;
+                }
+                // Tracker is deep enough in the chain, it can be deleted
+                tracker_index.remove(&uuid);
+                if let Some(tracker) = dbm.load_tracker(uuid) {
+                    let dispute_txid = tracker.dispute_tx.txid();
+                    // Trackers added before receipts were issued won't have one, so it is built from the tracker data.
+                    let mut receipt = dbm.load_response_receipt(uuid).unwrap_or_else(|| {
+                        ResponseReceipt::new(
+                            Locator::new(dispute_txid),
+                            dispute_txid,
+                            tracker.penalty_tx.txid(),
+                            h,
+                            None,
+                        )
+                    });
+                    receipt.set_confirmation_height(h);
+                    receipt.sign(&self.signing_key);
+                    let _ = dbm.store_response_receipt(uuid, tracker.user_id, &receipt);
                     completed_trackers.push(uuid);
-                } else {
-                    log::info!("{uuid} received a confirmation (count={confirmations})");
                 }
-            } else if let ConfirmationStatus::InMempoolSince(h) = penalty_summary.status {
-                // Log all transactions that have missed confirmations
-                log::info!(
-                    "Transaction missed a confirmation: {} (missed conf count: {})",
-                    penalty_summary.penalty_txid,
-                    current_height - h
-                );
             }
         }
 
@@ -286,15 +553,18 @@ impl Responder {
         // NOTE: We are draining the reorged trackers set, meaning that we won't try sending these disputes again.
         let reorged_trackers: Vec<UUID> = self.reorged_trackers.lock().unwrap().drain().collect();
         let mut carrier = self.carrier.lock().unwrap();
-        let dbm = self.dbm.lock().unwrap();
+        let mut dbm = self.dbm.lock().unwrap();
+        let mut tracker_index = self.tracker_index.lock().unwrap();
 
         let mut rejected = Vec::new();
+        let mut outcomes = Vec::new();
         // Republish all the dispute transactions of the reorged trackers.
         for uuid in reorged_trackers {
             let tracker = dbm.load_tracker(uuid).unwrap();
             let dispute_txid = tracker.dispute_tx.txid();
             // Try to publish the dispute transaction.
-            let should_publish_penalty = match carrier.send_transaction(&tracker.dispute_tx) {
+            let dispute_status = carrier.send_transaction(&tracker.dispute_tx);
+            let should_publish_penalty = match dispute_status {
                 ConfirmationStatus::InMempoolSince(_) => {
                     log::info!(
                         "Reorged dispute tx (txid={}) is in the mempool now",
@@ -325,22 +595,31 @@ impl Responder {
 
             if should_publish_penalty {
                 // Try to rebroadcast the penalty tx.
-                if let ConfirmationStatus::Rejected(_) =
+                if let ConfirmationStatus::Rejected(code) =
                     carrier.send_transaction(&tracker.penalty_tx)
                 {
-                    rejected.push(uuid)
+                    rejected.push(uuid);
+                    outcomes.push(rejection_outcome(uuid, &tracker, code, height));
                 } else {
                     // The penalty might actually be confirmed (ConfirmationStatus::IrrevocablyResolved) since bitcoind
                     // is fully synced with the stronger chain already, but we won't know which block was it confirmed in.
                     // We should see the tracker appear in the blockchain in the next couple of connected blocks.
                     dbm.update_tracker_status(uuid, &ConfirmationStatus::InMempoolSince(height))
-                        .unwrap()
+                        .unwrap();
+                    let _ = dbm.increase_rebroadcast_attempts(uuid);
+                    tracker_index.set_confirmation_height(uuid, None);
                 }
-            } else {
-                rejected.push(uuid)
+            } else if let ConfirmationStatus::Rejected(code) = dispute_status {
+                rejected.push(uuid);
+                outcomes.push(rejection_outcome(uuid, &tracker, code, height));
             }
         }
 
+        // Rejected trackers are deleted by the caller.
+        rejected.iter().for_each(|uuid| tracker_index.remove(uuid));
+        if !outcomes.is_empty() {
+            dbm.store_appointment_outcomes(&outcomes);
+        }
         (!rejected.is_empty()).then_some(rejected)
     }
 
@@ -351,9 +630,10 @@ impl Responder {
     ///
     /// Returns a vector of rejected trackers during rebroadcast if any were rejected, [None] otherwise.
     fn rebroadcast_stale_txs(&self, height: u32) -> Option<Vec<UUID>> {
-        let dbm = self.dbm.lock().unwrap();
+        let mut dbm = self.dbm.lock().unwrap();
         let mut carrier = self.carrier.lock().unwrap();
         let mut rejected = Vec::new();
+        let mut outcomes = Vec::new();
 
         // Retry sending trackers which have been in the mempool since more than `CONFIRMATIONS_BEFORE_RETRY` blocks.
         let stale_confirmation_status =
@@ -372,21 +652,46 @@ impl Responder {
             );
             // Rebroadcast the penalty transaction.
             let status = carrier.send_transaction(&tracker.penalty_tx);
-            if let ConfirmationStatus::Rejected(_) = status {
+            if let ConfirmationStatus::Rejected(code) = status {
+                self.tracker_index.lock().unwrap().remove(&uuid);
                 rejected.push(uuid);
+                outcomes.push(rejection_outcome(uuid, &tracker, code, height));
             } else {
                 // DISCUSS: What if the tower was down for some time and was later force updated while this penalty got on-chain?
                 // Sending it will yield `ConfirmationStatus::IrrevocablyResolved` which would panic here.
                 // We might want to replace `ConfirmationStatus::IrrevocablyResolved` variant with
                 // `ConfirmationStatus::ConfirmedIn(height - IRREVOCABLY_RESOLVED)
                 dbm.update_tracker_status(uuid, &status).unwrap();
+                let _ = dbm.increase_rebroadcast_attempts(uuid);
             }
         }
 
+        if !outcomes.is_empty() {
+            dbm.store_appointment_outcomes(&outcomes);
+        }
         (!rejected.is_empty()).then_some(rejected)
     }
 }
 
+/// Builds the [AppointmentOutcome] of a tracker dropped at `height` because one of its transactions was rejected.
+fn rejection_outcome(
+    uuid: UUID,
+    tracker: &TransactionTracker,
+    rpc_error_code: i32,
+    height: u32,
+) -> AppointmentOutcome {
+    let dispute_txid = tracker.dispute_tx.txid();
+    AppointmentOutcome::new(
+        uuid,
+        Locator::new(dispute_txid),
+        tracker.user_id,
+        dispute_txid,
+        FailureKind::Rejected,
+        Some(rpc_error_code),
+        height,
+    )
+}
+
 /// Listen implementation by the [Responder]. Handles monitoring and reorgs.
 impl chain::Listen for Responder {
     /// Handles the monitoring process by the [Responder].
@@ -414,6 +719,13 @@ impl chain::Listen for Responder {
             .collect();
         self.tx_index.lock().unwrap().update(*header, &txs);
 
+        // Drop the trackers of the users deleted in this block (e.g. outdated ones). The Gatekeeper processes the
+        // block first, so they are already gone.
+        self.tracker_index
+            .lock()
+            .unwrap()
+            .remove_users(|user_id| self.gatekeeper.is_registered(*user_id));
+
         // Delete trackers completed at this height
         if let Some(trackers) = self.check_confirmations(txs.keys().cloned().collect(), height) {
             self.gatekeeper.delete_appointments(trackers, true);
@@ -456,13 +768,12 @@ impl chain::Listen for Responder {
         // TODO: Not only confirmed trackers need to be marked as reorged, but trackers that hasn't confirmed but their
         // dispute did confirm in the reorged block. We can pull dispute txids of non confirmed penalties and get their
         // confirmation block from our tx_index.
-        self.reorged_trackers.lock().unwrap().extend(
-            self.dbm
-                .lock()
-                .unwrap()
-                .load_trackers_with_confirmation_status(ConfirmationStatus::ConfirmedIn(height))
-                .unwrap(),
-        );
+        let mut reorged_trackers = self.reorged_trackers.lock().unwrap();
+        let mut tracker_index = self.tracker_index.lock().unwrap();
+        for uuid in tracker_index.confirmed_at(height) {
+            tracker_index.set_confirmation_height(uuid, None);
+            reorged_trackers.insert(uuid);
+        }
     }
 }
 
@@ -472,17 +783,18 @@ mod tests {
     use lightning::chain::Listen;
     use teos_common::appointment::Locator;
 
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::iter::FromIterator;
     use std::sync::{Arc, Mutex};
 
     use crate::dbm::DBM;
+    use crate::gatekeeper::DEFAULT_PLAN;
     use crate::rpc_errors;
     use crate::test_utils::{
         create_carrier, generate_dummy_appointment, generate_dummy_appointment_with_user,
         generate_uuid, get_last_n_blocks, get_random_breach, get_random_tracker, get_random_tx,
-        store_appointment_and_its_user, BitcoindStopper, Blockchain, MockedServerQuery, DURATION,
-        EXPIRY_DELTA, SLOTS, START_HEIGHT,
+        get_tower_keypair, store_appointment_and_its_user, BitcoindStopper, Blockchain,
+        MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
     };
 
     use teos_common::constants::IRREVOCABLY_RESOLVED;
@@ -503,6 +815,7 @@ mod tests {
             // Same in-memory data.
             *self.reorged_trackers.lock().unwrap() == *other.reorged_trackers.lock().unwrap() &&
             *self.tx_index.lock().unwrap() == *other.tx_index.lock().unwrap() &&
+            *self.tracker_index.lock().unwrap() == *other.tracker_index.lock().unwrap() &&
             // && Same DB data.
             self.get_trackers() == other.get_trackers()
         }
@@ -531,19 +844,25 @@ mod tests {
                 tracker.user_id,
                 Some(&tracker.dispute_tx.txid()),
             );
-            store_appointment_and_its_user(&self.dbm.lock().unwrap(), &appointment);
+            store_appointment_and_its_user(&mut self.dbm.lock().unwrap(), &appointment);
             self.dbm
                 .lock()
                 .unwrap()
                 .store_tracker(appointment.uuid(), tracker)
                 .unwrap();
+            self.tracker_index.lock().unwrap().insert(
+                appointment.uuid(),
+                tracker.user_id,
+                tracker.penalty_tx.txid(),
+                tracker.status,
+            );
         }
 
         fn store_dummy_appointment_to_db(&self) -> (UserId, UUID) {
             let appointment = generate_dummy_appointment(None);
             let (uuid, user_id) = (appointment.uuid(), appointment.user_id);
             // Store the appointment and the user to the DB.
-            store_appointment_and_its_user(&self.dbm.lock().unwrap(), &appointment);
+            store_appointment_and_its_user(&mut self.dbm.lock().unwrap(), &appointment);
             (user_id, uuid)
         }
     }
@@ -564,7 +883,18 @@ mod tests {
 
         let (carrier, bitcoind_stopper) = create_carrier(query, chain.tip().height);
         (
-            Responder::new(&last_n_blocks, chain.tip().height, carrier, gatekeeper, dbm),
+            Responder::new(
+                &last_n_blocks
+                    .iter()
+                    .map(BlockTxids::from)
+                    .collect::<Vec<_>>(),
+                chain.tip().height,
+                IRREVOCABLY_RESOLVED as usize,
+                carrier,
+                gatekeeper,
+                get_tower_keypair().0,
+                dbm,
+            ),
             bitcoind_stopper,
         )
     }
@@ -579,6 +909,8 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
             dbm.clone(),
         );
         create_responder(chain, Arc::new(gk), dbm, mocked_query).await
@@ -606,6 +938,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_penalty_progress() {
+        let attempts = 2;
+        let height = 100;
+
+        assert_eq!(
+            PenaltyProgress::new(ConfirmationStatus::InMempoolSince(90), attempts, height),
+            PenaltyProgress {
+                in_mempool_since: Some(90),
+                confirmations: 0,
+                rebroadcast_attempts: attempts
+            }
+        );
+        // A transaction confirmed in the current block has one confirmation
+        assert_eq!(
+            PenaltyProgress::new(ConfirmationStatus::ConfirmedIn(height), attempts, height),
+            PenaltyProgress {
+                in_mempool_since: None,
+                confirmations: 1,
+                rebroadcast_attempts: attempts
+            }
+        );
+        assert_eq!(
+            PenaltyProgress::untracked(Some(height - IRREVOCABLY_RESOLVED), height),
+            PenaltyProgress {
+                in_mempool_since: None,
+                confirmations: IRREVOCABLY_RESOLVED + 1,
+                rebroadcast_attempts: 0
+            }
+        );
+        assert_eq!(
+            PenaltyProgress::untracked(None, height),
+            PenaltyProgress::default()
+        );
+    }
+
     #[test]
     fn test_confirmation_status_to_db_data() {
         // Analogous to the previous test, this will only construct ConfirmedIn and InMempolSince statuses.
@@ -654,6 +1022,23 @@ mod tests {
         assert_eq!(responder, another_r);
     }
 
+    #[tokio::test]
+    async fn test_reload() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (responder, _s) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm.clone())
+                .await;
+        let (another_r, _) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm).await;
+
+        // Trackers added to the database behind the back of a Responder are not indexed until it is reloaded
+        responder.add_random_tracker(ConfirmationStatus::InMempoolSince(START_HEIGHT as u32));
+        assert_ne!(responder, another_r);
+        another_r.reload();
+        assert_eq!(responder, another_r);
+    }
+
     #[tokio::test]
     async fn test_handle_breach_accepted() {
         let start_height = START_HEIGHT as u32;
@@ -776,12 +1161,25 @@ mod tests {
         assert_eq!(
             responder.dbm.lock().unwrap().load_tracker(uuid).unwrap(),
             TransactionTracker::new(
-                breach,
+                breach.clone(),
                 user_id,
                 ConfirmationStatus::InMempoolSince(start_height)
             )
         );
 
+        // A signed receipt is issued for the tracker
+        let receipt = responder
+            .dbm
+            .lock()
+            .unwrap()
+            .load_response_receipt(uuid)
+            .unwrap();
+        assert_eq!(receipt.dispute_txid(), breach.dispute_tx.txid());
+        assert_eq!(receipt.penalty_txid(), breach.penalty_tx.txid());
+        assert_eq!(receipt.broadcast_height(), start_height);
+        assert!(!receipt.is_resolved());
+        assert!(receipt.verify(&UserId(get_tower_keypair().1)));
+
         // Adding a confirmed tracker should result in the same but with the height being set.
 
         let (user_id, uuid) = responder.store_dummy_appointment_to_db();
@@ -944,6 +1342,21 @@ mod tests {
             HashSet::from_iter(responder.check_confirmations(txids, target_height).unwrap())
         );
 
+        // And their receipts should now prove the penalty was irrevocably resolved
+        for uuid in completed {
+            let receipt = responder
+                .dbm
+                .lock()
+                .unwrap()
+                .load_response_receipt(uuid)
+                .unwrap();
+            assert_eq!(
+                receipt.confirmation_height(),
+                Some(target_height - constants::IRREVOCABLY_RESOLVED)
+            );
+            assert!(receipt.verify(&UserId(get_tower_keypair().1)));
+        }
+
         // The ones in mempool should still be there (at the same height)
         for uuid in in_mempool {
             assert_eq!(
@@ -984,9 +1397,163 @@ mod tests {
                     .status,
                 ConfirmationStatus::ConfirmedIn(42)
             );
+            assert!(!responder
+                .dbm
+                .lock()
+                .unwrap()
+                .load_response_receipt(uuid)
+                .unwrap()
+                .is_resolved());
         }
     }
 
+    #[tokio::test]
+    async fn test_check_confirmations_reorg() {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let (responder, _s) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm).await;
+
+        let height = START_HEIGHT as u32 + 1;
+        let tracker = responder.add_random_tracker(ConfirmationStatus::InMempoolSince(height - 1));
+        let uuid = tracker.uuid();
+        let penalty_txids = HashSet::from_iter([tracker.penalty_tx.txid()]);
+
+        // The penalty gets confirmed
+        assert!(responder
+            .check_confirmations(penalty_txids.clone(), height)
+            .is_none());
+        assert_eq!(
+            responder.get_trackers()[&uuid].status,
+            ConfirmationStatus::ConfirmedIn(height)
+        );
+
+        // And the block it was confirmed in gets reorged out
+        responder.block_disconnected(&chain.tip().header, height);
+        assert!(responder.reorged_trackers.lock().unwrap().contains(&uuid));
+
+        // A block is connected at the same height, but the penalty is not in it. It'll be rebroadcast
+        assert!(responder
+            .check_confirmations(HashSet::new(), height)
+            .is_none());
+        assert!(responder.handle_reorged_txs(height).is_none());
+        assert_eq!(
+            responder.get_trackers()[&uuid].status,
+            ConfirmationStatus::InMempoolSince(height)
+        );
+
+        // The penalty gets confirmed in the next block
+        assert!(responder
+            .check_confirmations(penalty_txids, height + 1)
+            .is_none());
+        assert_eq!(
+            responder.get_trackers()[&uuid].status,
+            ConfirmationStatus::ConfirmedIn(height + 1)
+        );
+
+        // So the tracker is only completed once the new block is deep enough
+        assert!(responder
+            .check_confirmations(HashSet::new(), height + IRREVOCABLY_RESOLVED)
+            .is_none());
+        assert_eq!(
+            responder
+                .check_confirmations(HashSet::new(), height + 1 + IRREVOCABLY_RESOLVED)
+                .unwrap(),
+            vec![uuid]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_confirmations_after_restart() {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let (responder, _s) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm.clone())
+                .await;
+
+        let height = START_HEIGHT as u32;
+        let in_mempool = responder.add_random_tracker(ConfirmationStatus::InMempoolSince(height));
+        let confirmed = responder.add_random_tracker(ConfirmationStatus::ConfirmedIn(height));
+
+        // The indexes are rebuilt from the database when the Responder is restarted
+        let (responder, _s) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm).await;
+
+        assert!(responder
+            .check_confirmations(
+                HashSet::from_iter([in_mempool.penalty_tx.txid()]),
+                height + 1
+            )
+            .is_none());
+        assert_eq!(
+            responder.get_trackers()[&in_mempool.uuid()].status,
+            ConfirmationStatus::ConfirmedIn(height + 1)
+        );
+        assert_eq!(
+            responder
+                .check_confirmations(HashSet::new(), height + IRREVOCABLY_RESOLVED)
+                .unwrap(),
+            vec![confirmed.uuid()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_confirmations_deleted_trackers() {
+        let (responder, _s) = init_responder(MockedServerQuery::Regular).await;
+        let height = START_HEIGHT as u32;
+
+        // Trackers deleted by other components are dropped from the indexes once they are looked up
+        let in_mempool = responder.add_random_tracker(ConfirmationStatus::InMempoolSince(height));
+        let confirmed = responder.add_random_tracker(ConfirmationStatus::ConfirmedIn(height));
+        responder
+            .gatekeeper
+            .delete_appointments(vec![in_mempool.uuid(), confirmed.uuid()], false);
+
+        assert!(responder
+            .check_confirmations(
+                HashSet::from_iter([in_mempool.penalty_tx.txid()]),
+                height + IRREVOCABLY_RESOLVED
+            )
+            .is_none());
+        assert_eq!(
+            *responder.tracker_index.lock().unwrap(),
+            TrackerIndex::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tracker_index_outdated_users() {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let mut chain = Blockchain::default().with_height(START_HEIGHT * 2);
+        let (responder, _s) =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &mut chain, dbm).await;
+        let target_block_height = chain.get_block_count() + 1;
+
+        // Add a tracker whose penalty never confirms to a user that gets outdated in the next block
+        let user_id = get_random_user_id();
+        let tracker = get_random_tracker(
+            user_id,
+            ConfirmationStatus::InMempoolSince(target_block_height - 1),
+        );
+        responder
+            .gatekeeper
+            .add_outdated_user(user_id, target_block_height);
+        responder.add_dummy_tracker(&tracker);
+
+        // The Gatekeeper processes the block first, deleting the user and its trackers. The Responder follows suit
+        let block = chain.generate(None);
+        responder
+            .gatekeeper
+            .block_connected(&block, target_block_height);
+        responder.block_connected(&block, target_block_height);
+
+        assert_eq!(responder.get_trackers_count(), 0);
+        assert_eq!(
+            *responder.tracker_index.lock().unwrap(),
+            TrackerIndex::default()
+        );
+    }
+
     #[tokio::test]
     async fn test_handle_reorged_txs() {
         let (responder, _s) = init_responder(MockedServerQuery::InMempoool).await;
@@ -1017,6 +1584,15 @@ mod tests {
                     .status,
                 ConfirmationStatus::InMempoolSince(height)
             );
+            // Rebroadcasting the penalty counts as an attempt
+            assert_eq!(
+                responder
+                    .dbm
+                    .lock()
+                    .unwrap()
+                    .load_rebroadcast_attempts(uuid),
+                1
+            );
         }
     }
 
@@ -1080,24 +1656,23 @@ mod tests {
         assert!(responder.rebroadcast_stale_txs(height).is_none());
 
         for (uuid, former_status) in statues {
-            let status = responder
-                .dbm
-                .lock()
-                .unwrap()
-                .load_tracker(uuid)
-                .unwrap()
-                .status;
+            let dbm = responder.dbm.lock().unwrap();
+            let status = dbm.load_tracker(uuid).unwrap().status;
+            let attempts = dbm.load_rebroadcast_attempts(uuid);
             if let ConfirmationStatus::InMempoolSince(h) = former_status {
                 if height - h >= CONFIRMATIONS_BEFORE_RETRY as u32 {
                     // Transactions which stayed for more than `CONFIRMATIONS_BEFORE_RETRY` should have been rebroadcasted.
                     assert_eq!(status, ConfirmationStatus::InMempoolSince(height));
+                    assert_eq!(attempts, 1);
                 } else {
                     // Others left untouched.
                     assert_eq!(status, former_status);
+                    assert_eq!(attempts, 0);
                 }
             } else {
                 // Confirmed transactions left untouched as well.
                 assert_eq!(status, former_status);
+                assert_eq!(attempts, 0);
             }
         }
     }
@@ -1137,6 +1712,20 @@ mod tests {
             .collect();
         assert_eq!(should_reject, rejected);
 
+        // The rejections are recorded so users can tell why the penalties were dropped
+        let outcomes = responder
+            .dbm
+            .lock()
+            .unwrap()
+            .load_appointment_outcomes(None);
+        assert_eq!(
+            outcomes.iter().map(|o| o.uuid).collect::<HashSet<_>>(),
+            rejected
+        );
+        assert!(outcomes.iter().all(|o| o.kind == FailureKind::Rejected
+            && o.rpc_error_code == Some(rpc_errors::RPC_VERIFY_ERROR)
+            && o.height == height));
+
         for (uuid, former_status) in statues {
             let status = responder
                 .dbm
@@ -1175,7 +1764,10 @@ mod tests {
         let mut users = Vec::new();
         for _ in 0..21 {
             let user_id = get_random_user_id();
-            responder.gatekeeper.add_update_user(user_id).unwrap();
+            responder
+                .gatekeeper
+                .add_update_user(user_id, DEFAULT_PLAN)
+                .unwrap();
             users.push(user_id);
         }
 
@@ -1242,7 +1834,7 @@ mod tests {
         let standalone_user_id = get_random_user_id();
         responder
             .gatekeeper
-            .add_update_user(standalone_user_id)
+            .add_update_user(standalone_user_id, DEFAULT_PLAN)
             .unwrap();
 
         let mut missed_confirmation_trackers = Vec::new();
@@ -1424,7 +2016,10 @@ mod tests {
 
         // Add user to the database
         let user_id = get_random_user_id();
-        responder.gatekeeper.add_update_user(user_id).unwrap();
+        responder
+            .gatekeeper
+            .add_update_user(user_id, DEFAULT_PLAN)
+            .unwrap();
 
         let mut reorged = Vec::new();
         let block_range = START_HEIGHT - 10..START_HEIGHT;
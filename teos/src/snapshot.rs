@@ -0,0 +1,712 @@
+//! Logic related to moving the tower state around: restoring database snapshots and exporting / importing the tower
+//! data in a portable format.
+//!
+//! Snapshots are created by the running tower (`teos-cli backup`) and are restored on startup (`teosd restore`),
+//! replacing the whole database. Exports are JSON lines files holding the users (alongside their access lists, backups,
+//! pending payments and response receipts), registration tokens, appointments and trackers of the tower. They do not
+//! depend on the storage backend, so they can be used to migrate between backends.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use bitcoin::consensus;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, Txid};
+use lightning::ln::PaymentHash;
+
+use teos_common::appointment::{Appointment, Locator};
+use teos_common::backup::Backup;
+use teos_common::dbm::DatabaseConnection;
+use teos_common::receipts::ResponseReceipt;
+use teos_common::UserId;
+
+use crate::dbm::DBM;
+use crate::extended_appointment::{ExtendedAppointment, UUID};
+use crate::gatekeeper::UserInfo;
+use crate::payments::{Invoice, PendingPayment};
+use crate::responder::{ConfirmationStatus, TransactionTracker};
+
+/// Packs the reasons why restoring, exporting or importing the tower state may fail.
+#[derive(Debug)]
+pub enum SnapshotError {
+    InvalidSnapshot(String),
+    KeyMismatch,
+    UnknownBlock(BlockHash),
+    MalformedRecord(usize, String),
+    DatabaseError(String),
+    IoError(io::Error),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::IoError(e)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::InvalidSnapshot(e) => write!(f, "Invalid snapshot: {e}"),
+            SnapshotError::KeyMismatch => write!(
+                f,
+                "The snapshot belongs to a different tower (tower keys do not match). Use a fresh data directory to restore it"
+            ),
+            SnapshotError::UnknownBlock(block_hash) => write!(
+                f,
+                "The last block known by the snapshot ({block_hash}) is not part of the current chain"
+            ),
+            SnapshotError::MalformedRecord(line, e) => write!(f, "Malformed record at line {line}: {e}"),
+            SnapshotError::DatabaseError(e) => write!(f, "Database error: {e}"),
+            SnapshotError::IoError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Replaces the tower database with a snapshot created by [DBM::backup].
+///
+/// The snapshot is only restored if it holds the same tower key as the database (if the database holds any) and if
+/// the last block it knows about is still part of the best chain (checked using `in_best_chain`). Otherwise the tower
+/// would either change its identity or start from a block it cannot connect to.
+pub fn restore<F>(dbm: &mut DBM, path: &Path, in_best_chain: F) -> Result<(), SnapshotError>
+where
+    F: FnOnce(&BlockHash) -> bool,
+{
+    if !path.is_file() {
+        return Err(SnapshotError::InvalidSnapshot(format!(
+            "{} not found",
+            path.display()
+        )));
+    }
+    let snapshot =
+        DBM::load_snapshot(path).map_err(|e| SnapshotError::InvalidSnapshot(e.to_string()))?;
+
+    let snapshot_key = snapshot
+        .load_tower_key()
+        .ok_or_else(|| SnapshotError::InvalidSnapshot("no tower key found".to_owned()))?;
+    if matches!(dbm.load_tower_key(), Some(sk) if sk != snapshot_key) {
+        return Err(SnapshotError::KeyMismatch);
+    }
+    if let Some(block_hash) = snapshot.load_last_known_block() {
+        if !in_best_chain(&block_hash) {
+            return Err(SnapshotError::UnknownBlock(block_hash));
+        }
+    }
+
+    dbm.restore(path)
+        .map_err(|e| SnapshotError::DatabaseError(e.to_string()))
+}
+
+/// A line of an export file. Also used to stream database changes to standby towers.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ExportRecord {
+    User {
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+        available_slots: u32,
+        subscription_start: u32,
+        subscription_expiry: u32,
+        plan: String,
+    },
+    Appointment {
+        #[serde(with = "hex::serde")]
+        uuid: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        locator: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        encrypted_blob: Vec<u8>,
+        to_self_delay: u32,
+        user_signature: String,
+        start_block: u32,
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+    },
+    Tracker {
+        #[serde(with = "hex::serde")]
+        uuid: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        dispute_tx: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        penalty_tx: Vec<u8>,
+        height: u32,
+        confirmed: bool,
+    },
+    AllowedUser {
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+    },
+    BannedUser {
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+    },
+    RegistrationToken {
+        token: String,
+    },
+    PendingPayment {
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+        plan: String,
+        invoice: String,
+        #[serde(with = "hex::serde")]
+        payment_hash: Vec<u8>,
+        expires_at: u64,
+    },
+    Backup {
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        data: Vec<u8>,
+        version: u32,
+    },
+    ResponseReceipt {
+        #[serde(with = "hex::serde")]
+        uuid: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        user_id: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        locator: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        dispute_txid: Vec<u8>,
+        #[serde(with = "hex::serde")]
+        penalty_txid: Vec<u8>,
+        broadcast_height: u32,
+        confirmation_height: Option<u32>,
+        signature: String,
+    },
+}
+
+impl ExportRecord {
+    pub(crate) fn user(user_id: UserId, user_info: UserInfo) -> Self {
+        ExportRecord::User {
+            user_id: user_id.to_vec(),
+            available_slots: user_info.available_slots,
+            subscription_start: user_info.subscription_start,
+            subscription_expiry: user_info.subscription_expiry,
+            plan: user_info.plan,
+        }
+    }
+
+    pub(crate) fn appointment(uuid: UUID, appointment: ExtendedAppointment) -> Self {
+        ExportRecord::Appointment {
+            uuid: uuid.to_vec(),
+            locator: appointment.locator().to_vec(),
+            encrypted_blob: appointment.encrypted_blob().clone(),
+            to_self_delay: appointment.to_self_delay(),
+            user_signature: appointment.user_signature,
+            start_block: appointment.start_block,
+            user_id: appointment.user_id.to_vec(),
+        }
+    }
+
+    /// Builds the record of a tracker. Only trackers that can be stored (confirmed or in mempool) have one.
+    pub(crate) fn tracker(uuid: UUID, tracker: TransactionTracker) -> Option<Self> {
+        let (height, confirmed) = tracker.status.to_db_data()?;
+        Some(ExportRecord::Tracker {
+            uuid: uuid.to_vec(),
+            dispute_tx: consensus::serialize(&tracker.dispute_tx),
+            penalty_tx: consensus::serialize(&tracker.penalty_tx),
+            height,
+            confirmed,
+        })
+    }
+
+    pub(crate) fn pending_payment(user_id: UserId, pending: PendingPayment) -> Self {
+        ExportRecord::PendingPayment {
+            user_id: user_id.to_vec(),
+            plan: pending.plan,
+            invoice: pending.invoice.bolt11,
+            payment_hash: pending.invoice.payment_hash.0.to_vec(),
+            expires_at: pending.expires_at,
+        }
+    }
+
+    pub(crate) fn backup(user_id: UserId, backup: Backup) -> Self {
+        ExportRecord::Backup {
+            user_id: user_id.to_vec(),
+            data: backup.data,
+            version: backup.version,
+        }
+    }
+
+    /// Builds the record of a response receipt. Only signed receipts (the ones the tower stores) have one.
+    pub(crate) fn response_receipt(
+        uuid: UUID,
+        user_id: UserId,
+        receipt: ResponseReceipt,
+    ) -> Option<Self> {
+        Some(ExportRecord::ResponseReceipt {
+            uuid: uuid.to_vec(),
+            user_id: user_id.to_vec(),
+            locator: receipt.locator().to_vec(),
+            dispute_txid: receipt.dispute_txid().to_vec(),
+            penalty_txid: receipt.penalty_txid().to_vec(),
+            broadcast_height: receipt.broadcast_height(),
+            confirmation_height: receipt.confirmation_height(),
+            signature: receipt.signature()?,
+        })
+    }
+
+    /// Gets the table the record belongs to, alongside its key.
+    pub(crate) fn key(&self) -> (&'static str, &[u8]) {
+        match self {
+            ExportRecord::User { user_id, .. } => ("users", user_id),
+            ExportRecord::Appointment { uuid, .. } => ("appointments", uuid),
+            ExportRecord::Tracker { uuid, .. } => ("trackers", uuid),
+            ExportRecord::AllowedUser { user_id } => ("allowed_users", user_id),
+            ExportRecord::BannedUser { user_id } => ("banned_users", user_id),
+            ExportRecord::RegistrationToken { token } => ("registration_tokens", token.as_bytes()),
+            ExportRecord::PendingPayment { user_id, .. } => ("pending_payments", user_id),
+            ExportRecord::Backup { user_id, .. } => ("backups", user_id),
+            ExportRecord::ResponseReceipt { uuid, .. } => ("response_receipts", uuid),
+        }
+    }
+
+    /// Stores the record into the database, failing if it already exists.
+    fn store(self, dbm: &mut DBM) -> Result<(), String> {
+        self.persist(dbm, false)
+    }
+
+    /// Stores the record into the database, updating it if it already exists.
+    pub(crate) fn store_or_update(self, dbm: &mut DBM) -> Result<(), String> {
+        self.persist(dbm, true)
+    }
+
+    fn persist(self, dbm: &mut DBM, update: bool) -> Result<(), String> {
+        match self {
+            ExportRecord::User {
+                user_id,
+                available_slots,
+                subscription_start,
+                subscription_expiry,
+                plan,
+            } => {
+                let user_id = UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?;
+                let user_info =
+                    UserInfo::new(available_slots, subscription_start, subscription_expiry)
+                        .with_plan(plan);
+                if update && dbm.load_user(user_id).is_some() {
+                    dbm.update_user(user_id, &user_info);
+                    Ok(())
+                } else {
+                    dbm.store_user(user_id, &user_info)
+                }
+            }
+            ExportRecord::Appointment {
+                uuid,
+                locator,
+                encrypted_blob,
+                to_self_delay,
+                user_signature,
+                start_block,
+                user_id,
+            } => {
+                let uuid = UUID::from_slice(&uuid).map_err(|_| "invalid uuid")?;
+                let locator = Locator::from_slice(&locator).map_err(|_| "invalid locator")?;
+                let user_id = UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?;
+                let appointment = ExtendedAppointment::new(
+                    Appointment::new(locator, encrypted_blob, to_self_delay),
+                    user_id,
+                    user_signature,
+                    start_block,
+                );
+                if update && dbm.appointment_exists(uuid) {
+                    dbm.update_appointment(uuid, &appointment)
+                } else {
+                    dbm.store_appointment(uuid, &appointment)
+                }
+            }
+            ExportRecord::Tracker {
+                uuid,
+                dispute_tx,
+                penalty_tx,
+                height,
+                confirmed,
+            } => {
+                let uuid = UUID::from_slice(&uuid).map_err(|_| "invalid uuid")?;
+                let appointment = dbm
+                    .load_appointment(uuid)
+                    .ok_or("trackers must come after their appointment")?;
+                let tracker = TransactionTracker {
+                    dispute_tx: consensus::deserialize(&dispute_tx)
+                        .map_err(|_| "invalid dispute_tx")?,
+                    penalty_tx: consensus::deserialize(&penalty_tx)
+                        .map_err(|_| "invalid penalty_tx")?,
+                    status: ConfirmationStatus::from_db_data(height, confirmed),
+                    user_id: appointment.user_id,
+                };
+                if update && dbm.load_tracker(uuid).is_some() {
+                    dbm.update_tracker_status(uuid, &tracker.status)
+                } else {
+                    dbm.store_tracker(uuid, &tracker)
+                }
+            }
+            ExportRecord::AllowedUser { user_id } => {
+                dbm.store_allowed_user(UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?)
+            }
+            ExportRecord::BannedUser { user_id } => {
+                dbm.store_banned_user(UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?)
+            }
+            ExportRecord::RegistrationToken { token } => {
+                if update && dbm.has_registration_token(&token) {
+                    Ok(())
+                } else {
+                    dbm.store_registration_token(&token)
+                }
+            }
+            ExportRecord::PendingPayment {
+                user_id,
+                plan,
+                invoice,
+                payment_hash,
+                expires_at,
+            } => {
+                let user_id = UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?;
+                let payment_hash = PaymentHash(
+                    payment_hash
+                        .try_into()
+                        .map_err(|_| "invalid payment_hash")?,
+                );
+                dbm.store_pending_payment(
+                    user_id,
+                    &PendingPayment::new(
+                        plan,
+                        Invoice {
+                            bolt11: invoice,
+                            payment_hash,
+                        },
+                        expires_at,
+                    ),
+                )
+            }
+            ExportRecord::Backup {
+                user_id,
+                data,
+                version,
+            } => {
+                let user_id = UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?;
+                dbm.store_backup(user_id, &Backup::new(data, version))
+            }
+            ExportRecord::ResponseReceipt {
+                uuid,
+                user_id,
+                locator,
+                dispute_txid,
+                penalty_txid,
+                broadcast_height,
+                confirmation_height,
+                signature,
+            } => {
+                let uuid = UUID::from_slice(&uuid).map_err(|_| "invalid uuid")?;
+                let user_id = UserId::from_slice(&user_id).map_err(|_| "invalid user_id")?;
+                let receipt = ResponseReceipt::with_signature(
+                    Locator::from_slice(&locator).map_err(|_| "invalid locator")?,
+                    Txid::from_slice(&dispute_txid).map_err(|_| "invalid dispute_txid")?,
+                    Txid::from_slice(&penalty_txid).map_err(|_| "invalid penalty_txid")?,
+                    broadcast_height,
+                    confirmation_height,
+                    signature,
+                );
+                dbm.store_response_receipt(uuid, user_id, &receipt)
+            }
+        }
+        .map_err(|e| format!("cannot be stored ({e:?})"))
+    }
+}
+
+/// Loads the data of the tower as [ExportRecord]s.
+///
+/// Users come first, and trackers right after the appointment they come from, so the records can be stored in a single
+/// pass.
+pub(crate) fn load_records(dbm: &DBM) -> Vec<ExportRecord> {
+    let mut records = Vec::new();
+    for (user_id, user_info) in dbm.load_all_users() {
+        records.push(ExportRecord::user(user_id, user_info));
+    }
+    for user_id in dbm.load_allowed_users() {
+        records.push(ExportRecord::AllowedUser {
+            user_id: user_id.to_vec(),
+        });
+    }
+    for user_id in dbm.load_banned_users() {
+        records.push(ExportRecord::BannedUser {
+            user_id: user_id.to_vec(),
+        });
+    }
+    for token in dbm.load_registration_tokens() {
+        records.push(ExportRecord::RegistrationToken { token });
+    }
+    for (user_id, pending) in dbm.load_pending_payments() {
+        records.push(ExportRecord::pending_payment(user_id, pending));
+    }
+    for (user_id, backup) in dbm.load_backups() {
+        records.push(ExportRecord::backup(user_id, backup));
+    }
+    for (uuid, (user_id, receipt)) in dbm.load_response_receipts(None) {
+        records.extend(ExportRecord::response_receipt(uuid, user_id, receipt));
+    }
+    for (uuid, appointment) in dbm.load_appointments(None) {
+        records.push(ExportRecord::appointment(uuid, appointment));
+    }
+    for (uuid, tracker) in dbm.load_trackers(None) {
+        // Triggered appointments are not loaded alongside the rest, they come with their tracker
+        if let Some(appointment) = dbm.load_appointment(uuid) {
+            records.push(ExportRecord::appointment(uuid, appointment));
+            records.extend(ExportRecord::tracker(uuid, tracker));
+        }
+    }
+
+    records
+}
+
+/// Writes the data of the tower to `writer`, one JSON object per line. Returns the number
+/// of written records.
+pub fn export<W: Write>(dbm: &DBM, mut writer: W) -> Result<usize, SnapshotError> {
+    let records = load_records(dbm);
+    for record in records.iter() {
+        serde_json::to_writer(&mut writer, record).map_err(io::Error::from)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    Ok(records.len())
+}
+
+/// Imports the records of an export (created by [export]) into the tower database. Returns the number of imported
+/// records.
+///
+/// The import is atomic: if any of the records is malformed or cannot be stored (e.g. the user or appointment already
+/// exists) nothing is imported.
+pub fn import<R: BufRead>(dbm: &mut DBM, reader: R) -> Result<usize, SnapshotError> {
+    let database_error = |e: rusqlite::Error| SnapshotError::DatabaseError(e.to_string());
+
+    dbm.get_connection()
+        .execute_batch("BEGIN")
+        .map_err(database_error)?;
+    let mut imported = 0;
+    let mut result = Ok(());
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                result = Err(SnapshotError::IoError(e));
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Err(e) = serde_json::from_str::<ExportRecord>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|record| record.store(dbm))
+        {
+            result = Err(SnapshotError::MalformedRecord(i + 1, e));
+            break;
+        }
+        imported += 1;
+    }
+
+    match result {
+        Ok(()) => {
+            dbm.get_connection()
+                .execute_batch("COMMIT")
+                .map_err(database_error)?;
+            Ok(imported)
+        }
+        Err(e) => {
+            // The locator filter may be left with some of the rolled back locators. That only leads to a few
+            // unnecessary database lookups
+            dbm.get_connection()
+                .execute_batch("ROLLBACK")
+                .map_err(database_error)?;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use tempdir::TempDir;
+
+    use teos_common::cryptography::get_random_keypair;
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::test_utils::{
+        generate_dummy_appointment_with_user, generate_uuid, get_random_tracker,
+    };
+
+    /// Creates a database with a user (allowed and with a backup), one appointment and one tracker.
+    fn populated_dbm() -> DBM {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        dbm.store_user(
+            user_id,
+            &UserInfo::new(21, 42, 100).with_plan("pro".to_owned()),
+        )
+        .unwrap();
+        dbm.store_allowed_user(user_id).unwrap();
+        dbm.store_backup(user_id, &Backup::new(vec![1, 2, 3], 1))
+            .unwrap();
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        dbm.store_tracker(
+            uuid,
+            &get_random_tracker(user_id, ConfirmationStatus::ConfirmedIn(100)),
+        )
+        .unwrap();
+
+        dbm
+    }
+
+    #[test]
+    fn test_restore() {
+        let tmp_dir = TempDir::new("teos_snapshot").unwrap();
+        let path = tmp_dir.path().join("snapshot.sql3");
+        let source = populated_dbm();
+        let (sk, _) = get_random_keypair();
+        source.store_tower_key(&sk).unwrap();
+        let block_hash = BlockHash::hash(&[1, 2, 3]);
+        source.store_last_known_block(&block_hash).unwrap();
+        source.backup(&path).unwrap();
+
+        // The snapshot can be restored into an empty database
+        let mut dbm = DBM::in_memory().unwrap();
+        restore(&mut dbm, &path, |h| *h == block_hash).unwrap();
+        assert_eq!(dbm.load_tower_key(), Some(sk));
+        assert_eq!(dbm.load_all_users(), source.load_all_users());
+        assert_eq!(dbm.load_appointments(None), source.load_appointments(None));
+        assert_eq!(dbm.load_trackers(None), source.load_trackers(None));
+
+        // And into one that belongs to the same tower
+        restore(&mut dbm, &path, |_| true).unwrap();
+    }
+
+    #[test]
+    fn test_restore_key_mismatch() {
+        let tmp_dir = TempDir::new("teos_snapshot").unwrap();
+        let path = tmp_dir.path().join("snapshot.sql3");
+        let source = DBM::in_memory().unwrap();
+        source.store_tower_key(&get_random_keypair().0).unwrap();
+        source.backup(&path).unwrap();
+
+        let mut dbm = populated_dbm();
+        dbm.store_tower_key(&get_random_keypair().0).unwrap();
+        let users = dbm.load_all_users();
+        assert!(matches!(
+            restore(&mut dbm, &path, |_| true),
+            Err(SnapshotError::KeyMismatch)
+        ));
+        // The database is left untouched
+        assert_eq!(dbm.load_all_users(), users);
+    }
+
+    #[test]
+    fn test_restore_unknown_block() {
+        let tmp_dir = TempDir::new("teos_snapshot").unwrap();
+        let path = tmp_dir.path().join("snapshot.sql3");
+        let source = DBM::in_memory().unwrap();
+        source.store_tower_key(&get_random_keypair().0).unwrap();
+        let block_hash = BlockHash::hash(&[1, 2, 3]);
+        source.store_last_known_block(&block_hash).unwrap();
+        source.backup(&path).unwrap();
+
+        let mut dbm = DBM::in_memory().unwrap();
+        assert!(matches!(
+            restore(&mut dbm, &path, |_| false),
+            Err(SnapshotError::UnknownBlock(h)) if h == block_hash
+        ));
+        assert_eq!(dbm.load_tower_key(), None);
+    }
+
+    #[test]
+    fn test_restore_invalid_snapshot() {
+        let tmp_dir = TempDir::new("teos_snapshot").unwrap();
+        let mut dbm = DBM::in_memory().unwrap();
+
+        // Missing file
+        let path = tmp_dir.path().join("snapshot.sql3");
+        assert!(matches!(
+            restore(&mut dbm, &path, |_| true),
+            Err(SnapshotError::InvalidSnapshot(_))
+        ));
+
+        // Not a database
+        std::fs::write(&path, "not a database").unwrap();
+        assert!(matches!(
+            restore(&mut dbm, &path, |_| true),
+            Err(SnapshotError::InvalidSnapshot(_))
+        ));
+
+        // A database without tower key
+        std::fs::remove_file(&path).unwrap();
+        DBM::in_memory().unwrap().backup(&path).unwrap();
+        assert!(matches!(
+            restore(&mut dbm, &path, |_| true),
+            Err(SnapshotError::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source = populated_dbm();
+        let mut exported = Vec::new();
+        // A user (allowed and with a backup), two appointments and a tracker
+        assert_eq!(export(&source, &mut exported).unwrap(), 6);
+        assert_eq!(exported.iter().filter(|b| **b == b'\n').count(), 6);
+
+        let mut dbm = DBM::in_memory().unwrap();
+        assert_eq!(import(&mut dbm, exported.as_slice()).unwrap(), 6);
+        assert_eq!(dbm.load_all_users(), source.load_all_users());
+        assert_eq!(dbm.load_allowed_users(), source.load_allowed_users());
+        assert_eq!(dbm.load_backups(), source.load_backups());
+        assert_eq!(dbm.load_appointments(None), source.load_appointments(None));
+        assert_eq!(dbm.load_trackers(None), source.load_trackers(None));
+
+        // Importing the same data twice fails, since the users already exist
+        assert!(matches!(
+            import(&mut dbm, exported.as_slice()),
+            Err(SnapshotError::MalformedRecord(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_import_is_atomic() {
+        let user_id = get_random_user_id();
+        let user =
+            serde_json::to_string(&ExportRecord::user(user_id, UserInfo::new(1, 2, 3))).unwrap();
+        let tracker = serde_json::to_string(
+            &ExportRecord::tracker(
+                generate_uuid(),
+                get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(2)),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // Trackers cannot be imported without their appointment, making the whole import fail
+        let mut dbm = DBM::in_memory().unwrap();
+        let data = format!("{user}\n\n{tracker}\n");
+        assert!(matches!(
+            import(&mut dbm, data.as_bytes()),
+            Err(SnapshotError::MalformedRecord(3, _))
+        ));
+        assert!(dbm.load_all_users().is_empty());
+
+        // Lines that are not records are rejected too
+        assert!(matches!(
+            import(&mut dbm, "{\"type\": \"unknown\"}".as_bytes()),
+            Err(SnapshotError::MalformedRecord(1, _))
+        ));
+    }
+}
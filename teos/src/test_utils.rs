@@ -8,8 +8,11 @@
 */
 
 use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use jsonrpc_http_server::jsonrpc_core::error::ErrorCode as JsonRpcErrorCode;
 use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, IoHandler, Params, Value};
@@ -25,9 +28,12 @@ use bitcoin::hash_types::BlockHash;
 use bitcoin::hash_types::Txid;
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::bip158::BlockFilter;
 use bitcoin::util::hash::bitcoin_merkle_root;
 use bitcoin::util::uint::Uint256;
 use bitcoin::Witness;
+use lightning::ln::PaymentHash;
 use lightning_block_sync::poll::{
     ChainPoller, Poll, Validate, ValidatedBlock, ValidatedBlockHeader,
 };
@@ -36,19 +42,26 @@ use lightning_block_sync::{
 };
 
 use teos_common::constants::IRREVOCABLY_RESOLVED;
-use teos_common::cryptography::{get_random_bytes, get_random_keypair};
+use teos_common::cryptography::get_random_bytes;
 use teos_common::test_utils::{generate_random_appointment, get_random_user_id, TXID_HEX, TX_HEX};
 use teos_common::UserId;
 
 use crate::api::internal::InternalAPI;
+use crate::api::rate_limit::{RateLimiter, RateLimits};
+use crate::audit::AuditLog;
+use crate::bitcoin_cli::BlockHashSource;
 use crate::carrier::Carrier;
 use crate::dbm::DBM;
 use crate::extended_appointment::{ExtendedAppointment, UUID};
-use crate::gatekeeper::{Gatekeeper, UserInfo};
+use crate::gatekeeper::{Gatekeeper, SubscriptionPlan, UserInfo};
+use crate::payments::{Invoice, InvoiceBackend, InvoiceBackendError, Payments};
 use crate::protos as msgs;
+use crate::replication::ReplicationSource;
 use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
 use crate::rpc_errors;
-use crate::watcher::{Breach, Watcher};
+use crate::tls::ClientCertificates;
+use crate::tx_index::BlockTxids;
+use crate::watcher::{Breach, RegistrationGate, Watcher, WatcherConfig};
 
 pub(crate) const SLOTS: u32 = 21;
 pub(crate) const DURATION: u32 = 500;
@@ -278,6 +291,17 @@ impl BlockSource for Blockchain {
     }
 }
 
+impl BlockHashSource for Blockchain {
+    fn get_block_hash(&self, height: u32) -> AsyncBlockSourceResult<'_, BlockHash> {
+        Box::pin(async move {
+            match self.blocks.get(height as usize) {
+                Some(block) => Ok(block.block_hash()),
+                None => Err(BlockSourceError::persistent("block height out of range")),
+            }
+        })
+    }
+}
+
 pub(crate) fn generate_uuid() -> UUID {
     let mut rng = rand::thread_rng();
 
@@ -341,7 +365,7 @@ pub(crate) fn get_random_tracker(
     TransactionTracker::new(breach, user_id, status)
 }
 
-pub(crate) fn store_appointment_and_its_user(dbm: &DBM, appointment: &ExtendedAppointment) {
+pub(crate) fn store_appointment_and_its_user(dbm: &mut DBM, appointment: &ExtendedAppointment) {
     dbm.store_user(
         appointment.user_id,
         &UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY),
@@ -389,6 +413,12 @@ pub(crate) fn create_carrier(query: MockedServerQuery, height: u32) -> (Carrier,
     )
 }
 
+/// Gets the tower keys used in tests. They are fixed so every component signing on behalf of the tower uses the same ones.
+pub(crate) fn get_tower_keypair() -> (SecretKey, PublicKey) {
+    let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+    (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))
+}
+
 pub(crate) async fn create_responder(
     chain: &mut Blockchain,
     gatekeeper: Arc<Gatekeeper>,
@@ -405,7 +435,18 @@ pub(crate) async fn create_responder(
     let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
     let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, height);
 
-    Responder::new(&last_n_blocks, height, carrier, gatekeeper, dbm)
+    Responder::new(
+        &last_n_blocks
+            .iter()
+            .map(BlockTxids::from)
+            .collect::<Vec<_>>(),
+        height,
+        IRREVOCABLY_RESOLVED as usize,
+        carrier,
+        gatekeeper,
+        get_tower_keypair().0,
+        dbm,
+    )
 }
 
 pub(crate) async fn create_watcher(
@@ -414,30 +455,117 @@ pub(crate) async fn create_watcher(
     gatekeeper: Arc<Gatekeeper>,
     bitcoind_mock: BitcoindMock,
     dbm: Arc<Mutex<DBM>>,
+    registration_gate: RegistrationGate,
 ) -> (Watcher, BitcoindStopper) {
     let last_n_blocks = get_last_n_blocks(chain, 6).await;
 
     start_server(bitcoind_mock.server);
-    let (tower_sk, tower_pk) = get_random_keypair();
-    let tower_id = UserId(tower_pk);
     (
         Watcher::new(
             gatekeeper,
             responder,
             &last_n_blocks,
             chain.get_block_count(),
-            tower_sk,
-            tower_id,
+            WatcherConfig::new(6, registration_gate),
+            get_tower_keypair().0,
             dbm,
         ),
         bitcoind_mock.stopper,
     )
 }
+/// [InvoiceBackend] keeping the issued invoices in memory. Invoices are settled on demand.
+#[derive(Default)]
+pub(crate) struct MockInvoiceBackend {
+    /// Amount and settlement status of the issued invoices, by payment hash.
+    invoices: Mutex<HashMap<PaymentHash, (u64, bool)>>,
+    unreachable: Mutex<bool>,
+}
+
+impl MockInvoiceBackend {
+    pub fn new() -> Self {
+        MockInvoiceBackend::default()
+    }
+
+    pub fn settle(&self, payment_hash: &PaymentHash) {
+        self.invoices
+            .lock()
+            .unwrap()
+            .get_mut(payment_hash)
+            .unwrap()
+            .1 = true;
+    }
+
+    /// Settles an invoice given its BOLT11 encoding.
+    pub fn settle_invoice(&self, bolt11: &str) {
+        let raw_payment_hash = hex::decode(bolt11.trim_start_matches("lnbcrt")).unwrap();
+        self.settle(&PaymentHash(raw_payment_hash.try_into().unwrap()));
+    }
+
+    pub fn get_amount(&self, payment_hash: &PaymentHash) -> Option<u64> {
+        self.invoices
+            .lock()
+            .unwrap()
+            .get(payment_hash)
+            .map(|(amount, _)| *amount)
+    }
+
+    pub fn set_unreachable(&self) {
+        *self.unreachable.lock().unwrap() = true;
+    }
+
+    fn check_reachable(&self) -> Result<(), InvoiceBackendError> {
+        if *self.unreachable.lock().unwrap() {
+            Err(InvoiceBackendError("Connection refused".to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl InvoiceBackend for MockInvoiceBackend {
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        _: &str,
+        _: u32,
+    ) -> Result<Invoice, InvoiceBackendError> {
+        self.check_reachable()?;
+        let payment_hash = PaymentHash(get_random_bytes(32).try_into().unwrap());
+        self.invoices
+            .lock()
+            .unwrap()
+            .insert(payment_hash, (amount_msat, false));
+
+        Ok(Invoice {
+            bolt11: format!("lnbcrt{}", hex::encode(payment_hash.0)),
+            payment_hash,
+        })
+    }
+
+    async fn is_settled(&self, payment_hash: &PaymentHash) -> Result<bool, InvoiceBackendError> {
+        self.check_reachable()?;
+        Ok(self
+            .invoices
+            .lock()
+            .unwrap()
+            .get(payment_hash)
+            .map_or(false, |(_, settled)| *settled))
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct ApiConfig {
     slots: u32,
     duration: u32,
     bitcoind_reachable: bool,
+    private_mode: bool,
+    plans: BTreeMap<String, SubscriptionPlan>,
+    invoice_backend: Option<Arc<MockInvoiceBackend>>,
+    registration_gate: RegistrationGate,
+    rate_limiter: Arc<RateLimiter>,
+    client_certificates: Arc<ClientCertificates>,
+    replication: Option<(SecretKey, Duration)>,
 }
 
 impl ApiConfig {
@@ -446,6 +574,13 @@ impl ApiConfig {
             slots,
             duration,
             bitcoind_reachable: true,
+            private_mode: false,
+            plans: BTreeMap::new(),
+            invoice_backend: None,
+            registration_gate: RegistrationGate::default(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimits::unlimited())),
+            client_certificates: default_client_certificates(),
+            replication: None,
         }
     }
 
@@ -453,6 +588,48 @@ impl ApiConfig {
         self.bitcoind_reachable = false;
         self.clone()
     }
+
+    pub fn private_mode(&mut self) -> Self {
+        self.private_mode = true;
+        self.clone()
+    }
+
+    pub fn plan(&mut self, name: &str, plan: SubscriptionPlan) -> Self {
+        self.plans.insert(name.to_owned(), plan);
+        self.clone()
+    }
+
+    pub fn invoice_backend(&mut self, backend: Arc<MockInvoiceBackend>) -> Self {
+        self.invoice_backend = Some(backend);
+        self.clone()
+    }
+
+    pub fn registration_gate(&mut self, gate: RegistrationGate) -> Self {
+        self.registration_gate = gate;
+        self.clone()
+    }
+
+    pub fn rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self.clone()
+    }
+
+    pub fn client_certificates(&mut self, client_certificates: Arc<ClientCertificates>) -> Self {
+        self.client_certificates = client_certificates;
+        self.clone()
+    }
+
+    pub fn replication(&mut self, tower_sk: SecretKey, heartbeat_interval: Duration) -> Self {
+        self.replication = Some((tower_sk, heartbeat_interval));
+        self.clone()
+    }
+}
+
+/// Client certificates pointing to a directory that does not exist. Good enough as long as no certificate is issued.
+fn default_client_certificates() -> Arc<ClientCertificates> {
+    Arc::new(
+        ClientCertificates::new(std::env::temp_dir().join("teos_no_client_certificates")).unwrap(),
+    )
 }
 
 impl Default for ApiConfig {
@@ -461,6 +638,13 @@ impl Default for ApiConfig {
             slots: SLOTS,
             duration: DURATION,
             bitcoind_reachable: true,
+            private_mode: false,
+            plans: BTreeMap::new(),
+            invoice_backend: None,
+            registration_gate: RegistrationGate::default(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimits::unlimited())),
+            client_certificates: default_client_certificates(),
+            replication: None,
         }
     }
 }
@@ -477,6 +661,8 @@ pub(crate) async fn create_api_with_config(
         api_config.slots,
         api_config.duration,
         EXPIRY_DELTA,
+        api_config.plans.clone(),
+        api_config.private_mode,
         dbm.clone(),
     ));
     let responder =
@@ -487,17 +673,34 @@ pub(crate) async fn create_api_with_config(
         gk.clone(),
         bitcoind_mock,
         dbm.clone(),
+        api_config.registration_gate,
     )
     .await;
 
     let bitcoind_reachable = Arc::new((Mutex::new(api_config.bitcoind_reachable), Condvar::new()));
     let (shutdown_trigger, _) = triggered::trigger();
+    let payments = api_config
+        .invoice_backend
+        .map(|backend| Payments::new(backend, 3600, dbm.clone()));
+    let replication = api_config
+        .replication
+        .map(|(tower_sk, heartbeat_interval)| {
+            let dbm = dbm.lock().unwrap();
+            dbm.set_replication_log(true).unwrap();
+            ReplicationSource::new(dbm.reader(), tower_sk, heartbeat_interval)
+        });
     (
         Arc::new(InternalAPI::new(
             Arc::new(watcher),
+            Arc::new(chain),
             vec![msgs::NetworkAddress::from_ipv4("address".to_string(), 21)],
             bitcoind_reachable,
             shutdown_trigger,
+            payments,
+            api_config.rate_limiter,
+            api_config.client_certificates,
+            AuditLog::new(dbm, 0),
+            replication,
         )),
         stopper,
     )
@@ -538,20 +741,28 @@ pub(crate) struct BitcoindMock {
 pub(crate) struct MockOptions {
     error_code: Option<i64>,
     in_mempool: bool,
+    block_filter: Option<String>,
 }
 
 impl MockOptions {
     pub fn with_error(error_code: i64) -> Self {
         Self {
             error_code: Some(error_code),
-            in_mempool: false,
+            ..Default::default()
         }
     }
 
     pub fn in_mempool() -> Self {
         Self {
-            error_code: None,
             in_mempool: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_block_filter(filter: &BlockFilter) -> Self {
+        Self {
+            block_filter: Some(hex::encode(&filter.content)),
+            ..Default::default()
         }
     }
 }
@@ -571,6 +782,10 @@ impl BitcoindMock {
             BitcoindMock::add_getrawtransaction(&mut io, options.in_mempool);
         }
 
+        if let Some(filter) = options.block_filter {
+            BitcoindMock::add_getblockfilter(&mut io, filter);
+        }
+
         let server = ServerBuilder::new(io)
             .threads(3)
             .start_http(&"127.0.0.1:0".parse().unwrap())
@@ -612,6 +827,12 @@ impl BitcoindMock {
         })
     }
 
+    fn add_getblockfilter(io: &mut IoHandler, filter: String) {
+        io.add_sync_method("getblockfilter", move |_params: Params| {
+            Ok(serde_json::json!({ "filter": filter, "header": TXID_HEX }))
+        });
+    }
+
     pub fn url(&self) -> &str {
         &self.url
     }
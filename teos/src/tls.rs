@@ -6,8 +6,75 @@
 */
 
 use rcgen::{Certificate, KeyPair, RcgenError};
+use std::collections::HashSet;
 use std::convert::TryFrom;
-use std::path::Path;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::{ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{self, ClientHello, ResolvesServerCert};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::service::Interceptor;
+use tonic::Status;
+use triggered::Listener;
+
+use crate::audit::INTERNAL_CLIENT;
+
+/// Name of the file (within the data directory) holding the serial numbers of the revoked client certificates.
+pub const REVOKED_CLIENTS_FILE: &str = "revoked_clients";
+/// Name of the directory (within the data directory) where issued client certificates are stored.
+pub const CLIENTS_DIR: &str = "clients";
+/// Subject alternative names every certificate generated by the tower is valid for (see [default_alt_names]).
+const DEFAULT_SUBJECT_ALT_NAMES: [&str; 2] = ["cln", "localhost"];
+/// Name the client certificate generated alongside the tower certificates (`client.pem`) goes by.
+pub const DEFAULT_CLIENT_NAME: &str = "default";
+
+/// Roles a client of the private API can be granted, from less to more privileged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ClientRole {
+    /// Can only query the tower.
+    ReadOnly,
+    /// Can also manage the tower users.
+    Operator,
+    /// Can do anything, including stopping the tower and managing client certificates.
+    Admin,
+}
+
+impl FromStr for ClientRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "readonly" => Ok(ClientRole::ReadOnly),
+            "operator" => Ok(ClientRole::Operator),
+            "admin" => Ok(ClientRole::Admin),
+            _ => Err(format!(
+                "Unknown client role: {s}. Expected readonly, operator or admin"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ClientRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ClientRole::ReadOnly => "readonly",
+            ClientRole::Operator => "operator",
+            ClientRole::Admin => "admin",
+        };
+        write!(f, "{s}")
+    }
+}
 
 /// Packs the reasons why generating mtls certificates may fail.
 #[derive(Debug)]
@@ -48,23 +115,100 @@ impl TryFrom<&Identity> for Certificate {
     }
 }
 
+/// Generates (or loads) the CA, server and default client identities of the private API.
+///
+/// The server certificate is valid for `server_alt_names` on top of the default names. It is regenerated if the
+/// alternative names it was generated with do not match the given ones.
 pub fn tls_init(
     directory: &Path,
+    server_alt_names: &[String],
 ) -> Result<(tonic::transport::Identity, Vec<u8>), GenCertificateFailure> {
-    let ca = generate_or_load_identity("teos Root CA", directory, "ca", None)?;
-    let server = generate_or_load_identity("teos grpc Server", directory, "server", Some(&ca))?;
-    let _client = generate_or_load_identity("teos grpc Client", directory, "client", Some(&ca))?;
+    let default_alt_names = default_alt_names();
+    let mut server_names = default_alt_names.clone();
+    for name in server_alt_names {
+        if !server_names.contains(name) {
+            server_names.push(name.clone());
+        }
+    }
+
+    let server_cert_path = directory.join("server.pem");
+    if let Ok(pem) = std::fs::read(&server_cert_path) {
+        let mut current_names = certificate_alt_names(&pem).unwrap_or_default();
+        let mut expected_names = server_names.clone();
+        current_names.sort();
+        expected_names.sort();
+        if current_names != expected_names {
+            log::info!("The server certificate alternative names have changed. Regenerating it");
+            std::fs::remove_file(&server_cert_path)?;
+        }
+    }
+
+    let ca = generate_or_load_identity(
+        "teos Root CA",
+        directory,
+        "ca",
+        &default_alt_names,
+        None,
+        None,
+    )?;
+    let server = generate_or_load_identity(
+        "teos grpc Server",
+        directory,
+        "server",
+        &server_names,
+        Some(&ca),
+        None,
+    )?;
+    let _client = generate_or_load_identity(
+        "teos grpc Client",
+        directory,
+        "client",
+        &default_alt_names,
+        Some(&ca),
+        Some(ClientRole::Admin),
+    )?;
     let server_id = tonic::transport::Identity::from_pem(&server.certificate, &server.key);
 
     Ok((server_id, ca.certificate))
 }
 
-/// Generate a given identity
+/// Subject alternative names every certificate generated by the tower is valid for.
+fn default_alt_names() -> Vec<String> {
+    DEFAULT_SUBJECT_ALT_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Gets the DNS subject alternative names of a PEM encoded certificate.
+fn certificate_alt_names(pem: &[u8]) -> Option<Vec<String>> {
+    let der = rustls_pemfile::certs(&mut &pem[..])
+        .ok()?
+        .into_iter()
+        .next()?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(&der).ok()?;
+    let (_, alt_names) = certificate.tbs_certificate.subject_alternative_name()?;
+
+    Some(
+        alt_names
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                x509_parser::extensions::GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// Generate a given identity. Client identities get their role embedded as the certificate organizational unit.
 fn generate_or_load_identity(
     name: &str,
     directory: &Path,
     filename: &str,
+    subject_alt_names: &[String],
     parent: Option<&Identity>,
+    role: Option<ClientRole>,
 ) -> Result<Identity, GenCertificateFailure> {
     // Just our naming convention here.
     let cert_path = directory.join(format!("{filename}.pem"));
@@ -77,8 +221,7 @@ fn generate_or_load_identity(
         log::debug!("Generating a new certificate for key {key_path:?} at {cert_path:?}",);
 
         // Configure the certificate we want.
-        let subject_alt_names = vec!["cln".to_string(), "localhost".to_string()];
-        let mut params = rcgen::CertificateParams::new(subject_alt_names);
+        let mut params = rcgen::CertificateParams::new(subject_alt_names.to_vec());
         params.key_pair = Some(keypair);
         params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
         if parent.is_none() {
@@ -89,6 +232,11 @@ fn generate_or_load_identity(
         params
             .distinguished_name
             .push(rcgen::DnType::CommonName, name);
+        if let Some(role) = role {
+            params
+                .distinguished_name
+                .push(rcgen::DnType::OrganizationalUnitName, role.to_string());
+        }
 
         let cert = Certificate::from_params(params)?;
         std::fs::write(
@@ -104,3 +252,796 @@ fn generate_or_load_identity(
     let certificate = std::fs::read(cert_path)?;
     Ok(Identity { certificate, key })
 }
+
+/// Packs the reasons why managing client certificates may fail.
+#[derive(Debug)]
+pub enum ClientCertificateError {
+    InvalidName(String),
+    AlreadyExists(String),
+    NotFound(String),
+    InvalidCertificate,
+    GenCertificateFailure(GenCertificateFailure),
+    IoError(std::io::Error),
+}
+
+impl From<GenCertificateFailure> for ClientCertificateError {
+    fn from(e: GenCertificateFailure) -> Self {
+        ClientCertificateError::GenCertificateFailure(e)
+    }
+}
+
+impl From<std::io::Error> for ClientCertificateError {
+    fn from(e: std::io::Error) -> Self {
+        ClientCertificateError::IoError(e)
+    }
+}
+
+impl fmt::Display for ClientCertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientCertificateError::InvalidName(name) => write!(
+                f,
+                "Invalid client name: {name}. Use letters, numbers, dashes and underscores only"
+            ),
+            ClientCertificateError::AlreadyExists(name) => {
+                write!(f, "A client certificate named {name} already exists")
+            }
+            ClientCertificateError::NotFound(name) => {
+                write!(f, "No client certificate named {name} found")
+            }
+            ClientCertificateError::InvalidCertificate => write!(f, "Invalid client certificate"),
+            ClientCertificateError::GenCertificateFailure(e) => write!(f, "{e:?}"),
+            ClientCertificateError::IoError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Identity of a client of the private API, as embedded in its certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The certificate common name.
+    pub name: String,
+    /// The certificate (hex encoded) serial number.
+    pub serial: String,
+    /// The role granted to the client.
+    pub role: ClientRole,
+}
+
+impl ClientIdentity {
+    /// Identity of the trusted callers that run within the tower itself. They are granted [ClientRole::Admin].
+    pub fn internal() -> Self {
+        ClientIdentity {
+            name: INTERNAL_CLIENT.to_owned(),
+            serial: String::new(),
+            role: ClientRole::Admin,
+        }
+    }
+}
+
+/// Parses a DER encoded client certificate, returning the [ClientIdentity] embedded in it.
+///
+/// Certificates with no role (generated before roles were introduced) are granted [ClientRole::Admin].
+pub fn parse_client_certificate(der: &[u8]) -> Result<ClientIdentity, ClientCertificateError> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der)
+        .map_err(|_| ClientCertificateError::InvalidCertificate)?;
+    let subject = certificate.subject();
+    let role = match subject.iter_organizational_unit().next() {
+        Some(unit) => unit
+            .as_str()
+            .ok()
+            .and_then(|role| ClientRole::from_str(role).ok())
+            .ok_or(ClientCertificateError::InvalidCertificate)?,
+        None => ClientRole::Admin,
+    };
+    let name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    Ok(ClientIdentity {
+        name,
+        serial: hex::encode(certificate.tbs_certificate.raw_serial()),
+        role,
+    })
+}
+
+/// Summary of a client certificate issued by the tower.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub role: ClientRole,
+    pub revoked: bool,
+}
+
+/// Client certificates of the private API. Issues new certificates and keeps track of the revoked ones.
+///
+/// Issued certificates are stored under [CLIENTS_DIR] so they can be listed and revoked by name later on. Revoked
+/// certificates are kept there too, so their names cannot be reused.
+pub struct ClientCertificates {
+    /// The tower data directory, where the CA is stored.
+    directory: PathBuf,
+    /// Serial numbers of the revoked certificates. Mirrors [REVOKED_CLIENTS_FILE].
+    revoked: RwLock<HashSet<String>>,
+}
+
+impl ClientCertificates {
+    /// Creates a new [ClientCertificates] instance, loading the revocation list from the given data directory.
+    pub fn new(directory: PathBuf) -> Result<Self, std::io::Error> {
+        let revoked = match std::fs::read_to_string(directory.join(REVOKED_CLIENTS_FILE)) {
+            Ok(content) => content
+                .lines()
+                .map(str::trim)
+                .filter(|serial| !serial.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(ClientCertificates {
+            directory,
+            revoked: RwLock::new(revoked),
+        })
+    }
+
+    /// Path of the certificate of a given client.
+    fn certificate_path(&self, name: &str) -> PathBuf {
+        if name == DEFAULT_CLIENT_NAME {
+            self.directory.join("client.pem")
+        } else {
+            self.directory.join(CLIENTS_DIR).join(format!("{name}.pem"))
+        }
+    }
+
+    /// Loads the certificate of a given client.
+    fn load(&self, name: &str) -> Result<ClientIdentity, ClientCertificateError> {
+        let pem = match std::fs::read(self.certificate_path(name)) {
+            Ok(pem) => pem,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(ClientCertificateError::NotFound(name.to_owned()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let der = rustls_pemfile::certs(&mut pem.as_slice())?
+            .into_iter()
+            .next()
+            .ok_or(ClientCertificateError::InvalidCertificate)?;
+
+        parse_client_certificate(&der)
+    }
+
+    /// Issues a new client certificate with the given role, signed by the tower CA.
+    pub fn issue(&self, name: &str, role: ClientRole) -> Result<Identity, ClientCertificateError> {
+        if name.is_empty()
+            || name == DEFAULT_CLIENT_NAME
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ClientCertificateError::InvalidName(name.to_owned()));
+        }
+        if self.certificate_path(name).exists() {
+            return Err(ClientCertificateError::AlreadyExists(name.to_owned()));
+        }
+
+        let ca = generate_or_load_identity(
+            "teos Root CA",
+            &self.directory,
+            "ca",
+            &default_alt_names(),
+            None,
+            None,
+        )?;
+        let clients_dir = self.directory.join(CLIENTS_DIR);
+        std::fs::create_dir_all(&clients_dir)?;
+        Ok(generate_or_load_identity(
+            name,
+            &clients_dir,
+            name,
+            &default_alt_names(),
+            Some(&ca),
+            Some(role),
+        )?)
+    }
+
+    /// Revokes the certificate of a given client. Revoking an already revoked certificate is a no-op.
+    pub fn revoke(&self, name: &str) -> Result<(), ClientCertificateError> {
+        let serial = self.load(name)?.serial;
+        let mut revoked = self.revoked.write().unwrap();
+        if !revoked.contains(&serial) {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.directory.join(REVOKED_CLIENTS_FILE))?;
+            writeln!(file, "{serial}")?;
+            revoked.insert(serial);
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the certificate with the given serial number has been revoked.
+    pub fn is_revoked(&self, serial: &str) -> bool {
+        self.revoked.read().unwrap().contains(serial)
+    }
+
+    /// Lists the default client certificate followed by the issued ones (sorted by name).
+    pub fn list(&self) -> Result<Vec<ClientInfo>, ClientCertificateError> {
+        let mut names = Vec::new();
+        match std::fs::read_dir(self.directory.join(CLIENTS_DIR)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let file_name = entry?.file_name().to_string_lossy().into_owned();
+                    if let Some(name) = file_name.strip_suffix(".pem") {
+                        if !name.ends_with("-key") {
+                            names.push(name.to_owned());
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        names.sort();
+        names.insert(0, DEFAULT_CLIENT_NAME.to_owned());
+
+        let mut clients = Vec::new();
+        for name in names {
+            let identity = self.load(&name)?;
+            clients.push(ClientInfo {
+                name,
+                role: identity.role,
+                revoked: self.is_revoked(&identity.serial),
+            });
+        }
+
+        Ok(clients)
+    }
+}
+
+/// Interceptor of the private API.
+///
+/// Rejects requests from clients whose certificate has been revoked, and attaches the [ClientIdentity] embedded in
+/// the certificate to the rest, so each endpoint can check whether the client is allowed to call it.
+#[derive(Clone)]
+pub struct ClientAuthenticator {
+    clients: Arc<ClientCertificates>,
+}
+
+impl ClientAuthenticator {
+    /// Creates a new [ClientAuthenticator] instance.
+    pub fn new(clients: Arc<ClientCertificates>) -> Self {
+        ClientAuthenticator { clients }
+    }
+}
+
+impl Interceptor for ClientAuthenticator {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let certificates = request
+            .peer_certs()
+            .ok_or_else(|| Status::unauthenticated("Missing client certificate"))?;
+        let identity = certificates
+            .first()
+            .ok_or(ClientCertificateError::InvalidCertificate)
+            .and_then(|certificate| parse_client_certificate(certificate.get_ref()))
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+
+        if self.clients.is_revoked(&identity.serial) {
+            log::info!(
+                "Rejecting request from revoked client certificate {}",
+                identity.serial
+            );
+            return Err(Status::permission_denied(
+                "Client certificate has been revoked",
+            ));
+        }
+
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+/// Packs the reasons why loading the public API certificate may fail.
+#[derive(Debug)]
+pub enum LoadCertificateFailure {
+    IoError(std::io::Error),
+    NoCertificate,
+    NoKey,
+    UnsupportedKey,
+}
+
+impl From<std::io::Error> for LoadCertificateFailure {
+    fn from(e: std::io::Error) -> Self {
+        LoadCertificateFailure::IoError(e)
+    }
+}
+
+impl fmt::Display for LoadCertificateFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadCertificateFailure::IoError(e) => write!(f, "{e}"),
+            LoadCertificateFailure::NoCertificate => write!(f, "No PEM certificate found"),
+            LoadCertificateFailure::NoKey => write!(f, "No PEM private key found"),
+            LoadCertificateFailure::UnsupportedKey => write!(
+                f,
+                "Unsupported private key. Use either an RSA key or a PKCS#8 encoded ECDSA/Ed25519 key"
+            ),
+        }
+    }
+}
+
+/// Raw contents of a certificate and key pair, alongside the key ready to be served.
+struct LoadedCertificate {
+    certificate: Vec<u8>,
+    key: Vec<u8>,
+    certified_key: CertifiedKey,
+}
+
+/// Certificate (and key) served by the public API. Reloaded from disk if the files change.
+pub struct ReloadableCertificate {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    loaded: RwLock<LoadedCertificate>,
+}
+
+impl ReloadableCertificate {
+    /// Creates a new [ReloadableCertificate] instance, loading the certificate chain and key from the given PEM files.
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> Result<Self, LoadCertificateFailure> {
+        let loaded = load_certificate(std::fs::read(&cert_path)?, std::fs::read(&key_path)?)?;
+        Ok(ReloadableCertificate {
+            cert_path,
+            key_path,
+            loaded: RwLock::new(loaded),
+        })
+    }
+
+    /// Reloads the certificate and key if any of the files has changed. Returns whether they have been reloaded.
+    ///
+    /// The current certificate is kept if the new one cannot be loaded.
+    pub fn reload_if_changed(&self) -> Result<bool, LoadCertificateFailure> {
+        let certificate = std::fs::read(&self.cert_path)?;
+        let key = std::fs::read(&self.key_path)?;
+        {
+            let loaded = self.loaded.read().unwrap();
+            if loaded.certificate == certificate && loaded.key == key {
+                return Ok(false);
+            }
+        }
+
+        *self.loaded.write().unwrap() = load_certificate(certificate, key)?;
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertificate {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        Some(self.loaded.read().unwrap().certified_key.clone())
+    }
+}
+
+/// Builds a [CertifiedKey] out of a PEM encoded certificate chain and key.
+fn load_certificate(
+    certificate: Vec<u8>,
+    key: Vec<u8>,
+) -> Result<LoadedCertificate, LoadCertificateFailure> {
+    let chain: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut certificate.as_slice())?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if chain.is_empty() {
+        return Err(LoadCertificateFailure::NoCertificate);
+    }
+
+    let der_key = rustls_pemfile::read_all(&mut key.as_slice())?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(k) | rustls_pemfile::Item::PKCS8Key(k) => Some(k),
+            _ => None,
+        })
+        .ok_or(LoadCertificateFailure::NoKey)?;
+    let signing_key = sign::any_supported_type(&rustls::PrivateKey(der_key))
+        .map_err(|_| LoadCertificateFailure::UnsupportedKey)?;
+
+    Ok(LoadedCertificate {
+        certificate,
+        key,
+        certified_key: CertifiedKey::new(chain, Arc::new(signing_key)),
+    })
+}
+
+/// Limits applied to the TLS handshakes of incoming connections, so clients cannot exhaust the tower resources by
+/// opening connections and never completing the handshake.
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeLimits {
+    /// For how long a handshake can go on before the connection is dropped.
+    pub timeout: Duration,
+    /// Number of handshakes that can be in flight at the same time. Further connections wait to be accepted.
+    pub max_in_flight: usize,
+}
+
+impl Default for HandshakeLimits {
+    fn default() -> Self {
+        HandshakeLimits {
+            timeout: Duration::from_secs(10),
+            max_in_flight: 128,
+        }
+    }
+}
+
+/// For how long to stop accepting connections after failing to accept one (e.g. for running out of file descriptors).
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections from `listener` and runs their TLS handshake with `acceptor` until `shutdown_signal` is
+/// triggered. Connections are handed to `handle` once the handshake succeeds.
+pub async fn accept_tls<F, Fut>(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    limits: HandshakeLimits,
+    shutdown_signal: Listener,
+    handle: F,
+) where
+    F: Fn(TlsStream<TcpStream>, SocketAddr) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let in_flight = Arc::new(Semaphore::new(limits.max_in_flight));
+    loop {
+        let permit = tokio::select! {
+            permit = in_flight.clone().acquire_owned() => permit.unwrap(),
+            _ = shutdown_signal.clone() => break,
+        };
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::error!("Cannot accept incoming connection. {e}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = shutdown_signal.clone() => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(limits.timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    log::debug!("TLS handshake with {addr} failed. {e}");
+                    return;
+                }
+                Err(_) => {
+                    log::debug!("TLS handshake with {addr} timed out");
+                    return;
+                }
+            };
+            drop(permit);
+            handle(stream, addr).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn generate_certificate(name: &str) -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_owned()]).unwrap();
+        (
+            cert.serialize_pem().unwrap(),
+            cert.serialize_private_key_pem(),
+        )
+    }
+
+    fn certificate_der(identity: &Identity) -> Vec<u8> {
+        rustls_pemfile::certs(&mut identity.certificate.as_slice())
+            .unwrap()
+            .remove(0)
+    }
+
+    fn served_certificate(certificate: &ReloadableCertificate) -> Vec<u8> {
+        certificate.loaded.read().unwrap().certified_key.cert[0]
+            .0
+            .clone()
+    }
+
+    #[test]
+    fn test_load_certificate() {
+        let (cert, key) = generate_certificate("localhost");
+        let loaded = load_certificate(cert.clone().into_bytes(), key.clone().into_bytes()).unwrap();
+        assert_eq!(loaded.certified_key.cert.len(), 1);
+
+        assert!(matches!(
+            load_certificate(Vec::new(), key.into_bytes()),
+            Err(LoadCertificateFailure::NoCertificate)
+        ));
+        assert!(matches!(
+            load_certificate(cert.clone().into_bytes(), cert.into_bytes()),
+            Err(LoadCertificateFailure::NoKey)
+        ));
+    }
+
+    #[test]
+    fn test_reload_if_changed() {
+        let tmp_dir = TempDir::new("teos_tls").unwrap();
+        let cert_path = tmp_dir.path().join("cert.pem");
+        let key_path = tmp_dir.path().join("key.pem");
+
+        let (cert, key) = generate_certificate("localhost");
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let certificate = ReloadableCertificate::new(cert_path.clone(), key_path.clone()).unwrap();
+        let served = served_certificate(&certificate);
+        assert!(!certificate.reload_if_changed().unwrap());
+
+        // Replacing the files makes the new certificate to be served
+        let (new_cert, new_key) = generate_certificate("localhost");
+        std::fs::write(&cert_path, &new_cert).unwrap();
+        std::fs::write(&key_path, &new_key).unwrap();
+        assert!(certificate.reload_if_changed().unwrap());
+        assert_ne!(served_certificate(&certificate), served);
+
+        // A broken certificate is not loaded, and the old one keeps being served
+        let served = served_certificate(&certificate);
+        std::fs::write(&cert_path, "").unwrap();
+        assert!(matches!(
+            certificate.reload_if_changed(),
+            Err(LoadCertificateFailure::NoCertificate)
+        ));
+        assert_eq!(served_certificate(&certificate), served);
+    }
+
+    #[test]
+    fn test_client_role_from_str() {
+        for role in [
+            ClientRole::ReadOnly,
+            ClientRole::Operator,
+            ClientRole::Admin,
+        ] {
+            assert_eq!(ClientRole::from_str(&role.to_string()).unwrap(), role);
+        }
+        assert!(ClientRole::from_str("root").is_err());
+
+        // Roles are ordered by privilege
+        assert!(ClientRole::ReadOnly < ClientRole::Operator);
+        assert!(ClientRole::Operator < ClientRole::Admin);
+    }
+
+    #[test]
+    fn test_parse_client_certificate() {
+        let tmp_dir = TempDir::new("teos_tls").unwrap();
+        let ca =
+            generate_or_load_identity("ca", tmp_dir.path(), "ca", &default_alt_names(), None, None)
+                .unwrap();
+        let client = generate_or_load_identity(
+            "client",
+            tmp_dir.path(),
+            "client",
+            &default_alt_names(),
+            Some(&ca),
+            Some(ClientRole::Operator),
+        )
+        .unwrap();
+        let identity = parse_client_certificate(&certificate_der(&client)).unwrap();
+        assert_eq!(identity.name, "client");
+        assert!(!identity.serial.is_empty());
+        assert_eq!(identity.role, ClientRole::Operator);
+
+        // Certificates with no role are granted admin access
+        let legacy = generate_or_load_identity(
+            "legacy",
+            tmp_dir.path(),
+            "legacy",
+            &default_alt_names(),
+            Some(&ca),
+            None,
+        )
+        .unwrap();
+        let legacy_identity = parse_client_certificate(&certificate_der(&legacy)).unwrap();
+        assert_ne!(legacy_identity.serial, identity.serial);
+        assert_eq!(legacy_identity.role, ClientRole::Admin);
+
+        assert!(matches!(
+            parse_client_certificate(&[0; 32]),
+            Err(ClientCertificateError::InvalidCertificate)
+        ));
+    }
+
+    #[test]
+    fn test_issue_client_certificate() {
+        let tmp_dir = TempDir::new("teos_tls").unwrap();
+        tls_init(tmp_dir.path(), &[]).unwrap();
+        let clients = ClientCertificates::new(tmp_dir.path().to_path_buf()).unwrap();
+
+        let identity = clients.issue("monitoring", ClientRole::ReadOnly).unwrap();
+        let client = parse_client_certificate(&certificate_der(&identity)).unwrap();
+        assert_eq!(client.name, "monitoring");
+        assert_eq!(client.role, ClientRole::ReadOnly);
+        assert!(tmp_dir
+            .path()
+            .join(CLIENTS_DIR)
+            .join("monitoring.pem")
+            .exists());
+        assert!(tmp_dir
+            .path()
+            .join(CLIENTS_DIR)
+            .join("monitoring-key.pem")
+            .exists());
+
+        // Names cannot be reused, and must be made of letters, numbers, dashes and underscores
+        assert!(matches!(
+            clients.issue("monitoring", ClientRole::Admin),
+            Err(ClientCertificateError::AlreadyExists(..))
+        ));
+        for name in ["", DEFAULT_CLIENT_NAME, "../ca", "with space"] {
+            assert!(matches!(
+                clients.issue(name, ClientRole::Admin),
+                Err(ClientCertificateError::InvalidName(..))
+            ));
+        }
+
+        clients.issue("ops", ClientRole::Operator).unwrap();
+        assert_eq!(
+            clients.list().unwrap(),
+            vec![
+                ClientInfo {
+                    name: DEFAULT_CLIENT_NAME.to_owned(),
+                    role: ClientRole::Admin,
+                    revoked: false
+                },
+                ClientInfo {
+                    name: "monitoring".to_owned(),
+                    role: ClientRole::ReadOnly,
+                    revoked: false
+                },
+                ClientInfo {
+                    name: "ops".to_owned(),
+                    role: ClientRole::Operator,
+                    revoked: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_revoke_client_certificate() {
+        let tmp_dir = TempDir::new("teos_tls").unwrap();
+        tls_init(tmp_dir.path(), &[]).unwrap();
+        let clients = ClientCertificates::new(tmp_dir.path().to_path_buf()).unwrap();
+        let identity = clients.issue("monitoring", ClientRole::ReadOnly).unwrap();
+        let serial = parse_client_certificate(&certificate_der(&identity))
+            .unwrap()
+            .serial;
+
+        assert!(!clients.is_revoked(&serial));
+        clients.revoke("monitoring").unwrap();
+        assert!(clients.is_revoked(&serial));
+        // Revoking twice is fine
+        clients.revoke("monitoring").unwrap();
+        assert!(matches!(
+            clients.revoke("unknown"),
+            Err(ClientCertificateError::NotFound(..))
+        ));
+
+        // The revocation list is persisted
+        let clients = ClientCertificates::new(tmp_dir.path().to_path_buf()).unwrap();
+        assert!(clients.is_revoked(&serial));
+        assert_eq!(
+            std::fs::read_to_string(tmp_dir.path().join(REVOKED_CLIENTS_FILE)).unwrap(),
+            format!("{serial}\n")
+        );
+
+        // The default client can be revoked too
+        clients.revoke(DEFAULT_CLIENT_NAME).unwrap();
+        assert!(clients.list().unwrap().iter().all(|client| client.revoked));
+    }
+
+    #[test]
+    fn test_tls_init_alt_names() {
+        let tmp_dir = TempDir::new("teos_tls").unwrap();
+        let server_cert_path = tmp_dir.path().join("server.pem");
+        let read_alt_names = || {
+            let mut names =
+                certificate_alt_names(&std::fs::read(&server_cert_path).unwrap()).unwrap();
+            names.sort();
+            names
+        };
+
+        tls_init(tmp_dir.path(), &[]).unwrap();
+        assert_eq!(read_alt_names(), vec!["cln", "localhost"]);
+        let ca = std::fs::read(tmp_dir.path().join("ca.pem")).unwrap();
+        let server_key = std::fs::read(tmp_dir.path().join("server-key.pem")).unwrap();
+
+        // The server certificate is kept as long as the names do not change
+        tls_init(tmp_dir.path(), &["localhost".to_owned()]).unwrap();
+        assert_eq!(
+            std::fs::read(tmp_dir.path().join("server-key.pem")).unwrap(),
+            server_key
+        );
+
+        // And regenerated otherwise. The CA is kept so clients do not need to be set up again
+        let alt_names = vec!["tower.example.com".to_owned()];
+        tls_init(tmp_dir.path(), &alt_names).unwrap();
+        assert_eq!(
+            read_alt_names(),
+            vec!["cln", "localhost", "tower.example.com"]
+        );
+        assert_ne!(
+            std::fs::read(tmp_dir.path().join("server-key.pem")).unwrap(),
+            server_key
+        );
+        assert_eq!(std::fs::read(tmp_dir.path().join("ca.pem")).unwrap(), ca);
+    }
+
+    #[tokio::test]
+    async fn test_accept_tls() {
+        use tokio::io::AsyncReadExt;
+        use tokio::sync::mpsc;
+        use tokio_rustls::rustls::{ClientConfig, NoClientAuth, ServerConfig};
+        use tokio_rustls::webpki::DNSNameRef;
+        use tokio_rustls::TlsConnector;
+
+        let tmp_dir = TempDir::new("teos_accept_tls").unwrap();
+        let (cert, key) = generate_certificate("localhost");
+        std::fs::write(tmp_dir.path().join("cert.pem"), &cert).unwrap();
+        std::fs::write(tmp_dir.path().join("key.pem"), &key).unwrap();
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.cert_resolver = Arc::new(
+            ReloadableCertificate::new(
+                tmp_dir.path().join("cert.pem"),
+                tmp_dir.path().join("key.pem"),
+            )
+            .unwrap(),
+        );
+
+        // A single handshake can be in flight at a time, and it cannot take longer than the timeout
+        let limits = HandshakeLimits {
+            timeout: Duration::from_millis(500),
+            max_in_flight: 1,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(accept_tls(
+            listener,
+            TlsAcceptor::from(Arc::new(server_config)),
+            limits,
+            shutdown_signal,
+            move |_, addr| {
+                let tx = tx.clone();
+                async move {
+                    tx.send(addr).unwrap();
+                }
+            },
+        ));
+
+        // A client that never completes the handshake holds the only slot until it times out
+        let start = std::time::Instant::now();
+        let mut stalled = TcpStream::connect(addr).await.unwrap();
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add_pem_file(&mut cert.as_bytes())
+            .unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client_addr = stream.local_addr().unwrap();
+        TlsConnector::from(Arc::new(client_config))
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= limits.timeout);
+        assert_eq!(rx.recv().await, Some(client_addr));
+
+        // The stalled connection has been dropped
+        assert_eq!(stalled.read(&mut [0; 1]).await.unwrap(), 0);
+
+        shutdown_trigger.trigger();
+    }
+}
@@ -76,6 +76,25 @@ impl Value for Transaction {
     }
 }
 
+/// The header of a block alongside the ids of its transactions. All a [TxIndex] of block hashes needs to know about a
+/// block, so the index can be built without fetching the full blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTxids {
+    /// The header of the block.
+    pub header: BlockHeader,
+    /// The ids of the transactions included in the block.
+    pub txids: Vec<Txid>,
+}
+
+impl From<&ValidatedBlock> for BlockTxids {
+    fn from(block: &ValidatedBlock) -> Self {
+        BlockTxids {
+            header: block.header,
+            txids: block.txdata.iter().map(|tx| tx.txid()).collect(),
+        }
+    }
+}
+
 /// Data structure used to index locators computed from parsed blocks.
 ///
 /// Holds up to `size` blocks with their corresponding computed [Locator]s.
@@ -99,13 +118,21 @@ where
     V: Value + Clone,
     Self: Sized,
 {
-    pub fn new(last_n_blocks: &[ValidatedBlock], height: u32) -> Self {
-        let size = last_n_blocks.len();
+    /// Creates a new [TxIndex] able to hold up to `size` blocks, filling it with `last_n_blocks`.
+    ///
+    /// `last_n_blocks` are expected to be sorted from the most recent (at `height`) to the oldest. The index can be
+    /// started with fewer blocks than its capacity (e.g. if the chain is not that long yet), in which case it will fill
+    /// up as new blocks are connected.
+    pub fn new(last_n_blocks: &[ValidatedBlock], height: u32, size: usize) -> Self {
+        if last_n_blocks.len() > size {
+            panic!("last_n_blocks does not fit in the index");
+        }
+
         let mut tx_index = Self {
             index: HashMap::new(),
-            blocks: VecDeque::with_capacity(size),
+            blocks: VecDeque::with_capacity(size + 1),
             tx_in_block: HashMap::new(),
-            tip: height,
+            tip: 0,
             size,
         };
 
@@ -134,6 +161,7 @@ where
 
             tx_index.update(block.header, &map);
         }
+        tx_index.tip = height;
 
         tx_index
     }
@@ -167,11 +195,11 @@ where
             .collect();
 
         self.tx_in_block.insert(block_header.block_hash(), ks);
+        self.tip += 1;
 
         if self.is_full() {
             // Avoid logging during bootstrap
             log::debug!("New block added to index: {}", block_header.block_hash());
-            self.tip += 1;
             self.remove_oldest_block();
         }
     }
@@ -183,6 +211,7 @@ where
 
             // Blocks should be disconnected from last backwards. Log if that's not the case so we can revisit this and fix it.
             if let Some(ref h) = self.blocks.pop_back() {
+                self.tip -= 1;
                 if h != block_hash {
                     log::error!("Disconnected block does not match the oldest block stored in the TxIndex ({block_hash} != {h})");
                 }
@@ -203,6 +232,45 @@ where
     }
 }
 
+impl<K: Key + Copy> TxIndex<K, BlockHash> {
+    /// Creates a new [TxIndex] able to hold up to `size` blocks, filling it with the transaction ids of `last_n_blocks`.
+    ///
+    /// Works like [TxIndex::new], but only needs the transaction ids of each block instead of the full block.
+    pub fn from_txids(last_n_blocks: &[BlockTxids], height: u32, size: usize) -> Self {
+        if last_n_blocks.len() > size {
+            panic!("last_n_blocks does not fit in the index");
+        }
+
+        let mut tx_index = Self {
+            index: HashMap::new(),
+            blocks: VecDeque::with_capacity(size + 1),
+            tx_in_block: HashMap::new(),
+            tip: 0,
+            size,
+        };
+
+        for block in last_n_blocks.iter().rev() {
+            if let Some(prev_block_hash) = tx_index.blocks.back() {
+                if block.header.prev_blockhash != *prev_block_hash {
+                    panic!("last_n_blocks contains unchained blocks");
+                }
+            };
+
+            let block_hash = block.header.block_hash();
+            let map = block
+                .txids
+                .iter()
+                .map(|txid| (K::from_txid(*txid), block_hash))
+                .collect();
+
+            tx_index.update(block.header, &map);
+        }
+        tx_index.tip = height;
+
+        tx_index
+    }
+}
+
 impl<K: std::fmt::Debug + Key, V: std::fmt::Debug + Value> fmt::Display for TxIndex<K, V> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -251,7 +319,7 @@ mod tests {
             .map(|block| block.deref().clone())
             .collect();
 
-        let cache: TxIndex<Locator, Transaction> = TxIndex::new(&last_six_blocks, height);
+        let cache: TxIndex<Locator, Transaction> = TxIndex::new(&last_six_blocks, height, 6);
         assert_eq!(blocks.len(), cache.size);
         for block in blocks.iter() {
             assert!(cache.blocks().contains(&block.block_hash()));
@@ -267,6 +335,65 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_from_txids() {
+        let height = 10;
+        let mut chain = Blockchain::default().with_height(height as usize);
+        let last_six_blocks = get_last_n_blocks(&mut chain, 6).await;
+        let block_txids: Vec<BlockTxids> = last_six_blocks.iter().map(BlockTxids::from).collect();
+
+        // Building the index from the transaction ids is equivalent to building it from the full blocks
+        let cache: TxIndex<Txid, BlockHash> = TxIndex::from_txids(&block_txids, height, 6);
+        assert_eq!(cache, TxIndex::new(&last_six_blocks, height, 6));
+    }
+
+    #[tokio::test]
+    async fn test_new_fewer_blocks_than_size() {
+        // The index can be started with less blocks than its size (e.g. on a young chain), and it is filled up afterwards
+        let height = 3;
+        let cache_size = 6;
+        let mut chain = Blockchain::default().with_height(height);
+        let mut cache: TxIndex<Locator, Transaction> = TxIndex::new(
+            &get_last_n_blocks(&mut chain, height + 1).await,
+            height as u32,
+            cache_size,
+        );
+        assert_eq!(cache.blocks().len(), height + 1);
+        assert_eq!(cache.get_height(&chain.blocks[0].block_hash()), Some(0));
+        assert_eq!(
+            cache.get_height(&chain.tip().header.block_hash()),
+            Some(height)
+        );
+
+        for _ in height + 1..cache_size + 3 {
+            let block = chain.generate(None);
+            let locator_tx_map = block
+                .txdata
+                .iter()
+                .map(|tx| (Locator::new(tx.txid()), tx.clone()))
+                .collect();
+            cache.update(block.header, &locator_tx_map);
+
+            assert!(cache.blocks().len() <= cache_size);
+            assert_eq!(
+                cache.get_height(&block.block_hash()),
+                Some(chain.get_block_count() as usize)
+            );
+        }
+        assert_eq!(cache.blocks().len(), cache_size);
+        assert!(cache.get_height(&chain.blocks[0].block_hash()).is_none());
+
+        // Heights are kept right across reorgs
+        let tip = chain.disconnect_tip().unwrap();
+        cache.remove_disconnected_block(&tip.block_hash());
+        let block = chain.generate(None);
+        cache.update(block.header, &HashMap::new());
+        assert_eq!(
+            cache.get_height(&block.block_hash()),
+            Some(chain.get_block_count() as usize)
+        );
+    }
+
     #[tokio::test]
     async fn test_get_height() {
         let cache_size = 10;
@@ -279,7 +406,8 @@ mod tests {
         let last_block = last_n_blocks.first().unwrap();
         let mid = last_n_blocks.get(cache_size / 2).unwrap();
 
-        let cache: TxIndex<Locator, Transaction> = TxIndex::new(&last_n_blocks, height as u32);
+        let cache: TxIndex<Locator, Transaction> =
+            TxIndex::new(&last_n_blocks, height as u32, cache_size);
 
         assert_eq!(
             cache.get_height(&first_block.block_hash()).unwrap(),
@@ -300,6 +428,7 @@ mod tests {
         let cache: TxIndex<Locator, Transaction> = TxIndex::new(
             &get_last_n_blocks(&mut chain, cache_size).await,
             height as u32,
+            cache_size,
         );
 
         let fake_hash = BlockHash::default();
@@ -318,7 +447,7 @@ mod tests {
         let first_block = last_n_blocks.last().unwrap().deref().clone();
 
         // Init the cache with the 6 block before the last
-        let mut cache = TxIndex::new(&last_n_blocks, height);
+        let mut cache = TxIndex::new(&last_n_blocks, height, 6);
 
         // Update the cache with the last block
         let locator_tx_map = last_block
@@ -355,6 +484,7 @@ mod tests {
         let mut cache: TxIndex<Locator, Transaction> = TxIndex::new(
             &get_last_n_blocks(&mut chain, cache_size).await,
             height as u32,
+            cache_size,
         );
 
         // TxIndex::fix removes the last connected block and removes all the associated data
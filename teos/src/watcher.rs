@@ -1,25 +1,100 @@
 //! Logic related to the Watcher, the components in charge of watching for breaches on chain.
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
-use bitcoin::secp256k1::SecretKey;
-use bitcoin::{BlockHeader, Transaction};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::{Block, BlockHeader, Transaction, Txid};
 use lightning::chain;
+use lightning::ln::PaymentHash;
 use lightning_block_sync::poll::ValidatedBlock;
 
-use teos_common::appointment::{Appointment, Locator};
+use teos_common::appointment::{Appointment, AppointmentStatus, FailureKind, Locator};
+use teos_common::backup::Backup;
+use teos_common::constants::BACKUP_MAX_SIZE;
 use teos_common::cryptography;
-use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
+use teos_common::pow;
+use teos_common::protos as common_msgs;
+use teos_common::receipts::{
+    AppointmentReceipt, BackupReceipt, RegistrationReceipt, ResponseReceipt,
+};
 use teos_common::{TowerId, UserId};
 
-use crate::dbm::DBM;
+use crate::dbm::{DBReader, DBM};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
-use crate::gatekeeper::{Gatekeeper, MaxSlotsReached, UserInfo};
-use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
+use crate::gatekeeper::{Gatekeeper, RegistrationFailure, SubscriptionPlan, UserInfo};
+use crate::recovery::MissedBreach;
+use crate::responder::{ConfirmationStatus, PenaltyProgress, Responder, TransactionTracker};
 use crate::tx_index::TxIndex;
 
+/// Requirements new users have to meet in order to register with the tower.
+///
+/// Gating registrations makes minting user identities costly, protecting the tower against sybil attacks. Users that
+/// are already registered can renew their subscriptions without going through the gate again. If both gates are
+/// enabled, meeting either of them is enough.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegistrationGate {
+    /// Number of leading zero bits required for the registration proof of work. Zero means no proof is required.
+    pub pow_difficulty: u8,
+    /// Whether new users can register by redeeming a one-time token issued by the tower admin.
+    pub tokens: bool,
+}
+
+impl RegistrationGate {
+    /// Creates a new [RegistrationGate] instance.
+    pub fn new(pow_difficulty: u8, tokens: bool) -> Self {
+        RegistrationGate {
+            pow_difficulty,
+            tokens,
+        }
+    }
+
+    /// Whether registrations are gated at all.
+    pub fn is_enabled(&self) -> bool {
+        self.pow_difficulty > 0 || self.tokens
+    }
+}
+
+/// Tunable parameters of the [Watcher].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatcherConfig {
+    /// Number of blocks kept in the locator cache.
+    pub locator_cache_depth: usize,
+    /// The requirements new users have to meet to register.
+    pub registration_gate: RegistrationGate,
+}
+
+impl WatcherConfig {
+    /// Creates a new [WatcherConfig] instance.
+    pub fn new(locator_cache_depth: usize, registration_gate: RegistrationGate) -> Self {
+        WatcherConfig {
+            locator_cache_depth,
+            registration_gate,
+        }
+    }
+}
+
+/// What a user provides in order to pass the [RegistrationGate].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RegistrationProof {
+    /// Nonce for the registration proof of work (see [pow]).
+    pub pow_nonce: u64,
+    /// A one-time registration token issued by the tower admin.
+    pub token: Option<String>,
+}
+
+impl RegistrationProof {
+    /// Creates a new [RegistrationProof] instance. An empty `token` means no token was provided.
+    pub fn new(pow_nonce: u64, token: String) -> Self {
+        RegistrationProof {
+            pow_nonce,
+            token: Some(token).filter(|t| !t.is_empty()),
+        }
+    }
+}
+
 /// Structure holding data regarding a breach.
 ///
 /// Breaches are computed after spotting a [Locator] on chain and
@@ -43,6 +118,64 @@ impl Breach {
     }
 }
 
+/// Records why the tower could not respond to a triggered appointment.
+///
+/// Outcomes are kept after the appointment is deleted so users and admins can find out why a breach was not punished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AppointmentOutcome {
+    /// The identifier of the triggered appointment.
+    pub uuid: UUID,
+    /// The locator of the triggered appointment.
+    pub locator: Locator,
+    /// The [UserId] the appointment belongs to.
+    pub user_id: UserId,
+    /// The id of the transaction that triggered the appointment.
+    pub dispute_txid: Txid,
+    /// Why the tower could not respond.
+    pub kind: FailureKind,
+    /// The error code returned by bitcoind, only set for [FailureKind::Rejected].
+    pub rpc_error_code: Option<i32>,
+    /// The block height at which the breach was handled.
+    pub height: u32,
+}
+
+impl AppointmentOutcome {
+    /// Creates a new [AppointmentOutcome] instance.
+    pub fn new(
+        uuid: UUID,
+        locator: Locator,
+        user_id: UserId,
+        dispute_txid: Txid,
+        kind: FailureKind,
+        rpc_error_code: Option<i32>,
+        height: u32,
+    ) -> Self {
+        AppointmentOutcome {
+            uuid,
+            locator,
+            user_id,
+            dispute_txid,
+            kind,
+            rpc_error_code,
+            height,
+        }
+    }
+}
+
+impl From<AppointmentOutcome> for common_msgs::AppointmentOutcome {
+    fn from(outcome: AppointmentOutcome) -> Self {
+        common_msgs::AppointmentOutcome {
+            uuid: outcome.uuid.to_vec(),
+            locator: outcome.locator.to_vec(),
+            user_id: outcome.user_id.to_vec(),
+            dispute_txid: outcome.dispute_txid.to_vec(),
+            kind: outcome.kind as i32,
+            rpc_error_code: outcome.rpc_error_code.unwrap_or_default(),
+            height: outcome.height,
+        }
+    }
+}
+
 /// Packs the reasons why trying to add an appointment may fail.
 // TODO: It may be nice to create richer errors so the API can return richer rejection
 #[derive(Debug)]
@@ -51,6 +184,7 @@ pub(crate) enum AddAppointmentFailure {
     NotEnoughSlots,
     SubscriptionExpired(u32),
     AlreadyTriggered,
+    TooBig(u32),
 }
 
 /// Packs the reasons why trying to query an appointment may fail.
@@ -61,6 +195,13 @@ pub(crate) enum GetAppointmentFailure {
     NotFound,
 }
 
+/// Packs the reasons why trying to query the outcomes of an appointment may fail.
+#[derive(Debug)]
+pub(crate) enum GetAppointmentOutcomesFailure {
+    AuthenticationFailure,
+    SubscriptionExpired(u32),
+}
+
 /// Packs the reasons why trying to query a subscription info may fail.
 #[derive(Debug)]
 pub(crate) enum GetSubscriptionInfoFailure {
@@ -68,14 +209,36 @@ pub(crate) enum GetSubscriptionInfoFailure {
     SubscriptionExpired(u32),
 }
 
+/// Packs the reasons why trying to store a backup may fail.
+#[derive(Debug)]
+pub(crate) enum StoreBackupFailure {
+    AuthenticationFailure,
+    NotEnoughSlots,
+    SubscriptionExpired(u32),
+    TooBig,
+    OutdatedVersion(u32),
+}
+
+/// Packs the reasons why trying to query a backup may fail.
+#[derive(Debug)]
+pub(crate) enum GetBackupFailure {
+    AuthenticationFailure,
+    SubscriptionExpired(u32),
+    NotFound,
+}
+
 /// Wraps the returning information regarding a queried appointment.
 ///
-/// Either an [Appointment] or a [TransactionTracker] can be
+/// Either an [Appointment] or a [TransactionTracker] (alongside the [PenaltyProgress] and latest [ResponseReceipt]) can be
 /// returned depending on whether the appointment can be found in the [Watcher] or in the [Responder].
+/// Once the [Responder] stops tracking the penalty, only the [ResponseReceipt] is kept. It is returned alongside the
+/// terminal status of the appointment: resolved, rejected (if a rejection was recorded as an [AppointmentOutcome]), or
+/// just responded if the tower does not know how the penalty ended up.
 #[derive(Debug)]
 pub(crate) enum AppointmentInfo {
     Appointment(Appointment),
-    Tracker(TransactionTracker),
+    Tracker(TransactionTracker, PenaltyProgress, Option<ResponseReceipt>),
+    Responded(ResponseReceipt, PenaltyProgress, AppointmentStatus),
 }
 
 /// Types of new appointments stored in the [Watcher].
@@ -110,27 +273,38 @@ pub struct Watcher {
     pub tower_id: TowerId,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
     dbm: Arc<Mutex<DBM>>,
+    /// A pool of read-only connections to the database. Used by read-only paths so they don't contend with the [DBM].
+    db_reader: DBReader,
+    /// The requirements new users have to meet to register.
+    registration_gate: RegistrationGate,
 }
 
 impl Watcher {
-    /// Creates a new [Watcher] instance.
+    /// Creates a new [Watcher] instance. The tower identifier is derived from the `signing_key`.
     pub fn new(
         gatekeeper: Arc<Gatekeeper>,
         responder: Arc<Responder>,
         last_n_blocks: &[ValidatedBlock],
         last_known_block_height: u32,
+        config: WatcherConfig,
         signing_key: SecretKey,
-        tower_id: TowerId,
         dbm: Arc<Mutex<DBM>>,
     ) -> Self {
+        let db_reader = dbm.lock().unwrap().reader();
         Watcher {
-            locator_cache: Mutex::new(TxIndex::new(last_n_blocks, last_known_block_height)),
+            locator_cache: Mutex::new(TxIndex::new(
+                last_n_blocks,
+                last_known_block_height,
+                config.locator_cache_depth,
+            )),
             responder,
             gatekeeper,
             last_known_block_height: AtomicU32::new(last_known_block_height),
             signing_key,
-            tower_id,
+            tower_id: TowerId(PublicKey::from_secret_key(&Secp256k1::new(), &signing_key)),
+            db_reader,
             dbm,
+            registration_gate: config.registration_gate,
         }
     }
 
@@ -139,10 +313,104 @@ impl Watcher {
         self.get_appointments_count() == 0
     }
 
-    /// Registers a new user within the [Watcher]. This request is passed to the [Gatekeeper], who is in
-    /// charge of managing users.
-    pub(crate) fn register(&self, user_id: UserId) -> Result<RegistrationReceipt, MaxSlotsReached> {
-        let mut receipt = self.gatekeeper.add_update_user(user_id)?;
+    /// Gets the requirements new users have to meet to register.
+    pub(crate) fn get_registration_gate(&self) -> RegistrationGate {
+        self.registration_gate
+    }
+
+    /// Checks whether a user passes the [RegistrationGate]. Users that are already registered always pass it.
+    ///
+    /// If `redeem` is set, the registration token provided by the user (if any) is consumed.
+    fn check_registration_gate(
+        &self,
+        user_id: UserId,
+        proof: &RegistrationProof,
+        redeem: bool,
+    ) -> Result<(), RegistrationFailure> {
+        let gate = self.registration_gate;
+        if !gate.is_enabled() || self.gatekeeper.is_registered(user_id) {
+            return Ok(());
+        }
+
+        if let Some(token) = proof.token.as_ref().filter(|_| gate.tokens) {
+            let dbm = self.dbm.lock().unwrap();
+            let valid = if redeem {
+                dbm.remove_registration_token(token).is_ok()
+            } else {
+                dbm.has_registration_token(token)
+            };
+            if valid {
+                return Ok(());
+            }
+        }
+
+        if gate.pow_difficulty > 0 {
+            if pow::verify_pow(self.tower_id, user_id, proof.pow_nonce, gate.pow_difficulty) {
+                Ok(())
+            } else {
+                Err(RegistrationFailure::InvalidProofOfWork)
+            }
+        } else {
+            Err(RegistrationFailure::InvalidToken)
+        }
+    }
+
+    /// Gets the plan a user is asking to register for, provided they pass the [RegistrationGate]. Registration tokens
+    /// are not redeemed at this point. The plan lookup is passed to the [Gatekeeper].
+    pub(crate) fn get_registration_plan(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        proof: &RegistrationProof,
+    ) -> Result<(String, SubscriptionPlan), RegistrationFailure> {
+        let registration_plan = self.gatekeeper.get_registration_plan(user_id, plan)?;
+        self.check_registration_gate(user_id, proof, false)?;
+
+        Ok(registration_plan)
+    }
+
+    /// Registers a new user within the [Watcher] under a given subscription plan. This request is passed to the
+    /// [Gatekeeper], who is in charge of managing users.
+    ///
+    /// New users need to pass the [RegistrationGate] first. Registration tokens are redeemed at this point.
+    pub(crate) fn register(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        proof: &RegistrationProof,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        self.register_with_payment(user_id, plan, proof, None)
+    }
+
+    /// Same as [Watcher::register], but for users that have paid for the plan. The pending payment identified by
+    /// `payment_hash` is redeemed by the [Gatekeeper] alongside the subscription update.
+    pub(crate) fn register_paid(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        proof: &RegistrationProof,
+        payment_hash: &PaymentHash,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        self.register_with_payment(user_id, plan, proof, Some(payment_hash))
+    }
+
+    fn register_with_payment(
+        &self,
+        user_id: UserId,
+        plan: &str,
+        proof: &RegistrationProof,
+        payment_hash: Option<&PaymentHash>,
+    ) -> Result<RegistrationReceipt, RegistrationFailure> {
+        // Make sure the user can register under the given plan before redeeming their token
+        self.gatekeeper.get_registration_plan(user_id, plan)?;
+        self.check_registration_gate(user_id, proof, true)?;
+        let mut receipt = match payment_hash {
+            Some(payment_hash) => {
+                self.gatekeeper
+                    .add_update_paid_user(user_id, plan, payment_hash)?
+            }
+            None => self.gatekeeper.add_update_user(user_id, plan)?,
+        };
         receipt.sign(&self.signing_key);
 
         Ok(receipt)
@@ -153,6 +421,7 @@ impl Watcher {
     /// Appointments are only added provided:
     /// - The user is registered into the system
     /// - The user subscription has not expired
+    /// - The appointment blob does not exceed the maximum size allowed by the user subscription plan
     /// - The user has enough available slots to fit the appointment
     /// - The appointment hasn't been responded to yet (data cannot be found in the [Responder])
     ///
@@ -176,6 +445,12 @@ impl Watcher {
             return Err(AddAppointmentFailure::SubscriptionExpired(expiry));
         }
 
+        if let Some(max_blob_size) = self.gatekeeper.get_max_blob_size(user_id) {
+            if appointment.encrypted_blob.len() > max_blob_size as usize {
+                return Err(AddAppointmentFailure::TooBig(max_blob_size));
+            }
+        }
+
         let extended_appointment = ExtendedAppointment::new(
             appointment,
             user_id,
@@ -232,7 +507,7 @@ impl Watcher {
         uuid: UUID,
         appointment: &ExtendedAppointment,
     ) -> StoredAppointment {
-        let dbm = self.dbm.lock().unwrap();
+        let mut dbm = self.dbm.lock().unwrap();
         if dbm.appointment_exists(uuid) {
             log::debug!(
                 "User {} is updating appointment {uuid}",
@@ -280,6 +555,15 @@ impl Watcher {
                     user_id,
                 ) {
                     log::warn!("Appointment bounced in the Responder. Reason: {reason:?}");
+                    self.store_outcomes(&[AppointmentOutcome::new(
+                        uuid,
+                        appointment.locator(),
+                        user_id,
+                        dispute_tx.txid(),
+                        FailureKind::Rejected,
+                        Some(reason),
+                        self.last_known_block_height.load(Ordering::Acquire),
+                    )]);
                     self.gatekeeper.delete_appointments(vec![uuid], false);
                     TriggeredAppointment::Rejected
                 } else {
@@ -292,11 +576,20 @@ impl Watcher {
             // If data inside the encrypted blob is invalid, the appointment is accepted but the data is dropped.
             // (same as with data that bounces in the Responder). This reduces the appointment slot count so it
             // could be used to discourage user misbehavior.
-            Err(_) => {
+            Err(e) => {
                 log::info!(
                     "The appointment contained invalid data {}",
                     appointment.locator()
                 );
+                self.store_outcomes(&[AppointmentOutcome::new(
+                    uuid,
+                    appointment.locator(),
+                    user_id,
+                    dispute_tx.txid(),
+                    FailureKind::from(&e),
+                    None,
+                    self.last_known_block_height.load(Ordering::Acquire),
+                )]);
                 TriggeredAppointment::Invalid
             }
         }
@@ -308,7 +601,8 @@ impl Watcher {
     /// - The user is registered into the system
     /// - The user subscription has not expired
     /// - The appointment belongs to the user
-    /// - The appointment exists within the system (either in the [Watcher] or the [Responder])
+    /// - The appointment exists within the system (either in the [Watcher] or the [Responder]), or the tower has
+    ///   responded to it in the past
     pub(crate) fn get_appointment(
         &self,
         locator: Locator,
@@ -329,19 +623,92 @@ impl Watcher {
         }
 
         let uuid = UUID::new(locator, user_id);
-        let dbm = self.dbm.lock().unwrap();
+        let height = self.last_known_block_height.load(Ordering::Acquire);
+        let dbm = self.db_reader.get();
         dbm.load_tracker(uuid)
-            .map(AppointmentInfo::Tracker)
+            .map(|tracker| {
+                let progress = PenaltyProgress::new(
+                    tracker.status,
+                    dbm.load_rebroadcast_attempts(uuid),
+                    height,
+                );
+                AppointmentInfo::Tracker(tracker, progress, dbm.load_response_receipt(uuid))
+            })
             .or_else(|| {
                 dbm.load_appointment(uuid)
                     .map(|ext_app| AppointmentInfo::Appointment(ext_app.inner))
             })
+            .or_else(|| {
+                dbm.load_response_receipt(uuid).map(|receipt| {
+                    let progress =
+                        PenaltyProgress::untracked(receipt.confirmation_height(), height);
+                    let status = if receipt.is_resolved() {
+                        AppointmentStatus::DisputeResolved
+                    } else if dbm
+                        .load_appointment_outcomes(Some(uuid))
+                        .iter()
+                        .any(|outcome| {
+                            outcome.kind == FailureKind::Rejected
+                                && outcome.height >= receipt.broadcast_height()
+                        })
+                    {
+                        AppointmentStatus::PenaltyRejected
+                    } else {
+                        AppointmentStatus::DisputeResponded
+                    };
+                    AppointmentInfo::Responded(receipt, progress, status)
+                })
+            })
             .ok_or_else(|| {
                 log::info!("Cannot find {locator}");
                 GetAppointmentFailure::NotFound
             })
     }
 
+    /// Retrieves the recorded [AppointmentOutcome]s of a user appointment, oldest first.
+    ///
+    /// Outcomes can only be retrieved provided the user is registered into the system and the subscription has not
+    /// expired. An empty list is returned if the tower has never failed to respond to the appointment.
+    pub(crate) fn get_appointment_outcomes(
+        &self,
+        locator: Locator,
+        user_signature: &str,
+    ) -> Result<Vec<AppointmentOutcome>, GetAppointmentOutcomesFailure> {
+        let message = format!("get appointment outcomes {locator}");
+
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(message.as_bytes(), user_signature)
+            .map_err(|_| GetAppointmentOutcomesFailure::AuthenticationFailure)?;
+
+        let (has_subscription_expired, expiry) =
+            self.gatekeeper.has_subscription_expired(user_id).unwrap();
+
+        if has_subscription_expired {
+            return Err(GetAppointmentOutcomesFailure::SubscriptionExpired(expiry));
+        }
+
+        Ok(self
+            .db_reader
+            .get()
+            .load_appointment_outcomes(Some(UUID::new(locator, user_id))))
+    }
+
+    /// Gets all the [AppointmentOutcome]s recorded by the tower, oldest first.
+    pub(crate) fn get_all_appointment_outcomes(&self) -> Vec<AppointmentOutcome> {
+        self.db_reader.get().load_appointment_outcomes(None)
+    }
+
+    /// Persists the outcomes of the triggered appointments the tower could not respond to.
+    fn store_outcomes(&self, outcomes: &[AppointmentOutcome]) {
+        if !outcomes.is_empty() {
+            self.dbm
+                .lock()
+                .unwrap()
+                .store_appointment_outcomes(outcomes);
+        }
+    }
+
     /// Gets a map of breaches provided a map between locators and transactions.
     ///
     /// The provided map if intersected with the map of all locators monitored by [Watcher] and the result
@@ -373,36 +740,92 @@ impl Watcher {
     ///
     /// Decrypts triggered appointments using the dispute transaction ID and publishes them.
     /// If the decryption fails for some appointments or if it succeeds but they get rejected when sent to the network,
-    /// they are marked as an invalid breaches and returned.
+    /// they are marked as an invalid breaches and returned. The reason why each of them failed is recorded as an
+    /// [AppointmentOutcome] at the given `height`.
     /// [None] is returned if none of these breaches are invalid.
-    fn handle_breaches(&self, breaches: HashMap<Locator, Transaction>) -> Option<Vec<UUID>> {
+    fn handle_breaches(
+        &self,
+        breaches: HashMap<Locator, Transaction>,
+        height: u32,
+    ) -> Option<Vec<UUID>> {
         let mut invalid_breaches = Vec::new();
+        let mut outcomes = Vec::new();
 
+        // Appointments are only read here, so a reader can be held over the loop while the Responder uses the writer.
+        let dbm = self.db_reader.get();
         for (locator, dispute_tx) in breaches.into_iter() {
-            // WARNING(deadlock): Don't lock `self.dbm` over the loop since `Responder::handle_breach` uses it as well.
-            let uuids = self.dbm.lock().unwrap().load_uuids(locator);
+            let uuids = dbm.load_uuids(locator);
             for uuid in uuids {
-                let appointment = self.dbm.lock().unwrap().load_appointment(uuid).unwrap();
-                match cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid()) {
-                    Ok(penalty_tx) => {
-                        if let ConfirmationStatus::Rejected(_) = self.responder.handle_breach(
+                // The breach may have already been handled, e.g. if a rescan covers blocks the Watcher has already processed.
+                if self.responder.has_tracker(uuid) {
+                    log::info!("Tracker for {uuid} already found in Responder");
+                    continue;
+                }
+                let appointment = dbm.load_appointment(uuid).unwrap();
+                let failure =
+                    match cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid()) {
+                        Ok(penalty_tx) => match self.responder.handle_breach(
                             uuid,
                             Breach::new(dispute_tx.clone(), penalty_tx),
                             appointment.user_id,
                         ) {
-                            invalid_breaches.push(uuid);
-                        }
-                    }
-                    Err(_) => {
-                        invalid_breaches.push(uuid);
-                    }
+                            ConfirmationStatus::Rejected(code) => {
+                                Some((FailureKind::Rejected, Some(code)))
+                            }
+                            _ => None,
+                        },
+                        Err(e) => Some((FailureKind::from(&e), None)),
+                    };
+
+                if let Some((kind, rpc_error_code)) = failure {
+                    invalid_breaches.push(uuid);
+                    outcomes.push(AppointmentOutcome::new(
+                        uuid,
+                        locator,
+                        appointment.user_id,
+                        dispute_tx.txid(),
+                        kind,
+                        rpc_error_code,
+                        height,
+                    ));
                 }
             }
         }
+        drop(dbm);
+        self.store_outcomes(&outcomes);
 
         (!invalid_breaches.is_empty()).then_some(invalid_breaches)
     }
 
+    /// Checks a historical block for breaches of the appointments being watched.
+    ///
+    /// Works like [filtered_block_connected](chain::Listen::filtered_block_connected) but leaves the [LocatorCache]
+    /// and the last known block height untouched, so it can be run while the chain is being monitored.
+    ///
+    /// Returns the number of breaches found in the block.
+    pub(crate) fn rescan_block(&self, block: &Block, height: u32) -> usize {
+        log::info!("Rescanning block {} (height: {height})", block.block_hash());
+
+        let locator_tx_map = block
+            .txdata
+            .iter()
+            .map(|tx| (Locator::new(tx.txid()), tx.clone()))
+            .collect();
+
+        let breaches = self.get_breaches(locator_tx_map);
+        let n_breaches = breaches.len();
+        if let Some(invalid_breaches) = self.handle_breaches(breaches, height) {
+            self.gatekeeper.delete_appointments(invalid_breaches, false);
+        }
+
+        n_breaches
+    }
+
+    /// Gets the last block height known by the [Watcher].
+    pub(crate) fn get_last_known_block_height(&self) -> u32 {
+        self.last_known_block_height.load(Ordering::Acquire)
+    }
+
     /// Ges the number of users currently registered with the tower.
     pub(crate) fn get_registered_users_count(&self) -> usize {
         self.gatekeeper.get_registered_users_count()
@@ -410,7 +833,7 @@ impl Watcher {
 
     /// Gets the total number of appointments excluding trackers.
     pub(crate) fn get_appointments_count(&self) -> usize {
-        self.dbm.lock().unwrap().get_appointments_count()
+        self.db_reader.get().get_appointments_count()
     }
 
     /// Gets the total number of trackers in the [Responder].
@@ -420,7 +843,7 @@ impl Watcher {
 
     /// Gets all the appointments stored in the [Watcher] (from the database).
     pub(crate) fn get_all_watcher_appointments(&self) -> HashMap<UUID, ExtendedAppointment> {
-        self.dbm.lock().unwrap().load_appointments(None)
+        self.db_reader.get().load_appointments(None)
     }
 
     /// Gets all the appointments matching a specific locator from the [Watcher] (from the database).
@@ -428,12 +851,12 @@ impl Watcher {
         &self,
         locator: Locator,
     ) -> HashMap<UUID, ExtendedAppointment> {
-        self.dbm.lock().unwrap().load_appointments(Some(locator))
+        self.db_reader.get().load_appointments(Some(locator))
     }
 
     /// Gets all the trackers stored in the [Responder] (from the database).
     pub(crate) fn get_all_responder_trackers(&self) -> HashMap<UUID, TransactionTracker> {
-        self.dbm.lock().unwrap().load_trackers(None)
+        self.db_reader.get().load_trackers(None)
     }
 
     /// Gets all the trackers matching s specific locator from the [Responder] (from the database).
@@ -441,7 +864,7 @@ impl Watcher {
         &self,
         locator: Locator,
     ) -> HashMap<UUID, TransactionTracker> {
-        self.dbm.lock().unwrap().load_trackers(Some(locator))
+        self.db_reader.get().load_trackers(Some(locator))
     }
 
     /// Gets the list of all registered user ids.
@@ -454,6 +877,86 @@ impl Watcher {
         self.gatekeeper.get_user_info(user_id)
     }
 
+    /// Adds a user to the allow-list used when the tower is running in private mode.
+    pub(crate) fn add_allowed_user(&self, user_id: UserId) {
+        self.gatekeeper.add_allowed_user(user_id)
+    }
+
+    /// Removes a user from the allow-list. Returns whether the user was in the list.
+    pub(crate) fn remove_allowed_user(&self, user_id: UserId) -> bool {
+        self.gatekeeper.remove_allowed_user(user_id)
+    }
+
+    /// Gets the list of all allowed user ids.
+    pub(crate) fn get_allowed_users(&self) -> Vec<UserId> {
+        self.gatekeeper.get_allowed_users()
+    }
+
+    /// Issues `count` one-time registration tokens, returning them.
+    pub(crate) fn create_registration_tokens(&self, count: u32) -> Vec<String> {
+        let dbm = self.dbm.lock().unwrap();
+        (0..count)
+            .map(|_| {
+                let token = hex::encode(cryptography::get_random_bytes(16));
+                dbm.store_registration_token(&token).unwrap();
+                token
+            })
+            .collect()
+    }
+
+    /// Gets the registration tokens that have not been redeemed yet.
+    pub(crate) fn get_registration_tokens(&self) -> Vec<String> {
+        self.db_reader.get().load_registration_tokens()
+    }
+
+    /// Extends the subscription of a user by a given number of blocks. Returns the updated [UserInfo], if found.
+    pub(crate) fn extend_subscription(&self, user_id: UserId, blocks: u32) -> Option<UserInfo> {
+        self.gatekeeper.extend_subscription(user_id, blocks)
+    }
+
+    /// Sets the number of available slots of a user. Returns the updated [UserInfo], if found.
+    pub(crate) fn set_available_slots(&self, user_id: UserId, slots: u32) -> Option<UserInfo> {
+        self.gatekeeper.set_available_slots(user_id, slots)
+    }
+
+    /// Bans a user from the tower, deleting all their data.
+    pub(crate) fn ban_user(&self, user_id: UserId) {
+        self.gatekeeper.ban_user(user_id);
+        self.responder.forget_users(&[user_id]);
+    }
+
+    /// Lifts the ban of a user. Returns whether the user was banned.
+    pub(crate) fn unban_user(&self, user_id: UserId) -> bool {
+        self.gatekeeper.unban_user(user_id)
+    }
+
+    /// Gets the list of all banned user ids.
+    pub(crate) fn get_banned_users(&self) -> Vec<UserId> {
+        self.gatekeeper.get_banned_users()
+    }
+
+    /// Deletes a user alongside all their data. Returns whether the user was registered.
+    pub(crate) fn delete_user(&self, user_id: UserId) -> bool {
+        let deleted = self.gatekeeper.delete_user(user_id);
+        self.responder.forget_users(&[user_id]);
+        deleted
+    }
+
+    /// Gets the findings of the recovery scans run by the tower.
+    pub(crate) fn get_missed_breaches(&self) -> Vec<MissedBreach> {
+        self.db_reader.get().load_missed_breaches()
+    }
+
+    /// Writes a consistent snapshot of the tower database to `path`. The tower keeps running meanwhile.
+    pub(crate) fn backup_database(&self, path: &Path) -> Result<(), rusqlite::Error> {
+        self.db_reader.get().backup(path)
+    }
+
+    /// Gets the subscription plans offered by the tower.
+    pub(crate) fn get_subscription_plans(&self) -> Vec<(String, SubscriptionPlan)> {
+        self.gatekeeper.get_subscription_plans()
+    }
+
     /// Gets information about a user's subscription.
     pub(crate) fn get_subscription_info(
         &self,
@@ -476,6 +979,84 @@ impl Watcher {
         let (subscription_info, locators) = self.gatekeeper.get_user_info(user_id).unwrap();
         Ok((subscription_info, locators))
     }
+
+    /// Stores a user [Backup] in the tower, replacing the previous one (if any).
+    ///
+    /// Backups are only stored provided:
+    /// - The user is registered into the system
+    /// - The user subscription has not expired
+    /// - The backup is not bigger than [BACKUP_MAX_SIZE]
+    /// - The backup version is higher than the one of the stored backup
+    /// - The user has enough available slots to fit the backup
+    pub(crate) fn store_backup(
+        &self,
+        backup: Backup,
+        user_signature: String,
+    ) -> Result<(BackupReceipt, u32, u32), StoreBackupFailure> {
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(&backup.to_vec(), &user_signature)
+            .map_err(|_| StoreBackupFailure::AuthenticationFailure)?;
+
+        let (has_subscription_expired, expiry) =
+            self.gatekeeper.has_subscription_expired(user_id).unwrap();
+
+        if has_subscription_expired {
+            return Err(StoreBackupFailure::SubscriptionExpired(expiry));
+        }
+
+        if backup.data.len() > BACKUP_MAX_SIZE {
+            return Err(StoreBackupFailure::TooBig);
+        }
+
+        if let Some((version, _)) = self.db_reader.get().get_backup_version_and_length(user_id) {
+            if backup.version <= version {
+                log::info!(
+                    "Rejecting outdated backup from {user_id} (version {})",
+                    backup.version
+                );
+                return Err(StoreBackupFailure::OutdatedVersion(version));
+            }
+        }
+
+        // TODO: Same as with appointments, this is not atomic: slots are updated before the backup is stored.
+        let available_slots = self
+            .gatekeeper
+            .add_update_backup(user_id, &backup)
+            .map_err(|_| StoreBackupFailure::NotEnoughSlots)?;
+        self.dbm
+            .lock()
+            .unwrap()
+            .store_backup(user_id, &backup)
+            .unwrap();
+
+        let mut receipt = BackupReceipt::new(user_signature, backup.version);
+        receipt.sign(&self.signing_key);
+
+        Ok((receipt, available_slots, expiry))
+    }
+
+    /// Retrieves the [Backup] of a user from the tower.
+    pub(crate) fn get_backup(&self, signature: &str) -> Result<Backup, GetBackupFailure> {
+        let message = "get backup".to_string();
+
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(message.as_bytes(), signature)
+            .map_err(|_| GetBackupFailure::AuthenticationFailure)?;
+
+        let (has_subscription_expired, expiry) =
+            self.gatekeeper.has_subscription_expired(user_id).unwrap();
+
+        if has_subscription_expired {
+            return Err(GetBackupFailure::SubscriptionExpired(expiry));
+        }
+
+        self.db_reader
+            .get()
+            .load_backup(user_id)
+            .ok_or(GetBackupFailure::NotFound)
+    }
 }
 
 /// Listen implementation by the [Watcher]. Handles monitoring and reorgs.
@@ -510,7 +1091,9 @@ impl chain::Listen for Watcher {
             .update(*header, &locator_tx_map);
 
         // Get the breaches found in this block, handle them, and delete invalid ones.
-        if let Some(invalid_breaches) = self.handle_breaches(self.get_breaches(locator_tx_map)) {
+        if let Some(invalid_breaches) =
+            self.handle_breaches(self.get_breaches(locator_tx_map), height)
+        {
             self.gatekeeper.delete_appointments(invalid_breaches, false);
         }
 
@@ -536,12 +1119,13 @@ impl chain::Listen for Watcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use std::collections::{BTreeMap, HashSet};
     use std::iter::FromIterator;
     use std::ops::Deref;
     use std::sync::{Arc, Mutex};
 
     use crate::dbm::DBM;
+    use crate::gatekeeper::DEFAULT_PLAN;
     use crate::responder::ConfirmationStatus;
     use crate::rpc_errors;
     use crate::test_utils::{
@@ -549,7 +1133,8 @@ mod tests {
         generate_dummy_appointment_with_user, get_random_tx, BitcoindMock, BitcoindStopper,
         Blockchain, MockOptions, MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
     };
-    use teos_common::cryptography::get_random_keypair;
+    use teos_common::cryptography::{get_random_bytes, get_random_keypair};
+    use teos_common::test_utils::get_random_user_id;
 
     use bitcoin::secp256k1::{PublicKey, Secp256k1};
 
@@ -571,6 +1156,20 @@ mod tests {
             self.responder.add_dummy_tracker(tracker)
         }
 
+        pub(crate) fn add_dummy_outcome(&self, outcome: AppointmentOutcome) {
+            self.store_outcomes(&[outcome])
+        }
+
+        pub(crate) fn add_tracker_to_responder(
+            &self,
+            uuid: UUID,
+            breach: Breach,
+            user_id: UserId,
+            status: ConfirmationStatus,
+        ) {
+            self.responder.add_tracker(uuid, breach, user_id, status)
+        }
+
         pub(crate) fn add_random_tracker_to_responder(&self) -> TransactionTracker {
             // The confirmation status can be whatever here. Using the most common.
             self.responder
@@ -586,6 +1185,14 @@ mod tests {
     async fn init_watcher_with_db(
         chain: &mut Blockchain,
         dbm: Arc<Mutex<DBM>>,
+    ) -> (Watcher, BitcoindStopper) {
+        init_gated_watcher(chain, dbm, RegistrationGate::default()).await
+    }
+
+    async fn init_gated_watcher(
+        chain: &mut Blockchain,
+        dbm: Arc<Mutex<DBM>>,
+        registration_gate: RegistrationGate,
     ) -> (Watcher, BitcoindStopper) {
         let bitcoind_mock = BitcoindMock::new(MockOptions::default());
 
@@ -594,6 +1201,8 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            BTreeMap::new(),
+            false,
             dbm.clone(),
         ));
         let responder = create_responder(chain, gk.clone(), dbm.clone(), bitcoind_mock.url()).await;
@@ -603,6 +1212,7 @@ mod tests {
             gk.clone(),
             bitcoind_mock,
             dbm.clone(),
+            registration_gate,
         )
         .await
     }
@@ -634,7 +1244,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         // If we add some appointments to the system and create a new Watcher reusing the same db
         // (as if simulating a bootstrap from existing data), the data should be properly loaded.
@@ -663,7 +1275,9 @@ mod tests {
 
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        let receipt = watcher.register(user_id).unwrap();
+        let receipt = watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         assert_eq!(receipt.user_id(), user_id);
         assert_eq!(receipt.available_slots(), SLOTS);
@@ -680,27 +1294,170 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_add_appointment() {
-        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
-        let tip_txs = chain.blocks.last().unwrap().txdata.clone();
-        let (watcher, _s) = init_watcher(&mut chain).await;
+    async fn test_register_pow_gated() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gate = RegistrationGate::new(8, false);
+        let (watcher, _s) = init_gated_watcher(&mut chain, dbm, gate).await;
+
+        // A proof for a different tower is not valid, let alone no proof at all
+        let user_id = get_random_user_id();
+        let mut proof = RegistrationProof::new(
+            pow::solve_pow(get_random_user_id(), user_id, gate.pow_difficulty),
+            String::new(),
+        );
+        while pow::verify_pow(
+            watcher.tower_id,
+            user_id,
+            proof.pow_nonce,
+            gate.pow_difficulty,
+        ) {
+            proof.pow_nonce += 1;
+        }
+        assert_eq!(
+            watcher.register(user_id, DEFAULT_PLAN, &proof),
+            Err(RegistrationFailure::InvalidProofOfWork)
+        );
+        assert_eq!(
+            watcher.get_registration_plan(user_id, DEFAULT_PLAN, &proof),
+            Err(RegistrationFailure::InvalidProofOfWork)
+        );
 
-        // add_appointment should add a given appointment to the Watcher given the following logic:
-        //      - if the appointment does not exist for a given user, add the appointment
-        //      - if the appointment already exists for a given user, update the data
-        //      - if the appointment is already in the Responder, reject
-        //      - if the trigger for the appointment is in the cache, trigger straightaway
-        // In any of the cases where the appointment should be added to the Watcher, the appointment will be rejected if:
-        //      - the user does not have enough slots (either to add or update)
-        //      - the subscription has expired
+        // A valid proof lets the user in
+        proof.pow_nonce = pow::solve_pow(watcher.tower_id, user_id, gate.pow_difficulty);
+        assert!(watcher
+            .get_registration_plan(user_id, DEFAULT_PLAN, &proof)
+            .is_ok());
+        watcher.register(user_id, DEFAULT_PLAN, &proof).unwrap();
 
-        let tower_id = TowerId(PublicKey::from_secret_key(
-            &Secp256k1::new(),
-            &watcher.signing_key,
-        ));
-        let (user_sk, user_pk) = get_random_keypair();
+        // Renewing the subscription does not require a proof
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+
+        // Tokens are not accepted if the tower does not issue them
+        let token = watcher.create_registration_tokens(1).pop().unwrap();
+        assert_eq!(
+            watcher.register(
+                get_random_user_id(),
+                DEFAULT_PLAN,
+                &RegistrationProof::new(0, token)
+            ),
+            Err(RegistrationFailure::InvalidProofOfWork)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_token_gated() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (watcher, _s) =
+            init_gated_watcher(&mut chain, dbm, RegistrationGate::new(0, true)).await;
+
+        let user_id = get_random_user_id();
+        for proof in [
+            RegistrationProof::default(),
+            RegistrationProof::new(0, "unknown".to_owned()),
+        ] {
+            assert_eq!(
+                watcher.register(user_id, DEFAULT_PLAN, &proof),
+                Err(RegistrationFailure::InvalidToken)
+            );
+        }
+
+        let tokens = watcher.create_registration_tokens(2);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(
+            HashSet::<String>::from_iter(watcher.get_registration_tokens()),
+            HashSet::from_iter(tokens.clone())
+        );
+
+        // Checking the gate does not redeem the token, registering does
+        let proof = RegistrationProof::new(0, tokens[0].clone());
+        assert!(watcher
+            .get_registration_plan(user_id, DEFAULT_PLAN, &proof)
+            .is_ok());
+        assert!(watcher.get_registration_tokens().contains(&tokens[0]));
+        watcher.register(user_id, DEFAULT_PLAN, &proof).unwrap();
+        assert_eq!(watcher.get_registration_tokens(), vec![tokens[1].clone()]);
+
+        // Tokens can only be redeemed once
+        assert_eq!(
+            watcher.register(get_random_user_id(), DEFAULT_PLAN, &proof),
+            Err(RegistrationFailure::InvalidToken)
+        );
+
+        // Tokens are not redeemed if the registration is refused for any other reason
+        let proof = RegistrationProof::new(0, tokens[1].clone());
+        assert_eq!(
+            watcher.register(get_random_user_id(), "unknown", &proof),
+            Err(RegistrationFailure::UnknownPlan)
+        );
+        assert_eq!(watcher.get_registration_tokens(), vec![tokens[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_register_pow_or_token_gated() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gate = RegistrationGate::new(8, true);
+        let (watcher, _s) = init_gated_watcher(&mut chain, dbm, gate).await;
+
+        // If both gates are enabled, either of them is enough
+        let user_id = get_random_user_id();
+        let pow_nonce = pow::solve_pow(watcher.tower_id, user_id, gate.pow_difficulty);
+        watcher
+            .register(
+                user_id,
+                DEFAULT_PLAN,
+                &RegistrationProof::new(pow_nonce, String::new()),
+            )
+            .unwrap();
+
+        let token = watcher.create_registration_tokens(1).pop().unwrap();
+        watcher
+            .register(
+                get_random_user_id(),
+                DEFAULT_PLAN,
+                &RegistrationProof::new(0, token.clone()),
+            )
+            .unwrap();
+
+        // An invalid (e.g. already redeemed) token is reported as a missing proof of work, which is still an option
+        let user_id = get_random_user_id();
+        let mut proof = RegistrationProof::new(0, token);
+        assert_eq!(
+            watcher.register(user_id, DEFAULT_PLAN, &proof),
+            Err(RegistrationFailure::InvalidProofOfWork)
+        );
+        proof.pow_nonce = pow::solve_pow(watcher.tower_id, user_id, gate.pow_difficulty);
+        watcher.register(user_id, DEFAULT_PLAN, &proof).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment() {
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let tip_txs = chain.blocks.last().unwrap().txdata.clone();
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        // add_appointment should add a given appointment to the Watcher given the following logic:
+        //      - if the appointment does not exist for a given user, add the appointment
+        //      - if the appointment already exists for a given user, update the data
+        //      - if the appointment is already in the Responder, reject
+        //      - if the trigger for the appointment is in the cache, trigger straightaway
+        // In any of the cases where the appointment should be added to the Watcher, the appointment will be rejected if:
+        //      - the user does not have enough slots (either to add or update)
+        //      - the subscription has expired
+
+        let tower_id = TowerId(PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &watcher.signing_key,
+        ));
+        let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
         let appointment = generate_dummy_appointment(None).inner;
         let user_sig = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
 
@@ -716,7 +1473,9 @@ mod tests {
         // Add the same appointment but for another user
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user2_id).unwrap();
+        watcher
+            .register(user2_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let user2_sig = cryptography::sign(&appointment.to_vec(), &user2_sk).unwrap();
         let (receipt, slots, expiry) = watcher
@@ -874,7 +1633,9 @@ mod tests {
         // Register the user
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
         let dispute_txid = get_random_tx().txid();
 
         let (uuid, appointment) =
@@ -913,7 +1674,9 @@ mod tests {
         // Register the user
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let dispute_tx = get_random_tx();
         let (uuid, appointment) =
@@ -942,9 +1705,24 @@ mod tests {
             watcher.store_triggered_appointment(uuid, &appointment, user_id, &dispute_tx),
             TriggeredAppointment::Rejected,
         );
-        // In this case the appointment is not kept in the Responder nor in the database
+        // In this case the appointment is not kept in the Responder nor in the database, but the outcome is recorded
         assert!(!watcher.responder.has_tracker(uuid));
         assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid));
+        assert_eq!(
+            watcher
+                .db_reader
+                .get()
+                .load_appointment_outcomes(Some(uuid)),
+            vec![AppointmentOutcome::new(
+                uuid,
+                appointment.locator(),
+                user_id,
+                dispute_tx.txid(),
+                FailureKind::Rejected,
+                Some(rpc_errors::RPC_VERIFY_ERROR),
+                START_HEIGHT as u32,
+            )]
+        );
 
         // Invalid triggered appointments should not be passed to the Responder
         // Use a dispute_tx that does not match the appointment to replicate a decryption error
@@ -954,9 +1732,24 @@ mod tests {
             watcher.store_triggered_appointment(uuid, &appointment, user_id, &dispute_tx),
             TriggeredAppointment::Invalid,
         );
-        // The appointment is not kept anywhere
+        // The appointment is not kept anywhere, but the outcome is recorded
         assert!(!watcher.responder.has_tracker(uuid));
         assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid));
+        assert_eq!(
+            watcher
+                .db_reader
+                .get()
+                .load_appointment_outcomes(Some(uuid)),
+            vec![AppointmentOutcome::new(
+                uuid,
+                appointment.locator(),
+                user_id,
+                dispute_tx.txid(),
+                FailureKind::Decryption,
+                None,
+                START_HEIGHT as u32,
+            )]
+        );
     }
 
     #[tokio::test]
@@ -977,7 +1770,9 @@ mod tests {
         // If the user does exist and there's an appointment with the given locator belonging to him, it will be returned
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
         watcher
             .add_appointment(
                 appointment.clone(),
@@ -993,9 +1788,7 @@ mod tests {
 
         match info {
             AppointmentInfo::Appointment(a) => assert_eq!(a, appointment),
-            AppointmentInfo::Tracker { .. } => {
-                panic!("Should have received an appointment, not a tracker")
-            }
+            _ => panic!("Should have received an appointment"),
         }
 
         // If the appointment is in the Responder (in the form of a Tracker), data should be also returned
@@ -1017,18 +1810,66 @@ mod tests {
             .get_appointment(appointment.locator, &tracker_signature)
             .unwrap();
 
-        match info {
-            AppointmentInfo::Appointment { .. } => {
-                panic!("Should have received an tracker, not an appointment")
+        // The tracker comes alongside its progress and the receipt issued by the Responder when the penalty was accepted
+        let receipt = match info {
+            AppointmentInfo::Tracker(t, progress, receipt) => {
+                assert_eq!(t, tracker);
+                assert_eq!(progress.in_mempool_since, Some(chain.get_block_count()));
+                receipt.unwrap()
             }
-            AppointmentInfo::Tracker(t) => assert_eq!(t, tracker),
-        }
+            _ => panic!("Should have received a tracker"),
+        };
+        assert!(receipt.verify(&watcher.tower_id));
+        assert_eq!(receipt.locator(), appointment.locator);
+        assert_eq!(receipt.broadcast_height(), chain.get_block_count());
+        assert!(!receipt.is_resolved());
+
+        // Once the tracker is gone, only the receipt is kept. Unless the tower knows how the penalty ended up, the
+        // appointment is reported as responded
+        watcher.gatekeeper.delete_appointments(vec![uuid], true);
+        assert!(matches!(
+            watcher.get_appointment(appointment.locator, &tracker_signature),
+            Ok(AppointmentInfo::Responded(r, progress, AppointmentStatus::DisputeResponded))
+                if r == receipt && progress == PenaltyProgress::default()
+        ));
+
+        // The appointment is only reported as rejected if the rejection was recorded
+        watcher.store_outcomes(&[AppointmentOutcome::new(
+            uuid,
+            appointment.locator,
+            user_id,
+            receipt.dispute_txid(),
+            FailureKind::Rejected,
+            Some(rpc_errors::RPC_VERIFY_REJECTED),
+            chain.get_block_count(),
+        )]);
+        assert!(matches!(
+            watcher.get_appointment(appointment.locator, &tracker_signature),
+            Ok(AppointmentInfo::Responded(r, _, AppointmentStatus::PenaltyRejected)) if r == receipt
+        ));
+
+        let mut resolved_receipt = receipt;
+        resolved_receipt.set_confirmation_height(chain.get_block_count());
+        resolved_receipt.sign(&watcher.signing_key);
+        watcher
+            .dbm
+            .lock()
+            .unwrap()
+            .store_response_receipt(uuid, user_id, &resolved_receipt)
+            .unwrap();
+        assert!(matches!(
+            watcher.get_appointment(appointment.locator, &tracker_signature),
+            Ok(AppointmentInfo::Responded(r, progress, AppointmentStatus::DisputeResolved))
+                if r == resolved_receipt && progress.confirmations == 1
+        ));
 
         // If the user does exists but the requested locator does not belong to any of their associated appointments,
         // NotFound should be returned.
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user2_id).unwrap();
+        watcher
+            .register(user2_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let signature2 = cryptography::sign(message.as_bytes(), &user2_sk).unwrap();
         assert!(matches!(
@@ -1047,6 +1888,248 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_get_appointment_outcomes() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let dispute_tx = get_random_tx();
+        let locator = Locator::new(dispute_tx.txid());
+        let message = format!("get appointment outcomes {locator}");
+
+        // If the user cannot be properly identified, the request will fail
+        let wrong_sig = String::from_utf8((0..65).collect()).unwrap();
+        assert!(matches!(
+            watcher.get_appointment_outcomes(locator, &wrong_sig),
+            Err(GetAppointmentOutcomesFailure::AuthenticationFailure)
+        ));
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+        let signature = cryptography::sign(message.as_bytes(), &user_sk).unwrap();
+
+        // If nothing went wrong with the appointment, there are no outcomes
+        assert_eq!(
+            watcher
+                .get_appointment_outcomes(locator, &signature)
+                .unwrap(),
+            Vec::new()
+        );
+
+        // Outcomes of the appointment are returned once recorded, but only to its owner
+        let uuid = UUID::new(locator, user_id);
+        let outcome = AppointmentOutcome::new(
+            uuid,
+            locator,
+            user_id,
+            dispute_tx.txid(),
+            FailureKind::Decryption,
+            None,
+            START_HEIGHT as u32,
+        );
+        watcher.add_dummy_outcome(outcome.clone());
+        assert_eq!(
+            watcher
+                .get_appointment_outcomes(locator, &signature)
+                .unwrap(),
+            vec![outcome]
+        );
+
+        let (user2_sk, user2_pk) = get_random_keypair();
+        watcher
+            .register(
+                UserId(user2_pk),
+                DEFAULT_PLAN,
+                &RegistrationProof::default(),
+            )
+            .unwrap();
+        let signature2 = cryptography::sign(message.as_bytes(), &user2_sk).unwrap();
+        assert!(watcher
+            .get_appointment_outcomes(locator, &signature2)
+            .unwrap()
+            .is_empty());
+
+        // If the user subscription has expired, the request will fail
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            watcher.get_appointment_outcomes(locator, &signature),
+            Err(GetAppointmentOutcomesFailure::SubscriptionExpired { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_store_backup() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let backup = Backup::new(get_random_bytes(100), 1);
+        let signature = cryptography::sign(&backup.to_vec(), &user_sk).unwrap();
+
+        // Non-registered users cannot store backups
+        assert!(matches!(
+            watcher.store_backup(backup.clone(), signature.clone()),
+            Err(StoreBackupFailure::AuthenticationFailure)
+        ));
+
+        // Registered ones get a signed receipt back, and the backup takes slots from their subscription
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+        let (receipt, slots, expiry) = watcher
+            .store_backup(backup.clone(), signature.clone())
+            .unwrap();
+        assert_eq!(slots, SLOTS - 1);
+        assert_eq!(expiry, START_HEIGHT as u32 + DURATION);
+        assert_eq!(receipt.version(), backup.version);
+        assert_eq!(receipt.user_signature(), signature);
+        assert!(receipt.verify(&watcher.tower_id));
+        assert_eq!(
+            watcher.dbm.lock().unwrap().load_backup(user_id),
+            Some(backup.clone())
+        );
+
+        // Backups cannot be replaced by older (or same) versions
+        assert!(matches!(
+            watcher.store_backup(backup.clone(), signature),
+            Err(StoreBackupFailure::OutdatedVersion(1))
+        ));
+
+        // But they can by newer ones
+        let new_backup = Backup::new(get_random_bytes(200), 2);
+        let (receipt, slots, _) = watcher
+            .store_backup(
+                new_backup.clone(),
+                cryptography::sign(&new_backup.to_vec(), &user_sk).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(receipt.version(), 2);
+        assert_eq!(slots, SLOTS - 1);
+        assert_eq!(
+            watcher.dbm.lock().unwrap().load_backup(user_id),
+            Some(new_backup)
+        );
+
+        // Backups over the size limit are rejected
+        let big_backup = Backup::new(get_random_bytes(BACKUP_MAX_SIZE + 1), 3);
+        assert!(matches!(
+            watcher.store_backup(
+                big_backup.clone(),
+                cryptography::sign(&big_backup.to_vec(), &user_sk).unwrap(),
+            ),
+            Err(StoreBackupFailure::TooBig)
+        ));
+
+        // So are backups that do not fit in the user subscription
+        watcher
+            .gatekeeper
+            .get_registered_users()
+            .lock()
+            .unwrap()
+            .get_mut(&user_id)
+            .unwrap()
+            .available_slots = 0;
+        let backup = Backup::new(get_random_bytes(BACKUP_MAX_SIZE), 3);
+        assert!(matches!(
+            watcher.store_backup(
+                backup.clone(),
+                cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+            ),
+            Err(StoreBackupFailure::NotEnoughSlots)
+        ));
+
+        // Or if the subscription has expired
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            watcher.store_backup(
+                backup.clone(),
+                cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+            ),
+            Err(StoreBackupFailure::SubscriptionExpired { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_backup() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let signature = cryptography::sign("get backup".as_bytes(), &user_sk).unwrap();
+
+        assert!(matches!(
+            watcher.get_backup(&signature),
+            Err(GetBackupFailure::AuthenticationFailure)
+        ));
+
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+        assert!(matches!(
+            watcher.get_backup(&signature),
+            Err(GetBackupFailure::NotFound)
+        ));
+
+        let backup = Backup::new(get_random_bytes(100), 1);
+        watcher
+            .store_backup(
+                backup.clone(),
+                cryptography::sign(&backup.to_vec(), &user_sk).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(watcher.get_backup(&signature).unwrap(), backup);
+
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            watcher.get_backup(&signature),
+            Err(GetBackupFailure::SubscriptionExpired { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_paths_do_not_lock_dbm() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        watcher
+            .register(UserId(user_pk), DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+        let appointment = generate_dummy_appointment(None).inner;
+        watcher
+            .add_appointment(
+                appointment.clone(),
+                cryptography::sign(&appointment.to_vec(), &user_sk).unwrap(),
+            )
+            .unwrap();
+
+        // Read-only paths are served by the reader pool, so they don't wait for the writer (e.g. while processing a block)
+        let _writer = watcher.dbm.lock().unwrap();
+        let message = format!("get appointment {}", appointment.locator);
+        let signature = cryptography::sign(message.as_bytes(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.get_appointment(appointment.locator, &signature),
+            Ok(AppointmentInfo::Appointment(a)) if a == appointment
+        ));
+        assert_eq!(watcher.get_appointments_count(), 1);
+        assert_eq!(watcher.get_all_watcher_appointments().len(), 1);
+        assert_eq!(watcher.get_trackers_count(), 0);
+        assert!(watcher.get_all_responder_trackers().is_empty());
+        assert_eq!(
+            watcher.get_user_info(UserId(user_pk)).unwrap().1,
+            vec![appointment.locator]
+        );
+    }
+
     #[tokio::test]
     async fn test_get_breaches() {
         let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
@@ -1060,7 +2143,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         // Add some of them to the Watcher
         let mut breaches = HashMap::new();
@@ -1091,7 +2176,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         // Let the watcher track these breaches.
         for (_, tx) in breaches.iter() {
@@ -1100,7 +2187,10 @@ mod tests {
             watcher.add_appointment(appointment, signature).unwrap();
         }
 
-        assert!(watcher.handle_breaches(breaches).is_none())
+        assert!(watcher
+            .handle_breaches(breaches, START_HEIGHT as u32)
+            .is_none());
+        assert!(watcher.get_all_appointment_outcomes().is_empty());
     }
 
     #[tokio::test]
@@ -1116,7 +2206,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let mut rejected = HashSet::new();
         // Let the watcher track these breaches.
@@ -1135,7 +2227,80 @@ mod tests {
 
         assert_eq!(
             rejected,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
+        );
+
+        // The reason why the appointments were rejected is recorded
+        let outcomes = watcher.get_all_appointment_outcomes();
+        assert_eq!(
+            rejected,
+            HashSet::from_iter(outcomes.iter().map(|outcome| outcome.uuid))
+        );
+        for outcome in outcomes {
+            assert_eq!(outcome.kind, FailureKind::Decryption);
+            assert_eq!(outcome.rpc_error_code, None);
+            assert_eq!(outcome.height, START_HEIGHT as u32);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_breaches_rejected_deserialization() {
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+
+        // A blob that decrypts properly but does not contain a valid transaction. Scripts bigger than the maximum
+        // vector size can be serialized, but not deserialized.
+        let dispute_tx = get_random_tx();
+        let locator = Locator::new(dispute_tx.txid());
+        let mut oversized_tx = get_random_tx();
+        oversized_tx.output[0].script_pubkey =
+            bitcoin::Script::from(vec![0; bitcoin::consensus::encode::MAX_VEC_SIZE + 1]);
+        let appointment = Appointment::new(
+            locator,
+            cryptography::encrypt(&oversized_tx, &dispute_tx.txid()).unwrap(),
+            42,
+        );
+        // The blob is too big to fit the user slots, so the appointment is stored straight away
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        let uuid = UUID::new(locator, user_id);
+        watcher
+            .dbm
+            .lock()
+            .unwrap()
+            .store_appointment(
+                uuid,
+                &ExtendedAppointment::new(appointment, user_id, signature, START_HEIGHT as u32),
+            )
+            .unwrap();
+
+        assert_eq!(
+            watcher.handle_breaches(
+                HashMap::from_iter([(locator, dispute_tx.clone())]),
+                START_HEIGHT as u32
+            ),
+            Some(vec![uuid])
+        );
+        assert_eq!(
+            watcher.get_all_appointment_outcomes(),
+            vec![AppointmentOutcome::new(
+                uuid,
+                locator,
+                user_id,
+                dispute_tx.txid(),
+                FailureKind::Deserialization,
+                None,
+                START_HEIGHT as u32,
+            )]
         );
     }
 
@@ -1159,7 +2324,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let mut uuids = HashSet::new();
         // Let the watcher track these breaches.
@@ -1174,8 +2341,23 @@ mod tests {
 
         assert_eq!(
             uuids,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
+        );
+
+        // The error returned by bitcoind is recorded
+        let outcomes = watcher.get_all_appointment_outcomes();
+        assert_eq!(
+            uuids,
+            HashSet::from_iter(outcomes.iter().map(|outcome| outcome.uuid))
         );
+        for outcome in outcomes {
+            assert_eq!(outcome.kind, FailureKind::Rejected);
+            assert_eq!(outcome.rpc_error_code, Some(rpc_errors::RPC_VERIFY_ERROR));
+        }
     }
 
     #[tokio::test]
@@ -1191,7 +2373,9 @@ mod tests {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let mut rejected_breaches = HashSet::new();
         // Let the watcher track these breaches.
@@ -1210,7 +2394,55 @@ mod tests {
 
         assert_eq!(
             rejected_breaches,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rescan_block() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        watcher
+            .register(UserId(user_pk), DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+
+        let dispute_tx = get_random_tx();
+        let appointment = generate_dummy_appointment(Some(&dispute_tx.txid())).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), signature)
+            .unwrap();
+        let uuid = UUID::new(appointment.locator, UserId(user_pk));
+
+        // Rescanning a block with the dispute triggers the appointment, but leaves the cache and the height untouched
+        let block = chain.generate(Some(vec![dispute_tx]));
+        assert_eq!(watcher.rescan_block(&block, chain.get_block_count()), 1);
+        assert!(watcher.responder.has_tracker(uuid));
+        assert!(watcher
+            .locator_cache
+            .lock()
+            .unwrap()
+            .get(&appointment.locator)
+            .is_none());
+        assert_eq!(
+            watcher.get_last_known_block_height(),
+            chain.get_block_count() - 1
+        );
+
+        // Rescanning the same block again does not hand the breach to the Responder twice
+        assert_eq!(watcher.rescan_block(&block, chain.get_block_count()), 1);
+        assert_eq!(watcher.get_trackers_count(), 1);
+
+        // A block with no breaches does nothing
+        assert_eq!(
+            watcher.rescan_block(&chain.generate(None), chain.get_block_count()),
+            0
         );
     }
 
@@ -1248,8 +2480,12 @@ mod tests {
         let user_id = UserId(user_pk);
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user_id).unwrap();
-        watcher.register(user2_id).unwrap();
+        watcher
+            .register(user_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
+        watcher
+            .register(user2_id, DEFAULT_PLAN, &RegistrationProof::default())
+            .unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let uuid1 = UUID::new(appointment.locator, user_id);
@@ -0,0 +1,439 @@
+//! Runs a primary and a standby `teosd` side by side (each with its own data dir) and checks the standby takes over
+//! once the primary is gone.
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{Block, BlockHash, BlockHeader, Transaction, TxIn, Txid};
+use jsonrpc_http_server::jsonrpc_core::{
+    Compatibility, Error, IoHandler, Params, Result as RpcResult, Value,
+};
+use jsonrpc_http_server::{Server, ServerBuilder};
+use rusqlite::{Connection, OpenFlags};
+use tempdir::TempDir;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+use teos::protos as msgs;
+use teos::protos::private_tower_services_client::PrivateTowerServicesClient;
+use teos::protos::public_tower_services_client::PublicTowerServicesClient;
+use teos_common::appointment::{Appointment, Locator};
+use teos_common::cryptography::{self, get_random_keypair};
+use teos_common::protos as common_msgs;
+use teos_common::UserId;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A regtest chain, starting at the genesis block, shared with the bitcoind serving it.
+#[derive(Clone)]
+struct Chain(Arc<Mutex<Vec<Block>>>);
+
+impl Chain {
+    fn new() -> Self {
+        Chain(Arc::new(Mutex::new(vec![genesis_block(Network::Regtest)])))
+    }
+
+    /// Mines a block on top of the tip, returning its hash.
+    fn mine(&self) -> BlockHash {
+        let mut blocks = self.0.lock().unwrap();
+        let tip = blocks.last().unwrap().header;
+        // The lock time makes the only transaction in the block (and therefore the block) unique
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: tip.block_hash(),
+                merkle_root: Default::default(),
+                time: tip.time + 1,
+                bits: tip.bits,
+                nonce: 0,
+            },
+            txdata: vec![Transaction {
+                version: 1,
+                lock_time: blocks.len() as u32,
+                input: vec![TxIn::default()],
+                output: Vec::new(),
+            }],
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while block.header.validate_pow(&block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+
+        let block_hash = block.block_hash();
+        blocks.push(block);
+        block_hash
+    }
+
+    /// Gets a block alongside its height.
+    fn get(&self, params: Params) -> RpcResult<(Block, usize)> {
+        let params: Vec<Value> = params.parse()?;
+        let block_hash = params
+            .first()
+            .and_then(Value::as_str)
+            .and_then(|hash| BlockHash::from_hex(hash).ok())
+            .ok_or_else(Error::invalid_request)?;
+        let blocks = self.0.lock().unwrap();
+        blocks
+            .iter()
+            .position(|block| block.block_hash() == block_hash)
+            .map(|height| (blocks[height].clone(), height))
+            .ok_or_else(Error::invalid_request)
+    }
+}
+
+/// Serves the given regtest chain, which is all the towers need to bootstrap and follow it.
+fn run_bitcoind(chain: Chain) -> Server {
+    // bitcoind (and therefore its clients) still speaks JSON-RPC 1.0
+    let mut io = IoHandler::with_compatibility(Compatibility::Both);
+    let tip_chain = chain.clone();
+    io.add_sync_method("getblockchaininfo", move |_params: Params| {
+        let blocks = tip_chain.0.lock().unwrap();
+        Ok(serde_json::json!({
+            "chain": "regtest",
+            "blocks": blocks.len() - 1,
+            "headers": blocks.len() - 1,
+            "bestblockhash": blocks.last().unwrap().block_hash().to_hex(),
+            "initialblockdownload": false,
+            "pruned": false,
+        }))
+    });
+    let header_chain = chain.clone();
+    io.add_sync_method("getblockheader", move |params: Params| {
+        let (block, height) = header_chain.get(params)?;
+        let header = block.header;
+        // All blocks share the difficulty of the genesis block, which carries two units of work
+        let mut response = serde_json::json!({
+            "hash": header.block_hash().to_hex(),
+            "chainwork": format!("{:064x}", 2 * (height + 1)),
+            "height": height,
+            "version": header.version,
+            "merkleroot": header.merkle_root.to_hex(),
+            "time": header.time,
+            "nonce": header.nonce,
+            "bits": format!("{:08x}", header.bits),
+        });
+        if height > 0 {
+            response["previousblockhash"] = Value::String(header.prev_blockhash.to_hex());
+        }
+        Ok(response)
+    });
+    let block_chain = chain.clone();
+    io.add_sync_method("getblock", move |params: Params| {
+        Ok(Value::String(serialize_hex(&block_chain.get(params)?.0)))
+    });
+    io.add_sync_method("getblockhash", move |params: Params| {
+        let (height,): (usize,) = params.parse()?;
+        chain
+            .0
+            .lock()
+            .unwrap()
+            .get(height)
+            .map(|block| Value::String(block.block_hash().to_hex()))
+            .ok_or_else(Error::invalid_request)
+    });
+
+    ServerBuilder::new(io)
+        .threads(2)
+        .start_http(&"127.0.0.1:0".parse().unwrap())
+        .unwrap()
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A running `teosd`. The process is killed when dropped, so failing tests do not leave it behind.
+struct Tower {
+    process: Child,
+    rpc_port: u16,
+    internal_api_port: u16,
+    logs: Receiver<String>,
+}
+
+impl Tower {
+    fn run(data_dir: &Path, bitcoind_port: u16, config: &str) -> Self {
+        let rpc_port = free_port();
+        let internal_api_port = free_port();
+        fs::write(
+            data_dir.join("teos.toml"),
+            format!(
+                "btc_network = \"regtest\"\n\
+                 btc_rpc_user = \"user\"\n\
+                 btc_rpc_password = \"passwd\"\n\
+                 btc_rpc_connect = \"127.0.0.1\"\n\
+                 btc_rpc_port = {bitcoind_port}\n\
+                 api_port = {}\n\
+                 rpc_port = {rpc_port}\n\
+                 internal_api_port = {internal_api_port}\n\
+                 polling_delta = 1\n\
+                 replication_heartbeat_interval = 1\n\
+                 replication_failover_timeout = 3\n\
+                 {config}",
+                free_port(),
+            ),
+        )
+        .unwrap();
+
+        let mut process = Command::new(env!("CARGO_BIN_EXE_teosd"))
+            .arg("--datadir")
+            .arg(data_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // The logs are drained in the background so the tower never blocks writing them
+        let (sender, logs) = mpsc::channel();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            process,
+            rpc_port,
+            internal_api_port,
+            logs,
+        }
+    }
+
+    fn wait_for_log(&self, message: &str) {
+        let deadline = Instant::now() + TIMEOUT;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.logs.recv_timeout(remaining) {
+                Ok(line) if line.contains(message) => return,
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+        panic!("The tower did not log \"{}\" in time", message);
+    }
+
+    async fn rpc_client(
+        &self,
+        data_dir: &Path,
+    ) -> Result<PrivateTowerServicesClient<Channel>, tonic::transport::Error> {
+        let read = |name: &str| fs::read(data_dir.join(name)).unwrap();
+        let tls = ClientTlsConfig::new()
+            .domain_name("localhost")
+            .ca_certificate(Certificate::from_pem(read("ca.pem")))
+            .identity(Identity::from_pem(
+                read("client.pem"),
+                read("client-key.pem"),
+            ));
+        let channel = Channel::from_shared(format!("http://127.0.0.1:{}", self.rpc_port))
+            .unwrap()
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(PrivateTowerServicesClient::new(channel))
+    }
+
+    /// Polls the RPC of the tower until it is serving, and returns its info.
+    async fn wait_for_tower_info(&self, data_dir: &Path) -> msgs::GetTowerInfoResponse {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            if data_dir.join("client-key.pem").exists() {
+                if let Ok(mut client) = self.rpc_client(data_dir).await {
+                    if let Ok(response) = client.get_tower_info(()).await {
+                        return response.into_inner();
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!(
+            "The tower RPC did not come up in time. Logs:\n{}",
+            self.logs.try_iter().collect::<Vec<_>>().join("\n")
+        );
+    }
+
+    async fn public_client(&self) -> PublicTowerServicesClient<Channel> {
+        PublicTowerServicesClient::connect(format!("http://127.0.0.1:{}", self.internal_api_port))
+            .await
+            .unwrap()
+    }
+
+    async fn register(&self, user_id: UserId) -> common_msgs::RegisterResponse {
+        self.public_client()
+            .await
+            .register(common_msgs::RegisterRequest {
+                user_id: user_id.to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    async fn add_appointment(&self, user_sk: &SecretKey) {
+        let appointment = Appointment::new(Locator::new(Txid::default()), vec![1; 100], 42);
+        self.public_client()
+            .await
+            .add_appointment(common_msgs::AddAppointmentRequest {
+                signature: cryptography::sign(&appointment.to_vec(), user_sk).unwrap(),
+                appointment: Some(appointment.into()),
+            })
+            .await
+            .unwrap();
+    }
+}
+
+impl Drop for Tower {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+/// Config of a standby of `primary`. The standby authenticates against it using the client certificate of the primary.
+fn standby_config(primary: &Tower, primary_dir: &Path) -> String {
+    let primary_cert = |name: &str| primary_dir.join(name).display().to_string();
+    format!(
+        "replication = \"standby\"\n\
+         replication_primary = \"127.0.0.1:{}\"\n\
+         replication_ca_cert_path = \"{}\"\n\
+         replication_cert_path = \"{}\"\n\
+         replication_key_path = \"{}\"",
+        primary.rpc_port,
+        primary_cert("ca.pem"),
+        primary_cert("client.pem"),
+        primary_cert("client-key.pem"),
+    )
+}
+
+/// Gets the subscription expiry of a user straight from the database of a tower.
+fn stored_subscription_expiry(data_dir: &Path, user_id: UserId) -> Option<u32> {
+    let connection = Connection::open_with_flags(
+        data_dir.join("regtest").join("teos_db.sql3"),
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .unwrap();
+    connection
+        .query_row(
+            "SELECT subscription_expiry FROM users WHERE user_id = (?)",
+            [user_id.to_vec()],
+            |row| row.get(0),
+        )
+        .ok()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_standby_takes_over() {
+    let bitcoind = run_bitcoind(Chain::new());
+    let bitcoind_port = bitcoind.address().port();
+
+    let primary_dir = TempDir::new("teos_primary").unwrap();
+    let primary = Tower::run(
+        primary_dir.path(),
+        bitcoind_port,
+        "replication = \"primary\"",
+    );
+    let primary_info = primary.wait_for_tower_info(primary_dir.path()).await;
+    primary.register(UserId(get_random_keypair().1)).await;
+
+    let standby_dir = TempDir::new("teos_standby").unwrap();
+    let standby = Tower::run(
+        standby_dir.path(),
+        bitcoind_port,
+        &standby_config(&primary, primary_dir.path()),
+    );
+    standby.wait_for_log("Monitoring the chain with broadcasting disabled");
+    // The standby does not serve anything while following its primary
+    assert!(TcpStream::connect(("127.0.0.1", standby.rpc_port)).is_err());
+
+    drop(primary);
+    standby.wait_for_log("Taking over");
+    let standby_info = standby.wait_for_tower_info(standby_dir.path()).await;
+    assert_eq!(standby_info.tower_id, primary_info.tower_id);
+    assert_eq!(standby_info.n_registered_users, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_standby_keeps_renewed_users() {
+    // Mine a few blocks first, the Responder assumes it is not following a brand new chain
+    let chain = Chain::new();
+    let start_height = 10;
+    for _ in 0..start_height {
+        chain.mine();
+    }
+    let bitcoind = run_bitcoind(chain.clone());
+    let bitcoind_port = bitcoind.address().port();
+    // Subscriptions are outdated 3 + 1 blocks after registering
+    let subscription = "subscription_duration = 3\nexpiry_delta = 1";
+
+    let primary_dir = TempDir::new("teos_primary").unwrap();
+    let primary = Tower::run(
+        primary_dir.path(),
+        bitcoind_port,
+        &format!("replication = \"primary\"\n{subscription}"),
+    );
+    primary.wait_for_tower_info(primary_dir.path()).await;
+    let (user_sk, user_pk) = get_random_keypair();
+    let user_id = UserId(user_pk);
+    assert_eq!(
+        primary.register(user_id).await.subscription_expiry,
+        start_height + 3
+    );
+    primary.add_appointment(&user_sk).await;
+
+    let standby_dir = TempDir::new("teos_standby").unwrap();
+    let standby = Tower::run(
+        standby_dir.path(),
+        bitcoind_port,
+        &format!(
+            "{}\n{subscription}",
+            standby_config(&primary, primary_dir.path())
+        ),
+    );
+    standby.wait_for_log("Monitoring the chain with broadcasting disabled");
+
+    // The user renews their subscription once the standby is following the primary
+    let renewed_expiry = primary.register(user_id).await.subscription_expiry;
+    assert_eq!(renewed_expiry, start_height + 6);
+    let deadline = Instant::now() + TIMEOUT;
+    while stored_subscription_expiry(standby_dir.path(), user_id) != Some(renewed_expiry) {
+        assert!(
+            Instant::now() < deadline,
+            "The renewal was not replicated in time"
+        );
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    // Go past the height the user would have been outdated at had they not renewed
+    let mut block_hash = chain.mine();
+    while chain.0.lock().unwrap().len() as u32 <= start_height + 5 {
+        block_hash = chain.mine();
+    }
+    primary.wait_for_log(&block_hash.to_string());
+    standby.wait_for_log(&block_hash.to_string());
+
+    drop(primary);
+    standby.wait_for_log("Taking over");
+    let standby_info = standby.wait_for_tower_info(standby_dir.path()).await;
+    assert_eq!(standby_info.n_registered_users, 1);
+    assert_eq!(standby_info.n_watcher_appointments, 1);
+    assert_eq!(
+        stored_subscription_expiry(standby_dir.path(), user_id),
+        Some(renewed_expiry)
+    );
+}
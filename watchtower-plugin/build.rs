@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Towers can be reached through their public gRPC interface, defined alongside the rest of the tower services
+    tonic_build::configure()
+        .extern_path(".common.teos.v2", "::teos_common::protos")
+        .compile(
+            &["../teos/proto/teos/v2/tower_services.proto"],
+            &["../teos/proto/teos/v2", "../teos-common/proto/"],
+        )?;
+
+    Ok(())
+}
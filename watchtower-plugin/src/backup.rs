@@ -0,0 +1,285 @@
+//! Logic related to backing up the node's static channel backup (SCB) with the towers.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::{sha256, Hash};
+
+use teos_common::backup::Backup;
+use teos_common::cryptography;
+use teos_common::errors;
+use teos_common::TowerId;
+
+use crate::net::http::{self, StoreBackupError};
+use crate::wt_client::WTClient;
+use crate::TowerStatus;
+
+/// Name of the static channel backup file kept by CLN in its network directory.
+pub const SCB_FILE_NAME: &str = "emergency.recover";
+
+/// Component in charge of uploading the static channel backup of the node to the registered towers.
+///
+/// The backup file is polled periodically. Every time its content changes, a new version of the backup is encrypted using
+/// the client secret key and sent to every reachable tower. Towers that do not have the latest version (e.g. because they
+/// were unreachable, or have been registered after the last change) are sent it on the following polls.
+pub struct BackupManager {
+    wt_client: Arc<Mutex<WTClient>>,
+    /// Path to the backup file.
+    path: PathBuf,
+    /// How often the backup file is checked for changes.
+    polling_interval: Duration,
+    /// Hash of the last seen content of the backup file.
+    last_seen: Option<sha256::Hash>,
+    /// The latest version of the (encrypted) backup alongside the client signature.
+    current: Option<(Backup, String)>,
+    /// Towers that rejected the current version of the backup. They won't be sent it again.
+    rejected: HashSet<TowerId>,
+}
+
+impl BackupManager {
+    /// Creates a new [BackupManager] instance.
+    pub fn new(wt_client: Arc<Mutex<WTClient>>, path: PathBuf, polling_interval: Duration) -> Self {
+        BackupManager {
+            wt_client,
+            path,
+            polling_interval,
+            last_seen: None,
+            current: None,
+            rejected: HashSet::new(),
+        }
+    }
+
+    /// Starts the backup manager's main logic loop. This method will keep running until the plugin is stopped.
+    pub async fn manage_backups(&mut self) {
+        log::info!("Starting backup manager ({})", self.path.display());
+        loop {
+            self.check_backup().await;
+            tokio::time::sleep(self.polling_interval).await;
+        }
+    }
+
+    /// Checks the backup file for changes, creating a new version of the backup if needed, and sends the latest version to
+    /// the towers that do not have it yet.
+    async fn check_backup(&mut self) {
+        match tokio::fs::read(&self.path).await {
+            Ok(content) => {
+                let hash = sha256::Hash::hash(&content);
+                if self.last_seen != Some(hash) {
+                    log::info!("Backup file changed. Creating a new backup version");
+                    self.new_version(&content);
+                    self.last_seen = Some(hash);
+                }
+            }
+            Err(e) => log::debug!("Cannot read backup file: {e}"),
+        }
+
+        self.send_backup().await;
+    }
+
+    /// Creates a new version of the backup given the content of the backup file.
+    ///
+    /// Versions are derived from the current time so they keep increasing across restarts.
+    fn new_version(&mut self, content: &[u8]) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let version = self
+            .current
+            .as_ref()
+            .map_or(now, |(backup, _)| now.max(backup.version + 1));
+
+        let user_sk = self.wt_client.lock().unwrap().user_sk;
+        let backup = Backup::new(
+            cryptography::encrypt_backup(content, &user_sk).unwrap(),
+            version,
+        );
+        let signature = cryptography::sign(&backup.to_vec(), &user_sk).unwrap();
+
+        self.current = Some((backup, signature));
+        self.rejected.clear();
+    }
+
+    /// Sends the current version of the backup to all the reachable towers that do not have it yet.
+    async fn send_backup(&mut self) {
+        let (backup, signature) = if let Some(current) = &self.current {
+            current
+        } else {
+            return;
+        };
+
+        // Locked state is not Send, so we need to clone the bare minimum.
+        let (towers, proxy) = {
+            let state = self.wt_client.lock().unwrap();
+            let towers = state
+                .towers
+                .iter()
+                .filter(|(id, info)| {
+                    info.status.is_reachable()
+                        && !self.rejected.contains(id)
+                        && !matches!(state.get_backup_receipt(**id), Some(r) if r.version() >= backup.version)
+                })
+                .map(|(id, info)| (*id, info.net_addr.clone()))
+                .collect::<Vec<_>>();
+            (towers, state.proxy.clone())
+        };
+
+        for (tower_id, net_addr) in towers {
+            match http::store_backup(tower_id, &net_addr, &proxy, backup, signature).await {
+                Ok((slots, receipt)) => {
+                    log::info!("Backup (version={}) stored by {tower_id}", backup.version);
+                    self.wt_client
+                        .lock()
+                        .unwrap()
+                        .add_backup_receipt(tower_id, slots, &receipt);
+                }
+                Err(StoreBackupError::RequestError(e)) => {
+                    if e.is_connection() {
+                        log::warn!("{tower_id} cannot be reached. Backup will be retried later");
+                        self.wt_client
+                            .lock()
+                            .unwrap()
+                            .set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+                    } else {
+                        log::warn!("Cannot send backup to {tower_id}. Error: {e:?}");
+                    }
+                }
+                Err(StoreBackupError::ApiError(e)) => {
+                    log::warn!(
+                        "{tower_id} rejected the backup. Error: {}, error_code: {}",
+                        e.error,
+                        e.error_code
+                    );
+                    // Subscription issues may be solved by the user, so the backup is retried in that case.
+                    if e.error_code != errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR {
+                        self.rejected.insert(tower_id);
+                    }
+                }
+                Err(StoreBackupError::SignatureError(recovered_id)) => {
+                    log::warn!("Backup receipt from {tower_id} is signed by {recovered_id}. Not sending it any further backups");
+                    self.rejected.insert(tower_id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use tempdir::TempDir;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use teos_common::net::http::Endpoint;
+    use teos_common::test_utils::get_random_registration_receipt;
+
+    use crate::net::http::ApiError;
+
+    async fn init_manager(tmp_path: &TempDir, server_url: String) -> BackupManager {
+        let mut wt_client =
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await;
+        let receipt = get_random_registration_receipt();
+        wt_client
+            .add_update_tower(
+                TowerId(cryptography::get_random_keypair().1),
+                &server_url,
+                &receipt,
+            )
+            .unwrap();
+
+        BackupManager::new(
+            Arc::new(Mutex::new(wt_client)),
+            tmp_path.path().join(SCB_FILE_NAME),
+            Duration::from_secs(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_check_backup_missing_file() {
+        let tmp_path = TempDir::new("watchtower_backup").unwrap();
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::StoreBackup.path().as_str())
+            .expect(0)
+            .create_async()
+            .await;
+
+        // Nothing is sent while there is no backup file
+        let mut manager = init_manager(&tmp_path, server.url()).await;
+        manager.check_backup().await;
+        assert!(manager.current.is_none());
+        api_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_backup() {
+        let tmp_path = TempDir::new("watchtower_backup").unwrap();
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::StoreBackup.path().as_str())
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(ApiError {
+                    error: "error_msg".to_owned(),
+                    error_code: errors::BACKUP_TOO_BIG,
+                })
+                .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let mut manager = init_manager(&tmp_path, server.url()).await;
+        let user_sk = manager.wt_client.lock().unwrap().user_sk;
+
+        // A new version is created (and sent) when the file is found
+        std::fs::write(&manager.path, b"scb").unwrap();
+        manager.check_backup().await;
+        let (backup, signature) = manager.current.clone().unwrap();
+        assert_eq!(
+            cryptography::decrypt_backup(&backup.data, &user_sk).unwrap(),
+            b"scb"
+        );
+        assert!(cryptography::verify(
+            &backup.to_vec(),
+            &signature,
+            &manager.wt_client.lock().unwrap().user_id.0
+        ));
+        assert_eq!(manager.rejected.len(), 1);
+
+        // If the file does not change, nothing is created (nor sent to towers that rejected the current version)
+        manager.check_backup().await;
+        assert_eq!(manager.current.as_ref().unwrap().0, backup);
+
+        // Otherwise a newer version is created and sent
+        std::fs::write(&manager.path, b"new scb").unwrap();
+        manager.check_backup().await;
+        let (new_backup, _) = manager.current.clone().unwrap();
+        assert!(new_backup.version > backup.version);
+        assert_eq!(
+            cryptography::decrypt_backup(&new_backup.data, &user_sk).unwrap(),
+            b"new scb"
+        );
+
+        api_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_backup_unreachable_tower() {
+        let tmp_path = TempDir::new("watchtower_backup").unwrap();
+        let mut manager = init_manager(&tmp_path, "http://unreachable.tower".to_owned()).await;
+
+        std::fs::write(&manager.path, b"scb").unwrap();
+        manager.check_backup().await;
+
+        // The tower is flagged as temporary unreachable and will be sent the backup once it is reachable again
+        let state = manager.wt_client.lock().unwrap();
+        let (tower_id, tower) = state.towers.iter().next().unwrap();
+        assert!(tower.status.is_temporary_unreachable());
+        assert!(!manager.rejected.contains(tower_id));
+    }
+}
@@ -13,6 +13,12 @@ pub const WT_MAX_RETRY_TIME_DESC: &str = "for how long (in seconds) a retry stra
 pub const WT_AUTO_RETRY_DELAY: &str = "watchtower-auto-retry-delay";
 pub const DEFAULT_WT_AUTO_RETRY_DELAY: i64 = 28800;
 pub const WT_AUTO_RETRY_DELAY_DESC: &str = "how long (in seconds) a retrier will wait before auto-retrying a failed tower. Defaults to once every 8 hours";
+pub const WT_BACKUP_INTERVAL: &str = "watchtower-backup-interval";
+pub const DEFAULT_WT_BACKUP_INTERVAL: i64 = 60;
+pub const WT_BACKUP_INTERVAL_DESC: &str = "how often (in seconds) the static channel backup (emergency.recover) is checked for changes to be backed up with the towers. Set to 0 to disable. Defaults to 1 min";
+pub const WT_MAX_SUBSCRIPTION_PRICE: &str = "watchtower-max-subscription-price";
+pub const DEFAULT_WT_MAX_SUBSCRIPTION_PRICE: i64 = 100000;
+pub const WT_MAX_SUBSCRIPTION_PRICE_DESC: &str = "maximum price (in msat) the plugin will pay when registering with a tower that charges for subscriptions. Set to 0 to never pay. Defaults to 100 sat";
 pub const DEV_WT_MAX_RETRY_INTERVAL: &str = "dev-watchtower-max-retry-interval";
 pub const DEFAULT_DEV_WT_MAX_RETRY_INTERVAL: i64 = 900;
 pub const DEV_WT_MAX_RETRY_INTERVAL_DESC: &str =
@@ -22,7 +28,7 @@ pub const DEV_WT_MAX_RETRY_INTERVAL_DESC: &str =
 
 pub const RPC_REGISTER_TOWER: &str = "registertower";
 pub const RPC_REGISTER_TOWER_DESC: &str =
-    "Registers the client public key (user id) with the tower";
+    "Registers the client public key (user id) with the tower, optionally pinning its TLS certificate";
 pub const RPC_GET_REGISTRATION_RECEIPT: &str = "getregistrationreceipt";
 pub const RPC_GET_REGISTRATION_RECEIPT_DESC: &str =
     "Gets the latest registration receipt given a tower id";
@@ -32,6 +38,12 @@ pub const RPC_GET_APPOINTMENT_DESC: &str =
 pub const RPC_GET_APPOINTMENT_RECEIPT: &str = "getappointmentreceipt";
 pub const RPC_GET_APPOINTMENT_RECEIPT_DESC: &str =
     "Gets a (local) appointment receipt given a tower id and a locator";
+pub const RPC_GET_RESPONSE_RECEIPT: &str = "getresponsereceipt";
+pub const RPC_GET_RESPONSE_RECEIPT_DESC: &str =
+    "Gets the latest (local) response receipt given a tower id and a locator";
+pub const RPC_GET_APPOINTMENT_OUTCOMES: &str = "getappointmentoutcomes";
+pub const RPC_GET_APPOINTMENT_OUTCOMES_DESC: &str =
+    "Gets the reasons why the tower could not respond to a triggered appointment given a tower id and a locator";
 pub const RPC_GET_SUBSCRIPTION_INFO: &str = "getsubscriptioninfo";
 pub const RPC_GET_SUBSCRIPTION_INFO_DESC: &str =
     "Gets the subscription information directly from the tower";
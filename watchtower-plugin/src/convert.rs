@@ -1,4 +1,5 @@
 use std::fmt;
+use std::net::Ipv6Addr;
 use std::{convert::TryFrom, str::FromStr};
 
 use hex::FromHex;
@@ -8,6 +9,7 @@ use serde_json::json;
 use bitcoin::{Transaction, Txid};
 
 use teos_common::appointment::Locator;
+use teos_common::net::format_host;
 use teos_common::TowerId;
 
 /// Errors related to the `registertower` command.
@@ -16,6 +18,9 @@ pub enum RegisterError {
     InvalidId(String),
     InvalidHost(String),
     InvalidPort(String),
+    InvalidPlan(String),
+    InvalidToken(String),
+    InvalidCert(String),
     InvalidFormat(String),
 }
 
@@ -25,6 +30,9 @@ impl std::fmt::Display for RegisterError {
             RegisterError::InvalidId(x) => write!(f, "{x}"),
             RegisterError::InvalidHost(x) => write!(f, "{x}"),
             RegisterError::InvalidPort(x) => write!(f, "{x}"),
+            RegisterError::InvalidPlan(x) => write!(f, "{x}"),
+            RegisterError::InvalidToken(x) => write!(f, "{x}"),
+            RegisterError::InvalidCert(x) => write!(f, "{x}"),
             RegisterError::InvalidFormat(x) => write!(f, "{x}"),
         }
     }
@@ -36,6 +44,9 @@ pub struct RegisterParams {
     pub tower_id: TowerId,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub plan: Option<String>,
+    pub token: Option<String>,
+    pub cert: Option<String>,
 }
 
 impl RegisterParams {
@@ -59,19 +70,33 @@ impl RegisterParams {
                 .map_err(|_| RegisterError::InvalidId("Invalid tower id".to_owned()))?,
             host: None,
             port: None,
+            plan: None,
+            token: None,
+            cert: None,
         })
     }
 
     fn with_host(self, host: &str) -> Result<Self, RegisterError> {
-        if host.is_empty() {
+        // The host may be prefixed by its scheme
+        let (scheme, hostname) = host.split_once("://").unwrap_or(("http", host));
+        if hostname.is_empty() {
             Err(RegisterError::InvalidHost("hostname is empty".to_owned()))
+        } else if !["http", "https", "grpcs"].contains(&scheme) {
+            Err(RegisterError::InvalidHost(format!(
+                "Unsupported scheme: {scheme}. Expected either http, https or grpcs"
+            )))
         } else if host.contains(' ') {
             Err(RegisterError::InvalidHost(
                 "hostname contains white spaces".to_owned(),
             ))
         } else {
+            // Bare IPv6 literals are bracketed so a port can be appended to them
+            let host = match host.split_once("://") {
+                Some((scheme, hostname)) => format!("{scheme}://{}", format_host(hostname)),
+                None => format_host(host),
+            };
             Ok(Self {
-                host: Some(String::from(host)),
+                host: Some(host),
                 ..self
             })
         }
@@ -89,6 +114,54 @@ impl RegisterParams {
             })
         }
     }
+
+    fn with_plan(self, plan: &serde_json::Value) -> Result<Self, RegisterError> {
+        let plan = plan
+            .as_str()
+            .ok_or_else(|| RegisterError::InvalidPlan("plan must be a string".to_owned()))?;
+        if plan.is_empty() || plan.contains(' ') {
+            Err(RegisterError::InvalidPlan(format!(
+                "plan must be a non-empty string with no white spaces. Received: '{plan}'"
+            )))
+        } else {
+            Ok(Self {
+                plan: Some(String::from(plan)),
+                ..self
+            })
+        }
+    }
+
+    fn with_token(self, token: &serde_json::Value) -> Result<Self, RegisterError> {
+        let token = token
+            .as_str()
+            .ok_or_else(|| RegisterError::InvalidToken("token must be a string".to_owned()))?;
+        if token.is_empty() || token.contains(' ') {
+            Err(RegisterError::InvalidToken(format!(
+                "token must be a non-empty string with no white spaces. Received: '{token}'"
+            )))
+        } else {
+            Ok(Self {
+                token: Some(String::from(token)),
+                ..self
+            })
+        }
+    }
+
+    fn with_cert(self, cert: &serde_json::Value) -> Result<Self, RegisterError> {
+        let cert = cert
+            .as_str()
+            .ok_or_else(|| RegisterError::InvalidCert("cert must be a string".to_owned()))?;
+        if cert.is_empty() {
+            Err(RegisterError::InvalidCert(
+                "cert must be the path to the tower certificate".to_owned(),
+            ))
+        } else {
+            Ok(Self {
+                cert: Some(String::from(cert)),
+                ..self
+            })
+        }
+    }
 }
 
 impl TryFrom<serde_json::Value> for RegisterParams {
@@ -105,9 +178,26 @@ impl TryFrom<serde_json::Value> for RegisterParams {
 
                 match v.next() {
                     Some(x) => {
-                        let mut v = x.split(':');
-                        let host = v.next();
-                        let port = if let Some(p) = v.next() {
+                        // The scheme (if any) is split apart so its colon is not mistaken for the port separator
+                        let (scheme, x) = x.split_once("://").map_or(("", x), |(scheme, x)| (scheme, x));
+                        // IPv6 literals must be bracketed so their colons are not mistaken for the port separator either
+                        let (h, p) = if x.starts_with('[') {
+                            let end = x.find(']').ok_or_else(|| RegisterError::InvalidHost(format!("Unclosed IPv6 bracket: {x}")))?;
+                            let (h, rest) = x.split_at(end + 1);
+                            if h[1..end].parse::<Ipv6Addr>().is_err() {
+                                return Err(RegisterError::InvalidHost(format!("Invalid IPv6 address: {h}")));
+                            }
+                            match rest.strip_prefix(':') {
+                                Some(p) => (h, Some(p)),
+                                None if rest.is_empty() => (h, None),
+                                None => return Err(RegisterError::InvalidHost(format!("Unexpected data after IPv6 address: {rest}"))),
+                            }
+                        } else {
+                            let mut v = x.split(':');
+                            (v.next().unwrap(), v.next())
+                        };
+                        let host = Some(if scheme.is_empty() { h.to_owned() } else { format!("{scheme}://{h}") });
+                        let port = if let Some(p) = p {
                             p.parse()
                                 .map(Some)
                                 .map_err(|_| RegisterError::InvalidPort(format!("Port is not a number: {p}")))?
@@ -115,7 +205,7 @@ impl TryFrom<serde_json::Value> for RegisterParams {
                             None
                         };
 
-                        RegisterParams::new(tower_id, host, port)
+                        RegisterParams::new(tower_id, host.as_deref(), port)
                     }
                     None => RegisterParams::from_id(tower_id),
                 }
@@ -125,7 +215,7 @@ impl TryFrom<serde_json::Value> for RegisterParams {
 
                 match param_count {
                     1 => RegisterParams::try_from(a.pop().unwrap()),
-                    2 | 3 => {
+                    2..=6 => {
                         let tower_id = a.get(0).unwrap().as_str().ok_or_else(|| RegisterError::InvalidId("tower_id must be a string".to_string()))?;
                         let host = Some(a.get(1).unwrap().as_str().ok_or_else(|| RegisterError::InvalidHost("host must be a string".to_string()))?);
                         let port = if let Some(p) = a.get(2) {
@@ -134,17 +224,39 @@ impl TryFrom<serde_json::Value> for RegisterParams {
                             None
                         };
 
-                        RegisterParams::new(tower_id, host, port)
+                        let mut params = RegisterParams::new(tower_id, host, port)?;
+                        if let Some(plan) = a.get(3) {
+                            params = params.with_plan(plan)?;
+                        }
+                        if let Some(token) = a.get(4) {
+                            params = params.with_token(token)?;
+                        }
+                        match a.get(5) {
+                            Some(cert) => params.with_cert(cert),
+                            None => Ok(params),
+                        }
                     }
-                    _ => Err(RegisterError::InvalidFormat(format!("Unexpected request format. The request needs 1-3 parameters. Received: {param_count}"))),
+                    _ => Err(RegisterError::InvalidFormat(format!("Unexpected request format. The request needs 1-6 parameters. Received: {param_count}"))),
                 }
             },
             serde_json::Value::Object(mut m) => {
+                // The plan, token and cert are not positional (they can be set without host and port), so they are
+                // handled apart from the rest
+                if let Some(cert) = m.remove("cert") {
+                    return RegisterParams::try_from(serde_json::Value::Object(m))?.with_cert(&cert);
+                }
+                if let Some(token) = m.remove("token") {
+                    return RegisterParams::try_from(serde_json::Value::Object(m))?.with_token(&token);
+                }
+                if let Some(plan) = m.remove("plan") {
+                    return RegisterParams::try_from(serde_json::Value::Object(m))?.with_plan(&plan);
+                }
+
                 let allowed_keys = ["tower_id", "host", "port"];
                 let param_count = m.len();
 
                  if m.is_empty() || param_count > allowed_keys.len() {
-                    Err(RegisterError::InvalidFormat(format!("Unexpected request format. The request needs 1-3 parameters. Received: {param_count}")))
+                    Err(RegisterError::InvalidFormat(format!("Unexpected request format. The request needs 1-4 parameters. Received: {param_count}")))
                  } else if !m.contains_key(allowed_keys[0]){
                     Err(RegisterError::InvalidId(format!("{} is mandatory", allowed_keys[0])))
                  } else if !m.iter().all(|(k, _)| allowed_keys.contains(&k.as_str())) {
@@ -161,7 +273,7 @@ impl TryFrom<serde_json::Value> for RegisterParams {
                 }
             },
             _ => Err(RegisterError::InvalidFormat(
-                format!("Unexpected request format. Expected: 'tower_id[@host][:port]' or 'tower_id [host] [port]'. Received: '{value}'"),
+                format!("Unexpected request format. Expected: 'tower_id[@host][:port]' or 'tower_id [host] [port] [plan] [token] [cert]'. Received: '{value}'"),
             )),
         }
     }
@@ -305,6 +417,30 @@ mod tests {
             let host = "myhost";
             assert_eq!(params.with_host(host).unwrap().host, Some(host.to_owned()));
 
+            // The host may come with its scheme, as long as it is http, https or grpcs
+            for host in ["http://myhost", "https://myhost", "grpcs://myhost"] {
+                let params = RegisterParams::from_id(VALID_ID).unwrap();
+                assert_eq!(params.with_host(host).unwrap().host, Some(host.to_owned()));
+            }
+            // Bare IPv6 literals are bracketed
+            for (host, expected) in [
+                ("::1", "[::1]"),
+                ("[::1]", "[::1]"),
+                ("https://2001:db8::1", "https://[2001:db8::1]"),
+            ] {
+                let params = RegisterParams::from_id(VALID_ID).unwrap();
+                assert_eq!(
+                    params.with_host(host).unwrap().host,
+                    Some(expected.to_owned())
+                );
+            }
+            for host in ["ftp://myhost", "https://"] {
+                assert!(matches!(
+                    RegisterParams::from_id(VALID_ID).unwrap().with_host(host),
+                    Err(RegisterError::InvalidHost(..))
+                ));
+            }
+
             // Host must not be empty not have spaces
             assert!(matches!(
                 RegisterParams::from_id(VALID_ID).unwrap().with_host(""),
@@ -340,6 +476,7 @@ mod tests {
             let ok = [
                 format!("{VALID_ID}@host:80"),
                 format!("{VALID_ID}@host"),
+                format!("{VALID_ID}@https://host:80"),
                 VALID_ID.to_string(),
             ];
             let wrong_id = ["", "id@host:80", "@host:80", "@:80"];
@@ -357,6 +494,30 @@ mod tests {
                 assert!(matches!(p, Ok(..)));
             }
 
+            // The scheme is kept as part of the host
+            let p = RegisterParams::try_from(json!(format!("{VALID_ID}@https://host:80"))).unwrap();
+            assert_eq!(p.host, Some("https://host".to_owned()));
+            assert_eq!(p.port, Some(80));
+
+            // IPv6 literals are kept bracketed
+            let p = RegisterParams::try_from(json!(format!("{VALID_ID}@[::1]:9814"))).unwrap();
+            assert_eq!(p.host, Some("[::1]".to_owned()));
+            assert_eq!(p.port, Some(9814));
+            let p = RegisterParams::try_from(json!(format!("{VALID_ID}@https://[2001:db8::1]")))
+                .unwrap();
+            assert_eq!(p.host, Some("https://[2001:db8::1]".to_owned()));
+            assert_eq!(p.port, None);
+            for s in [
+                format!("{VALID_ID}@[::1"),
+                format!("{VALID_ID}@[::1]9814"),
+                format!("{VALID_ID}@[host]:9814"),
+            ] {
+                assert!(matches!(
+                    RegisterParams::try_from(json!(s)),
+                    Err(RegisterError::InvalidHost(..))
+                ));
+            }
+
             for s in wrong_id {
                 let v = serde_json::Value::Array(vec![serde_json::Value::String(s.to_string())]);
                 let p = RegisterParams::try_from(v);
@@ -404,8 +565,40 @@ mod tests {
             let p = RegisterParams::try_from(json!(vec![&id, &host, &string_port]));
             assert!(matches!(p, Err(RegisterError::InvalidPort(..))));
 
-            // Wrong param count (params should be 1-3)
-            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &id]));
+            // Plan as fourth param
+            let plan = json!("premium");
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &plan])).unwrap();
+            assert_eq!(p.plan, Some("premium".to_owned()));
+
+            // Wrong plan
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &port]));
+            assert!(matches!(p, Err(RegisterError::InvalidPlan(..))));
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &json!("")]));
+            assert!(matches!(p, Err(RegisterError::InvalidPlan(..))));
+
+            // Token as fifth param
+            let token = json!("f00dbabe");
+            let p =
+                RegisterParams::try_from(json!(vec![&id, &host, &port, &plan, &token])).unwrap();
+            assert_eq!(p.token, Some("f00dbabe".to_owned()));
+
+            // Wrong token
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &plan, &port]));
+            assert!(matches!(p, Err(RegisterError::InvalidToken(..))));
+
+            // Cert as sixth param
+            let cert = json!("/path/to/cert.pem");
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &plan, &token, &cert]))
+                .unwrap();
+            assert_eq!(p.cert, Some("/path/to/cert.pem".to_owned()));
+
+            // Wrong cert
+            let p = RegisterParams::try_from(json!(vec![&id, &host, &port, &plan, &token, &port]));
+            assert!(matches!(p, Err(RegisterError::InvalidCert(..))));
+
+            // Wrong param count (params should be 1-6)
+            let p =
+                RegisterParams::try_from(json!(vec![&id, &host, &port, &plan, &token, &cert, &id]));
             assert!(matches!(p, Err(RegisterError::InvalidFormat(..))));
         }
 
@@ -454,7 +647,7 @@ mod tests {
             ])));
             assert!(matches!(p, Err(RegisterError::InvalidFormat(..))));
 
-            // Wrong param count (params should be 1-3)
+            // Wrong param count (params should be 1-4)
             let p = RegisterParams::try_from(json!(HashMap::from([
                 ("tower_id", &id),
                 ("host", &host),
@@ -462,6 +655,62 @@ mod tests {
                 ("another_param", &json!(0))
             ])));
             assert!(matches!(p, Err(RegisterError::InvalidFormat(..))));
+
+            // The plan can be set with or without host and port
+            let plan = json!("premium");
+            for v in [
+                HashMap::from([
+                    ("tower_id", &id),
+                    ("host", &host),
+                    ("port", &port),
+                    ("plan", &plan),
+                ]),
+                HashMap::from([("tower_id", &id), ("plan", &plan)]),
+            ] {
+                let p = RegisterParams::try_from(json!(v)).unwrap();
+                assert_eq!(p.plan, Some("premium".to_owned()));
+            }
+
+            // Wrong plan
+            let p = RegisterParams::try_from(json!(HashMap::from([
+                ("tower_id", &id),
+                ("plan", &port)
+            ])));
+            assert!(matches!(p, Err(RegisterError::InvalidPlan(..))));
+
+            // The token can be set alongside the plan, or on its own
+            let token = json!("f00dbabe");
+            for v in [
+                HashMap::from([("tower_id", &id), ("plan", &plan), ("token", &token)]),
+                HashMap::from([("tower_id", &id), ("token", &token)]),
+            ] {
+                let p = RegisterParams::try_from(json!(v)).unwrap();
+                assert_eq!(p.token, Some("f00dbabe".to_owned()));
+            }
+
+            // Wrong token
+            let p = RegisterParams::try_from(json!(HashMap::from([
+                ("tower_id", &id),
+                ("token", &json!(""))
+            ])));
+            assert!(matches!(p, Err(RegisterError::InvalidToken(..))));
+
+            // The cert can be set on its own too
+            let cert = json!("/path/to/cert.pem");
+            for v in [
+                HashMap::from([("tower_id", &id), ("host", &host), ("cert", &cert)]),
+                HashMap::from([("tower_id", &id), ("token", &token), ("cert", &cert)]),
+            ] {
+                let p = RegisterParams::try_from(json!(v)).unwrap();
+                assert_eq!(p.cert, Some("/path/to/cert.pem".to_owned()));
+            }
+
+            // Wrong cert
+            let p = RegisterParams::try_from(json!(HashMap::from([
+                ("tower_id", &id),
+                ("cert", &json!(""))
+            ])));
+            assert!(matches!(p, Err(RegisterError::InvalidCert(..))));
         }
 
         #[test]
@@ -5,20 +5,25 @@ use std::str::FromStr;
 
 use rusqlite::{params, Connection, Error as SqliteError};
 
+use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::SecretKey;
+use bitcoin::Txid;
 
 use teos_common::appointment::{Appointment, Locator};
 use teos_common::dbm::{DatabaseConnection, DatabaseManager, Error};
-use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
+use teos_common::receipts::{
+    AppointmentReceipt, BackupReceipt, RegistrationReceipt, ResponseReceipt,
+};
 use teos_common::{TowerId, UserId};
 
 use crate::{AppointmentStatus, MisbehaviorProof, TowerInfo, TowerStatus, TowerSummary};
 
-const TABLES: [&str; 8] = [
+const TABLES: [&str; 10] = [
     "CREATE TABLE IF NOT EXISTS towers (
     tower_id INT PRIMARY KEY,
     net_addr TEXT NOT NULL,
-    available_slots INT NOT NULL
+    available_slots INT NOT NULL,
+    tls_cert TEXT
 )",
     "CREATE TABLE IF NOT EXISTS appointments (
     locator INT PRIMARY KEY,
@@ -53,6 +58,7 @@ const TABLES: [&str; 8] = [
     subscription_start INT NOT NULL,
     subscription_expiry INT NOT NULL,
     signature BLOB NOT NULL,
+    plan TEXT NOT NULL DEFAULT '',
     PRIMARY KEY (tower_id, subscription_expiry),
     FOREIGN KEY(tower_id)
         REFERENCES towers(tower_id)
@@ -80,6 +86,28 @@ const TABLES: [&str; 8] = [
     "CREATE TABLE IF NOT EXISTS keys (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     key INT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS backup_receipts (
+    tower_id INT PRIMARY KEY,
+    version INT NOT NULL,
+    user_signature BLOB NOT NULL,
+    tower_signature BLOB NOT NULL,
+    FOREIGN KEY(tower_id)
+        REFERENCES towers(tower_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS response_receipts (
+    locator INT NOT NULL,
+    tower_id INT NOT NULL,
+    dispute_txid INT NOT NULL,
+    penalty_txid INT NOT NULL,
+    broadcast_height INT NOT NULL,
+    confirmation_height INT,
+    tower_signature BLOB NOT NULL,
+    PRIMARY KEY (locator, tower_id),
+    FOREIGN KEY(tower_id)
+        REFERENCES towers(tower_id)
+        ON DELETE CASCADE
 )",
 ];
 
@@ -109,6 +137,10 @@ impl DBM {
         connection.execute("PRAGMA foreign_keys=1;", [])?;
         let mut dbm = Self { connection };
         dbm.create_tables(Vec::from_iter(TABLES))?;
+        // Databases created before subscription plans were introduced have no plan column.
+        dbm.add_column("registration_receipts", "plan", "TEXT NOT NULL DEFAULT ''")?;
+        // Same for pinned tower certificates.
+        dbm.add_column("towers", "tls_cert", "TEXT")?;
 
         Ok(dbm)
     }
@@ -159,9 +191,9 @@ impl DBM {
         )
         .map_err(Error::Unknown)?;
         tx.execute(
-                "INSERT INTO registration_receipts (tower_id, available_slots, subscription_start, subscription_expiry, signature) 
-                    VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![tower_id.to_vec(), receipt.available_slots(), receipt.subscription_start(), receipt.subscription_expiry(), receipt.signature()]).map_err( Error::Unknown)?;
+                "INSERT INTO registration_receipts (tower_id, available_slots, subscription_start, subscription_expiry, signature, plan) 
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![tower_id.to_vec(), receipt.available_slots(), receipt.subscription_start(), receipt.subscription_expiry(), receipt.signature(), receipt.plan()]).map_err( Error::Unknown)?;
 
         tx.commit().map_err(Error::Unknown)
     }
@@ -220,7 +252,7 @@ impl DBM {
         let mut stmt = self
             .connection
             .prepare(
-                "SELECT available_slots, subscription_start, subscription_expiry, signature
+                "SELECT available_slots, subscription_start, subscription_expiry, signature, plan
                     FROM registration_receipts 
                     WHERE tower_id = ?1 AND subscription_expiry = (SELECT MAX(subscription_expiry) 
                         FROM registration_receipts 
@@ -233,14 +265,25 @@ impl DBM {
             let start: u32 = row.get(1).unwrap();
             let expiry: u32 = row.get(2).unwrap();
             let signature: String = row.get(3).unwrap();
+            let plan: String = row.get(4).unwrap();
 
             Ok(RegistrationReceipt::with_signature(
-                user_id, slots, start, expiry, signature,
+                user_id, slots, start, expiry, plan, signature,
             ))
         })
         .ok()
     }
 
+    /// Stores the certificate pinned for a given tower, or removes it if `tls_cert` is `None`.
+    pub fn store_tower_tls_cert(
+        &self,
+        tower_id: TowerId,
+        tls_cert: Option<&str>,
+    ) -> Result<(), Error> {
+        let query = "UPDATE towers SET tls_cert=?1 WHERE tower_id=?2";
+        self.update_data(query, params![tls_cert, tower_id.to_vec()])
+    }
+
     /// Removes a tower record from the database.
     ///
     /// This triggers a cascade deletion of all related data, such as appointments, appointment receipts, etc. As long as there is a single
@@ -255,7 +298,7 @@ impl DBM {
         let mut towers = HashMap::new();
         let mut stmt = self
             .connection
-            .prepare("SELECT tw.tower_id, tw.net_addr, tw.available_slots, rr.subscription_start, rr.subscription_expiry 
+            .prepare("SELECT tw.tower_id, tw.net_addr, tw.available_slots, rr.subscription_start, rr.subscription_expiry, tw.tls_cert 
                         FROM towers AS tw 
                         JOIN registration_receipts AS rr 
                         JOIN (SELECT tower_id, MAX(subscription_expiry) AS max_se 
@@ -273,6 +316,7 @@ impl DBM {
             let available_slots: u32 = row.get(2).unwrap();
             let start: u32 = row.get(3).unwrap();
             let expiry: u32 = row.get(4).unwrap();
+            let tls_cert: Option<String> = row.get(5).unwrap();
 
             let mut tower = TowerSummary::with_appointments(
                 net_addr,
@@ -282,6 +326,7 @@ impl DBM {
                 self.load_appointment_locators(tower_id, AppointmentStatus::Pending),
                 self.load_appointment_locators(tower_id, AppointmentStatus::Invalid),
             );
+            tower.net_addr = tower.net_addr.with_tls_cert(tls_cert);
 
             if self.exists_misbehaving_proof(tower_id) {
                 tower.status = TowerStatus::Misbehaving;
@@ -561,6 +606,100 @@ impl DBM {
         appointments
     }
 
+    /// Stores a backup receipt into the database, replacing the previous one of the given tower (if any).
+    pub fn store_backup_receipt(
+        &mut self,
+        tower_id: TowerId,
+        available_slots: u32,
+        receipt: &BackupReceipt,
+    ) -> Result<(), SqliteError> {
+        let tx = self.get_mut_connection().transaction().unwrap();
+        tx.execute(
+            "INSERT OR REPLACE INTO backup_receipts (tower_id, version, user_signature, tower_signature)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![
+                tower_id.to_vec(),
+                receipt.version(),
+                receipt.user_signature(),
+                receipt.signature()
+            ],
+        )?;
+        tx.execute(
+            "UPDATE towers SET available_slots=?1 WHERE tower_id=?2",
+            params![available_slots, tower_id.to_vec()],
+        )?;
+        tx.commit()
+    }
+
+    /// Loads the latest backup receipt of a given tower from the database.
+    pub fn load_backup_receipt(&self, tower_id: TowerId) -> Option<BackupReceipt> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT version, user_signature, tower_signature FROM backup_receipts WHERE tower_id = ?")
+            .unwrap();
+
+        stmt.query_row([tower_id.to_vec()], |row| {
+            let version = row.get::<_, u32>(0).unwrap();
+            let user_sig = row.get::<_, String>(1).unwrap();
+            let tower_sig = row.get::<_, String>(2).unwrap();
+
+            Ok(BackupReceipt::with_signature(user_sig, version, tower_sig))
+        })
+        .ok()
+    }
+
+    /// Stores a response receipt into the database. Newer receipts for the same appointment replace older ones.
+    pub fn store_response_receipt(
+        &self,
+        tower_id: TowerId,
+        receipt: &ResponseReceipt,
+    ) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO response_receipts (locator, tower_id, dispute_txid, penalty_txid, broadcast_height, confirmation_height, tower_signature)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+        self.store_data(
+            query,
+            params![
+                receipt.locator().to_vec(),
+                tower_id.to_vec(),
+                receipt.dispute_txid().to_vec(),
+                receipt.penalty_txid().to_vec(),
+                receipt.broadcast_height(),
+                receipt.confirmation_height(),
+                receipt.signature()
+            ],
+        )
+    }
+
+    /// Loads the latest response receipt of a given appointment from a given tower from the database.
+    pub fn load_response_receipt(
+        &self,
+        tower_id: TowerId,
+        locator: Locator,
+    ) -> Option<ResponseReceipt> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT dispute_txid, penalty_txid, broadcast_height, confirmation_height, tower_signature FROM response_receipts WHERE tower_id = ?1 and locator = ?2")
+            .unwrap();
+
+        stmt.query_row(params![tower_id.to_vec(), locator.to_vec()], |row| {
+            let dispute_txid = row.get::<_, Vec<u8>>(0).unwrap();
+            let penalty_txid = row.get::<_, Vec<u8>>(1).unwrap();
+            let broadcast_height = row.get::<_, u32>(2).unwrap();
+            let confirmation_height = row.get::<_, Option<u32>>(3).unwrap();
+            let tower_sig = row.get::<_, String>(4).unwrap();
+
+            Ok(ResponseReceipt::with_signature(
+                locator,
+                Txid::from_slice(&dispute_txid).unwrap(),
+                Txid::from_slice(&penalty_txid).unwrap(),
+                broadcast_height,
+                confirmation_height,
+                tower_sig,
+            ))
+        })
+        .ok()
+    }
+
     /// Stores a misbehaving proof into the database.
     ///
     /// A misbehaving proof is proof that the tower has signed an appointment using a key different
@@ -649,8 +788,8 @@ mod tests {
 
     use teos_common::cryptography::get_random_keypair;
     use teos_common::test_utils::{
-        generate_random_appointment, get_random_registration_receipt, get_random_user_id,
-        get_registration_receipt_from_previous,
+        generate_random_appointment, get_random_registration_receipt, get_random_response_receipt,
+        get_random_user_id, get_registration_receipt_from_previous,
     };
 
     impl DBM {
@@ -833,6 +972,46 @@ mod tests {
         assert_eq!(dbm.load_towers(), HashMap::new());
     }
 
+    #[test]
+    fn test_store_tower_tls_cert() {
+        let mut dbm = DBM::in_memory().unwrap();
+
+        let tower_id = get_random_user_id();
+        let net_addr = "https://talaia.watch";
+        let receipt = get_random_registration_receipt();
+
+        // The tower must exist for its certificate to be stored
+        assert!(matches!(
+            dbm.store_tower_tls_cert(tower_id, Some("cert")),
+            Err(Error::NotFound)
+        ));
+
+        dbm.store_tower_record(tower_id, net_addr, &receipt)
+            .unwrap();
+        assert_eq!(dbm.load_towers()[&tower_id].net_addr.tls_cert(), None);
+
+        dbm.store_tower_tls_cert(tower_id, Some("cert")).unwrap();
+        assert_eq!(
+            dbm.load_towers()[&tower_id].net_addr.tls_cert(),
+            Some("cert")
+        );
+
+        // Updating the tower record keeps the certificate
+        dbm.store_tower_record(
+            tower_id,
+            net_addr,
+            &get_registration_receipt_from_previous(&receipt),
+        )
+        .unwrap();
+        assert_eq!(
+            dbm.load_towers()[&tower_id].net_addr.tls_cert(),
+            Some("cert")
+        );
+
+        dbm.store_tower_tls_cert(tower_id, None).unwrap();
+        assert_eq!(dbm.load_towers()[&tower_id].net_addr.tls_cert(), None);
+    }
+
     #[test]
     fn test_remove_tower_record() {
         let mut dbm = DBM::in_memory().unwrap();
@@ -952,6 +1131,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_store_load_backup_receipt() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let tower_id = get_random_user_id();
+        assert!(dbm.load_backup_receipt(tower_id).is_none());
+
+        let receipt = get_random_registration_receipt();
+        dbm.store_tower_record(tower_id, "talaia.watch", &receipt)
+            .unwrap();
+
+        let backup_receipt = BackupReceipt::with_signature(
+            "user_signature".to_owned(),
+            1,
+            "tower_signature".to_owned(),
+        );
+        dbm.store_backup_receipt(tower_id, 21, &backup_receipt)
+            .unwrap();
+        assert_eq!(dbm.load_backup_receipt(tower_id).unwrap(), backup_receipt);
+        assert_eq!(
+            dbm.load_towers().get(&tower_id).unwrap().available_slots,
+            21
+        );
+
+        // Newer receipts replace older ones
+        let backup_receipt = BackupReceipt::with_signature(
+            "user_signature".to_owned(),
+            2,
+            "tower_signature".to_owned(),
+        );
+        dbm.store_backup_receipt(tower_id, 20, &backup_receipt)
+            .unwrap();
+        assert_eq!(dbm.load_backup_receipt(tower_id).unwrap(), backup_receipt);
+
+        // Receipts are removed alongside the tower
+        dbm.remove_tower_record(tower_id).unwrap();
+        assert!(dbm.load_backup_receipt(tower_id).is_none());
+    }
+
+    #[test]
+    fn test_store_load_response_receipt() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let (tower_sk, tower_pk) = get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let mut receipt = get_random_response_receipt(tower_sk);
+        assert!(dbm
+            .load_response_receipt(tower_id, receipt.locator())
+            .is_none());
+
+        dbm.store_tower_record(tower_id, "talaia.watch", &get_random_registration_receipt())
+            .unwrap();
+        dbm.store_response_receipt(tower_id, &receipt).unwrap();
+        assert_eq!(
+            dbm.load_response_receipt(tower_id, receipt.locator()),
+            Some(receipt.clone())
+        );
+
+        // Newer receipts replace older ones
+        receipt.set_confirmation_height(100);
+        receipt.sign(&tower_sk);
+        dbm.store_response_receipt(tower_id, &receipt).unwrap();
+        assert_eq!(
+            dbm.load_response_receipt(tower_id, receipt.locator()),
+            Some(receipt.clone())
+        );
+
+        // Receipts are removed alongside the tower
+        dbm.remove_tower_record(tower_id).unwrap();
+        assert!(dbm
+            .load_response_receipt(tower_id, receipt.locator())
+            .is_none());
+    }
+
     #[test]
     fn test_load_appointment_locators() {
         // `load_appointment_locators` is used to load locators from either `appointment_receipts`, `pending_appointments` or `invalid_appointments`
@@ -8,10 +8,20 @@ use teos_common::net::NetAddr;
 use teos_common::receipts::AppointmentReceipt;
 use teos_common::TowerId;
 
+// FIXME: This is a temporary fix. See https://github.com/tokio-rs/prost/issues/661
+#[allow(clippy::derive_partial_eq_without_eq)]
+// Streaming RPCs get their associated type named after the (snake case) method.
+#[allow(non_camel_case_types)]
+pub mod protos {
+    tonic::include_proto!("teos.v2");
+}
+
+pub mod backup;
 pub mod constants;
 pub mod convert;
 pub mod dbm;
 pub mod net;
+pub mod payments;
 pub mod retrier;
 mod ser;
 pub mod wt_client;
@@ -167,7 +177,9 @@ impl TowerSummary {
         subscription_start: u32,
         subscription_expiry: u32,
     ) {
-        self.net_addr = NetAddr::new(net_addr);
+        // The pinned certificate (if any) is updated separately
+        let tls_cert = self.net_addr.tls_cert().map(|c| c.to_owned());
+        self.net_addr = NetAddr::new(net_addr).with_tls_cert(tls_cert);
         self.available_slots = available_slots;
         self.subscription_start = subscription_start;
         self.subscription_expiry = subscription_expiry;
@@ -2,6 +2,7 @@ use std::convert::TryFrom;
 use std::env;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use home::home_dir;
 use serde_json::json;
@@ -12,18 +13,21 @@ use cln_plugin::options::{ConfigOption, Value};
 use cln_plugin::{anyhow, Builder, Error, Plugin};
 
 use teos_common::appointment::{Appointment, Locator};
-use teos_common::net::http::Endpoint;
-use teos_common::net::NetAddr;
+use teos_common::net::cln_rpc::ClnRpc;
+use teos_common::net::{NetAddr, GRPC_SCHEME};
+use teos_common::pow::{self, MAX_POW_DIFFICULTY};
 use teos_common::protos as common_msgs;
+use teos_common::receipts::ResponseReceipt;
 use teos_common::TowerId;
 use teos_common::{cryptography, errors};
 
+use watchtower_plugin::backup::{BackupManager, SCB_FILE_NAME};
 use watchtower_plugin::convert::{CommitmentRevocation, GetAppointmentParams, RegisterParams};
 use watchtower_plugin::net::http::{
-    self, get_request, post_request, process_post_response, AddAppointmentError, ApiResponse,
-    RequestError,
+    self, AddAppointmentError, ApiResponse, Registration, RequestError,
 };
 use watchtower_plugin::net::ProxyInfo;
+use watchtower_plugin::payments;
 use watchtower_plugin::retrier::RetryManager;
 use watchtower_plugin::wt_client::{RevocationData, WTClient};
 use watchtower_plugin::{constants, TowerStatus};
@@ -69,7 +73,6 @@ async fn register(
     let params = RegisterParams::try_from(v).map_err(|x| anyhow!(x))?;
     let mut host = params.host.unwrap_or_else(|| "localhost".to_owned());
     let tower_id = params.tower_id;
-    let user_id = plugin.state().lock().unwrap().user_id;
 
     // TODO: The user should pick the start_time or, at least, check the returned start time against it's known block height.
     // Otherwise the tower could just generate a subscription starting far in the future. For this we need to access lightning RPC
@@ -80,24 +83,86 @@ async fn register(
             .map_err(|_| anyhow!("{} out of range", constants::WT_PORT))?,
     );
 
-    let tower_net_addr = {
-        if !host.starts_with("http://") {
-            host = format!("http://{host}")
+    if !host.starts_with("http://")
+        && !host.starts_with("https://")
+        && !host.starts_with(GRPC_SCHEME)
+    {
+        host = format!("http://{host}")
+    }
+    let net_addr = NetAddr::new(format!("{host}:{port}"));
+
+    // A pinned certificate is kept across registrations unless a new one is provided
+    let tls_cert = match params.cert {
+        Some(path) => {
+            if !net_addr.is_tls() {
+                return Err(anyhow!(
+                    "Certificates can only be pinned for https or grpcs towers"
+                ));
+            }
+            Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Cannot read the tower certificate ({path}): {e}"))?,
+            )
         }
-        NetAddr::new(format!("{host}:{port}"))
+        None if net_addr.is_tls() => plugin
+            .state()
+            .lock()
+            .unwrap()
+            .towers
+            .get(&tower_id)
+            .and_then(|tower| tower.net_addr.tls_cert().map(|c| c.to_owned())),
+        None => None,
     };
+    if net_addr.is_grpc() && tls_cert.is_none() {
+        return Err(anyhow!("grpcs towers require a pinned certificate (cert)"));
+    }
+    let tower_net_addr = net_addr.with_tls_cert(tls_cert);
 
     let proxy = plugin.state().lock().unwrap().proxy.clone();
 
-    let receipt = http::register(tower_id, user_id, &tower_net_addr, &proxy)
-        .await
-        .map_err(|e| {
-            let mut state = plugin.state().lock().unwrap();
-            if e.is_connection() && state.towers.contains_key(&tower_id) {
-                state.set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+    let plan = params.plan.unwrap_or_default();
+    let token = params.token.unwrap_or_default();
+    let registration =
+        send_registration(&plugin, tower_id, &plan, &token, &tower_net_addr, &proxy).await?;
+    let receipt = match registration {
+        Registration::Registered(receipt) => receipt,
+        // Paid plans need the invoice to be paid first. The subscription is activated by registering again
+        Registration::PaymentRequired(invoice) => {
+            let max_price = u64::try_from(
+                plugin
+                    .option(constants::WT_MAX_SUBSCRIPTION_PRICE)
+                    .unwrap()
+                    .as_i64()
+                    .unwrap(),
+            )
+            .map_err(|_| anyhow!("{} out of range", constants::WT_MAX_SUBSCRIPTION_PRICE))?;
+            let configuration = plugin.configuration();
+            let rpc = ClnRpc::new(
+                PathBuf::from(configuration.lightning_dir).join(configuration.rpc_file),
+            );
+
+            let amount = payments::pay_invoice(&rpc, &invoice, max_price)
+                .await
+                .map_err(|e| anyhow!("Cannot pay for the subscription. {e}"))?;
+            log::info!("Paid {amount} msat to {tower_id} for the subscription");
+
+            match send_registration(&plugin, tower_id, &plan, &token, &tower_net_addr, &proxy)
+                .await?
+            {
+                Registration::Registered(receipt) => receipt,
+                _ => {
+                    return Err(anyhow!(
+                        "The tower did not activate the subscription after the invoice was paid"
+                    ))
+                }
             }
-            to_cln_error(e)
-        })?;
+        }
+        Registration::ProofOfWorkRequired => {
+            return Err(anyhow!(
+                "The tower did not accept the registration proof of work"
+            ))
+        }
+    };
 
     if !receipt.verify(&tower_id) {
         return Err(anyhow!(
@@ -105,10 +170,8 @@ async fn register(
         ));
     }
 
-    plugin
-        .state()
-        .lock()
-        .unwrap()
+    let mut state = plugin.state().lock().unwrap();
+    state
         .add_update_tower(tower_id, tower_net_addr.net_addr(), &receipt).map_err(|e| {
             if e.is_expiry() {
                 anyhow!("Registration receipt contains a subscription expiry that is not higher than the one we are currently registered for")
@@ -116,6 +179,8 @@ async fn register(
                 anyhow!("Registration receipt does not contain more slots than the ones we are currently registered for")
             }
         })?;
+    state.set_tower_tls_cert(tower_id, tower_net_addr.tls_cert().map(|c| c.to_owned()));
+    drop(state);
 
     log::info!(
         "Registration succeeded. Available slots: {}. Subscription period (block height range): ({}-{})",
@@ -127,6 +192,63 @@ async fn register(
     Ok(json!(receipt))
 }
 
+/// Sends a registration request to a given tower. The tower is flagged as temporary unreachable if it cannot be reached.
+///
+/// If the tower requires a proof of work to register, the proof is computed and the request is sent again.
+async fn send_registration(
+    plugin: &Plugin<Arc<Mutex<WTClient>>>,
+    tower_id: TowerId,
+    plan: &str,
+    token: &str,
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<Registration, Error> {
+    let user_id = plugin.state().lock().unwrap().user_id;
+    let handle_error = |e: RequestError| {
+        let mut state = plugin.state().lock().unwrap();
+        if e.is_connection() && state.towers.contains_key(&tower_id) {
+            state.set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+        }
+        to_cln_error(e)
+    };
+
+    let registration = http::register(tower_id, user_id, plan, 0, token, tower_net_addr, proxy)
+        .await
+        .map_err(handle_error)?;
+    if registration != Registration::ProofOfWorkRequired {
+        return Ok(registration);
+    }
+
+    // Towers gating registrations advertise the proof of work difficulty alongside their subscription plans
+    let difficulty = http::get_subscription_plans(tower_net_addr, proxy)
+        .await
+        .map_err(handle_error)?
+        .pow_difficulty;
+    if difficulty == 0 || difficulty > MAX_POW_DIFFICULTY as u32 {
+        return Err(anyhow!(
+            "The tower requires an unexpected proof of work difficulty ({difficulty})"
+        ));
+    }
+
+    log::info!(
+        "Computing the registration proof of work for {tower_id} (difficulty: {difficulty})"
+    );
+    let pow_nonce =
+        tokio::task::spawn_blocking(move || pow::solve_pow(tower_id, user_id, difficulty as u8))
+            .await?;
+    http::register(
+        tower_id,
+        user_id,
+        plan,
+        pow_nonce,
+        token,
+        tower_net_addr,
+        proxy,
+    )
+    .await
+    .map_err(handle_error)
+}
+
 /// Gets the latest registration receipt from the client to a given tower (if it exists).
 ///
 /// This is pulled from the database
@@ -164,26 +286,18 @@ async fn get_subscription_info(
 
     let signature = cryptography::sign("get subscription info".as_bytes(), &user_sk).unwrap();
 
-    let response: common_msgs::GetSubscriptionInfoResponse = process_post_response(
-        post_request(
-            &tower_net_addr,
-            Endpoint::GetSubscriptionInfo,
-            &common_msgs::GetSubscriptionInfoRequest { signature },
-            &proxy,
-        )
-        .await,
-    )
-    .await
-    .map_err(|e| {
-        if e.is_connection() {
-            plugin
-                .state()
-                .lock()
-                .unwrap()
-                .set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
-        }
-        to_cln_error(e)
-    })?;
+    let response = http::get_subscription_info(&tower_net_addr, &proxy, signature)
+        .await
+        .map_err(|e| {
+            if e.is_connection() {
+                plugin
+                    .state()
+                    .lock()
+                    .unwrap()
+                    .set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+            }
+            to_cln_error(e)
+        })?;
 
     Ok(json!(response))
 }
@@ -210,29 +324,80 @@ async fn get_appointment(
     )
     .unwrap();
 
-    let response: ApiResponse<common_msgs::GetAppointmentResponse> = process_post_response(
-        post_request(
-            &tower_net_addr,
-            Endpoint::GetAppointment,
-            &common_msgs::GetAppointmentRequest {
-                locator: params.locator.to_vec(),
-                signature,
-            },
-            &proxy,
-        )
-        .await,
-    )
-    .await
-    .map_err(|e| {
-        if e.is_connection() {
-            plugin
-                .state()
-                .lock()
-                .unwrap()
-                .set_tower_status(params.tower_id, TowerStatus::TemporaryUnreachable);
+    let response = http::get_appointment(&tower_net_addr, &proxy, params.locator, signature)
+        .await
+        .map_err(|e| {
+            if e.is_connection() {
+                plugin
+                    .state()
+                    .lock()
+                    .unwrap()
+                    .set_tower_status(params.tower_id, TowerStatus::TemporaryUnreachable);
+            }
+            to_cln_error(e)
+        })?;
+
+    // Response receipts are kept as proof of the tower having responded to the breach.
+    if let ApiResponse::Response(common_msgs::GetAppointmentResponse {
+        response_receipt: Some(ref r),
+        ..
+    }) = response
+    {
+        match ResponseReceipt::try_from(r.clone()) {
+            // The receipt must be for the requested appointment, otherwise it would not prove anything
+            Ok(receipt) if receipt.is_for(params.locator) && receipt.verify(&params.tower_id) => {
+                plugin
+                    .state()
+                    .lock()
+                    .unwrap()
+                    .add_response_receipt(params.tower_id, &receipt)
+            }
+            _ => log::warn!(
+                "Received an invalid response receipt from {} (locator={})",
+                params.tower_id,
+                params.locator
+            ),
         }
-        to_cln_error(e)
-    })?;
+    }
+
+    Ok(json!(response))
+}
+
+/// Gets the reasons why a tower could not respond to a triggered appointment given a tower_id and a locator.
+async fn get_appointment_outcomes(
+    plugin: Plugin<Arc<Mutex<WTClient>>>,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let params = GetAppointmentParams::try_from(v).map_err(|x| anyhow!(x))?;
+
+    let (user_sk, tower_net_addr, proxy) = {
+        let state = plugin.state().lock().unwrap();
+        if let Some(info) = state.towers.get(&params.tower_id) {
+            Ok((state.user_sk, info.net_addr.clone(), state.proxy.clone()))
+        } else {
+            Err(anyhow!("Unknown tower id: {}", params.tower_id))
+        }
+    }?;
+
+    let signature = cryptography::sign(
+        format!("get appointment outcomes {}", params.locator).as_bytes(),
+        &user_sk,
+    )
+    .unwrap();
+
+    let response =
+        http::get_appointment_outcomes(&tower_net_addr, &proxy, params.locator, signature)
+            .await
+            .map_err(|e| {
+                if e.is_connection() {
+                    plugin
+                        .state()
+                        .lock()
+                        .unwrap()
+                        .set_tower_status(params.tower_id, TowerStatus::TemporaryUnreachable);
+                }
+                to_cln_error(e)
+            })?;
 
     Ok(json!(response))
 }
@@ -263,6 +428,32 @@ async fn get_appointment_receipt(
     }
 }
 
+/// Gets the latest response receipt from the client given a tower_id and a locator (if it exists).
+///
+/// This is pulled from the database. Receipts are stored every time they are received via `getappointment`.
+async fn get_response_receipt(
+    plugin: Plugin<Arc<Mutex<WTClient>>>,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let params = GetAppointmentParams::try_from(v).map_err(|x| anyhow!(x))?;
+    let state = plugin.state().lock().unwrap();
+
+    if let Some(r) = state.get_response_receipt(params.tower_id, params.locator) {
+        Ok(json!(r))
+    } else if state.towers.contains_key(&params.tower_id) {
+        Err(anyhow!(
+            "Cannot find a response receipt for {} within {}. Has the tower responded to it?",
+            params.locator,
+            params.tower_id
+        ))
+    } else {
+        Err(anyhow!(
+            "Cannot find {} within the known towers. Have you registered?",
+            params.tower_id
+        ))
+    }
+}
+
 /// Lists all the registered towers.
 ///
 /// The given information comes from memory, so it is summarized.
@@ -314,11 +505,11 @@ async fn ping(
             state.proxy.clone(),
         )
     };
-    let response = get_request(&tower_net_addr, Endpoint::Ping, &proxy)
+    let response = http::ping(&tower_net_addr, &proxy)
         .await
         .map_err(to_cln_error)?;
 
-    if response.status().is_success() {
+    if response.is_success() {
         Ok(json!("Tower is reachable"))
     } else {
         Err(anyhow!(format!(
@@ -474,6 +665,16 @@ async fn on_commitment_revocation(
                             state.add_pending_appointment(tower_id, &appointment);
                             send_to_retrier(&state, tower_id, appointment.locator);
                         }
+                        errors::RATE_LIMIT_EXCEEDED => {
+                            log::warn!(
+                                "{tower_id} is rate limiting us. Adding {} to pending appointments",
+                                appointment.locator
+                            );
+                            let mut state = plugin.state().lock().unwrap();
+                            state.set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+                            state.add_pending_appointment(tower_id, &appointment);
+                            send_to_retrier(&state, tower_id, appointment.locator);
+                        }
 
                         _ => {
                             log::warn!(
@@ -549,6 +750,16 @@ async fn main() -> Result<(), Error> {
             Value::Integer(constants::DEFAULT_WT_AUTO_RETRY_DELAY),
             constants::WT_AUTO_RETRY_DELAY_DESC,
         ))
+        .option(ConfigOption::new(
+            constants::WT_BACKUP_INTERVAL,
+            Value::Integer(constants::DEFAULT_WT_BACKUP_INTERVAL),
+            constants::WT_BACKUP_INTERVAL_DESC,
+        ))
+        .option(ConfigOption::new(
+            constants::WT_MAX_SUBSCRIPTION_PRICE,
+            Value::Integer(constants::DEFAULT_WT_MAX_SUBSCRIPTION_PRICE),
+            constants::WT_MAX_SUBSCRIPTION_PRICE_DESC,
+        ))
         .option(ConfigOption::new(
             constants::DEV_WT_MAX_RETRY_INTERVAL,
             Value::Integer(constants::DEFAULT_DEV_WT_MAX_RETRY_INTERVAL),
@@ -569,11 +780,21 @@ async fn main() -> Result<(), Error> {
             constants::RPC_GET_APPOINTMENT_DESC,
             get_appointment,
         )
+        .rpcmethod(
+            constants::RPC_GET_APPOINTMENT_OUTCOMES,
+            constants::RPC_GET_APPOINTMENT_OUTCOMES_DESC,
+            get_appointment_outcomes,
+        )
         .rpcmethod(
             constants::RPC_GET_APPOINTMENT_RECEIPT,
             constants::RPC_GET_APPOINTMENT_RECEIPT_DESC,
             get_appointment_receipt,
         )
+        .rpcmethod(
+            constants::RPC_GET_RESPONSE_RECEIPT,
+            constants::RPC_GET_RESPONSE_RECEIPT_DESC,
+            get_response_receipt,
+        )
         .rpcmethod(
             constants::RPC_GET_SUBSCRIPTION_INFO,
             constants::RPC_GET_SUBSCRIPTION_INFO_DESC,
@@ -665,7 +886,32 @@ async fn main() -> Result<(), Error> {
         e
     })?;
 
+    let backup_interval = u32::try_from(
+        midstate
+            .option(constants::WT_BACKUP_INTERVAL)
+            .unwrap()
+            .as_i64()
+            .unwrap(),
+    )
+    .map_err(|e| {
+        log::error!("{} out of range", constants::WT_BACKUP_INTERVAL);
+        e
+    })?;
+    let scb_path = PathBuf::from(&midstate.configuration().lightning_dir).join(SCB_FILE_NAME);
+
     let plugin = midstate.start(wt_client.clone()).await?;
+    if backup_interval > 0 {
+        let wt_client = wt_client.clone();
+        tokio::spawn(async move {
+            BackupManager::new(
+                wt_client,
+                scb_path,
+                Duration::from_secs(backup_interval as u64),
+            )
+            .manage_backups()
+            .await
+        });
+    }
     tokio::spawn(async move {
         RetryManager::new(
             wt_client,
@@ -0,0 +1,584 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Uri};
+use tonic::{Code, Status};
+use x509_parser::extensions::GeneralName;
+
+use teos_common::errors;
+use teos_common::net::http::Endpoint;
+use teos_common::net::{get_host, AddressType, NetAddr};
+use teos_common::protos as common_msgs;
+
+use crate::net::http::{ApiError, ApiResponse, RequestError, TowerResponse};
+use crate::net::ProxyInfo;
+use crate::protos::public_tower_services_client::PublicTowerServicesClient;
+
+/// Port the public gRPC interface is reached at if the address of the tower does not specify one.
+const DEFAULT_GRPC_PORT: u16 = 443;
+
+/// Identifies a channel to a tower: its address, its pinned certificate and the proxy it is reached through (if any).
+type ChannelKey = (String, String, Option<String>);
+
+/// Channels to the towers reached so far. Channels multiplex requests over a single connection, so there is one per
+/// tower, reused by all the requests sent to it.
+static CHANNELS: Mutex<BTreeMap<ChannelKey, Channel>> = Mutex::new(BTreeMap::new());
+
+/// A request to the public gRPC interface of a tower.
+#[tonic::async_trait]
+pub trait GrpcRequest: Send + Sync + 'static {
+    /// The endpoint of the HTTP API the request maps to.
+    const ENDPOINT: Endpoint;
+    /// The message the tower replies with.
+    type Response: Send + 'static;
+
+    /// Sends the request through the given client.
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status>;
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::RegisterRequest {
+    const ENDPOINT: Endpoint = Endpoint::Register;
+    type Response = common_msgs::RegisterResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.register(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::AddAppointmentRequest {
+    const ENDPOINT: Endpoint = Endpoint::AddAppointment;
+    type Response = common_msgs::AddAppointmentResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.add_appointment(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::GetAppointmentRequest {
+    const ENDPOINT: Endpoint = Endpoint::GetAppointment;
+    type Response = common_msgs::GetAppointmentResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.get_appointment(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::GetSubscriptionInfoRequest {
+    const ENDPOINT: Endpoint = Endpoint::GetSubscriptionInfo;
+    type Response = common_msgs::GetSubscriptionInfoResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.get_subscription_info(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::StoreBackupRequest {
+    const ENDPOINT: Endpoint = Endpoint::StoreBackup;
+    type Response = common_msgs::StoreBackupResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.store_backup(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::GetBackupRequest {
+    const ENDPOINT: Endpoint = Endpoint::GetBackup;
+    type Response = common_msgs::GetBackupResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.get_backup(self).await
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcRequest for common_msgs::GetAppointmentOutcomesRequest {
+    const ENDPOINT: Endpoint = Endpoint::GetAppointmentOutcomes;
+    type Response = common_msgs::GetAppointmentOutcomesResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.get_appointment_outcomes(self).await
+    }
+}
+
+/// Getting the subscription plans takes no parameters.
+#[tonic::async_trait]
+impl GrpcRequest for () {
+    const ENDPOINT: Endpoint = Endpoint::GetSubscriptionPlans;
+    type Response = common_msgs::GetSubscriptionPlansResponse;
+
+    async fn send(
+        self,
+        client: &mut PublicTowerServicesClient<Channel>,
+    ) -> Result<tonic::Response<Self::Response>, Status> {
+        client.get_subscription_plans(self).await
+    }
+}
+
+/// Gets the name the certificate of a tower is verified against.
+///
+/// Towers reached by hostname are verified against it. The rest (reached by IP or onion address) are verified against
+/// the name their pinned certificate advertises, that is, the first DNS name it has been issued for.
+fn tls_name(tower_net_addr: &NetAddr, cert: &str) -> Result<String, RequestError> {
+    if let AddressType::Hostname = tower_net_addr.addr_type() {
+        return Ok(get_host(tower_net_addr.net_addr()).to_owned());
+    }
+
+    let invalid_cert =
+        |e: String| RequestError::ConnectionError(format!("Invalid tower certificate: {e}"));
+    let (_, pem) = x509_parser::pem::parse_x509_pem(cert.as_bytes())
+        .map_err(|e| invalid_cert(e.to_string()))?;
+    let certificate = pem.parse_x509().map_err(|e| invalid_cert(e.to_string()))?;
+    certificate
+        .tbs_certificate
+        .subject_alternative_name()
+        .and_then(|(_, alt_names)| {
+            alt_names.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+        })
+        .ok_or_else(|| invalid_cert("it is not issued for any DNS name".to_owned()))
+}
+
+/// Connects to the public gRPC interface of a tower, through the proxy if it has to be used.
+///
+/// The certificate of the tower must be pinned, since it is the only one trusted for the connection (see [tls_name]
+/// for the name it is verified against).
+async fn connect(
+    tower_net_addr: &NetAddr,
+    cert: &str,
+    proxy: Option<&ProxyInfo>,
+) -> Result<Channel, RequestError> {
+    let endpoint = Channel::from_shared(tower_net_addr.grpc_url())
+        .map_err(|e| RequestError::ConnectionError(format!("Invalid tower address: {e}")))?
+        .tls_config(
+            ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(cert))
+                .domain_name(tls_name(tower_net_addr, cert)?),
+        )
+        .map_err(|e| RequestError::ConnectionError(format!("Invalid tower certificate: {e}")))?;
+
+    match proxy {
+        Some(proxy) => {
+            let proxy_host = proxy.get_socks_host();
+            endpoint
+                .connect_with_connector(tower::service_fn(move |uri: Uri| {
+                    let proxy_host = proxy_host.clone();
+                    async move {
+                        let host = get_host(uri.host().unwrap_or_default()).to_owned();
+                        let port = uri.port_u16().unwrap_or(DEFAULT_GRPC_PORT);
+                        tokio_socks::tcp::Socks5Stream::connect(proxy_host.as_str(), (host, port))
+                            .await
+                            .map(|stream| stream.into_inner())
+                    }
+                }))
+                .await
+        }
+        None => endpoint.connect().await,
+    }
+    .map_err(|e| {
+        log::debug!("An error ocurred when connecting to the tower: {e}");
+        RequestError::ConnectionError("Cannot connect to the tower. Connection refused".to_owned())
+    })
+}
+
+/// Sends a request to the public gRPC interface of a tower (see [send]).
+async fn call<R: GrpcRequest>(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    request: R,
+) -> Result<Result<R::Response, Status>, RequestError> {
+    // The futures of gRPC calls are not Sync (which the plugin RPC methods require), so calls run on their own task
+    let tower_net_addr = tower_net_addr.clone();
+    let proxy = proxy.clone();
+    tokio::spawn(async move { send(&tower_net_addr, &proxy, request).await })
+        .await
+        .map_err(|e| {
+            RequestError::Unexpected(format!("The request to the tower was aborted. {e}"))
+        })?
+}
+
+/// Sends a request to the public gRPC interface of a tower, reusing the channel to the tower if there is one already.
+///
+/// Transport errors are reported as [RequestError::ConnectionError] (and the channel is dropped, so the next request
+/// reconnects), while the errors the tower replies with are returned as they are.
+async fn send<R: GrpcRequest>(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    request: R,
+) -> Result<Result<R::Response, Status>, RequestError> {
+    let cert = tower_net_addr.tls_cert().ok_or_else(|| {
+        RequestError::ConnectionError("gRPC towers require a pinned certificate".to_owned())
+    })?;
+    let proxy = match proxy {
+        Some(proxy) if proxy.always_use || tower_net_addr.is_onion() => Some(proxy),
+        None if tower_net_addr.is_onion() => {
+            return Err(RequestError::ConnectionError(
+                "Cannot connect to an onion address without a proxy".to_owned(),
+            ))
+        }
+        _ => None,
+    };
+    let key = (
+        tower_net_addr.net_addr().to_owned(),
+        cert.to_owned(),
+        proxy.map(|proxy| proxy.get_socks_host()),
+    );
+
+    let cached = CHANNELS.lock().unwrap().get(&key).cloned();
+    let channel = match cached {
+        Some(channel) => channel,
+        None => {
+            let channel = connect(tower_net_addr, cert, proxy).await?;
+            CHANNELS
+                .lock()
+                .unwrap()
+                .insert(key.clone(), channel.clone());
+            channel
+        }
+    };
+
+    match request
+        .send(&mut PublicTowerServicesClient::new(channel))
+        .await
+    {
+        Ok(response) => Ok(Ok(response.into_inner())),
+        // Only transport errors carry a source, the statuses replied by the tower do not
+        Err(status) if status.source().is_some() => {
+            log::debug!("An error ocurred when sending data to the tower: {status}");
+            CHANNELS.lock().unwrap().remove(&key);
+            Err(RequestError::ConnectionError(
+                "Cannot connect to the tower. Connection refused".to_owned(),
+            ))
+        }
+        Err(status) => Ok(Err(status)),
+    }
+}
+
+/// Sends a request to the public gRPC interface of a tower. Errors replied by the tower are reported the same way
+/// the HTTP API reports them.
+pub async fn request<R: GrpcRequest>(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    request: R,
+) -> Result<ApiResponse<R::Response>, RequestError> {
+    Ok(match call(tower_net_addr, proxy, request).await? {
+        Ok(response) => ApiResponse::Response(response),
+        Err(status) => ApiResponse::Error(ApiError {
+            error: status.message().to_owned(),
+            error_code: match status.code() {
+                // NotFound is mapped to APPOINTMENT_NOT_FOUND by default
+                Code::NotFound if R::ENDPOINT == Endpoint::GetBackup => errors::BACKUP_NOT_FOUND,
+                _ => errors::status_error_code(&status),
+            },
+        }),
+    })
+}
+
+/// Checks whether the public gRPC interface of a tower is reachable. It has no ping, so the cheapest call is used
+/// instead.
+pub async fn ping(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<TowerResponse, RequestError> {
+    let code = match call(tower_net_addr, proxy, ()).await? {
+        Ok(_) => Code::Ok,
+        Err(status) => status.code(),
+    };
+    Ok(TowerResponse::new(
+        code == Code::Ok,
+        code.description().to_owned(),
+        Vec::new(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::{Identity, Server, ServerTlsConfig};
+    use tonic::{Request, Response};
+
+    use teos_common::test_utils::{generate_random_appointment, get_random_user_id};
+    use teos_common::TowerId;
+
+    use crate::net::http::{self, AddAppointmentError, Registration};
+    use crate::protos::public_tower_services_server::{
+        PublicTowerServices, PublicTowerServicesServer,
+    };
+
+    /// Mocks the public gRPC interface of a tower.
+    struct MockTower;
+
+    #[tonic::async_trait]
+    impl PublicTowerServices for MockTower {
+        async fn register(
+            &self,
+            request: Request<common_msgs::RegisterRequest>,
+        ) -> Result<Response<common_msgs::RegisterResponse>, Status> {
+            let req = request.into_inner();
+            Ok(Response::new(common_msgs::RegisterResponse {
+                user_id: req.user_id,
+                available_slots: 21,
+                subscription_start: 100,
+                subscription_expiry: 4420,
+                plan: req.plan,
+                subscription_signature: "signature".to_owned(),
+                ..Default::default()
+            }))
+        }
+
+        async fn add_appointment(
+            &self,
+            _: Request<common_msgs::AddAppointmentRequest>,
+        ) -> Result<Response<common_msgs::AddAppointmentResponse>, Status> {
+            Err(Status::resource_exhausted(
+                "Too many requests from this user. Try again later",
+            ))
+        }
+
+        async fn get_appointment(
+            &self,
+            _: Request<common_msgs::GetAppointmentRequest>,
+        ) -> Result<Response<common_msgs::GetAppointmentResponse>, Status> {
+            Err(Status::not_found("Appointment not found"))
+        }
+
+        async fn get_subscription_info(
+            &self,
+            _: Request<common_msgs::GetSubscriptionInfoRequest>,
+        ) -> Result<Response<common_msgs::GetSubscriptionInfoResponse>, Status> {
+            Err(Status::unimplemented("Not needed by the tests"))
+        }
+
+        async fn store_backup(
+            &self,
+            _: Request<common_msgs::StoreBackupRequest>,
+        ) -> Result<Response<common_msgs::StoreBackupResponse>, Status> {
+            Err(Status::unimplemented("Not needed by the tests"))
+        }
+
+        async fn get_backup(
+            &self,
+            _: Request<common_msgs::GetBackupRequest>,
+        ) -> Result<Response<common_msgs::GetBackupResponse>, Status> {
+            Err(Status::not_found("No backup found for this user"))
+        }
+
+        async fn get_appointment_outcomes(
+            &self,
+            _: Request<common_msgs::GetAppointmentOutcomesRequest>,
+        ) -> Result<Response<common_msgs::GetAppointmentOutcomesResponse>, Status> {
+            Err(Status::unimplemented("Not needed by the tests"))
+        }
+
+        async fn get_subscription_plans(
+            &self,
+            _: Request<()>,
+        ) -> Result<Response<common_msgs::GetSubscriptionPlansResponse>, Status> {
+            Ok(Response::new(common_msgs::GetSubscriptionPlansResponse {
+                pow_difficulty: 8,
+                ..Default::default()
+            }))
+        }
+    }
+
+    /// Name the certificates of the mock towers are issued for.
+    const TOWER_NAME: &str = "tower.example";
+
+    fn generate_certificate() -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec![TOWER_NAME.to_owned()]).unwrap();
+        (
+            cert.serialize_pem().unwrap(),
+            cert.serialize_private_key_pem(),
+        )
+    }
+
+    /// Runs a mock tower in the background, reachable by IP. Returns its `grpcs://` address, pinned to the tower
+    /// certificate, and the count of connections accepted by the tower.
+    async fn run_mock_tower() -> (NetAddr, Arc<AtomicUsize>) {
+        let (cert, key) = generate_certificate();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let incoming = TcpListenerStream::new(listener).map(move |stream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            stream
+        });
+
+        let server = Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(&cert, key)))
+            .unwrap()
+            .add_service(PublicTowerServicesServer::new(MockTower));
+        tokio::spawn(server.serve_with_incoming(incoming));
+
+        (
+            NetAddr::new(format!("grpcs://{addr}")).with_tls_cert(Some(cert)),
+            connections,
+        )
+    }
+
+    #[test]
+    fn test_tls_name() {
+        let cert = generate_certificate().0;
+
+        // Towers reached by hostname are verified against it
+        let net_addr = NetAddr::new("grpcs://teos.example:9814".to_owned());
+        assert_eq!(tls_name(&net_addr, &cert).unwrap(), "teos.example");
+
+        // The rest are verified against the name their certificate is issued for
+        let net_addr = NetAddr::new("grpcs://127.0.0.1:9814".to_owned());
+        assert_eq!(tls_name(&net_addr, &cert).unwrap(), TOWER_NAME);
+
+        // Which must be a valid certificate
+        assert!(matches!(
+            tls_name(&net_addr, "not a certificate"),
+            Err(RequestError::ConnectionError(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        // The tower is reached by IP, so its certificate is verified against the name it is issued for
+        let (net_addr, connections) = run_mock_tower().await;
+
+        let plans: common_msgs::GetSubscriptionPlansResponse =
+            http::get_subscription_plans(&net_addr, &None)
+                .await
+                .unwrap();
+        assert_eq!(plans.pow_difficulty, 8);
+
+        let response = http::ping(&net_addr, &None).await.unwrap();
+        assert!(response.is_success());
+
+        let user_id = get_random_user_id();
+        match http::register(
+            TowerId(get_random_user_id().0),
+            user_id,
+            "",
+            0,
+            "",
+            &net_addr,
+            &None,
+        )
+        .await
+        .unwrap()
+        {
+            Registration::Registered(receipt) => {
+                assert_eq!(receipt.user_id(), user_id);
+                assert_eq!(receipt.available_slots(), 21);
+            }
+            r => panic!("Unexpected registration outcome: {:?}", r),
+        }
+
+        // All the requests share the same connection
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_error() {
+        let (net_addr, _) = run_mock_tower().await;
+
+        // Errors are reported the same way the HTTP API does
+        match http::add_appointment(
+            TowerId(get_random_user_id().0),
+            &net_addr,
+            &None,
+            &generate_random_appointment(None),
+            "signature",
+        )
+        .await
+        {
+            Err(AddAppointmentError::ApiError(e)) => {
+                assert_eq!(e.error_code, errors::RATE_LIMIT_EXCEEDED)
+            }
+            r => panic!("Unexpected add_appointment outcome: {:?}", r),
+        }
+
+        // Missing backups are not reported as missing appointments
+        match request(
+            &net_addr,
+            &None,
+            common_msgs::GetBackupRequest {
+                signature: "signature".to_owned(),
+            },
+        )
+        .await
+        .unwrap()
+        {
+            ApiResponse::Error(e) => assert_eq!(e.error_code, errors::BACKUP_NOT_FOUND),
+            r => panic!("Unexpected get_backup outcome: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_certificate() {
+        let (net_addr, _) = run_mock_tower().await;
+
+        // The certificate of the tower must be pinned
+        let unpinned = net_addr.clone().with_tls_cert(None);
+        assert!(matches!(
+            ping(&unpinned, &None).await,
+            Err(RequestError::ConnectionError(..))
+        ));
+
+        // And it must be the one the tower uses, even if issued for the same name
+        let wrong_pin = net_addr.with_tls_cert(Some(generate_certificate().0));
+        assert!(matches!(
+            ping(&wrong_pin, &None).await,
+            Err(RequestError::ConnectionError(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_onion_without_proxy() {
+        let net_addr = NetAddr::new(
+            "grpcs://recnedb7xfhzjdrcgxongzli3a6qyrv5jwgowoho3v5g3rwk7kkglrid.onion:9814"
+                .to_owned(),
+        )
+        .with_tls_cert(Some(generate_certificate().0));
+        assert_eq!(
+            ping(&net_addr, &None).await.unwrap_err(),
+            RequestError::ConnectionError(
+                "Cannot connect to an onion address without a proxy".to_owned()
+            )
+        );
+    }
+}
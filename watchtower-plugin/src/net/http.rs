@@ -1,15 +1,17 @@
-use reqwest::{Method, Response};
+use reqwest::Method;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use teos_common::appointment::Appointment;
+use teos_common::appointment::{Appointment, Locator};
+use teos_common::backup::Backup;
 use teos_common::cryptography;
+use teos_common::errors;
 use teos_common::net::http::Endpoint;
 use teos_common::net::NetAddr;
 use teos_common::protos as common_msgs;
-use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
+use teos_common::receipts::{AppointmentReceipt, BackupReceipt, RegistrationReceipt};
 use teos_common::{TowerId, UserId};
 
-use crate::net::ProxyInfo;
+use crate::net::{grpc, ProxyInfo};
 use crate::MisbehaviorProof;
 
 /// Represents a generic api response.
@@ -27,6 +29,41 @@ pub struct ApiError {
     pub error_code: u8,
 }
 
+/// A raw response from the HTTP API of the tower. Pinging the public gRPC interface of a tower also yields one, with
+/// no body.
+#[derive(Debug)]
+pub struct TowerResponse {
+    success: bool,
+    status: String,
+    body: Vec<u8>,
+}
+
+impl TowerResponse {
+    /// Creates a new [TowerResponse] instance.
+    pub fn new(success: bool, status: String, body: Vec<u8>) -> Self {
+        TowerResponse {
+            success,
+            status,
+            body,
+        }
+    }
+
+    /// Whether the tower processed the request successfully.
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    /// The status of the response (e.g. `200 OK`), for display purposes.
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// Parses the body of the response.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
 /// Errors related to requests sent to the tower.
 #[derive(Debug, PartialEq, Eq)]
 pub enum RequestError {
@@ -55,35 +92,156 @@ impl From<RequestError> for AddAppointmentError {
     }
 }
 
+/// Errors related to the `store_backup` requests to the tower.
+#[derive(Debug)]
+pub enum StoreBackupError {
+    RequestError(RequestError),
+    ApiError(ApiError),
+    /// The receipt was not signed by the tower. Contains the recovered id.
+    SignatureError(TowerId),
+}
+
+impl From<RequestError> for StoreBackupError {
+    fn from(r: RequestError) -> Self {
+        StoreBackupError::RequestError(r)
+    }
+}
+
+/// The outcome of a registration request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Registration {
+    /// The user has been registered. Holds the registration receipt.
+    Registered(RegistrationReceipt),
+    /// The requested plan has a price. Holds the BOLT11 invoice to be paid before registering again.
+    PaymentRequired(String),
+    /// The tower requires new users to provide a proof of work, and the provided one (if any) was not valid.
+    ProofOfWorkRequired,
+}
+
 /// Handles the logic of interacting with the `register` endpoint of the tower.
+///
+/// `pow_nonce` and `token` are only needed to register with towers that gate registrations (they are ignored otherwise).
 pub async fn register(
     tower_id: TowerId,
     user_id: UserId,
+    plan: &str,
+    pow_nonce: u64,
+    token: &str,
     tower_net_addr: &NetAddr,
     proxy: &Option<ProxyInfo>,
-) -> Result<RegistrationReceipt, RequestError> {
+) -> Result<Registration, RequestError> {
     log::info!("Registering in the Eye of Satoshi (tower_id={tower_id})");
-    process_post_response(
-        post_request(
-            tower_net_addr,
-            Endpoint::Register,
-            &common_msgs::RegisterRequest {
-                user_id: user_id.to_vec(),
-            },
-            proxy,
-        )
-        .await,
+    let response = send(
+        tower_net_addr,
+        proxy,
+        common_msgs::RegisterRequest {
+            user_id: user_id.to_vec(),
+            plan: plan.to_owned(),
+            pow_nonce,
+            registration_token: token.to_owned(),
+        },
+    )
+    .await?;
+
+    match response {
+        ApiResponse::Response(r) if r.invoice.is_empty() => Ok(Registration::Registered(
+            RegistrationReceipt::with_signature(
+                user_id,
+                r.available_slots,
+                r.subscription_start,
+                r.subscription_expiry,
+                r.plan,
+                r.subscription_signature,
+            ),
+        )),
+        ApiResponse::Response(r) => Ok(Registration::PaymentRequired(r.invoice)),
+        ApiResponse::Error(e) if e.error_code == errors::REGISTRATION_POW_REQUIRED => {
+            Ok(Registration::ProofOfWorkRequired)
+        }
+        ApiResponse::Error(e) => Err(RequestError::Unexpected(e.error)),
+    }
+}
+
+/// Handles the logic of interacting with the `get_subscription_plans` endpoint of the tower.
+pub async fn get_subscription_plans(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<common_msgs::GetSubscriptionPlansResponse, RequestError> {
+    if tower_net_addr.is_grpc() {
+        return match grpc::request(tower_net_addr, proxy, ()).await? {
+            ApiResponse::Response(r) => Ok(r),
+            ApiResponse::Error(e) => Err(RequestError::Unexpected(e.error)),
+        };
+    }
+
+    process_post_response(get_request(tower_net_addr, Endpoint::GetSubscriptionPlans, proxy).await)
+        .await
+}
+
+/// Handles the logic of interacting with the `get_subscription_info` endpoint of the tower.
+pub async fn get_subscription_info(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    signature: String,
+) -> Result<common_msgs::GetSubscriptionInfoResponse, RequestError> {
+    match send(
+        tower_net_addr,
+        proxy,
+        common_msgs::GetSubscriptionInfoRequest { signature },
+    )
+    .await?
+    {
+        ApiResponse::Response(r) => Ok(r),
+        ApiResponse::Error(e) => Err(RequestError::Unexpected(e.error)),
+    }
+}
+
+/// Handles the logic of interacting with the `get_appointment` endpoint of the tower.
+pub async fn get_appointment(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    locator: Locator,
+    signature: String,
+) -> Result<ApiResponse<common_msgs::GetAppointmentResponse>, RequestError> {
+    send(
+        tower_net_addr,
+        proxy,
+        common_msgs::GetAppointmentRequest {
+            locator: locator.to_vec(),
+            signature,
+        },
     )
     .await
-    .map(|r: common_msgs::RegisterResponse| {
-        RegistrationReceipt::with_signature(
-            user_id,
-            r.available_slots,
-            r.subscription_start,
-            r.subscription_expiry,
-            r.subscription_signature,
-        )
-    })
+}
+
+/// Handles the logic of interacting with the `get_appointment_outcomes` endpoint of the tower.
+pub async fn get_appointment_outcomes(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    locator: Locator,
+    signature: String,
+) -> Result<ApiResponse<common_msgs::GetAppointmentOutcomesResponse>, RequestError> {
+    send(
+        tower_net_addr,
+        proxy,
+        common_msgs::GetAppointmentOutcomesRequest {
+            locator: locator.to_vec(),
+            signature,
+        },
+    )
+    .await
+}
+
+/// Checks whether the tower is reachable.
+pub async fn ping(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<TowerResponse, RequestError> {
+    if tower_net_addr.is_grpc() {
+        grpc::ping(tower_net_addr, proxy).await
+    } else {
+        get_request(tower_net_addr, Endpoint::Ping, proxy).await
+    }
 }
 
 /// Encapsulates the logging and response parsing of sending and appointment to the tower.
@@ -120,18 +278,8 @@ pub async fn send_appointment(
         signature: signature.to_owned(),
     };
 
-    match process_post_response(
-        post_request(
-            tower_net_addr,
-            Endpoint::AddAppointment,
-            &request_data,
-            proxy,
-        )
-        .await,
-    )
-    .await?
-    {
-        ApiResponse::Response::<common_msgs::AddAppointmentResponse>(r) => {
+    match send(tower_net_addr, proxy, request_data).await? {
+        ApiResponse::Response(r) => {
             let receipt = AppointmentReceipt::with_signature(
                 signature.to_owned(),
                 r.start_block,
@@ -154,35 +302,96 @@ pub async fn send_appointment(
     }
 }
 
-/// A generic function to send a request to a tower.
+/// Handles the logic of interacting with the `store_backup` endpoint of the tower.
+pub async fn store_backup(
+    tower_id: TowerId,
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    backup: &Backup,
+    signature: &str,
+) -> Result<(u32, BackupReceipt), StoreBackupError> {
+    log::debug!(
+        "Sending backup (version={}) to tower {tower_id}",
+        backup.version
+    );
+    let request_data = common_msgs::StoreBackupRequest {
+        backup: backup.data.clone(),
+        version: backup.version,
+        signature: signature.to_owned(),
+    };
+
+    match send(tower_net_addr, proxy, request_data).await? {
+        ApiResponse::Response(r) => {
+            let receipt =
+                BackupReceipt::with_signature(signature.to_owned(), r.version, r.signature);
+            let recovered_id = TowerId(
+                cryptography::recover_pk(&receipt.to_vec(), &receipt.signature().unwrap()).unwrap(),
+            );
+            if recovered_id == tower_id {
+                log::debug!("Backup accepted and signed by {tower_id}");
+                Ok((r.available_slots, receipt))
+            } else {
+                Err(StoreBackupError::SignatureError(recovered_id))
+            }
+        }
+        ApiResponse::Error(e) => Err(StoreBackupError::ApiError(e)),
+    }
+}
+
+/// Sends a request to a tower, through its public gRPC interface if it has a `grpcs://` address, or through its HTTP
+/// API otherwise.
+async fn send<R>(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+    request: R,
+) -> Result<ApiResponse<R::Response>, RequestError>
+where
+    R: grpc::GrpcRequest + Serialize,
+    R::Response: DeserializeOwned,
+{
+    if tower_net_addr.is_grpc() {
+        grpc::request(tower_net_addr, proxy, request).await
+    } else {
+        process_post_response(post_request(tower_net_addr, R::ENDPOINT, &request, proxy).await)
+            .await
+    }
+}
+
+/// A generic function to send a request to the HTTP API of a tower.
 async fn request<S: Serialize>(
     tower_net_addr: &NetAddr,
     endpoint: Endpoint,
     proxy: &Option<ProxyInfo>,
     method: Method,
     data: Option<S>,
-) -> Result<Response, RequestError> {
-    let client = if let Some(proxy) = proxy {
+) -> Result<TowerResponse, RequestError> {
+    let mut client_builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
         if proxy.always_use || tower_net_addr.is_onion() {
-            reqwest::Client::builder()
-                .proxy(
-                    reqwest::Proxy::http(proxy.get_socks_addr())
-                        .map_err(|e| RequestError::ConnectionError(format!("{e}")))?,
-                )
-                .build()
-                .map_err(|e| RequestError::ConnectionError(format!("{e}")))?
-        } else {
-            reqwest::Client::new()
+            client_builder = client_builder.proxy(
+                reqwest::Proxy::http(proxy.get_socks_addr())
+                    .map_err(|e| RequestError::ConnectionError(format!("{e}")))?,
+            );
         }
-    } else {
+    } else if tower_net_addr.is_onion() {
         // If there is no proxy we only build the client as long as the address is not onion
-        if tower_net_addr.is_onion() {
-            return Err(RequestError::ConnectionError(
-                "Cannot connect to an onion address without a proxy".to_owned(),
-            ));
-        }
-        reqwest::Client::new()
-    };
+        return Err(RequestError::ConnectionError(
+            "Cannot connect to an onion address without a proxy".to_owned(),
+        ));
+    }
+
+    // If the tower certificate is pinned, it is the only one trusted for the connection
+    if let Some(cert) = tower_net_addr.tls_cert() {
+        client_builder = client_builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes()).map_err(
+                |e| RequestError::ConnectionError(format!("Invalid tower certificate: {e}")),
+            )?);
+    }
+
+    let client = client_builder
+        .build()
+        .map_err(|e| RequestError::ConnectionError(format!("{e}")))?;
 
     let mut request_builder = client.request(
         method,
@@ -193,7 +402,7 @@ async fn request<S: Serialize>(
         request_builder = request_builder.json(&data);
     }
 
-    request_builder.send().await.map_err(|e| {
+    let response = request_builder.send().await.map_err(|e| {
         log::debug!("An error ocurred when sending data to the tower: {e}");
         if e.is_connect() | e.is_timeout() {
             RequestError::ConnectionError(
@@ -202,7 +411,17 @@ async fn request<S: Serialize>(
         } else {
             RequestError::Unexpected("Unexpected error ocurred (see logs for more info)".to_owned())
         }
-    })
+    })?;
+
+    let status = response.status();
+    let body = response.bytes().await.map_err(|e| {
+        RequestError::DeserializeError(format!("Unexpected response body. Error: {e}"))
+    })?;
+    Ok(TowerResponse::new(
+        status.is_success(),
+        status.to_string(),
+        body.to_vec(),
+    ))
 }
 
 pub async fn post_request<S: Serialize>(
@@ -210,7 +429,7 @@ pub async fn post_request<S: Serialize>(
     endpoint: Endpoint,
     data: S,
     proxy: &Option<ProxyInfo>,
-) -> Result<Response, RequestError> {
+) -> Result<TowerResponse, RequestError> {
     request(tower_net_addr, endpoint, proxy, Method::POST, Some(data)).await
 }
 
@@ -218,21 +437,17 @@ pub async fn get_request(
     tower_net_addr: &NetAddr,
     endpoint: Endpoint,
     proxy: &Option<ProxyInfo>,
-) -> Result<Response, RequestError> {
+) -> Result<TowerResponse, RequestError> {
     request::<()>(tower_net_addr, endpoint, proxy, Method::GET, None).await
 }
 
 /// Generic function to process the response of a given post request.
 pub async fn process_post_response<T: DeserializeOwned>(
-    post_request: Result<Response, RequestError>,
+    post_request: Result<TowerResponse, RequestError>,
 ) -> Result<T, RequestError> {
-    // TODO: Check if this can be switched for a map. Not sure how to handle async with maps
-    match post_request {
-        Ok(r) => r.json().await.map_err(|e| {
-            RequestError::DeserializeError(format!("Unexpected response body. Error: {e}"))
-        }),
-        Err(e) => Err(e),
-    }
+    post_request?.json().map_err(|e| {
+        RequestError::DeserializeError(format!("Unexpected response body. Error: {e}"))
+    })
 }
 
 #[cfg(test)]
@@ -284,6 +499,9 @@ mod tests {
         let receipt = register(
             TowerId(tower_pk),
             registration_receipt.user_id(),
+            registration_receipt.plan(),
+            0,
+            "",
             &NetAddr::new(server.url()),
             &None,
         )
@@ -291,7 +509,138 @@ mod tests {
         .unwrap();
 
         api_mock.assert_async().await;
-        assert_eq!(receipt, registration_receipt);
+        assert_eq!(receipt, Registration::Registered(registration_receipt));
+    }
+
+    #[tokio::test]
+    async fn test_register_payment_required() {
+        let user_id = get_random_user_id();
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::Register.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::RegisterResponse {
+                    user_id: user_id.to_vec(),
+                    plan: "premium".to_owned(),
+                    invoice: "lnbcrt10n1".to_owned(),
+                    ..Default::default()
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let registration = register(
+            get_random_user_id(),
+            user_id,
+            "premium",
+            0,
+            "",
+            &NetAddr::new(server.url()),
+            &None,
+        )
+        .await
+        .unwrap();
+
+        api_mock.assert_async().await;
+        assert_eq!(
+            registration,
+            Registration::PaymentRequired("lnbcrt10n1".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_gated() {
+        let mut server = mockito::Server::new_async().await;
+
+        // Towers asking for a proof of work do not make registration fail, the proof can be computed and sent
+        let api_mock = server
+            .mock("POST", Endpoint::Register.path().as_str())
+            .match_body(mockito::Matcher::PartialJson(
+                json!({"pow_nonce": 42, "registration_token": ""}),
+            ))
+            .with_status(403)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(ApiError {
+                    error: "Registration requires a valid proof of work (difficulty: 8)".to_owned(),
+                    error_code: errors::REGISTRATION_POW_REQUIRED,
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let registration = register(
+            get_random_user_id(),
+            get_random_user_id(),
+            "",
+            42,
+            "",
+            &NetAddr::new(server.url()),
+            &None,
+        )
+        .await
+        .unwrap();
+        api_mock.assert_async().await;
+        assert_eq!(registration, Registration::ProofOfWorkRequired);
+
+        // Any other error is reported back
+        let error_message = "Registration requires a valid registration token";
+        let api_mock = server
+            .mock("POST", Endpoint::Register.path().as_str())
+            .match_body(mockito::Matcher::PartialJson(
+                json!({"registration_token": "f00dbabe"}),
+            ))
+            .with_status(403)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(ApiError {
+                    error: error_message.to_owned(),
+                    error_code: errors::REGISTRATION_TOKEN_REQUIRED,
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let error = register(
+            get_random_user_id(),
+            get_random_user_id(),
+            "",
+            0,
+            "f00dbabe",
+            &NetAddr::new(server.url()),
+            &None,
+        )
+        .await
+        .unwrap_err();
+        api_mock.assert_async().await;
+        assert_eq!(error, RequestError::Unexpected(error_message.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_get_subscription_plans() {
+        let response = common_msgs::GetSubscriptionPlansResponse {
+            plans: Vec::new(),
+            pow_difficulty: 8,
+            registration_token_required: false,
+        };
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("GET", Endpoint::GetSubscriptionPlans.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!(response).to_string())
+            .create_async()
+            .await;
+
+        let plans = get_subscription_plans(&NetAddr::new(server.url()), &None)
+            .await
+            .unwrap();
+        api_mock.assert_async().await;
+        assert_eq!(plans, response);
     }
 
     #[tokio::test]
@@ -299,6 +648,9 @@ mod tests {
         let error = register(
             get_random_user_id(),
             get_random_user_id(),
+            "",
+            0,
+            "",
             &NetAddr::new("http://server_addr".to_owned()),
             &None,
         )
@@ -308,6 +660,17 @@ mod tests {
         assert!(matches!(error, RequestError::ConnectionError { .. }))
     }
 
+    #[tokio::test]
+    async fn test_request_invalid_pinned_cert() {
+        let net_addr = NetAddr::new("https://server_addr".to_owned())
+            .with_tls_cert(Some("not a certificate".to_owned()));
+        let error = get_request(&net_addr, Endpoint::Ping, &None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, RequestError::ConnectionError { .. }))
+    }
+
     #[tokio::test]
     async fn test_register_deserialize_error() {
         let mut server = mockito::Server::new_async().await;
@@ -322,6 +685,9 @@ mod tests {
         let error = register(
             get_random_user_id(),
             get_random_user_id(),
+            "",
+            0,
+            "",
             &NetAddr::new(server.url()),
             &None,
         )
@@ -522,6 +888,115 @@ mod tests {
         assert!(matches!(error, AddAppointmentError::ApiError { .. }));
     }
 
+    #[tokio::test]
+    async fn test_store_backup() {
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let backup = Backup::new(cryptography::get_random_bytes(100), 1);
+        let mut receipt = BackupReceipt::new("user_sig".into(), backup.version);
+        receipt.sign(&tower_sk);
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::StoreBackup.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::StoreBackupResponse {
+                    version: backup.version,
+                    available_slots: 21,
+                    subscription_expiry: 42,
+                    signature: receipt.signature().unwrap(),
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let (slots, r) = store_backup(
+            TowerId(tower_pk),
+            &NetAddr::new(server.url()),
+            &None,
+            &backup,
+            receipt.user_signature(),
+        )
+        .await
+        .unwrap();
+
+        api_mock.assert_async().await;
+        assert_eq!(slots, 21);
+        assert_eq!(r, receipt);
+    }
+
+    #[tokio::test]
+    async fn test_store_backup_misbehaving() {
+        let (sybil_tower_sk, sybil_tower_pk) = cryptography::get_random_keypair();
+        let backup = Backup::new(cryptography::get_random_bytes(100), 1);
+        let mut receipt = BackupReceipt::new("user_sig".into(), backup.version);
+        receipt.sign(&sybil_tower_sk);
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::StoreBackup.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::StoreBackupResponse {
+                    version: backup.version,
+                    available_slots: 21,
+                    subscription_expiry: 42,
+                    signature: receipt.signature().unwrap(),
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let error = store_backup(
+            get_random_user_id(),
+            &NetAddr::new(server.url()),
+            &None,
+            &backup,
+            receipt.user_signature(),
+        )
+        .await
+        .unwrap_err();
+
+        api_mock.assert_async().await;
+        assert!(
+            matches!(error, StoreBackupError::SignatureError(id) if id == TowerId(sybil_tower_pk))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_backup_api_error() {
+        let api_error = ApiError {
+            error: "error_msg".to_owned(),
+            error_code: 98,
+        };
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("POST", Endpoint::StoreBackup.path().as_str())
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(json!(api_error).to_string())
+            .create_async()
+            .await;
+
+        let error = store_backup(
+            get_random_user_id(),
+            &NetAddr::new(server.url()),
+            &None,
+            &Backup::new(cryptography::get_random_bytes(100), 1),
+            "user_sig",
+        )
+        .await
+        .unwrap_err();
+
+        api_mock.assert_async().await;
+        assert!(matches!(error, StoreBackupError::ApiError { .. }));
+    }
+
     #[tokio::test]
     async fn test_request() {
         let mut server = mockito::Server::new_async().await;
@@ -544,7 +1019,7 @@ mod tests {
         .await;
 
         api_mock_post.assert_async().await;
-        assert!(matches!(response_post, Ok(Response { .. })));
+        assert!(matches!(response_post, Ok(TowerResponse { .. })));
 
         // Test with GET
         let api_mock_get = server
@@ -564,7 +1039,7 @@ mod tests {
         .await;
 
         api_mock_get.assert_async().await;
-        assert!(matches!(response_get, Ok(Response { .. })));
+        assert!(matches!(response_get, Ok(TowerResponse { .. })));
     }
 
     #[tokio::test]
@@ -605,7 +1080,7 @@ mod tests {
 
         api_mock.assert_async().await;
 
-        assert!(matches!(response, Ok(Response { .. })));
+        assert!(matches!(response, Ok(TowerResponse { .. })));
     }
 
     #[tokio::test]
@@ -639,7 +1114,7 @@ mod tests {
         .await;
 
         api_mock.assert_async().await;
-        assert!(matches!(response, Ok(Response { .. })));
+        assert!(matches!(response, Ok(TowerResponse { .. })));
     }
 
     #[tokio::test]
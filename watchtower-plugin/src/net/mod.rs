@@ -1,5 +1,6 @@
 use cln_plugin::messages;
 use serde::Deserialize;
+pub mod grpc;
 pub mod http;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -20,6 +21,11 @@ impl ProxyInfo {
     }
 
     pub fn get_socks_addr(&self) -> String {
-        format!("socks5h://{}:{}", self.inner.address, self.inner.port)
+        format!("socks5h://{}", self.get_socks_host())
+    }
+
+    /// Gets the `host:port` the proxy is listening at.
+    pub fn get_socks_host(&self) -> String {
+        format!("{}:{}", self.inner.address, self.inner.port)
     }
 }
@@ -0,0 +1,133 @@
+//! Logic related to paying for tower subscriptions.
+
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use teos_common::net::cln_rpc::{ClnRpc, ClnRpcError};
+
+/// Errors that can be raised when paying for a subscription.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaymentError {
+    /// The CLN RPC could not decode or pay the invoice.
+    Rpc(ClnRpcError),
+    /// The invoice does not specify an amount.
+    MissingAmount,
+    /// The invoice amount (in msat) is over the maximum the user is willing to pay.
+    TooExpensive(u64),
+    /// The payment did not complete. Holds the payment status.
+    NotCompleted(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentError::Rpc(e) => write!(f, "{e}"),
+            PaymentError::MissingAmount => write!(f, "The invoice does not specify an amount"),
+            PaymentError::TooExpensive(amount) => write!(
+                f,
+                "The subscription price ({amount} msat) is over the configured maximum"
+            ),
+            PaymentError::NotCompleted(status) => {
+                write!(f, "The payment did not complete (status: {status})")
+            }
+        }
+    }
+}
+
+impl From<ClnRpcError> for PaymentError {
+    fn from(e: ClnRpcError) -> Self {
+        PaymentError::Rpc(e)
+    }
+}
+
+/// Parses an msat amount from the CLN RPC. Older versions encode amounts as strings with an `msat` suffix.
+fn parse_msat(amount: &Value) -> Option<u64> {
+    amount.as_u64().or_else(|| {
+        amount
+            .as_str()
+            .and_then(|s| s.trim_end_matches("msat").parse().ok())
+    })
+}
+
+/// Pays a subscription invoice through CLN, provided its amount does not exceed `max_price_msat`.
+pub async fn pay_invoice(
+    rpc: &ClnRpc,
+    invoice: &str,
+    max_price_msat: u64,
+) -> Result<u64, PaymentError> {
+    let decoded = rpc.call("decodepay", json!({ "bolt11": invoice })).await?;
+    let amount = parse_msat(&decoded["amount_msat"]).ok_or(PaymentError::MissingAmount)?;
+    if amount > max_price_msat {
+        return Err(PaymentError::TooExpensive(amount));
+    }
+
+    let result = rpc.call("pay", json!({ "bolt11": invoice })).await?;
+    match result["status"].as_str() {
+        Some("complete") => Ok(amount),
+        status => Err(PaymentError::NotCompleted(
+            status.unwrap_or("unknown").to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::test_utils::run_cln_rpc_mock;
+
+    const INVOICE: &str = "lnbcrt10n1";
+    const PRICE: u64 = 1000;
+
+    async fn run_mock(amount_msat: Value, pay_status: &'static str) -> ClnRpc {
+        let path = run_cln_rpc_mock(move |method, params| {
+            assert_eq!(params["bolt11"], INVOICE);
+            match method {
+                "decodepay" => json!({"result": {"amount_msat": amount_msat}}),
+                "pay" => json!({"result": {"status": pay_status}}),
+                _ => json!({"error": {"code": -32601, "message": "Unknown command"}}),
+            }
+        })
+        .await;
+
+        ClnRpc::new(path)
+    }
+
+    #[tokio::test]
+    async fn test_pay_invoice() {
+        let rpc = run_mock(json!(PRICE), "complete").await;
+        assert_eq!(pay_invoice(&rpc, INVOICE, PRICE).await, Ok(PRICE));
+
+        // Older versions of CLN encode amounts as strings
+        let rpc = run_mock(json!(format!("{PRICE}msat")), "complete").await;
+        assert_eq!(pay_invoice(&rpc, INVOICE, PRICE).await, Ok(PRICE));
+    }
+
+    #[tokio::test]
+    async fn test_pay_invoice_too_expensive() {
+        let rpc = run_mock(json!(PRICE), "complete").await;
+        assert_eq!(
+            pay_invoice(&rpc, INVOICE, PRICE - 1).await,
+            Err(PaymentError::TooExpensive(PRICE))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pay_invoice_missing_amount() {
+        let rpc = run_mock(Value::Null, "complete").await;
+        assert_eq!(
+            pay_invoice(&rpc, INVOICE, PRICE).await,
+            Err(PaymentError::MissingAmount)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pay_invoice_not_completed() {
+        let rpc = run_mock(json!(PRICE), "pending").await;
+        assert_eq!(
+            pay_invoice(&rpc, INVOICE, PRICE).await,
+            Err(PaymentError::NotCompleted("pending".to_owned()))
+        );
+    }
+}
@@ -12,7 +12,7 @@ use teos_common::cryptography;
 use teos_common::errors;
 use teos_common::UserId as TowerId;
 
-use crate::net::http::{self, AddAppointmentError};
+use crate::net::http::{self, AddAppointmentError, Registration};
 use crate::wt_client::{RevocationData, WTClient};
 use crate::{MisbehaviorProof, TowerStatus};
 
@@ -449,7 +449,15 @@ impl Retrier {
 
         // If the tower state is subscription_error we need to re-register first. If we cannot, then the retry is aborted.
         if status.is_subscription_error() {
-            let receipt = http::register(tower_id, user_id, &net_addr, &proxy)
+            // Renew under the same plan we were subscribed to
+            let plan = self
+                .wt_client
+                .lock()
+                .unwrap()
+                .get_registration_receipt(tower_id)
+                .map_or_else(String::new, |r| r.plan().to_owned());
+            // Renewals are not gated by towers, so no proof of work nor token is needed
+            let receipt = match http::register(tower_id, user_id, &plan, 0, "", &net_addr, &proxy)
                 .await
                 .map_err(|e| {
                     log::debug!("Cannot renew registration with tower. Error: {e:?}");
@@ -457,7 +465,24 @@ impl Retrier {
                         "Cannot renew registration with tower".to_owned(),
                         false,
                     ))
-                })?;
+                })? {
+                Registration::Registered(receipt) => receipt,
+                // Payments are never made on the user's behalf, they need to go through `registertower`
+                Registration::PaymentRequired(_) => {
+                    return Err(Error::permanent(RetryError::Subscription(
+                        "The tower requires a payment to renew the subscription. Use registertower to renew it"
+                            .to_owned(),
+                        true,
+                    )))
+                }
+                Registration::ProofOfWorkRequired => {
+                    return Err(Error::permanent(RetryError::Subscription(
+                        "The tower requires a proof of work to register. Use registertower to register again"
+                            .to_owned(),
+                        true,
+                    )))
+                }
+            };
             if !receipt.verify(&tower_id) {
                 return Err(Error::permanent(RetryError::Subscription("Registration receipt contains bad signature. Are you using the right tower_id?".to_owned(), true)));
             }
@@ -529,6 +554,12 @@ impl Retrier {
                                         false,
                                     )));
                                 }
+                                errors::RATE_LIMIT_EXCEEDED => {
+                                    log::warn!(
+                                        "{tower_id} is rate limiting us. Tower will be retried later"
+                                    );
+                                    return Err(Error::transient(RetryError::Unreachable));
+                                }
                                 _ => {
                                     log::warn!(
                                         "{tower_id} rejected the appointment. Error: {}, error_code: {}",
@@ -582,7 +613,7 @@ mod tests {
 
     use teos_common::errors;
     use teos_common::net::http::Endpoint;
-    use teos_common::protos::AddAppointmentRequest;
+    use teos_common::protos::{self as common_msgs, AddAppointmentRequest};
     use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
     use teos_common::test_utils::{
         generate_random_appointment, get_random_registration_receipt, get_random_user_id,
@@ -1091,8 +1122,13 @@ mod tests {
         // Add a tower with pending appointments
         let (tower_sk, tower_pk) = cryptography::get_random_keypair();
         let tower_id = TowerId(tower_pk);
-        let mut registration_receipt =
-            RegistrationReceipt::new(wt_client.lock().unwrap().user_id, 21, 42, 420);
+        let mut registration_receipt = RegistrationReceipt::new(
+            wt_client.lock().unwrap().user_id,
+            21,
+            42,
+            420,
+            String::new(),
+        );
         registration_receipt.sign(&tower_sk);
         wt_client
             .lock()
@@ -1547,6 +1583,118 @@ mod tests {
         api_mock.assert_async().await;
     }
 
+    #[tokio::test]
+    async fn test_retry_tower_rate_limited() {
+        let (_, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let wt_client = Arc::new(Mutex::new(
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await,
+        ));
+        let mut server = mockito::Server::new_async().await;
+
+        // The tower we'd like to retry sending appointments to has to exist within the plugin
+        let receipt = get_random_registration_receipt();
+        wt_client
+            .lock()
+            .unwrap()
+            .add_update_tower(tower_id, &server.url(), &receipt)
+            .unwrap();
+
+        let api_mock = server
+            .mock("POST", Endpoint::AddAppointment.path().as_str())
+            .with_status(429)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(ApiError {
+                    error: "Too many requests from this user. Try again later".to_owned(),
+                    error_code: errors::RATE_LIMIT_EXCEEDED,
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let appointment = generate_random_appointment(None);
+        wt_client
+            .lock()
+            .unwrap()
+            .add_pending_appointment(tower_id, &appointment);
+
+        // Rate limited appointments are kept as pending so they can be retried later
+        let retrier = Retrier::new(
+            wt_client.clone(),
+            tower_id,
+            HashSet::from([appointment.locator]),
+        );
+        let r = retrier.run().await;
+
+        assert_eq!(r, Err(Error::transient(RetryError::Unreachable)));
+        assert!(wt_client
+            .lock()
+            .unwrap()
+            .towers
+            .get(&tower_id)
+            .unwrap()
+            .pending_appointments
+            .contains(&appointment.locator));
+        api_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_retry_tower_subscription_payment_required() {
+        let (_, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let wt_client = Arc::new(Mutex::new(
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await,
+        ));
+        let mut server = mockito::Server::new_async().await;
+
+        // The tower has to exist within the plugin, with its subscription in need of renewal
+        let receipt = get_random_registration_receipt();
+        wt_client
+            .lock()
+            .unwrap()
+            .add_update_tower(tower_id, &server.url(), &receipt)
+            .unwrap();
+        wt_client
+            .lock()
+            .unwrap()
+            .set_tower_status(tower_id, TowerStatus::SubscriptionError);
+
+        // The tower asks for a payment to renew the subscription
+        let api_mock = server
+            .mock("POST", Endpoint::Register.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::RegisterResponse {
+                    invoice: "lnbcrt10n1".to_owned(),
+                    ..Default::default()
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let appointment = generate_random_appointment(None);
+        wt_client
+            .lock()
+            .unwrap()
+            .add_pending_appointment(tower_id, &appointment);
+
+        // Payments are not made by the retrier, so the retry fails permanently
+        let retrier = Retrier::new(wt_client, tower_id, HashSet::from([appointment.locator]));
+        let r = retrier.run().await;
+
+        assert!(matches!(
+            r,
+            Err(Error::Permanent(RetryError::Subscription(_, true)))
+        ));
+        api_mock.assert_async().await;
+    }
+
     #[tokio::test]
     async fn test_retry_tower_rejected() {
         let (_, tower_pk) = cryptography::get_random_keypair();
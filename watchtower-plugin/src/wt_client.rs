@@ -9,7 +9,9 @@ use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use teos_common::appointment::{Appointment, Locator};
 use teos_common::cryptography;
 use teos_common::dbm::Error as DBError;
-use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
+use teos_common::receipts::{
+    AppointmentReceipt, BackupReceipt, RegistrationReceipt, ResponseReceipt,
+};
 use teos_common::{TowerId, UserId};
 
 use crate::dbm::DBM;
@@ -179,6 +181,18 @@ impl WTClient {
         Ok(())
     }
 
+    /// Pins (or unpins, if `tls_cert` is `None`) the certificate used to connect to a given tower.
+    pub fn set_tower_tls_cert(&mut self, tower_id: TowerId, tls_cert: Option<String>) {
+        if let Some(tower) = self.towers.get_mut(&tower_id) {
+            self.dbm
+                .store_tower_tls_cert(tower_id, tls_cert.as_deref())
+                .unwrap();
+            tower.net_addr = tower.net_addr.clone().with_tls_cert(tls_cert);
+        } else {
+            log::error!("Cannot pin the certificate of an unknown tower ({tower_id})");
+        }
+    }
+
     /// Gets the latest registration receipt of a given tower.
     pub fn get_registration_receipt(&self, tower_id: TowerId) -> Option<RegistrationReceipt> {
         self.dbm.load_registration_receipt(tower_id, self.user_id)
@@ -241,6 +255,47 @@ impl WTClient {
         self.dbm.load_appointment_receipt(tower_id, locator)
     }
 
+    /// Adds a backup receipt to the tower record, replacing the previous one (if any).
+    pub fn add_backup_receipt(
+        &mut self,
+        tower_id: TowerId,
+        available_slots: u32,
+        receipt: &BackupReceipt,
+    ) {
+        if let Some(tower) = self.towers.get_mut(&tower_id) {
+            tower.available_slots = available_slots;
+
+            self.dbm
+                .store_backup_receipt(tower_id, available_slots, receipt)
+                .unwrap();
+        } else {
+            log::error!("Cannot add backup receipt to tower. Unknown tower_id: {tower_id}");
+        }
+    }
+
+    /// Gets the latest backup receipt of a given tower from the database (if found).
+    pub fn get_backup_receipt(&self, tower_id: TowerId) -> Option<BackupReceipt> {
+        self.dbm.load_backup_receipt(tower_id)
+    }
+
+    /// Adds a response receipt to the tower record, replacing the previous one for the same appointment (if any).
+    pub fn add_response_receipt(&mut self, tower_id: TowerId, receipt: &ResponseReceipt) {
+        if self.towers.contains_key(&tower_id) {
+            self.dbm.store_response_receipt(tower_id, receipt).unwrap();
+        } else {
+            log::error!("Cannot add response receipt to tower. Unknown tower_id: {tower_id}");
+        }
+    }
+
+    /// Gets the latest response receipt of a given tower and locator from the database (if found).
+    pub fn get_response_receipt(
+        &self,
+        tower_id: TowerId,
+        locator: Locator,
+    ) -> Option<ResponseReceipt> {
+        self.dbm.load_response_receipt(tower_id, locator)
+    }
+
     /// Adds a pending appointment to the tower record.
     pub fn add_pending_appointment(&mut self, tower_id: TowerId, appointment: &Appointment) {
         if let Some(tower) = self.towers.get_mut(&tower_id) {
@@ -312,7 +367,7 @@ mod tests {
 
     use teos_common::test_utils::{
         generate_random_appointment, get_random_appointment_receipt,
-        get_random_registration_receipt, get_random_user_id,
+        get_random_registration_receipt, get_random_response_receipt, get_random_user_id,
         get_registration_receipt_from_previous,
     };
 
@@ -370,6 +425,7 @@ mod tests {
             receipt.available_slots(),
             receipt.subscription_start(),
             receipt.subscription_expiry() + 1,
+            receipt.plan().to_owned(),
         );
         receipt_same_slots.sign(&tower_sk);
         let mut receipt_same_expiry = RegistrationReceipt::new(
@@ -377,6 +433,7 @@ mod tests {
             receipt.available_slots() + 1,
             receipt.subscription_start(),
             receipt.subscription_expiry(),
+            receipt.plan().to_owned(),
         );
         receipt_same_expiry.sign(&tower_sk);
 
@@ -463,6 +520,44 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_set_tower_tls_cert() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let mut wt_client =
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await;
+
+        // If the tower is unknown nothing will happen
+        let unknown_tower = get_random_user_id();
+        wt_client.set_tower_tls_cert(unknown_tower, Some("cert".to_owned()));
+        assert!(!wt_client.towers.contains_key(&unknown_tower));
+
+        // If the tower is known, the certificate is pinned both in memory and in the database
+        let receipt = get_random_registration_receipt();
+        let tower_id = get_random_user_id();
+        wt_client
+            .add_update_tower(tower_id, "https://talaia.watch", &receipt)
+            .unwrap();
+        wt_client.set_tower_tls_cert(tower_id, Some("cert".to_owned()));
+        assert_eq!(
+            wt_client.towers[&tower_id].net_addr.tls_cert(),
+            Some("cert")
+        );
+        assert_eq!(wt_client.dbm.load_towers(), wt_client.towers);
+
+        // Updating the subscription keeps the pinned certificate
+        wt_client
+            .add_update_tower(
+                tower_id,
+                "https://talaia.watch",
+                &get_registration_receipt_from_previous(&receipt),
+            )
+            .unwrap();
+        assert_eq!(
+            wt_client.towers[&tower_id].net_addr.tls_cert(),
+            Some("cert")
+        );
+    }
+
     #[tokio::test]
     async fn test_add_appointment_receipt() {
         let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
@@ -513,6 +608,65 @@ mod tests {
         assert_eq!(wt_client.load_tower_info(tower_id).unwrap(), tower_info);
     }
 
+    #[tokio::test]
+    async fn test_add_backup_receipt() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let mut wt_client =
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await;
+
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let mut backup_receipt = BackupReceipt::new("user_sig".to_owned(), 1);
+        backup_receipt.sign(&tower_sk);
+
+        // If we call this on an unknown tower it will simply do nothing
+        wt_client.add_backup_receipt(tower_id, 21, &backup_receipt);
+        assert!(wt_client.get_backup_receipt(tower_id).is_none());
+
+        // Add the tower to the state and try again
+        let registration_receipt = get_random_registration_receipt();
+        wt_client
+            .add_update_tower(tower_id, "talaia.watch", &registration_receipt)
+            .unwrap();
+        wt_client.add_backup_receipt(tower_id, 21, &backup_receipt);
+
+        assert_eq!(wt_client.towers.get(&tower_id).unwrap().available_slots, 21);
+        assert_eq!(
+            wt_client.get_backup_receipt(tower_id).unwrap(),
+            backup_receipt
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_response_receipt() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let mut wt_client =
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await;
+
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let response_receipt = get_random_response_receipt(tower_sk);
+
+        // If we call this on an unknown tower it will simply do nothing
+        wt_client.add_response_receipt(tower_id, &response_receipt);
+        assert!(wt_client
+            .get_response_receipt(tower_id, response_receipt.locator())
+            .is_none());
+
+        // Add the tower to the state and try again
+        let registration_receipt = get_random_registration_receipt();
+        wt_client
+            .add_update_tower(tower_id, "talaia.watch", &registration_receipt)
+            .unwrap();
+        wt_client.add_response_receipt(tower_id, &response_receipt);
+        assert_eq!(
+            wt_client
+                .get_response_receipt(tower_id, response_receipt.locator())
+                .unwrap(),
+            response_receipt
+        );
+    }
+
     #[tokio::test]
     async fn test_add_pending_appointment() {
         let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();